@@ -0,0 +1,557 @@
+//! The versioned JSON API under `/api/v1`.
+//!
+//! Clients authenticate with a personal access token created on the
+//! settings page and sent as `Authorization: Bearer wf_...`. Each token
+//! carries [`ApiScope`]s that limit what it can do, on top of what the
+//! owner's role allows. The API never reads the session cookie, which is why
+//! it can skip the CSRF check.
+//!
+//! Errors always have the body `{"error": {"code": ..., "message": ...}}`.
+//! Lists come back as `{"data": [...], "next_cursor": ...}`; passing
+//! `next_cursor` back as `?cursor=` gets the next page, and it's `null` on
+//! the last one.
+
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        FromRequestParts,
+    },
+    http::{
+        header::{AUTHORIZATION, WWW_AUTHENTICATE},
+        request::Parts,
+        HeaderValue, StatusCode,
+    },
+    response::{IntoResponse, Json, Response},
+};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tera::Tera;
+use utoipa::{IntoParams, ToSchema};
+
+use crate::auth::{create_email_token, hash_token, now_timestamp, Role};
+use crate::db::{self, Db, DbError};
+use crate::models::{ApiScope, ApiToken, Category, Comment, Notification, Post, Store, User};
+
+/// Start of every token, so leaked tokens are easy to search for
+pub const TOKEN_PREFIX: &str = "wf_";
+
+/// Characters of a token kept in the clear to tell tokens apart
+const DISPLAY_PREFIX_LEN: usize = 11;
+
+/// How often a token's last-used time is written
+const LAST_USED_INTERVAL_MINUTES: i64 = 5;
+
+pub const DEFAULT_PAGE_SIZE: i64 = 25;
+pub const MAX_PAGE_SIZE: i64 = 100;
+
+/// Generate a new personal access token
+pub fn create_token() -> String {
+    format!("{}{}", TOKEN_PREFIX, create_email_token())
+}
+
+/// The part of a token shown on the settings page, e.g. `wf_3fa9c01e`
+pub fn display_prefix(token: &str) -> String {
+    token.chars().take(DISPLAY_PREFIX_LEN).collect()
+}
+
+/// An error response. The status and `code` are for programs, the message
+/// for people.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub code: &'static str,
+    pub message: String,
+}
+
+/// Body of every error response
+#[derive(Serialize, ToSchema)]
+pub struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct ErrorDetail<'a> {
+    /// Stable, machine-readable: unauthorized, forbidden, not_found,
+    /// invalid_request, validation_failed or internal_error
+    code: &'a str,
+    message: &'a str,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError { status, code, message: message.into() }
+    }
+
+    pub fn unauthorized(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, "unauthorized", message)
+    }
+
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, "forbidden", message)
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, "not_found", message)
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, "invalid_request", message)
+    }
+
+    /// The request was well-formed but its values weren't acceptable
+    pub fn validation(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNPROCESSABLE_ENTITY, "validation_failed", message)
+    }
+
+    pub fn internal() -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Something went wrong")
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let body = ErrorBody { error: ErrorDetail { code: self.code, message: &self.message } };
+        let mut response = (self.status, Json(body)).into_response();
+        if self.status == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+impl From<DbError> for ApiError {
+    fn from(e: DbError) -> Self {
+        tracing::error!(error = %e, "API request failed");
+        ApiError::internal()
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(rejection.status(), "invalid_request", rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        Self::new(rejection.status(), "invalid_request", rejection.body_text())
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        Self::new(rejection.status(), "invalid_request", rejection.body_text())
+    }
+}
+
+/// The user a request's bearer token belongs to
+pub struct ApiUser {
+    pub user: User,
+    pub token: ApiToken,
+}
+
+impl ApiUser {
+    /// Fails unless the token was granted `scope`
+    pub fn require(&self, scope: ApiScope) -> Result<(), ApiError> {
+        if self.token.scopes.contains(&scope) {
+            Ok(())
+        } else {
+            Err(ApiError::forbidden(format!("This token doesn't have the {} scope", scope.to_str())))
+        }
+    }
+
+    /// Fails unless the token's owner has the permission `R` checks for
+    pub fn require_role<R: Role>(&self) -> Result<(), ApiError> {
+        if R::allows(&self.user.permissions) {
+            Ok(())
+        } else {
+            Err(ApiError::forbidden(R::DENIED))
+        }
+    }
+}
+
+impl FromRequestParts<(Db, Arc<Tera>)> for ApiUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, (db, _): &(Db, Arc<Tera>)) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .ok_or_else(|| ApiError::unauthorized("Send a personal access token as `Authorization: Bearer <token>`"))?;
+
+        let token_hash = hash_token(token);
+        let found = db.read(move |conn| {
+            let Some(token) = db::get_api_token_by_hash(conn, &token_hash)? else {
+                return Ok(None);
+            };
+            let Some(mut user) = db::get_user_by_id(conn, token.user_id)? else {
+                return Ok(None);
+            };
+            user.permissions = db::get_user_permissions(conn, user.id, &user.role)?;
+            Ok(Some((user, token)))
+        }).await?;
+
+        let Some((user, token)) = found.filter(|(user, _)| !user.banned) else {
+            return Err(ApiError::unauthorized("The token is invalid or has been revoked"));
+        };
+
+        let used_cutoff = (Utc::now() - Duration::minutes(LAST_USED_INTERVAL_MINUTES)).format("%Y-%m-%d %H:%M:%S").to_string();
+        if token.last_used_at.as_deref().is_none_or(|used| used < used_cutoff.as_str()) {
+            let token_id = token.id;
+            let _ = db.write(move |conn| db::touch_api_token(conn, token_id, &now_timestamp())).await;
+        }
+
+        Ok(ApiUser { user, token })
+    }
+}
+
+/// `?cursor=&limit=` on list endpoints
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PageQuery {
+    /// `next_cursor` from the previous page
+    pub cursor: Option<String>,
+    /// Items per page, 1 to 100; 25 if left out
+    pub limit: Option<i64>,
+}
+
+impl PageQuery {
+    /// The page size asked for, within bounds
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE)
+    }
+
+    /// Where the page starts: the position from the previous page's
+    /// `next_cursor`, or None for the first page
+    pub fn position(&self) -> Result<Option<i64>, ApiError> {
+        match self.cursor.as_deref().filter(|c| !c.is_empty()) {
+            Some(cursor) => cursor
+                .parse::<i64>()
+                .ok()
+                .filter(|p| *p >= 0)
+                .map(Some)
+                .ok_or_else(|| ApiError::bad_request("Invalid cursor")),
+            None => Ok(None),
+        }
+    }
+}
+
+/// One page of a list
+#[derive(Debug, Serialize, ToSchema)]
+pub struct Page<T> {
+    pub data: Vec<T>,
+    /// Pass back as `?cursor=` for the next page; null on the last page
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    /// A list that isn't paged
+    pub fn all(data: Vec<T>) -> Self {
+        Page { data, next_cursor: None }
+    }
+
+    /// A page from a query for `limit + 1` items. The extra item, if there
+    /// is one, only shows there's another page and is dropped; the cursor is
+    /// `position` of the last item kept.
+    pub fn from_overfetch<U>(mut items: Vec<U>, limit: i64, position: impl Fn(&U) -> i64, convert: impl Fn(U) -> T) -> Self {
+        let limit = limit.max(0) as usize;
+        let next_cursor = if items.len() > limit {
+            items.truncate(limit);
+            items.last().map(|item| position(item).to_string())
+        } else {
+            None
+        };
+        Page { data: items.into_iter().map(convert).collect(), next_cursor }
+    }
+}
+
+// ============ Response types ============
+
+/// The token's owner
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiMe {
+    pub id: i64,
+    pub username: String,
+    pub role: String,
+    pub flair: Option<String>,
+    pub karma: i64,
+    pub email_confirmed: bool,
+    pub created_at: String,
+    /// What the token used for this request may do
+    pub scopes: Vec<ApiScope>,
+}
+
+impl From<&ApiUser> for ApiMe {
+    fn from(api_user: &ApiUser) -> Self {
+        let user = &api_user.user;
+        ApiMe {
+            id: user.id,
+            username: user.username.clone(),
+            role: user.role.to_str().to_string(),
+            flair: user.flair.clone(),
+            karma: user.karma,
+            email_confirmed: user.email_confirmed,
+            created_at: user.created_at.clone(),
+            scopes: api_user.token.scopes.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiAuthor {
+    pub id: i64,
+    pub username: String,
+    pub role: Option<String>,
+    pub flair: Option<String>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiCategory {
+    pub id: i64,
+    pub name: String,
+    pub slug: String,
+    pub description: String,
+    pub post_count: i64,
+}
+
+impl From<Category> for ApiCategory {
+    fn from(c: Category) -> Self {
+        ApiCategory {
+            id: c.id,
+            name: c.name,
+            slug: c.slug,
+            description: c.description,
+            post_count: c.post_count.unwrap_or(0),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiPost {
+    pub id: i64,
+    pub title: String,
+    /// Markdown as written
+    pub body: String,
+    /// `body` rendered to HTML, with scripts, event handlers and other
+    /// unsafe markup removed
+    pub body_html: Option<String>,
+    pub score: i64,
+    pub category_id: i64,
+    pub category_slug: Option<String>,
+    pub author: ApiAuthor,
+    pub comment_count: i64,
+    pub pinned: bool,
+    pub best_answer_id: Option<i64>,
+    pub tags: Vec<String>,
+    pub created_at: String,
+    pub edited_at: Option<String>,
+}
+
+impl From<Post> for ApiPost {
+    fn from(p: Post) -> Self {
+        ApiPost {
+            id: p.id,
+            title: p.title,
+            body: p.body,
+            body_html: p.body_html,
+            score: p.score,
+            category_id: p.category_id,
+            category_slug: p.category_slug,
+            author: ApiAuthor {
+                id: p.user_id,
+                username: p.username.unwrap_or_default(),
+                role: p.user_role,
+                flair: p.user_flair,
+            },
+            comment_count: p.comment_count.unwrap_or(0),
+            pinned: p.pinned,
+            best_answer_id: p.best_answer_id,
+            tags: p.tags.unwrap_or_default().into_iter().map(|t| t.name).collect(),
+            created_at: p.created_at,
+            edited_at: p.edited_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiComment {
+    pub id: i64,
+    pub post_id: i64,
+    /// The comment this replies to
+    pub parent_id: Option<i64>,
+    /// Markdown as written
+    pub body: String,
+    /// `body` rendered to HTML, with scripts, event handlers and other
+    /// unsafe markup removed
+    pub body_html: Option<String>,
+    pub score: i64,
+    pub author: ApiAuthor,
+    pub is_best_answer: bool,
+    pub created_at: String,
+    pub edited_at: Option<String>,
+}
+
+impl From<Comment> for ApiComment {
+    fn from(c: Comment) -> Self {
+        ApiComment {
+            id: c.id,
+            post_id: c.post_id,
+            parent_id: c.parent_id,
+            body: c.body,
+            body_html: c.body_html,
+            score: c.score,
+            author: ApiAuthor {
+                id: c.user_id,
+                username: c.username.unwrap_or_default(),
+                role: c.user_role,
+                flair: c.user_flair,
+            },
+            is_best_answer: c.is_best_answer,
+            created_at: c.created_at,
+            edited_at: c.edited_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiStore {
+    pub id: i64,
+    pub name: String,
+    pub url: String,
+    pub description: Option<String>,
+    pub category: String,
+    pub positive_votes: i64,
+    pub total_votes: i64,
+    /// Percentage of positive votes; null until someone votes
+    pub reliability_score: Option<f64>,
+    pub created_at: String,
+}
+
+impl From<Store> for ApiStore {
+    fn from(s: Store) -> Self {
+        ApiStore {
+            id: s.id,
+            name: s.name,
+            url: s.url,
+            description: s.description,
+            category: s.category,
+            positive_votes: s.positive_votes,
+            total_votes: s.total_votes,
+            reliability_score: s.reliability_score,
+            created_at: s.created_at,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiNotification {
+    pub id: i64,
+    /// reply, mention, post_reply, best_answer, upvote or system
+    pub kind: String,
+    pub content: String,
+    pub read: bool,
+    pub post_id: Option<i64>,
+    pub comment_id: Option<i64>,
+    pub from_username: Option<String>,
+    pub created_at: String,
+}
+
+impl From<Notification> for ApiNotification {
+    fn from(n: Notification) -> Self {
+        ApiNotification {
+            id: n.id,
+            kind: n.notification_type.to_str().to_string(),
+            content: n.content,
+            read: n.read,
+            post_id: n.post_id,
+            comment_id: n.comment_id,
+            from_username: n.from_username,
+            created_at: n.created_at,
+        }
+    }
+}
+
+/// A post matching a search
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiSearchResult {
+    pub id: i64,
+    pub title: String,
+    /// HTML-escaped excerpt with the matched terms in `<mark>`
+    pub snippet: String,
+    pub category_slug: Option<String>,
+    pub author: ApiAuthor,
+    pub score: i64,
+    pub comment_count: i64,
+    pub created_at: String,
+}
+
+impl From<Post> for ApiSearchResult {
+    fn from(p: Post) -> Self {
+        ApiSearchResult {
+            id: p.id,
+            title: p.title,
+            snippet: p.snippet.unwrap_or_default(),
+            category_slug: p.category_slug,
+            author: ApiAuthor {
+                id: p.user_id,
+                username: p.username.unwrap_or_default(),
+                role: p.user_role,
+                flair: p.user_flair,
+            },
+            score: p.score,
+            comment_count: p.comment_count.unwrap_or(0),
+            created_at: p.created_at,
+        }
+    }
+}
+
+/// A post or comment's score after a vote
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ApiScore {
+    pub score: i64,
+}
+
+// ============ Request types ============
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewPost {
+    pub category_id: i64,
+    pub title: String,
+    /// Markdown
+    pub body: String,
+    /// Tag ids; unknown ids are ignored
+    #[serde(default)]
+    pub tags: Vec<i64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewComment {
+    pub body: String,
+    /// Reply to this comment on the same post
+    pub parent_id: Option<i64>,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewVote {
+    /// 1 or -1
+    pub value: i64,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewStore {
+    pub name: String,
+    /// http:// or https://
+    pub url: String,
+    pub description: Option<String>,
+    pub category: String,
+}
+
+#[derive(Debug, Deserialize, ToSchema)]
+pub struct NewStoreVote {
+    pub positive: bool,
+}
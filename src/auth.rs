@@ -1,13 +1,33 @@
 use argon2::{
-    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    password_hash::{rand_core::{OsRng, RngCore}, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
     Argon2,
 };
-use axum_extra::extract::cookie::{Cookie, CookieJar};
+use axum::{
+    extract::{FromRequestParts, Request, State},
+    http::{
+        header::{COOKIE, SET_COOKIE, USER_AGENT},
+        request::Parts,
+        HeaderMap, HeaderValue,
+    },
+    middleware::Next,
+    response::Response,
+    Extension,
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use ::cookie::CookieBuilder;
 use chrono::{Duration, Utc};
+use sha2::{Digest, Sha256};
+use std::convert::Infallible;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use tera::{Context, Tera};
 use uuid::Uuid;
 
+use crate::config::Config;
 use crate::db::{self, Db};
-use crate::models::User;
+use crate::error::{redirect, AppError};
+use crate::models::{Permission, Permissions, User};
+use crate::totp;
 
 // Re-export time crate types for cookie duration
 mod time {
@@ -38,26 +58,260 @@ pub fn create_session_token() -> String {
     Uuid::new_v4().to_string()
 }
 
-/// Get session expiry timestamp (30 days from now)
-pub fn session_expiry() -> String {
-    (Utc::now() + Duration::days(30)).format("%Y-%m-%d %H:%M:%S").to_string()
+/// Expiry timestamp for a session starting now that lasts `lifetime_days`
+pub fn session_expiry(lifetime_days: i64) -> String {
+    (Utc::now() + Duration::days(lifetime_days)).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// How long a password reset link stays valid
+pub const PASSWORD_RESET_MINUTES: i64 = 60;
+
+/// How long an email confirmation link stays valid
+pub const EMAIL_CONFIRMATION_HOURS: i64 = 48;
+
+/// Minimum wait between confirmation emails to the same account
+pub const EMAIL_RESEND_SECONDS: i64 = 60;
+
+/// Current time in the format timestamps are stored in
+pub fn now_timestamp() -> String {
+    Utc::now().format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Generate a token for a link sent by email: 32 random bytes, hex encoded
+pub fn create_email_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// SHA-256 of an emailed token, hex encoded. Only the hash is stored; the
+/// tokens are random enough that a slow hash isn't needed.
+pub fn hash_token(token: &str) -> String {
+    Sha256::digest(token.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Get password reset token expiry timestamp
+pub fn password_reset_expiry() -> String {
+    (Utc::now() + Duration::minutes(PASSWORD_RESET_MINUTES)).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Get email confirmation token expiry timestamp
+pub fn email_confirmation_expiry() -> String {
+    (Utc::now() + Duration::hours(EMAIL_CONFIRMATION_HOURS)).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Seconds until another confirmation email may be sent, given when the last
+/// one went out
+pub fn email_resend_wait(last_sent: Option<&str>) -> i64 {
+    let Some(sent) = last_sent.and_then(|t| chrono::NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S").ok()) else {
+        return 0;
+    };
+    let elapsed = (Utc::now().naive_utc() - sent).num_seconds();
+    (EMAIL_RESEND_SECONDS - elapsed).max(0)
+}
+
+/// How long the second login step waits for a code
+pub const LOGIN_CHALLENGE_MINUTES: i64 = 5;
+
+/// Wrong codes allowed per login before the password has to be entered again
+pub const LOGIN_CHALLENGE_ATTEMPTS: i64 = 5;
+
+/// Recovery codes issued when two-factor is enabled
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+/// Issuer name shown in authenticator apps
+pub const TOTP_ISSUER: &str = "Wrench Forum";
+
+/// Get login challenge expiry timestamp
+pub fn login_challenge_expiry() -> String {
+    (Utc::now() + Duration::minutes(LOGIN_CHALLENGE_MINUTES)).format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+/// Whether `code` is a current authenticator code or an unused recovery
+/// code for the user, spending it if so
+pub fn check_second_factor(conn: &rusqlite::Connection, user_id: i64, code: &str) -> rusqlite::Result<bool> {
+    let Some(user_totp) = db::get_user_totp(conn, user_id)?.filter(|t| t.enabled) else {
+        return Ok(false);
+    };
+
+    let secret = totp::base32_decode(&user_totp.secret).unwrap_or_default();
+    let now = Utc::now().timestamp() as u64;
+    let last_used = user_totp.last_used_step.map(|s| s as u64);
+    if let Some(step) = totp::verify(&secret, code, now, last_used) {
+        return db::use_totp_step(conn, user_id, step as i64);
+    }
+
+    let recovery = totp::normalize_recovery_code(code);
+    if recovery.is_empty() {
+        return Ok(false);
+    }
+    db::use_recovery_code(conn, user_id, &hash_token(&recovery), &now_timestamp())
+}
+
+/// Whether the user's role requires two-factor and they haven't set it up
+pub fn two_factor_setup_required(conn: &rusqlite::Connection, user: &User) -> rusqlite::Result<bool> {
+    Ok(!user.two_factor_enabled && db::get_two_factor_roles(conn)?.contains(&user.role))
+}
+
+/// Middleware for the admin and moderation routes: staff whose role requires
+/// two-factor are sent to set it up before they can use them
+pub async fn require_two_factor(
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    jar: CookieJar,
+    request: Request,
+    next: Next,
+) -> Response {
+    if let Some((user, _)) = ensure_session(jar, &db).await {
+        let required = db.read(move |conn| two_factor_setup_required(conn, &user)).await.unwrap_or(false);
+        if required {
+            return redirect(request.headers(), "/profile/two-factor");
+        }
+    }
+    next.run(request).await
+}
+
+/// How often a session's last-seen time is written
+pub const SESSION_SEEN_INTERVAL_MINUTES: i64 = 5;
+
+/// Middleware for the whole router. Keeps each session's last-seen time
+/// roughly current, and gives sessions marked after a role change a new
+/// token before the handler sees the request.
+pub async fn track_session(
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    Extension(config): Extension<Arc<Config>>,
+    jar: CookieJar,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let Some(token) = jar.get("session").map(|c| c.value().to_string()) else {
+        return next.run(request).await;
+    };
+    
+    let now = now_timestamp();
+    let lookup_token = token.clone();
+    let session = db.read(move |conn| db::get_session(conn, &lookup_token)).await.ok().flatten();
+    let Some(session) = session.filter(|s| s.expires_at > now) else {
+        return next.run(request).await;
+    };
+    
+    let seen_cutoff = (Utc::now() - Duration::minutes(SESSION_SEEN_INTERVAL_MINUTES)).format("%Y-%m-%d %H:%M:%S").to_string();
+    let stale = session.last_seen_at.as_deref().is_none_or(|seen| seen < seen_cutoff.as_str());
+    if !stale && !session.rotate_pending {
+        return next.run(request).await;
+    }
+    
+    let new_token = session.rotate_pending.then(create_session_token);
+    let update_token = new_token.clone();
+    let rotated = db.write(move |conn| {
+        db::touch_session(conn, &token, &now)?;
+        match update_token {
+            Some(new_token) => db::rotate_session_token(conn, &token, &new_token),
+            None => Ok(false),
+        }
+    }).await.unwrap_or(false);
+    
+    let Some(new_token) = new_token.filter(|_| rotated) else {
+        return next.run(request).await;
+    };
+    
+    // The handler reads the new token from the request's cookies
+    let jar = jar.add(Cookie::new("session", new_token.clone()));
+    let cookies = jar.iter().map(|c| format!("{}={}", c.name(), c.value())).collect::<Vec<_>>().join("; ");
+    if let Ok(value) = HeaderValue::from_str(&cookies) {
+        request.headers_mut().insert(COOKIE, value);
+    }
+    
+    let mut response = next.run(request).await;
+    // The new cookie lasts as long as the session it replaces
+    let remaining = chrono::NaiveDateTime::parse_from_str(&session.expires_at, "%Y-%m-%d %H:%M:%S")
+        .map(|expires| (expires.and_utc() - Utc::now()).num_seconds())
+        .unwrap_or(0);
+    let cookie = session_cookie(&new_token, time::Duration::seconds(remaining.max(0)), config.server.secure_cookies);
+    if let Ok(value) = HeaderValue::from_str(&cookie.to_string()) {
+        response.headers_mut().append(SET_COOKIE, value);
+    }
+    response
+}
+
+/// The id a session goes by on the sessions page: a prefix of its token's
+/// hash, so the page never contains a usable token
+pub fn session_public_id(token: &str) -> String {
+    hash_token(token)[..16].to_string()
+}
+
+/// The request's `User-Agent`, for recording with a new session
+pub fn user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(|ua| ua.chars().take(512).collect())
+}
+
+/// A short description of the browser and OS in a user agent string, e.g.
+/// "Firefox on Windows"
+pub fn describe_user_agent(user_agent: Option<&str>) -> String {
+    let Some(ua) = user_agent.filter(|ua| !ua.trim().is_empty()) else {
+        return "Unknown device".to_string();
+    };
+    
+    // Order matters: Edge and Opera also claim to be Chrome, and Chrome
+    // claims to be Safari
+    let browser = [
+        ("Edg/", "Edge"),
+        ("OPR/", "Opera"),
+        ("Firefox/", "Firefox"),
+        ("FxiOS/", "Firefox"),
+        ("CriOS/", "Chrome"),
+        ("Chrome/", "Chrome"),
+        ("Safari/", "Safari"),
+        ("curl/", "curl"),
+    ].into_iter().find(|(marker, _)| ua.contains(marker)).map(|(_, name)| name);
+    
+    let os = [
+        ("iPhone", "iPhone"),
+        ("iPad", "iPad"),
+        ("Android", "Android"),
+        ("Windows", "Windows"),
+        ("CrOS", "ChromeOS"),
+        ("Mac OS X", "macOS"),
+        ("Linux", "Linux"),
+    ].into_iter().find(|(marker, _)| ua.contains(marker)).map(|(_, name)| name);
+    
+    match (browser, os) {
+        (Some(browser), Some(os)) => format!("{} on {}", browser, os),
+        (Some(name), None) | (None, Some(name)) => name.to_string(),
+        (None, None) => "Unknown device".to_string(),
+    }
 }
 
 /// Check if a session is valid and return the user if so
-pub fn ensure_session(jar: CookieJar, db: &Db) -> Option<(User, CookieJar)> {
+pub async fn ensure_session(jar: CookieJar, db: &Db) -> Option<(User, CookieJar)> {
     let token = jar.get("session")?.value().to_string();
-    let conn = db.lock().ok()?;
     
-    let session = db::get_session(&conn, &token).ok()??;
+    let lookup_token = token.clone();
+    let (session, user) = db.read(move |conn| {
+        let session = match db::get_session(conn, &lookup_token)? {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let user = match db::get_user_by_id(conn, session.user_id)? {
+            Some(mut user) => {
+                user.permissions = db::get_user_permissions(conn, user.id, &user.role)?;
+                Some(user)
+            }
+            None => None,
+        };
+        Ok(Some((session, user)))
+    }).await.ok()??;
     
     // Check expiry
-    let now = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let now = now_timestamp();
     if session.expires_at < now {
-        let _ = db::delete_session(&conn, &token);
+        let _ = db.write(move |conn| db::delete_session(conn, &token)).await;
         return None;
     }
     
-    let user = db::get_user_by_id(&conn, session.user_id).ok()??;
+    let user = user?;
     
     if user.banned {
         return None;
@@ -66,36 +320,218 @@ pub fn ensure_session(jar: CookieJar, db: &Db) -> Option<(User, CookieJar)> {
     Some((user, jar))
 }
 
-/// Set the session cookie
-pub fn set_session_cookie(jar: CookieJar, token: &str) -> CookieJar {
-    let cookie = Cookie::build(("session", token.to_string()))
+/// The session's user, looked up once per request and kept in the request
+/// extensions for any other extractor that needs it
+#[derive(Clone)]
+struct SessionUser(Option<User>);
+
+async fn session_user(parts: &mut Parts, db: &Db) -> Option<User> {
+    if let Some(SessionUser(user)) = parts.extensions.get::<SessionUser>() {
+        return user.clone();
+    }
+    let jar = CookieJar::from_headers(&parts.headers);
+    let user = ensure_session(jar, db).await.map(|(user, _)| user);
+    parts.extensions.insert(SessionUser(user.clone()));
+    user
+}
+
+/// The signed-in user, if there is one
+pub struct MaybeUser(pub Option<User>);
+
+impl FromRequestParts<(Db, Arc<Tera>)> for MaybeUser {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, (db, _): &(Db, Arc<Tera>)) -> Result<Self, Self::Rejection> {
+        Ok(MaybeUser(session_user(parts, db).await))
+    }
+}
+
+/// The signed-in user. Without one the request is sent to the login page.
+pub struct CurrentUser(pub User);
+
+impl FromRequestParts<(Db, Arc<Tera>)> for CurrentUser {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, (db, _): &(Db, Arc<Tera>)) -> Result<Self, Self::Rejection> {
+        session_user(parts, db).await.map(CurrentUser).ok_or(AppError::Unauthorized)
+    }
+}
+
+/// A check on the user's permissions, for [`RequireRole`]
+pub trait Role {
+    /// Shown to signed-in users without the permission
+    const DENIED: &'static str;
+
+    fn allows(permissions: &Permissions) -> bool;
+}
+
+/// Declares a [`Role`] that needs a single permission
+macro_rules! permission_role {
+    ($(#[$doc:meta])* $name:ident, $permission:ident, $denied:literal) => {
+        $(#[$doc])*
+        pub struct $name;
+
+        impl Role for $name {
+            const DENIED: &'static str = $denied;
+
+            fn allows(permissions: &Permissions) -> bool {
+                permissions.has(Permission::$permission)
+            }
+        }
+    };
+}
+
+permission_role!(
+    /// Anyone who can post: verified mechanics and staff by default
+    Poster, Post, "Only verified mechanics can do that. Submit your credentials for verification first."
+);
+permission_role!(Commenter, Comment, "You don't have permission to comment");
+permission_role!(
+    /// Anyone who can submit and rate stores
+    StoreVoter, VoteStore, "Only verified mechanics can submit and vote on stores"
+);
+permission_role!(
+    /// Site-wide moderators, for work not tied to a category
+    ContentModerator, RemoveContent, "Moderator access required"
+);
+permission_role!(BanManager, Ban, "You don't have permission to ban users");
+permission_role!(VerificationReviewer, ReviewVerifications, "You don't have permission to review verifications");
+permission_role!(RoleManager, ManageRoles, "You don't have permission to manage roles");
+permission_role!(CategoryManager, ManageCategories, "You don't have permission to manage categories");
+permission_role!(SiteManager, ManageSite, "Admin access required");
+
+/// Anyone with a moderation permission or a category to moderate. Actions
+/// on a post or comment still check its category.
+pub struct Moderator;
+
+impl Role for Moderator {
+    const DENIED: &'static str = "Moderator access required";
+
+    fn allows(permissions: &Permissions) -> bool {
+        permissions.can_moderate()
+    }
+}
+
+/// Anyone with a permission managed from the admin panel
+pub struct Admin;
+
+impl Role for Admin {
+    const DENIED: &'static str = "Admin access required";
+
+    fn allows(permissions: &Permissions) -> bool {
+        permissions.can_administer()
+    }
+}
+
+/// The signed-in user, whose permissions pass `R`. Signed-out requests go to the
+/// login page; anyone else gets a 403.
+pub struct RequireRole<R: Role>(pub User, pub PhantomData<R>);
+
+impl<R: Role> FromRequestParts<(Db, Arc<Tera>)> for RequireRole<R> {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &(Db, Arc<Tera>)) -> Result<Self, Self::Rejection> {
+        let CurrentUser(user) = CurrentUser::from_request_parts(parts, state).await?;
+        if !R::allows(&user.permissions) {
+            return Err(AppError::forbidden(R::DENIED));
+        }
+        Ok(RequireRole(user, PhantomData))
+    }
+}
+
+/// A template context with what every full page needs: the signed-in user
+/// and their unread notification count
+pub struct PageContext(pub Context);
+
+impl FromRequestParts<(Db, Arc<Tera>)> for PageContext {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, (db, _): &(Db, Arc<Tera>)) -> Result<Self, Self::Rejection> {
+        let mut ctx = Context::new();
+        if let Some(user) = session_user(parts, db).await {
+            let user_id = user.id;
+            let unread_count = db.read(move |conn| db::get_unread_notification_count(conn, user_id)).await?;
+            ctx.insert("user", &user);
+            ctx.insert("unread_notifications", &unread_count);
+        }
+        Ok(PageContext(ctx))
+    }
+}
+
+/// A cookie for the whole site with the `SameSite` and `Secure` policy
+/// every cookie we set shares. `secure` comes from
+/// `server.secure_cookies`, off only for local development over plain http.
+pub fn site_cookie<'c>(name: &'c str, value: String, secure: bool) -> CookieBuilder<'c> {
+    Cookie::build((name, value))
         .path("/")
+        .same_site(SameSite::Lax)
+        .secure(secure)
+}
+
+fn session_cookie(token: &str, max_age: time::Duration, secure: bool) -> Cookie<'static> {
+    site_cookie("session", token.to_string(), secure)
         .http_only(true)
-        .max_age(time::Duration::days(30));
+        .max_age(max_age)
+        .build()
+        .into_owned()
+}
+
+/// Set the cookie for a session that lasts `lifetime_days`
+pub fn set_session_cookie(jar: CookieJar, token: &str, lifetime_days: i64, secure: bool) -> CookieJar {
+    jar.add(session_cookie(token, time::Duration::days(lifetime_days), secure))
+}
+
+/// Set the cookie tying the browser to a pending second login step
+pub fn set_login_challenge_cookie(jar: CookieJar, token: &str, secure: bool) -> CookieJar {
+    let cookie = site_cookie("login_challenge", token.to_string(), secure)
+        .path("/login")
+        .http_only(true)
+        .max_age(time::Duration::minutes(LOGIN_CHALLENGE_MINUTES));
     jar.add(cookie)
 }
 
+pub fn clear_login_challenge_cookie(jar: CookieJar) -> CookieJar {
+    jar.remove(Cookie::build("login_challenge").path("/login"))
+}
+
 /// Clear the session cookie
 pub fn clear_session_cookie(jar: CookieJar) -> CookieJar {
     jar.remove(Cookie::from("session"))
 }
 
-/// Validate email format (basic check)
+/// Validate email format: one `@`, a plausible local part and a domain of
+/// letter/digit/hyphen labels ending in an alphabetic TLD. Deliverability is
+/// checked separately by the confirmation email.
 pub fn is_valid_email(email: &str) -> bool {
     let email = email.trim();
-    if email.is_empty() || email.len() > 254 {
+    if email.is_empty() || email.len() > 254 || email.chars().any(char::is_whitespace) {
         return false;
     }
     
-    let parts: Vec<&str> = email.split('@').collect();
-    if parts.len() != 2 {
+    let Some((local, domain)) = email.split_once('@') else {
         return false;
-    }
+    };
     
-    let local = parts[0];
-    let domain = parts[1];
+    if local.is_empty() || local.len() > 64 || domain.contains('@') {
+        return false;
+    }
+    if local.starts_with('.') || local.ends_with('.') || local.contains("..") {
+        return false;
+    }
     
-    !local.is_empty() && !domain.is_empty() && domain.contains('.')
+    let labels: Vec<&str> = domain.split('.').collect();
+    if labels.len() < 2 {
+        return false;
+    }
+    let labels_ok = labels.iter().all(|label| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    });
+    let tld = labels[labels.len() - 1];
+    labels_ok && tld.chars().all(|c| c.is_ascii_alphabetic())
 }
 
 /// Validate username (alphanumeric, underscores, 3-20 chars)
@@ -135,6 +571,52 @@ mod tests {
         assert_eq!(token1.len(), 36); // UUID format
     }
 
+    #[test]
+    fn test_email_token_hashing() {
+        let token = create_email_token();
+        assert_eq!(token.len(), 64);
+        assert_ne!(token, create_email_token());
+
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_ne!(hash_token(&token), token);
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_describe_user_agent() {
+        let firefox = "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:128.0) Gecko/20100101 Firefox/128.0";
+        let safari = "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1";
+        let edge = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Safari/537.36 Edg/126.0.0.0";
+        let chrome = "Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/126.0.0.0 Mobile Safari/537.36";
+        assert_eq!(describe_user_agent(Some(firefox)), "Firefox on Windows");
+        assert_eq!(describe_user_agent(Some(safari)), "Safari on iPhone");
+        assert_eq!(describe_user_agent(Some(edge)), "Edge on macOS");
+        assert_eq!(describe_user_agent(Some(chrome)), "Chrome on Android");
+        assert_eq!(describe_user_agent(Some("curl/8.5.0")), "curl");
+        assert_eq!(describe_user_agent(Some("")), "Unknown device");
+        assert_eq!(describe_user_agent(None), "Unknown device");
+    }
+
+    #[test]
+    fn test_session_public_id() {
+        let id = session_public_id("token");
+        assert_eq!(id.len(), 16);
+        assert_eq!(id, session_public_id("token"));
+        assert_ne!(id, session_public_id("other"));
+        assert!(!id.contains("token"));
+    }
+
+    #[test]
+    fn test_email_resend_wait() {
+        assert_eq!(email_resend_wait(None), 0);
+        assert_eq!(email_resend_wait(Some("2000-01-01 00:00:00")), 0);
+        let wait = email_resend_wait(Some(&now_timestamp()));
+        assert!(wait > EMAIL_RESEND_SECONDS - 5 && wait <= EMAIL_RESEND_SECONDS);
+    }
+
     #[test]
     fn test_email_validation() {
         assert!(is_valid_email("test@example.com"));
@@ -144,6 +626,14 @@ mod tests {
         assert!(!is_valid_email("@example.com"));
         assert!(!is_valid_email("test@"));
         assert!(!is_valid_email("test@nodot"));
+        assert!(!is_valid_email("a@b@example.com"));
+        assert!(!is_valid_email("test@example..com"));
+        assert!(!is_valid_email("test@.example.com"));
+        assert!(!is_valid_email("test@example.123"));
+        assert!(!is_valid_email("test@-example.com"));
+        assert!(!is_valid_email(".test@example.com"));
+        assert!(!is_valid_email("te..st@example.com"));
+        assert!(is_valid_email("first.last+forum@mail.example.co.uk"));
     }
 
     #[test]
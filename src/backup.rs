@@ -0,0 +1,417 @@
+//! Online backups of the forum.
+//!
+//! A snapshot is a directory named `wrench-forum-YYYYMMDD-HHMMSS` holding a
+//! copy of the database, taken with SQLite's backup API, and a copy of the
+//! uploads directory. The database copy is taken in one step inside a single
+//! read transaction, so it's consistent and, with the database in WAL mode,
+//! never blocks the server's writes. Snapshots are written under a
+//! `.partial` name and renamed when complete, so a crash mid-backup never
+//! leaves something that looks restorable.
+//!
+//! The server takes one every `backup.interval_hours` and keeps the newest
+//! `backup.keep`. `wrench-forum backup` takes one on demand and
+//! `wrench-forum restore` puts one back.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDateTime, Utc};
+use rusqlite::backup::{Backup, StepResult};
+use rusqlite::{Connection, ErrorCode, OpenFlags};
+
+use crate::config::Config;
+use crate::db::{self, Db};
+use crate::models::BackupSnapshot;
+use crate::shutdown::Shutdown;
+
+/// Prefix of every snapshot directory's name
+pub const SNAPSHOT_PREFIX: &str = "wrench-forum-";
+
+/// Prefix of the copy of the database [`restore`] saves before replacing
+/// it. These aren't rotated.
+pub const PRE_RESTORE_PREFIX: &str = "pre-restore-";
+
+/// The database file inside a snapshot
+pub const SNAPSHOT_DB_FILE: &str = "wrench-forum.db";
+
+/// The uploads directory inside a snapshot
+pub const SNAPSHOT_UPLOADS_DIR: &str = "uploads";
+
+/// How long a copy waits on a busy database before giving up
+const COPY_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+pub enum BackupError {
+    Sqlite(rusqlite::Error),
+    Io(PathBuf, std::io::Error),
+    /// A snapshot that can't be restored into this build, with the reason
+    Incompatible(String),
+    /// The database was in use by something else, with what was being done
+    Busy(String),
+}
+
+impl std::fmt::Display for BackupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BackupError::Sqlite(e) => write!(f, "database error: {}", e),
+            BackupError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+            BackupError::Incompatible(reason) => write!(f, "{}", reason),
+            BackupError::Busy(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BackupError {}
+
+impl From<rusqlite::Error> for BackupError {
+    fn from(e: rusqlite::Error) -> Self {
+        BackupError::Sqlite(e)
+    }
+}
+
+fn io_error(path: &Path) -> impl FnOnce(std::io::Error) -> BackupError + '_ {
+    move |e| BackupError::Io(path.to_path_buf(), e)
+}
+
+/// Copy every page of `from` into `to` in a single step, waiting up to
+/// `timeout` for either to stop being busy
+fn copy_database(from: &Connection, to: &mut Connection, timeout: Duration) -> Result<(), BackupError> {
+    let backup = Backup::new(from, to)?;
+    let deadline = Instant::now() + timeout;
+    // A negative page count copies everything at once. Smaller steps would
+    // restart from scratch whenever the server writes in between.
+    loop {
+        match backup.step(-1)? {
+            StepResult::Done => return Ok(()),
+            // Busy or locked by a writer; wait for it to finish
+            _ if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(50)),
+            _ => return Err(BackupError::Busy(format!("the database was still busy after {:?}", timeout))),
+        }
+    }
+}
+
+/// Copy the files directly in `from` to `to`, returning how many there were
+/// and their total size. Uploads are never changed once written, so a hard
+/// link is as good as a copy and takes no space; it falls back to copying
+/// across filesystems.
+fn copy_files(from: &Path, to: &Path) -> Result<(i64, i64), BackupError> {
+    fs::create_dir_all(to).map_err(io_error(to))?;
+    if !from.is_dir() {
+        return Ok((0, 0));
+    }
+    let (mut count, mut size) = (0, 0);
+    for entry in fs::read_dir(from).map_err(io_error(from))? {
+        let entry = entry.map_err(io_error(from))?;
+        let metadata = entry.metadata().map_err(io_error(&entry.path()))?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let target = to.join(entry.file_name());
+        if target.exists() {
+            continue;
+        }
+        if fs::hard_link(entry.path(), &target).is_err() {
+            fs::copy(entry.path(), &target).map_err(io_error(&target))?;
+        }
+        count += 1;
+        size += metadata.len() as i64;
+    }
+    Ok((count, size))
+}
+
+/// Schema version of a database opened read-only; `None` if it isn't a
+/// wrench-forum database at all
+fn read_schema_version(conn: &Connection) -> Result<Option<i64>, BackupError> {
+    let has_table: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'",
+        [],
+        |row| row.get(0),
+    )?;
+    if !has_table {
+        return Ok(None);
+    }
+    Ok(Some(conn.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |row| row.get(0))?))
+}
+
+/// Where a snapshot is written until it's complete
+fn partial_path(dest: &Path) -> PathBuf {
+    let mut name = dest.as_os_str().to_owned();
+    name.push(".partial");
+    PathBuf::from(name)
+}
+
+/// Write a snapshot of the database at `db_path` and the files in
+/// `uploads_dir` to the directory `dest`, which mustn't exist yet
+pub fn create_snapshot(db_path: &str, uploads_dir: &str, dest: &Path) -> Result<BackupSnapshot, BackupError> {
+    let source = Connection::open_with_flags(db_path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+    write_snapshot(&source, uploads_dir, dest)
+}
+
+/// [`create_snapshot`] from an open connection
+fn write_snapshot(source: &Connection, uploads_dir: &str, dest: &Path) -> Result<BackupSnapshot, BackupError> {
+    if dest.exists() {
+        return Err(BackupError::Io(dest.to_path_buf(), std::io::ErrorKind::AlreadyExists.into()));
+    }
+    let partial = partial_path(dest);
+    if partial.exists() {
+        fs::remove_dir_all(&partial).map_err(io_error(&partial))?;
+    }
+    fs::create_dir_all(&partial).map_err(io_error(&partial))?;
+
+    let result = (|| {
+        let db_file = partial.join(SNAPSHOT_DB_FILE);
+        let mut copy = Connection::open(&db_file)?;
+        copy_database(source, &mut copy, COPY_TIMEOUT)?;
+        let schema_version = read_schema_version(&copy)?.unwrap_or(0);
+        drop(copy);
+        let db_size = fs::metadata(&db_file).map_err(io_error(&db_file))?.len() as i64;
+
+        let (upload_files, uploads_size) = copy_files(Path::new(uploads_dir), &partial.join(SNAPSHOT_UPLOADS_DIR))?;
+        Ok((schema_version, db_size + uploads_size, upload_files))
+    })();
+
+    match result {
+        Ok((schema_version, size_bytes, upload_files)) => {
+            fs::rename(&partial, dest).map_err(io_error(dest))?;
+            Ok(BackupSnapshot {
+                path: dest.display().to_string(),
+                schema_version,
+                size_bytes,
+                upload_files,
+            })
+        }
+        Err(e) => {
+            let _ = fs::remove_dir_all(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// Snapshots in `dir`, oldest first. Names sort by the time they were taken.
+pub fn list_snapshots(dir: &Path) -> Result<Vec<PathBuf>, BackupError> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir).map_err(io_error(dir))? {
+        let path = entry.map_err(io_error(dir))?.path();
+        let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+        if path.is_dir() && name.starts_with(SNAPSHOT_PREFIX) && !name.ends_with(".partial") {
+            snapshots.push(path);
+        }
+    }
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+/// Delete all but the newest `keep` snapshots in `dir`, returning the ones
+/// removed
+pub fn rotate(dir: &Path, keep: usize) -> Result<Vec<PathBuf>, BackupError> {
+    let snapshots = list_snapshots(dir)?;
+    let excess = snapshots.len().saturating_sub(keep);
+    let removed: Vec<PathBuf> = snapshots.into_iter().take(excess).collect();
+    for path in &removed {
+        fs::remove_dir_all(path).map_err(io_error(path))?;
+    }
+    Ok(removed)
+}
+
+/// A fresh snapshot directory in `dir`, named for the current time
+fn next_snapshot_path(dir: &Path, prefix: &str) -> PathBuf {
+    let stamp = Utc::now().format("%Y%m%d-%H%M%S");
+    let mut path = dir.join(format!("{}{}", prefix, stamp));
+    let mut n = 2;
+    while path.exists() || partial_path(&path).exists() {
+        path = dir.join(format!("{}{}-{}", prefix, stamp, n));
+        n += 1;
+    }
+    path
+}
+
+/// Take a snapshot into the configured backup directory and rotate out the
+/// oldest
+pub fn backup_to_dir(config: &Config) -> Result<BackupSnapshot, BackupError> {
+    let dir = Path::new(&config.backup.dir);
+    fs::create_dir_all(dir).map_err(io_error(dir))?;
+    let snapshot = create_snapshot(&config.database.path, &config.uploads.dir, &next_snapshot_path(dir, SNAPSHOT_PREFIX))?;
+    rotate(dir, config.backup.keep)?;
+    Ok(snapshot)
+}
+
+/// Run `take` off the async runtime and record the outcome in the
+/// `backups` table
+pub async fn run_and_record<F>(db: &Db, take: F) -> Result<BackupSnapshot, String>
+where
+    F: FnOnce() -> Result<BackupSnapshot, BackupError> + Send + 'static,
+{
+    let started_at = Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let result = match tokio::task::spawn_blocking(take).await {
+        Ok(result) => result.map_err(|e| e.to_string()),
+        Err(_) => Err("the backup task panicked".to_string()),
+    };
+
+    let outcome = result.clone();
+    db.write(move |conn| db::record_backup(conn, &started_at, outcome.as_ref().map_err(String::as_str)))
+        .await
+        .map_err(|e| format!("backup finished but couldn't be recorded: {}", e))?;
+    result
+}
+
+/// How long to wait before the next scheduled backup, given when the last
+/// successful one finished
+pub fn next_backup_delay(last_finished: Option<&str>, interval_hours: u64, now: chrono::DateTime<Utc>) -> Duration {
+    let Some(last) = last_finished.and_then(|t| NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S").ok()) else {
+        return Duration::ZERO;
+    };
+    let due = last.and_utc() + chrono::Duration::hours(interval_hours as i64);
+    (due - now).to_std().unwrap_or(Duration::ZERO)
+}
+
+/// Back up every `backup.interval_hours` until `shutdown`. The schedule
+/// picks up from the last successful backup, so restarts don't cause extra
+/// ones. A backup already under way when shutdown starts is finished.
+pub async fn run_schedule(db: Db, config: Arc<Config>, shutdown: Shutdown) {
+    let interval_hours = config.backup.interval_hours;
+    if interval_hours == 0 {
+        return;
+    }
+    loop {
+        let last = db.read(db::get_last_successful_backup).await.ok().flatten();
+        let delay = next_backup_delay(last.as_ref().map(|b| b.finished_at.as_str()), interval_hours, Utc::now());
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.wait() => return,
+        }
+
+        let config = config.clone();
+        match run_and_record(&db, move || backup_to_dir(&config)).await {
+            Ok(snapshot) => tracing::info!(path = %snapshot.path, size_bytes = snapshot.size_bytes, "scheduled backup finished"),
+            Err(e) => {
+                tracing::error!(error = %e, "scheduled backup failed");
+                // Don't retry in a tight loop; try again in an hour
+                tokio::select! {
+                    _ = tokio::time::sleep(Duration::from_secs(3600)) => {}
+                    _ = shutdown.wait() => return,
+                }
+            }
+        }
+    }
+}
+
+/// What [`restore`] did
+#[derive(Debug, Clone)]
+pub struct RestoreSummary {
+    /// Schema version of the snapshot as taken
+    pub schema_version: i64,
+    /// Migrations applied on top of it to bring it up to this build
+    pub migrations_applied: Vec<i64>,
+    pub upload_files: i64,
+    /// Where the database as it was before the restore was saved
+    pub previous: Option<PathBuf>,
+}
+
+/// Check that `snapshot` is a complete, intact snapshot this build can
+/// run, returning its schema version
+pub fn check_snapshot(snapshot: &Path) -> Result<i64, BackupError> {
+    let db_file = snapshot.join(SNAPSHOT_DB_FILE);
+    if !db_file.is_file() {
+        return Err(BackupError::Incompatible(format!("{} is not a backup: it has no {}", snapshot.display(), SNAPSHOT_DB_FILE)));
+    }
+    let conn = Connection::open_with_flags(&db_file, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX)?;
+    let integrity: String = conn.query_row("PRAGMA integrity_check", [], |row| row.get(0))?;
+    if integrity != "ok" {
+        return Err(BackupError::Incompatible(format!("the backup's database is damaged: {}", integrity)));
+    }
+    let Some(version) = read_schema_version(&conn)? else {
+        return Err(BackupError::Incompatible("the backup's database isn't a wrench-forum database".to_string()));
+    };
+    let latest = db::latest_schema_version();
+    if version > latest {
+        return Err(BackupError::Incompatible(format!(
+            "the backup has schema version {} but this build only knows up to {}; restore it with a newer wrench-forum",
+            version, latest
+        )));
+    }
+    Ok(version)
+}
+
+/// Take an exclusive lock on `conn`'s database, held until the connection
+/// is closed. Fails straight away if anything else is using it, which in
+/// WAL mode includes a server that's merely connected.
+fn lock_exclusively(conn: &Connection) -> Result<(), BackupError> {
+    conn.busy_timeout(Duration::ZERO)?;
+    conn.pragma_update(None, "locking_mode", "EXCLUSIVE")?;
+    // In exclusive locking mode the lock outlives the transaction; reading
+    // the schema is what takes it in WAL mode
+    match conn.execute_batch("BEGIN EXCLUSIVE; SELECT COUNT(*) FROM sqlite_master; COMMIT;") {
+        Ok(()) => Ok(()),
+        Err(rusqlite::Error::SqliteFailure(e, _)) if matches!(e.code, ErrorCode::DatabaseBusy | ErrorCode::DatabaseLocked) => {
+            Err(BackupError::Busy("the database is in use; stop the server before restoring".to_string()))
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Replace the configured database with the one in `snapshot` and bring it
+/// up to this build's schema, then put back any uploads that have gone
+/// missing. The current database is saved to the backup directory first.
+/// The server must not be running: the database is locked exclusively for
+/// the whole restore, and it's refused if that isn't possible.
+pub fn restore(config: &Config, snapshot: &Path) -> Result<RestoreSummary, BackupError> {
+    let schema_version = check_snapshot(snapshot)?;
+
+    let existed = Path::new(&config.database.path).exists();
+    let mut target = db::open_db(&config.database.path)?;
+    lock_exclusively(&target)?;
+
+    let previous = if existed {
+        let dir = Path::new(&config.backup.dir);
+        fs::create_dir_all(dir).map_err(io_error(dir))?;
+        let dest = next_snapshot_path(dir, PRE_RESTORE_PREFIX);
+        Some(PathBuf::from(write_snapshot(&target, &config.uploads.dir, &dest)?.path))
+    } else {
+        None
+    };
+
+    let source = Connection::open_with_flags(snapshot.join(SNAPSHOT_DB_FILE), OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    copy_database(&source, &mut target, COPY_TIMEOUT)?;
+    let migrations_applied = db::run_migrations(&target)?;
+
+    let (upload_files, _) = copy_files(&snapshot.join(SNAPSHOT_UPLOADS_DIR), Path::new(&config.uploads.dir))?;
+    Ok(RestoreSummary {
+        schema_version,
+        migrations_applied,
+        upload_files,
+        previous,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_copy_gives_up_on_a_busy_database() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let source = Connection::open(dir.path().join("source.db")).unwrap();
+        source.execute_batch("CREATE TABLE t (x INTEGER); INSERT INTO t VALUES (1);").unwrap();
+
+        // Something else is writing to the destination and doesn't stop
+        let dest_path = dir.path().join("dest.db");
+        let writer = Connection::open(&dest_path).unwrap();
+        writer.execute_batch("CREATE TABLE other (y INTEGER); BEGIN EXCLUSIVE; INSERT INTO other VALUES (1);").unwrap();
+
+        let mut dest = Connection::open(&dest_path).unwrap();
+        dest.busy_timeout(Duration::ZERO).unwrap();
+        let started = Instant::now();
+        let error = copy_database(&source, &mut dest, Duration::from_millis(200)).unwrap_err();
+        assert!(matches!(error, BackupError::Busy(_)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        writer.execute_batch("COMMIT").unwrap();
+        copy_database(&source, &mut dest, Duration::from_millis(200)).unwrap();
+        assert_eq!(dest.query_row("SELECT x FROM t", [], |r| r.get::<_, i64>(0)).unwrap(), 1);
+    }
+}
@@ -0,0 +1,398 @@
+//! Operator commands behind the `wrench-forum` binary's subcommands.
+//!
+//! Each works on a plain connection through the same `db` functions the
+//! handlers use, so an account made here is indistinguishable from one made
+//! through the site. Printing is left to `main`.
+
+use std::path::Path;
+
+use rusqlite::Connection;
+
+use crate::auth::{hash_password, is_valid_email, is_valid_password, is_valid_username};
+use crate::backup::{self, BackupError};
+use crate::config::Config;
+use crate::db;
+use crate::models::{BackupSnapshot, TorqueSpecDetails, User, VehicleDetails};
+use crate::totp;
+
+#[derive(Debug)]
+pub enum CliError {
+    Db(rusqlite::Error),
+    /// Arguments that can't be acted on, with a message for the operator
+    Usage(String),
+    Hash(argon2::password_hash::Error),
+    Backup(BackupError),
+}
+
+impl std::fmt::Display for CliError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CliError::Db(e) => write!(f, "database error: {}", e),
+            CliError::Usage(message) => write!(f, "{}", message),
+            CliError::Hash(e) => write!(f, "failed to hash password: {}", e),
+            CliError::Backup(e) => write!(f, "backup failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CliError {}
+
+impl From<rusqlite::Error> for CliError {
+    fn from(e: rusqlite::Error) -> Self {
+        CliError::Db(e)
+    }
+}
+
+impl From<argon2::password_hash::Error> for CliError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        CliError::Hash(e)
+    }
+}
+
+impl From<BackupError> for CliError {
+    fn from(e: BackupError) -> Self {
+        CliError::Backup(e)
+    }
+}
+
+fn usage(message: impl Into<String>) -> CliError {
+    CliError::Usage(message.into())
+}
+
+/// A random password for accounts made without one: 16 base32 characters
+pub fn generate_password() -> String {
+    let mut bytes = [0u8; 10];
+    rand::RngCore::fill_bytes(&mut rand::rngs::OsRng, &mut bytes);
+    totp::base32_encode(&bytes).to_lowercase()
+}
+
+fn check_password(password: &str) -> Result<(), CliError> {
+    if is_valid_password(password) {
+        Ok(())
+    } else {
+        Err(usage("Password must be at least 8 characters"))
+    }
+}
+
+pub fn find_user(conn: &Connection, username: &str) -> Result<User, CliError> {
+    db::get_user_by_username(conn, username)?.ok_or_else(|| usage(format!("No user named {:?}", username)))
+}
+
+/// A confirmed admin account, ready to sign in
+pub fn create_admin(conn: &Connection, email: &str, username: &str, password: &str) -> Result<i64, CliError> {
+    if !is_valid_email(email) {
+        return Err(usage(format!("{:?} is not a valid email address", email)));
+    }
+    if !is_valid_username(username) {
+        return Err(usage("Username must be 3-20 characters, alphanumeric and underscores only"));
+    }
+    check_password(password)?;
+    if db::get_user_by_email(conn, email)?.is_some() {
+        return Err(usage(format!("{} is already registered", email)));
+    }
+    if db::get_user_by_username(conn, username)?.is_some() {
+        return Err(usage(format!("Username {} is already taken", username)));
+    }
+
+    let password_hash = hash_password(password)?;
+    let tx = conn.unchecked_transaction()?;
+    let user_id = db::create_user(&tx, email, &password_hash, username)?;
+    db::update_user_role(&tx, user_id, "admin")?;
+    db::mark_email_confirmed(&tx, user_id)?;
+    tx.commit()?;
+    Ok(user_id)
+}
+
+/// Give the user a built-in or custom role. Their sessions pick it up on
+/// the next request.
+pub fn set_role(conn: &Connection, username: &str, role: &str) -> Result<(), CliError> {
+    let user = find_user(conn, username)?;
+    if db::get_role(conn, role)?.is_none() {
+        let roles = db::get_roles(conn)?.into_iter().map(|r| r.name).collect::<Vec<_>>();
+        return Err(usage(format!("Unknown role {:?}; roles are {}", role, roles.join(", "))));
+    }
+    db::update_user_role(conn, user.id, role)?;
+    Ok(())
+}
+
+/// Set a new password and lift any login lockout. Every session is signed
+/// out.
+pub fn reset_password(conn: &Connection, username: &str, password: &str) -> Result<(), CliError> {
+    check_password(password)?;
+    let user = find_user(conn, username)?;
+    let password_hash = hash_password(password)?;
+    db::set_user_password(conn, user.id, &password_hash)?;
+    db::unlock_user(conn, user.id)?;
+    Ok(())
+}
+
+/// Ban or unban the user; a ban also signs them out everywhere
+pub fn set_banned(conn: &Connection, username: &str, banned: bool) -> Result<(), CliError> {
+    let user = find_user(conn, username)?;
+    db::set_user_banned(conn, user.id, banned)?;
+    if banned {
+        db::delete_user_sessions(conn, user.id)?;
+    }
+    Ok(())
+}
+
+/// What [`seed_demo`] created
+#[derive(Debug, Clone, Default)]
+pub struct SeedSummary {
+    pub users: Vec<(String, String)>,
+    pub posts: usize,
+    pub comments: usize,
+    pub stores: usize,
+    pub torque_specs: usize,
+}
+
+struct DemoUser {
+    username: &'static str,
+    email: &'static str,
+    role: &'static str,
+    bio: &'static str,
+    specialties: &'static str,
+    location: &'static str,
+}
+
+const DEMO_USERS: &[DemoUser] = &[
+    DemoUser {
+        username: "admin",
+        email: "admin@wrench.forum",
+        role: "admin",
+        bio: "Keeps the lights on.",
+        specialties: "",
+        location: "",
+    },
+    DemoUser {
+        username: "MikeTheMechanic",
+        email: "mike@garage.com",
+        role: "verified_mechanic",
+        bio: "ASE master tech, 15 years at an independent shop.",
+        specialties: "Engine performance, diagnostics",
+        location: "Columbus, OH",
+    },
+    DemoUser {
+        username: "SarahWrench",
+        email: "sarah@autofix.com",
+        role: "verified_mechanic",
+        bio: "Twenty years turning wrenches, mostly European imports.",
+        specialties: "Transmissions, suspension",
+        location: "Portland, OR",
+    },
+    DemoUser {
+        username: "TransmissionJoe",
+        email: "joe@transmission.com",
+        role: "verified_mechanic",
+        bio: "Rebuilds automatics for a living, brakes for fun.",
+        specialties: "Automatic transmissions, ABS",
+        location: "Tulsa, OK",
+    },
+    DemoUser {
+        username: "ShopForeman",
+        email: "foreman@wrench.forum",
+        role: "moderator",
+        bio: "Keeping threads on topic.",
+        specialties: "Electrical",
+        location: "Denver, CO",
+    },
+    DemoUser {
+        username: "CarNewbie",
+        email: "newbie@email.com",
+        role: "unverified",
+        bio: "First car, first toolbox.",
+        specialties: "",
+        location: "Austin, TX",
+    },
+];
+
+/// (author, category slug, tag, title, body)
+const DEMO_POSTS: &[(&str, &str, &str, &str, &str)] = &[
+    ("MikeTheMechanic", "engine", "Tutorial", "Common P0300 Random Misfire Causes",
+     "Here are the most common causes I see for P0300 codes:\n\n1. Spark plugs worn or fouled\n2. Ignition coils failing\n3. Vacuum leaks\n4. Fuel injector issues\n5. Low fuel pressure\n\nAlways start with the basics - check spark plugs first. If you have over 80k miles and original plugs, replace them."),
+    ("SarahWrench", "transmission", "Discussion", "When to Change Transmission Fluid",
+     "I see a lot of debate about this. Here is my take after 20 years in the business:\n\nManual: Every 30-60k miles\nAutomatic: Every 30-60k miles if driven normally, more often if towing\n\nThe \"lifetime fluid\" marketing is nonsense. I have seen too many transmissions fail at 100k because people believed that."),
+    ("TransmissionJoe", "brakes", "Tutorial", "DIY Brake Job Tips",
+     "About to do your first brake job? Here are some tips:\n\n1. Always replace pads in pairs (both sides)\n2. Clean and lube the slide pins\n3. Compress the piston slowly\n4. Bed in new pads properly - 10 stops from 30mph\n5. Check your brake fluid level\n\nDo not cheap out on pads. I recommend ceramic for most daily drivers."),
+    ("MikeTheMechanic", "electrical", "Tutorial", "Diagnosing Parasitic Draw",
+     "Battery dead every morning? Here is how to find the draw:\n\n1. Fully charge battery\n2. Disconnect negative terminal\n3. Connect ammeter between terminal and cable\n4. Wait 30 min for modules to sleep\n5. Should read under 50mA\n\nIf higher, pull fuses one at a time to find the circuit. Most common culprits: aftermarket stereos, trunk lights, glove box lights."),
+    ("SarahWrench", "suspension", "Tutorial", "Symptoms of Worn Struts",
+     "How do you know when struts need replacing?\n\n- Bouncy ride, especially over bumps\n- Nose diving when braking\n- Uneven tire wear\n- Clunking noises\n- Vehicle sways in wind or on curves\n\nMost struts last 50-100k miles. If yours are original and over 80k, inspect them."),
+    ("TransmissionJoe", "engine", "Discussion", "Oil Change Intervals - The Real Story",
+     "Stop following the 3000 mile myth. Modern oils and engines do not need that.\n\nMost cars: Follow the manual (usually 5-7.5k with synthetic)\nIf you drive hard/short trips: Cut interval by 25%\nIf you tow: Use severe service interval\n\nCheck your oil level monthly regardless."),
+    ("MikeTheMechanic", "transmission", "Tutorial", "Automatic Transmission Slipping",
+     "Transmission slipping between gears? Check these first:\n\n1. Fluid level (with engine warm, in Park)\n2. Fluid condition (should be red, not brown)\n3. Check for codes\n\nIf fluid is brown or smells burnt, the damage may already be done. Fresh fluid can make it worse by loosening debris."),
+    ("SarahWrench", "electrical", "Discussion", "LED Headlight Conversions",
+     "Before swapping your halogens for LEDs:\n\n1. Check your state laws\n2. Reflector housings need specific LED bulbs\n3. Projector housings work better with LEDs\n4. You might need CANbus adapters\n5. Cooling is important - get bulbs with fans\n\nCheap Amazon LEDs often have poor beam patterns. Spend the money on quality."),
+    ("TransmissionJoe", "brakes", "Tutorial", "ABS Light Troubleshooting",
+     "ABS light on? Here is my diagnostic approach:\n\n1. Scan for codes (generic OBDII may not show ABS codes)\n2. Most common: wheel speed sensor issues\n3. Check sensor wiring at wheels\n4. Check reluctor rings for damage\n5. Check ABS module grounds\n\nWheel speed sensors are cheap and easy to replace yourself."),
+    ("MikeTheMechanic", "suspension", "Discussion", "Alignment After Suspension Work",
+     "Yes, you need an alignment after:\n- Replacing struts/shocks (sometimes)\n- Tie rod ends (always)\n- Control arms (always)\n- Ball joints (always)\n- Lowering/lifting\n\nDo not skip this. A bad alignment destroys tires fast and hurts handling."),
+    ("CarNewbie", "engine", "Question", "Rough idle after spark plug change",
+     "Changed the plugs on my 2012 Civic last weekend and now it idles rough and throws P0302. Did I mess something up?"),
+];
+
+/// (post title, author, body); the first reply to the newbie's question
+/// becomes its best answer
+const DEMO_COMMENTS: &[(&str, &str, &str)] = &[
+    ("Rough idle after spark plug change", "MikeTheMechanic",
+     "P0302 is cylinder 2. Most likely the coil on that cylinder isn't fully seated, or the plug gap is off. Pull the coil, check the boot and reseat it until it clicks."),
+    ("Rough idle after spark plug change", "CarNewbie",
+     "That was it! The coil boot was sitting on top of the plug. Idles smooth now, thanks."),
+    ("When to Change Transmission Fluid", "TransmissionJoe",
+     "Agreed. Drain and fill, never a flush on a high mileage unit that's never been serviced."),
+    ("Diagnosing Parasitic Draw", "ShopForeman",
+     "Add to the list: a stuck relay. Feel for a relay that's warm after the car has been sitting."),
+    ("DIY Brake Job Tips", "SarahWrench",
+     "And torque the caliper bracket bolts to spec - don't guess."),
+];
+
+/// (name, url, category, description)
+const DEMO_STORES: &[(&str, &str, &str, &str)] = &[
+    ("RockAuto", "https://www.rockauto.com", "Aftermarket Parts", "Huge catalog, cheap shipping from the same warehouse"),
+    ("FCP Euro", "https://www.fcpeuro.com", "OEM Parts", "Lifetime replacement on everything they sell"),
+    ("Amazon Automotive", "https://www.amazon.com/automotive", "General", ""),
+    ("Harbor Freight", "https://www.harborfreight.com", "Tools", "Fine for tools you use once"),
+    ("Advance Auto Parts", "https://www.advanceautoparts.com", "Aftermarket Parts", ""),
+];
+
+fn demo_spec(make: &str, model: &str, years: (i64, i64), engine_code: Option<&str>, fastener: &str, value: f64, unit: &str) -> TorqueSpecDetails {
+    TorqueSpecDetails {
+        make: make.to_string(),
+        model: model.to_string(),
+        year_from: years.0,
+        year_to: years.1,
+        engine_code: engine_code.map(String::from),
+        fastener: fastener.to_string(),
+        value,
+        unit: unit.to_string(),
+        sequence: None,
+        angle_degrees: None,
+        torque_to_yield: false,
+        source: Some("Factory service manual".to_string()),
+    }
+}
+
+fn demo_torque_specs() -> Vec<TorqueSpecDetails> {
+    vec![
+        demo_spec("Honda", "Civic", (2012, 2015), Some("R18Z1"), "Spark plug", 18.0, "Nm"),
+        demo_spec("Honda", "Civic", (2012, 2015), Some("R18Z1"), "Wheel lug nut", 108.0, "Nm"),
+        demo_spec("Ford", "F-150", (2015, 2020), None, "Front caliper bracket bolt", 148.0, "ft-lb"),
+        demo_spec("Toyota", "Camry", (2018, 2023), Some("A25A-FKS"), "Oil drain plug", 40.0, "Nm"),
+    ]
+}
+
+/// Fill an empty forum with realistic users, posts, comments, votes, stores
+/// and torque specs. Every demo account signs in with `password`.
+pub fn seed_demo(conn: &Connection, password: &str) -> Result<SeedSummary, CliError> {
+    check_password(password)?;
+    for user in DEMO_USERS {
+        if db::get_user_by_username(conn, user.username)?.is_some() || db::get_user_by_email(conn, user.email)?.is_some() {
+            return Err(usage(format!("Demo user {} already exists; seed an empty database", user.username)));
+        }
+    }
+
+    // The accounts share a password, so they can share one (slow) hash too
+    let password_hash = hash_password(password)?;
+    let tx = conn.unchecked_transaction()?;
+    let mut summary = SeedSummary::default();
+
+    let mut user_ids = std::collections::HashMap::new();
+    for user in DEMO_USERS {
+        let user_id = db::create_user(&tx, user.email, &password_hash, user.username)?;
+        db::update_user_role(&tx, user_id, user.role)?;
+        db::mark_email_confirmed(&tx, user_id)?;
+        let text = |value: &'static str| Some(value).filter(|v| !v.is_empty());
+        db::update_user_profile(&tx, user_id, text(user.bio), text(user.specialties), text(user.location), None)?;
+        user_ids.insert(user.username, user_id);
+        summary.users.push((user.username.to_string(), user.role.to_string()));
+    }
+    let voters: Vec<i64> = DEMO_USERS.iter().map(|u| user_ids[u.username]).collect();
+
+    let categories = db::get_categories(&tx)?;
+    let tags = db::get_all_tags(&tx)?;
+    let mut post_ids = std::collections::HashMap::new();
+    for (i, (author, slug, tag, title, body)) in DEMO_POSTS.iter().enumerate() {
+        let category = categories
+            .iter()
+            .find(|c| c.slug == *slug)
+            .ok_or_else(|| usage(format!("Category {} is missing", slug)))?;
+        let tag_ids: Vec<i64> = tags.iter().filter(|t| t.name == *tag).map(|t| t.id).collect();
+        let author_id = user_ids[author];
+        let post_id = db::create_post_with_tags(&tx, author_id, category.id, title, body, &tag_ids, None)?;
+        // A spread of scores, from the other members
+        for &voter in voters.iter().filter(|&&v| v != author_id).take(1 + i % 5) {
+            db::vote_post(&tx, voter, post_id, 1)?;
+        }
+        post_ids.insert(*title, post_id);
+        summary.posts += 1;
+    }
+
+    for (i, (title, author, body)) in DEMO_COMMENTS.iter().enumerate() {
+        let post_id = post_ids[title];
+        let comment_id = db::create_comment(&tx, post_id, user_ids[author], None, body)?;
+        if i == 0 {
+            db::set_best_answer(&tx, post_id, Some(comment_id))?;
+            db::vote_comment(&tx, user_ids["CarNewbie"], comment_id, 1)?;
+        }
+        summary.comments += 1;
+    }
+
+    let mechanics = ["MikeTheMechanic", "SarahWrench", "TransmissionJoe"].map(|name| user_ids[name]);
+    for (i, (name, url, category, description)) in DEMO_STORES.iter().enumerate() {
+        let description = Some(*description).filter(|d| !d.is_empty());
+        let store_id = db::create_store(&tx, name, url, description, category, mechanics[i % 3])?;
+        for (j, &voter) in mechanics.iter().enumerate() {
+            // Mostly positive, with a couple of dissenters
+            db::vote_store(&tx, store_id, voter, (i + j) % 4 != 2)?;
+        }
+        summary.stores += 1;
+    }
+
+    for (i, details) in demo_torque_specs().iter().enumerate() {
+        let submitter = mechanics[i % 3];
+        let spec_id = db::create_torque_spec(&tx, details, submitter)?;
+        for &voter in mechanics.iter().filter(|&&m| m != submitter) {
+            db::vote_torque_spec(&tx, spec_id, voter, true)?;
+        }
+        summary.torque_specs += 1;
+    }
+
+    let civic = VehicleDetails {
+        year: 2012,
+        make: "Honda".to_string(),
+        model: "Civic".to_string(),
+        trim: Some("LX".to_string()),
+        engine_code: Some("R18Z1".to_string()),
+        transmission: Some("Automatic".to_string()),
+        mileage: Some(118_000),
+    };
+    db::create_vehicle(&tx, user_ids["CarNewbie"], &civic, true)?;
+
+    db::create_announcement(
+        &tx,
+        "Welcome to the demo forum",
+        "Every account here is sample data. Sign in as any of them to look around.",
+        "info",
+        user_ids["admin"],
+        None,
+    )?;
+
+    tx.commit()?;
+    Ok(summary)
+}
+
+/// Take a snapshot into `output`, or into the backup directory with
+/// rotation, and record it alongside the server's scheduled ones
+pub fn backup(conn: &Connection, config: &Config, output: Option<&Path>) -> Result<BackupSnapshot, CliError> {
+    let started_at = chrono::Utc::now().format("%Y-%m-%d %H:%M:%S").to_string();
+    let result = match output {
+        Some(dir) => backup::create_snapshot(&config.database.path, &config.uploads.dir, dir),
+        None => backup::backup_to_dir(config),
+    };
+    match &result {
+        Ok(snapshot) => db::record_backup(conn, &started_at, Ok(snapshot))?,
+        Err(e) => db::record_backup(conn, &started_at, Err(&e.to_string()))?,
+    };
+    Ok(result?)
+}
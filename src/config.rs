@@ -0,0 +1,713 @@
+//! Server configuration.
+//!
+//! Every setting has a default. A TOML file can override any of them, and
+//! `WRENCH_*` environment variables and command-line flags override the
+//! file, in that order. The file is the one named by `--config` (or
+//! `WRENCH_CONFIG`); without either, `wrench-forum.toml` in the working
+//! directory is read if it exists.
+//!
+//! ```toml
+//! [server]
+//! bind = "127.0.0.1:3001"
+//!
+//! [database]
+//! path = "/srv/staging/wrench-forum.db"
+//!
+//! [uploads]
+//! dir = "/srv/staging/uploads"
+//!
+//! [mail]
+//! base_url = "https://staging.wrench.example"
+//! file = "/srv/staging/mail.log"
+//! ```
+//!
+//! [`Config::validate`] runs at startup and reports every problem at once,
+//! so a bad file fails fast instead of on the first request that needs it.
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::{self, DbOptions};
+use crate::mail;
+use crate::rate_limit::RateLimits;
+
+/// The config file read when none is named
+pub const DEFAULT_CONFIG_PATH: &str = "wrench-forum.toml";
+
+/// URL prefix uploads are served under, wherever they live on disk
+pub const UPLOADS_URL_PREFIX: &str = "/static/uploads";
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerConfig,
+    pub database: DatabaseConfig,
+    pub uploads: UploadsConfig,
+    pub sessions: SessionsConfig,
+    pub backup: BackupConfig,
+    pub logging: LoggingConfig,
+    pub mail: MailConfig,
+    pub rate_limit: RateLimitConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address and port to listen on
+    pub bind: SocketAddr,
+    /// Glob matching the Tera templates
+    pub templates: String,
+    /// Directory served under `/static`
+    pub static_dir: String,
+    /// Seconds to let in-flight requests and background jobs finish after
+    /// SIGTERM or Ctrl-C before exiting anyway
+    pub shutdown_timeout_seconds: u64,
+    /// Mark cookies `Secure`. Only turn this off for local development over
+    /// plain http.
+    pub secure_cookies: bool,
+    /// Take the client IP from `X-Forwarded-For`. Only safe behind a proxy
+    /// that sets it.
+    pub trust_proxy: bool,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind: SocketAddr::from(([0, 0, 0, 0], 3000)),
+            templates: "templates/**/*.html".to_string(),
+            static_dir: "static".to_string(),
+            shutdown_timeout_seconds: 30,
+            secure_cookies: true,
+            trust_proxy: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DatabaseConfig {
+    pub path: String,
+    /// Number of read-only connections kept in the pool
+    pub read_pool_size: u32,
+    /// Seconds a connection waits on a locked database before giving up
+    pub busy_timeout_seconds: u64,
+}
+
+impl Default for DatabaseConfig {
+    fn default() -> Self {
+        let options = DbOptions::default();
+        Self {
+            path: db::DEFAULT_DB_PATH.to_string(),
+            read_pool_size: options.read_pool_size,
+            busy_timeout_seconds: options.busy_timeout.as_secs(),
+        }
+    }
+}
+
+impl DatabaseConfig {
+    pub fn options(&self) -> DbOptions {
+        DbOptions {
+            read_pool_size: self.read_pool_size,
+            busy_timeout: Duration::from_secs(self.busy_timeout_seconds),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UploadsConfig {
+    /// Where uploaded files are written. They're served under
+    /// [`UPLOADS_URL_PREFIX`] even when this is outside the static directory.
+    pub dir: String,
+    /// Largest image a post can attach, in megabytes
+    pub max_file_mb: usize,
+    /// Largest avatar, in megabytes
+    pub max_avatar_mb: usize,
+}
+
+impl Default for UploadsConfig {
+    fn default() -> Self {
+        Self {
+            dir: "static/uploads".to_string(),
+            max_file_mb: 5,
+            max_avatar_mb: 2,
+        }
+    }
+}
+
+impl UploadsConfig {
+    pub fn max_file_bytes(&self) -> usize {
+        self.max_file_mb * 1024 * 1024
+    }
+
+    pub fn max_avatar_bytes(&self) -> usize {
+        self.max_avatar_mb * 1024 * 1024
+    }
+
+    /// Request body limit for the upload routes: the larger file limit plus
+    /// room for the multipart framing
+    pub fn body_limit(&self) -> usize {
+        self.max_file_bytes().max(self.max_avatar_bytes()) + 64 * 1024
+    }
+
+    /// Where the upload called `filename` is stored
+    pub fn path_for(&self, filename: &str) -> PathBuf {
+        Path::new(&self.dir).join(filename)
+    }
+
+    /// The file behind an upload URL, if it's one of ours
+    pub fn path_for_url(&self, url: &str) -> Option<PathBuf> {
+        let filename = url.strip_prefix(UPLOADS_URL_PREFIX)?.strip_prefix('/')?;
+        if filename.is_empty() || filename.contains(['/', '\\']) || filename.starts_with('.') {
+            return None;
+        }
+        Some(self.path_for(filename))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionsConfig {
+    /// Days a login lasts before the user has to sign in again
+    pub lifetime_days: i64,
+}
+
+impl Default for SessionsConfig {
+    fn default() -> Self {
+        Self { lifetime_days: 30 }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BackupConfig {
+    /// Where snapshots are written, one directory each
+    pub dir: String,
+    /// Hours between scheduled backups; 0 turns the schedule off
+    pub interval_hours: u64,
+    /// Snapshots kept before the oldest is deleted
+    pub keep: usize,
+}
+
+impl Default for BackupConfig {
+    fn default() -> Self {
+        Self {
+            dir: "backups".to_string(),
+            interval_hours: 24,
+            keep: 7,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// `text` for people reading a terminal, `json` for one object per line
+    pub format: String,
+    /// What to log, as a `tracing` filter like `info` or
+    /// `info,wrench_forum::db=debug`. `RUST_LOG` wins if it's set.
+    pub level: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: "text".to_string(),
+            level: "info".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MailConfig {
+    /// Public URL of the site, used in links in emails
+    pub base_url: String,
+    /// Sender address, e.g. `Wrench Forum <noreply@wrench.example>`
+    pub from: String,
+    /// Send through this SMTP relay; without it messages go to `file`
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    /// Left out of `wrench-forum config` output
+    #[serde(skip_serializing)]
+    pub smtp_password: Option<String>,
+    /// STARTTLS. Only turn this off for local catch-all servers.
+    pub smtp_tls: bool,
+    /// Without an SMTP relay, append messages to this file rather than
+    /// printing them
+    pub file: Option<String>,
+}
+
+impl Default for MailConfig {
+    fn default() -> Self {
+        Self {
+            base_url: mail::DEFAULT_BASE_URL.to_string(),
+            from: mail::DEFAULT_FROM.to_string(),
+            smtp_host: None,
+            smtp_port: 587,
+            smtp_username: None,
+            smtp_password: None,
+            smtp_tls: true,
+            file: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RateLimitConfig {
+    /// Minutes failed logins are counted over
+    pub login_window_minutes: i64,
+    /// Failed logins allowed from one IP per window
+    pub login_max_failures_per_ip: i64,
+    /// Failed logins for an account per window that lock it
+    pub login_lockout_threshold: i64,
+    pub login_lockout_minutes: i64,
+    /// Registrations from one IP per hour
+    pub register_max_per_ip: i64,
+    /// Password reset emails asked for from one IP per hour
+    pub password_reset_max_per_ip: i64,
+    /// Password reset emails asked for one address per hour
+    pub password_reset_max_per_account: i64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        let limits = RateLimits::default();
+        Self {
+            login_window_minutes: limits.window_minutes,
+            login_max_failures_per_ip: limits.max_failures_per_ip,
+            login_lockout_threshold: limits.lockout_threshold,
+            login_lockout_minutes: limits.lockout_minutes,
+            register_max_per_ip: limits.max_registrations_per_ip,
+            password_reset_max_per_ip: limits.max_password_resets_per_ip,
+            password_reset_max_per_account: limits.max_password_resets_per_account,
+        }
+    }
+}
+
+/// Settings that can be given as flags or environment variables. Anything
+/// set here wins over the config file.
+#[derive(Debug, Clone, Default, clap::Args)]
+pub struct Overrides {
+    /// Config file to read [default: wrench-forum.toml, if present]
+    #[arg(long, env = "WRENCH_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    /// Address and port to listen on
+    #[arg(long, env = "WRENCH_BIND", global = true)]
+    pub bind: Option<SocketAddr>,
+    /// Glob matching the Tera templates
+    #[arg(long, env = "WRENCH_TEMPLATES", global = true)]
+    pub templates: Option<String>,
+    /// Directory served under /static
+    #[arg(long, env = "WRENCH_STATIC_DIR", global = true)]
+    pub static_dir: Option<String>,
+    /// Seconds to wait for requests and background jobs on shutdown
+    #[arg(long, env = "WRENCH_SHUTDOWN_TIMEOUT_SECONDS", global = true)]
+    pub shutdown_timeout_seconds: Option<u64>,
+    /// SQLite database file
+    #[arg(long, env = "WRENCH_DATABASE", global = true)]
+    pub database: Option<String>,
+    /// Read-only connections kept in the pool
+    #[arg(long, env = "WRENCH_DB_READ_POOL_SIZE", global = true)]
+    pub db_read_pool_size: Option<u32>,
+    /// Seconds to wait on a locked database
+    #[arg(long, env = "WRENCH_DB_BUSY_TIMEOUT_SECONDS", global = true)]
+    pub db_busy_timeout_seconds: Option<u64>,
+    /// Directory uploaded files are written to
+    #[arg(long, env = "WRENCH_UPLOADS_DIR", global = true)]
+    pub uploads_dir: Option<String>,
+    /// Largest image upload, in megabytes
+    #[arg(long, env = "WRENCH_UPLOAD_MAX_FILE_MB", global = true)]
+    pub upload_max_file_mb: Option<usize>,
+    /// Largest avatar upload, in megabytes
+    #[arg(long, env = "WRENCH_UPLOAD_MAX_AVATAR_MB", global = true)]
+    pub upload_max_avatar_mb: Option<usize>,
+    /// Days a login lasts
+    #[arg(long, env = "WRENCH_SESSION_LIFETIME_DAYS", global = true)]
+    pub session_lifetime_days: Option<i64>,
+    /// Directory backups are written to
+    #[arg(long, env = "WRENCH_BACKUP_DIR", global = true)]
+    pub backup_dir: Option<String>,
+    /// Hours between scheduled backups, 0 for none
+    #[arg(long, env = "WRENCH_BACKUP_INTERVAL_HOURS", global = true)]
+    pub backup_interval_hours: Option<u64>,
+    /// Backups to keep
+    #[arg(long, env = "WRENCH_BACKUP_KEEP", global = true)]
+    pub backup_keep: Option<usize>,
+    /// Log format: text or json
+    #[arg(long, env = "WRENCH_LOG_FORMAT", global = true)]
+    pub log_format: Option<String>,
+    /// Log filter, e.g. info or debug
+    #[arg(long, env = "WRENCH_LOG_LEVEL", global = true)]
+    pub log_level: Option<String>,
+    /// Mark cookies Secure (true or false)
+    #[arg(long, env = "WRENCH_SECURE_COOKIES", global = true)]
+    pub secure_cookies: Option<bool>,
+    /// Take the client IP from X-Forwarded-For (true or false)
+    #[arg(long, env = "WRENCH_TRUST_PROXY", global = true)]
+    pub trust_proxy: Option<bool>,
+    /// Public URL used in links in emails
+    #[arg(long, env = "WRENCH_BASE_URL", global = true)]
+    pub base_url: Option<String>,
+    /// Sender address for emails
+    #[arg(long, env = "WRENCH_MAIL_FROM", global = true)]
+    pub mail_from: Option<String>,
+    /// SMTP relay to send email through
+    #[arg(long, env = "WRENCH_SMTP_HOST", global = true)]
+    pub smtp_host: Option<String>,
+    /// SMTP relay port
+    #[arg(long, env = "WRENCH_SMTP_PORT", global = true)]
+    pub smtp_port: Option<u16>,
+    /// SMTP username
+    #[arg(long, env = "WRENCH_SMTP_USERNAME", global = true)]
+    pub smtp_username: Option<String>,
+    /// SMTP password; prefer the variable, flags show up in `ps`
+    #[arg(long, env = "WRENCH_SMTP_PASSWORD", hide_env_values = true, global = true)]
+    pub smtp_password: Option<String>,
+    /// Use STARTTLS with the SMTP relay (true or false)
+    #[arg(long, env = "WRENCH_SMTP_TLS", global = true)]
+    pub smtp_tls: Option<bool>,
+    /// File to append emails to when there's no SMTP relay
+    #[arg(long, env = "WRENCH_MAIL_FILE", global = true)]
+    pub mail_file: Option<String>,
+    /// Minutes failed logins are counted over
+    #[arg(long, env = "WRENCH_LOGIN_WINDOW_MINUTES", global = true)]
+    pub login_window_minutes: Option<i64>,
+    /// Failed logins allowed from one IP per window
+    #[arg(long, env = "WRENCH_LOGIN_MAX_FAILURES_PER_IP", global = true)]
+    pub login_max_failures_per_ip: Option<i64>,
+    /// Failed logins for an account per window that lock it
+    #[arg(long, env = "WRENCH_LOGIN_LOCKOUT_THRESHOLD", global = true)]
+    pub login_lockout_threshold: Option<i64>,
+    /// Minutes an account stays locked
+    #[arg(long, env = "WRENCH_LOGIN_LOCKOUT_MINUTES", global = true)]
+    pub login_lockout_minutes: Option<i64>,
+    /// Registrations from one IP per hour
+    #[arg(long, env = "WRENCH_REGISTER_MAX_PER_IP", global = true)]
+    pub register_max_per_ip: Option<i64>,
+    /// Password reset emails from one IP per hour
+    #[arg(long, env = "WRENCH_PASSWORD_RESET_MAX_PER_IP", global = true)]
+    pub password_reset_max_per_ip: Option<i64>,
+    /// Password reset emails for one address per hour
+    #[arg(long, env = "WRENCH_PASSWORD_RESET_MAX_PER_ACCOUNT", global = true)]
+    pub password_reset_max_per_account: Option<i64>,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The config file couldn't be read
+    Read(PathBuf, std::io::Error),
+    /// The config file isn't valid TOML or has unknown or mistyped settings
+    Parse(PathBuf, String),
+    /// Settings that parse but can't work, one message each
+    Invalid(Vec<String>),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "can't read {}: {}", path.display(), e),
+            ConfigError::Parse(path, e) => write!(f, "{}: {}", path.display(), e),
+            ConfigError::Invalid(problems) => {
+                write!(f, "invalid configuration:")?;
+                for problem in problems {
+                    write!(f, "\n  - {}", problem)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Config {
+    /// Settings from TOML text; anything left out keeps its default
+    pub fn from_toml(text: &str) -> Result<Config, toml::de::Error> {
+        toml::from_str(text)
+    }
+
+    /// The effective configuration: the config file with `overrides` applied,
+    /// validated
+    pub fn load(overrides: &Overrides) -> Result<Config, ConfigError> {
+        let path = match &overrides.config {
+            Some(path) => Some(path.clone()),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_PATH)).filter(|p| p.exists()),
+        };
+        let mut config = match path {
+            Some(path) => {
+                let text = std::fs::read_to_string(&path).map_err(|e| ConfigError::Read(path.clone(), e))?;
+                Config::from_toml(&text).map_err(|e| ConfigError::Parse(path, e.to_string()))?
+            }
+            None => Config::default(),
+        };
+        config.apply(overrides);
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn apply(&mut self, overrides: &Overrides) {
+        let Overrides {
+            config: _,
+            bind,
+            templates,
+            static_dir,
+            shutdown_timeout_seconds,
+            database,
+            db_read_pool_size,
+            db_busy_timeout_seconds,
+            uploads_dir,
+            upload_max_file_mb,
+            upload_max_avatar_mb,
+            session_lifetime_days,
+            backup_dir,
+            backup_interval_hours,
+            backup_keep,
+            log_format,
+            log_level,
+            secure_cookies,
+            trust_proxy,
+            base_url,
+            mail_from,
+            smtp_host,
+            smtp_port,
+            smtp_username,
+            smtp_password,
+            smtp_tls,
+            mail_file,
+            login_window_minutes,
+            login_max_failures_per_ip,
+            login_lockout_threshold,
+            login_lockout_minutes,
+            register_max_per_ip,
+            password_reset_max_per_ip,
+            password_reset_max_per_account,
+        } = overrides.clone();
+
+        if let Some(bind) = bind {
+            self.server.bind = bind;
+        }
+        if let Some(templates) = templates {
+            self.server.templates = templates;
+        }
+        if let Some(static_dir) = static_dir {
+            self.server.static_dir = static_dir;
+        }
+        if let Some(seconds) = shutdown_timeout_seconds {
+            self.server.shutdown_timeout_seconds = seconds;
+        }
+        if let Some(path) = database {
+            self.database.path = path;
+        }
+        if let Some(size) = db_read_pool_size {
+            self.database.read_pool_size = size;
+        }
+        if let Some(seconds) = db_busy_timeout_seconds {
+            self.database.busy_timeout_seconds = seconds;
+        }
+        if let Some(dir) = uploads_dir {
+            self.uploads.dir = dir;
+        }
+        if let Some(mb) = upload_max_file_mb {
+            self.uploads.max_file_mb = mb;
+        }
+        if let Some(mb) = upload_max_avatar_mb {
+            self.uploads.max_avatar_mb = mb;
+        }
+        if let Some(days) = session_lifetime_days {
+            self.sessions.lifetime_days = days;
+        }
+        if let Some(dir) = backup_dir {
+            self.backup.dir = dir;
+        }
+        if let Some(hours) = backup_interval_hours {
+            self.backup.interval_hours = hours;
+        }
+        if let Some(keep) = backup_keep {
+            self.backup.keep = keep;
+        }
+        if let Some(format) = log_format {
+            self.logging.format = format;
+        }
+        if let Some(level) = log_level {
+            self.logging.level = level;
+        }
+        if let Some(secure) = secure_cookies {
+            self.server.secure_cookies = secure;
+        }
+        if let Some(trust) = trust_proxy {
+            self.server.trust_proxy = trust;
+        }
+        if let Some(url) = base_url {
+            self.mail.base_url = url;
+        }
+        if let Some(from) = mail_from {
+            self.mail.from = from;
+        }
+        if smtp_host.is_some() {
+            self.mail.smtp_host = smtp_host;
+        }
+        if let Some(port) = smtp_port {
+            self.mail.smtp_port = port;
+        }
+        if smtp_username.is_some() {
+            self.mail.smtp_username = smtp_username;
+        }
+        if smtp_password.is_some() {
+            self.mail.smtp_password = smtp_password;
+        }
+        if let Some(tls) = smtp_tls {
+            self.mail.smtp_tls = tls;
+        }
+        if mail_file.is_some() {
+            self.mail.file = mail_file;
+        }
+        if let Some(minutes) = login_window_minutes {
+            self.rate_limit.login_window_minutes = minutes;
+        }
+        if let Some(max) = login_max_failures_per_ip {
+            self.rate_limit.login_max_failures_per_ip = max;
+        }
+        if let Some(threshold) = login_lockout_threshold {
+            self.rate_limit.login_lockout_threshold = threshold;
+        }
+        if let Some(minutes) = login_lockout_minutes {
+            self.rate_limit.login_lockout_minutes = minutes;
+        }
+        if let Some(max) = register_max_per_ip {
+            self.rate_limit.register_max_per_ip = max;
+        }
+        if let Some(max) = password_reset_max_per_ip {
+            self.rate_limit.password_reset_max_per_ip = max;
+        }
+        if let Some(max) = password_reset_max_per_account {
+            self.rate_limit.password_reset_max_per_account = max;
+        }
+    }
+
+    /// The login, registration and password reset limits
+    pub fn rate_limits(&self) -> RateLimits {
+        let limits = &self.rate_limit;
+        RateLimits {
+            window_minutes: limits.login_window_minutes,
+            max_failures_per_ip: limits.login_max_failures_per_ip,
+            lockout_threshold: limits.login_lockout_threshold,
+            lockout_minutes: limits.login_lockout_minutes,
+            max_registrations_per_ip: limits.register_max_per_ip,
+            max_password_resets_per_ip: limits.password_reset_max_per_ip,
+            max_password_resets_per_account: limits.password_reset_max_per_account,
+            trust_proxy: self.server.trust_proxy,
+            ..RateLimits::default()
+        }
+    }
+
+    /// Every problem with the settings, so they can all be fixed in one go.
+    /// The uploads and backup directories are created when needed, so they
+    /// only have to be somewhere that can be created.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if self.server.templates.trim().is_empty() {
+            problems.push("server.templates can't be empty".to_string());
+        }
+        if !Path::new(&self.server.static_dir).is_dir() {
+            problems.push(format!("server.static_dir {:?} is not a directory", self.server.static_dir));
+        }
+        if !(1..=600).contains(&self.server.shutdown_timeout_seconds) {
+            problems.push(format!("server.shutdown_timeout_seconds must be between 1 and 600, got {}", self.server.shutdown_timeout_seconds));
+        }
+
+        if self.database.path.trim().is_empty() {
+            problems.push("database.path can't be empty".to_string());
+        } else if let Some(parent) = Path::new(&self.database.path).parent().filter(|p| !p.as_os_str().is_empty()) {
+            if !parent.is_dir() {
+                problems.push(format!("database.path {:?} is in a directory that doesn't exist", self.database.path));
+            }
+        }
+        if !(1..=64).contains(&self.database.read_pool_size) {
+            problems.push(format!("database.read_pool_size must be between 1 and 64, got {}", self.database.read_pool_size));
+        }
+        if self.database.busy_timeout_seconds > 300 {
+            problems.push(format!("database.busy_timeout_seconds must be at most 300, got {}", self.database.busy_timeout_seconds));
+        }
+
+        if self.uploads.dir.trim().is_empty() {
+            problems.push("uploads.dir can't be empty".to_string());
+        } else if Path::new(&self.uploads.dir).exists() && !Path::new(&self.uploads.dir).is_dir() {
+            problems.push(format!("uploads.dir {:?} is not a directory", self.uploads.dir));
+        }
+        for (name, mb) in [("max_file_mb", self.uploads.max_file_mb), ("max_avatar_mb", self.uploads.max_avatar_mb)] {
+            if !(1..=100).contains(&mb) {
+                problems.push(format!("uploads.{} must be between 1 and 100, got {}", name, mb));
+            }
+        }
+
+        if !(1..=365).contains(&self.sessions.lifetime_days) {
+            problems.push(format!("sessions.lifetime_days must be between 1 and 365, got {}", self.sessions.lifetime_days));
+        }
+
+        if self.backup.dir.trim().is_empty() {
+            problems.push("backup.dir can't be empty".to_string());
+        } else if Path::new(&self.backup.dir).exists() && !Path::new(&self.backup.dir).is_dir() {
+            problems.push(format!("backup.dir {:?} is not a directory", self.backup.dir));
+        }
+        if self.backup.interval_hours > 24 * 30 {
+            problems.push(format!("backup.interval_hours must be at most 720, got {}", self.backup.interval_hours));
+        }
+        if !(1..=365).contains(&self.backup.keep) {
+            problems.push(format!("backup.keep must be between 1 and 365, got {}", self.backup.keep));
+        }
+
+        if !["text", "json"].contains(&self.logging.format.as_str()) {
+            problems.push(format!("logging.format must be \"text\" or \"json\", got {:?}", self.logging.format));
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.logging.level) {
+            problems.push(format!("logging.level {:?} isn't a valid filter: {}", self.logging.level, e));
+        }
+
+        if !self.mail.base_url.starts_with("http://") && !self.mail.base_url.starts_with("https://") {
+            problems.push(format!("mail.base_url must start with http:// or https://, got {:?}", self.mail.base_url));
+        }
+        if self.mail.from.parse::<lettre::message::Mailbox>().is_err() {
+            problems.push(format!("mail.from {:?} isn't an email address", self.mail.from));
+        }
+        if self.mail.smtp_host.as_ref().is_some_and(|host| host.trim().is_empty()) {
+            problems.push("mail.smtp_host can't be empty; leave it out to write mail to a file".to_string());
+        }
+        if self.mail.smtp_port == 0 {
+            problems.push("mail.smtp_port can't be 0".to_string());
+        }
+        if self.mail.smtp_password.is_some() && self.mail.smtp_username.is_none() {
+            problems.push("mail.smtp_password is set without mail.smtp_username".to_string());
+        }
+        if self.mail.file.as_ref().is_some_and(|file| file.trim().is_empty()) {
+            problems.push("mail.file can't be empty; leave it out to print mail to stdout".to_string());
+        }
+
+        let limits = &self.rate_limit;
+        for (name, value) in [
+            ("login_window_minutes", limits.login_window_minutes),
+            ("login_max_failures_per_ip", limits.login_max_failures_per_ip),
+            ("login_lockout_threshold", limits.login_lockout_threshold),
+            ("login_lockout_minutes", limits.login_lockout_minutes),
+            ("register_max_per_ip", limits.register_max_per_ip),
+            ("password_reset_max_per_ip", limits.password_reset_max_per_ip),
+            ("password_reset_max_per_account", limits.password_reset_max_per_account),
+        ] {
+            if !(1..=10_000).contains(&value) {
+                problems.push(format!("rate_limit.{} must be between 1 and 10000, got {}", name, value));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Invalid(problems))
+        }
+    }
+}
@@ -0,0 +1,113 @@
+//! Cross-site request forgery protection.
+//!
+//! Requests that change state must send the browser's token in the
+//! `X-CSRF-Token` header; `base.html` adds it to every htmx request. For a
+//! signed-in browser the token is an HMAC keyed by its session token, so it
+//! is bound to the session and changes with every login: another site (or
+//! a subdomain that can plant cookies) can't know it. A signed-out browser,
+//! with only forms like login to submit, gets a random token instead and
+//! must echo it back as a double-submit cookie.
+//!
+//! Either way the token reaches the page's script in the `csrf` cookie,
+//! which is reissued whenever the session cookie changes. The cookie is
+//! only a way to deliver it: for signed-in requests the check is against
+//! the token derived from the session, never the cookie.
+//!
+//! The JSON API under `/api/v1` is exempt: it only accepts bearer tokens,
+//! which a browser never attaches on its own.
+
+use axum::{
+    extract::Request,
+    http::{header::SET_COOKIE, HeaderValue},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use axum_extra::extract::CookieJar;
+use cookie::Cookie;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::sync::Arc;
+
+use crate::auth::{create_email_token, site_cookie};
+use crate::config::Config;
+use crate::error::AppError;
+
+pub const COOKIE_NAME: &str = "csrf";
+pub const HEADER_NAME: &str = "x-csrf-token";
+
+/// The token for a signed-in browser, derived from its session token
+pub fn session_token(session: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(session.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(b"wrench-forum csrf");
+    mac.finalize().into_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Whether the token sent with a request matches the expected one
+pub fn token_matches(expected: Option<&str>, sent: Option<&str>) -> bool {
+    match (expected, sent) {
+        (Some(expected), Some(sent)) if !expected.is_empty() => {
+            expected.len() == sent.len()
+                && expected.bytes().zip(sent.bytes()).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+        }
+        _ => false,
+    }
+}
+
+/// The session a response leaves the browser with: the one it sets or
+/// clears, if any, otherwise the request's
+fn session_after(response: &Response, session: Option<String>) -> Option<String> {
+    let set = response
+        .headers()
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .filter_map(|v| Cookie::parse(v).ok())
+        .rfind(|c| c.name() == "session");
+    match set {
+        Some(cookie) => Some(cookie.value().to_string()).filter(|v| !v.is_empty()),
+        None => session,
+    }
+}
+
+/// Middleware for the whole router: rejects unsafe methods without the
+/// right token and keeps the browser's `csrf` cookie holding it
+pub async fn protect(Extension(config): Extension<Arc<Config>>, jar: CookieJar, request: Request, next: Next) -> Response {
+    let cookie = jar.get(COOKIE_NAME).map(|c| c.value().to_string());
+    let session = jar.get("session").map(|c| c.value().to_string()).filter(|v| !v.is_empty());
+
+    let api = request.uri().path().starts_with("/api/v1/");
+    if !request.method().is_safe() && !api {
+        let expected = match &session {
+            Some(session) => Some(session_token(session)),
+            None => cookie.clone(),
+        };
+        let sent = request.headers().get(HEADER_NAME).and_then(|v| v.to_str().ok());
+        if !token_matches(expected.as_deref(), sent) {
+            return AppError::forbidden("This form has expired. Reload the page and try again.").into_response();
+        }
+    }
+
+    let mut response = next.run(request).await;
+    if api {
+        return response;
+    }
+
+    let wanted = match session_after(&response, session.clone()) {
+        Some(session) => session_token(&session),
+        // Still signed out: keep the random token if there is one
+        None => match cookie.clone().filter(|_| session.is_none()) {
+            Some(token) => token,
+            None => create_email_token(),
+        },
+    };
+    if cookie.as_deref() != Some(wanted.as_str()) {
+        // Read by the page's script, so not http-only
+        let cookie = site_cookie(COOKIE_NAME, wanted, config.server.secure_cookies).build();
+        if let Ok(value) = HeaderValue::from_str(&cookie.to_string()) {
+            response.headers_mut().append(SET_COOKIE, value);
+        }
+    }
+
+    response
+}
@@ -1,269 +1,1011 @@
+use r2d2::{Pool, PooledConnection};
+use r2d2_sqlite::SqliteConnectionManager;
 use rusqlite::{Connection, Result, params};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, LazyLock, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
 use crate::models::*;
+use crate::telemetry;
 
-pub type Db = Arc<Mutex<Connection>>;
-
-pub fn init_db() -> Result<Db> {
-    let conn = Connection::open("wrench-forum.db")?;
-    create_tables(&conn)?;
-    seed_defaults(&conn)?;
-    Ok(Arc::new(Mutex::new(conn)))
-}
-
-pub fn init_db_with_path(path: &str) -> Result<Db> {
-    let conn = Connection::open(path)?;
-    create_tables(&conn)?;
-    Ok(Arc::new(Mutex::new(conn)))
-}
-
-fn create_tables(conn: &Connection) -> Result<()> {
-    conn.execute_batch(r#"
-        -- Core user table
-        CREATE TABLE IF NOT EXISTS users (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            email TEXT UNIQUE NOT NULL,
-            password_hash TEXT NOT NULL,
-            username TEXT UNIQUE NOT NULL,
-            role TEXT NOT NULL DEFAULT 'unverified',
-            created_at TEXT NOT NULL DEFAULT (datetime('now')),
-            banned INTEGER NOT NULL DEFAULT 0,
-            karma INTEGER NOT NULL DEFAULT 0,
-            flair TEXT
-        );
+pub const DEFAULT_DB_PATH: &str = "wrench-forum.db";
 
-        -- User profiles (extended info)
-        CREATE TABLE IF NOT EXISTS user_profiles (
-            user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
-            avatar_path TEXT,
-            bio TEXT,
-            specialties TEXT,
-            location TEXT,
-            website TEXT
-        );
+/// Tunables for opening the database
+#[derive(Debug, Clone)]
+pub struct DbOptions {
+    /// Number of read-only connections kept in the pool
+    pub read_pool_size: u32,
+    /// How long a connection waits on a locked database before giving up
+    pub busy_timeout: Duration,
+}
 
-        -- Verification requests
-        CREATE TABLE IF NOT EXISTS verification_requests (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            proof_text TEXT NOT NULL,
-            proof_type TEXT NOT NULL,
-            status TEXT NOT NULL DEFAULT 'pending',
-            reviewed_by INTEGER REFERENCES users(id),
-            created_at TEXT NOT NULL DEFAULT (datetime('now'))
-        );
+impl Default for DbOptions {
+    fn default() -> Self {
+        Self {
+            read_pool_size: 8,
+            busy_timeout: Duration::from_secs(5),
+        }
+    }
+}
 
-        -- Categories
-        CREATE TABLE IF NOT EXISTS categories (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            name TEXT NOT NULL,
-            slug TEXT UNIQUE NOT NULL,
-            description TEXT NOT NULL DEFAULT '',
-            icon TEXT,
-            color TEXT DEFAULT '#6b7280'
-        );
+#[derive(Debug)]
+pub enum DbError {
+    Sqlite(rusqlite::Error),
+    Pool(r2d2::Error),
+    /// The blocking task running the query panicked or was cancelled
+    TaskFailed,
+}
 
-        -- Post tags/flair
-        CREATE TABLE IF NOT EXISTS post_tags (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            name TEXT NOT NULL UNIQUE,
-            color TEXT NOT NULL DEFAULT '#6b7280',
-            description TEXT
-        );
+impl std::fmt::Display for DbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbError::Sqlite(e) => write!(f, "database error: {}", e),
+            DbError::Pool(e) => write!(f, "connection pool error: {}", e),
+            DbError::TaskFailed => write!(f, "database task failed"),
+        }
+    }
+}
 
-        -- Posts
-        CREATE TABLE IF NOT EXISTS posts (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            category_id INTEGER NOT NULL REFERENCES categories(id),
-            title TEXT NOT NULL,
-            body TEXT NOT NULL,
-            body_html TEXT,
-            score INTEGER NOT NULL DEFAULT 0,
-            created_at TEXT NOT NULL DEFAULT (datetime('now')),
-            edited_at TEXT,
-            removed INTEGER NOT NULL DEFAULT 0,
-            pinned INTEGER NOT NULL DEFAULT 0,
-            best_answer_id INTEGER REFERENCES comments(id)
-        );
+impl std::error::Error for DbError {}
 
-        -- Post to tag mapping
-        CREATE TABLE IF NOT EXISTS post_tag_map (
-            post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
-            tag_id INTEGER NOT NULL REFERENCES post_tags(id) ON DELETE CASCADE,
-            PRIMARY KEY (post_id, tag_id)
-        );
+impl From<rusqlite::Error> for DbError {
+    fn from(e: rusqlite::Error) -> Self {
+        DbError::Sqlite(e)
+    }
+}
 
-        -- Comments
-        CREATE TABLE IF NOT EXISTS comments (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            post_id INTEGER NOT NULL REFERENCES posts(id),
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            parent_id INTEGER REFERENCES comments(id),
-            body TEXT NOT NULL,
-            body_html TEXT,
-            score INTEGER NOT NULL DEFAULT 0,
-            created_at TEXT NOT NULL DEFAULT (datetime('now')),
-            edited_at TEXT,
-            removed INTEGER NOT NULL DEFAULT 0
-        );
+impl From<r2d2::Error> for DbError {
+    fn from(e: r2d2::Error) -> Self {
+        DbError::Pool(e)
+    }
+}
 
-        -- Votes (for posts and comments)
-        CREATE TABLE IF NOT EXISTS votes (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            post_id INTEGER REFERENCES posts(id),
-            comment_id INTEGER REFERENCES comments(id),
-            value INTEGER NOT NULL CHECK (value IN (-1, 1)),
-            created_at TEXT NOT NULL DEFAULT (datetime('now')),
-            UNIQUE(user_id, post_id, comment_id)
-        );
+/// Shared database handle: a pool of read-only WAL connections plus a single
+/// dedicated write connection. SQLite only allows one writer at a time, so
+/// serializing writes here avoids `SQLITE_BUSY` churn while reads proceed in
+/// parallel.
+///
+/// Handlers should go through the async [`Db::read`] / [`Db::write`], which
+/// run the closure on tokio's blocking pool. The sync accessors are for the
+/// CLI and tests.
+#[derive(Clone)]
+pub struct Db {
+    readers: Pool<SqliteConnectionManager>,
+    writer: Arc<Mutex<Connection>>,
+}
 
-        -- Stores
-        CREATE TABLE IF NOT EXISTS stores (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            name TEXT NOT NULL,
-            url TEXT NOT NULL,
-            description TEXT,
-            category TEXT NOT NULL,
-            submitted_by INTEGER NOT NULL REFERENCES users(id),
-            created_at TEXT NOT NULL DEFAULT (datetime('now'))
-        );
+impl Db {
+    pub fn open(path: &str, options: &DbOptions) -> std::result::Result<Db, DbError> {
+        let writer = open_db(path)?;
+        writer.busy_timeout(options.busy_timeout)?;
+        writer.execute_batch("PRAGMA journal_mode = WAL; PRAGMA synchronous = NORMAL;")?;
+        run_migrations(&writer)?;
+        seed_defaults(&writer)?;
+
+        let busy_timeout = options.busy_timeout;
+        let manager = SqliteConnectionManager::file(path).with_init(move |c| {
+            c.busy_timeout(busy_timeout)?;
+            c.execute_batch("PRAGMA query_only = ON;")
+        });
+        let readers = Pool::builder()
+            .max_size(options.read_pool_size)
+            .build(manager)?;
+
+        Ok(Db {
+            readers,
+            writer: Arc::new(Mutex::new(writer)),
+        })
+    }
 
-        -- Store votes
-        CREATE TABLE IF NOT EXISTS store_votes (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            store_id INTEGER NOT NULL REFERENCES stores(id),
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            positive INTEGER NOT NULL,
-            created_at TEXT NOT NULL DEFAULT (datetime('now')),
-            UNIQUE(store_id, user_id)
-        );
+    /// Check out a read-only connection, blocking until one is free
+    pub fn conn(&self) -> std::result::Result<PooledConnection<SqliteConnectionManager>, DbError> {
+        Ok(self.readers.get()?)
+    }
 
-        -- Reports
-        CREATE TABLE IF NOT EXISTS reports (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            reporter_id INTEGER NOT NULL REFERENCES users(id),
-            post_id INTEGER REFERENCES posts(id),
-            comment_id INTEGER REFERENCES comments(id),
-            reason TEXT NOT NULL,
-            resolved INTEGER NOT NULL DEFAULT 0,
-            resolved_by INTEGER REFERENCES users(id),
-            resolution_note TEXT,
-            created_at TEXT NOT NULL DEFAULT (datetime('now'))
-        );
+    /// Lock the write connection, blocking until it is free. A handler that
+    /// panicked while holding it does not take the server down with it:
+    /// SQLite rolls back its open transaction and the lock is reused.
+    pub fn write_conn(&self) -> MutexGuard<'_, Connection> {
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 
-        -- Sessions
-        CREATE TABLE IF NOT EXISTS sessions (
-            token TEXT PRIMARY KEY,
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            expires_at TEXT NOT NULL,
-            ip_address TEXT,
-            user_agent TEXT,
-            created_at TEXT NOT NULL DEFAULT (datetime('now'))
-        );
+    /// Run read-only queries on a pooled connection off the async runtime
+    pub async fn read<F, T>(&self, f: F) -> std::result::Result<T, DbError>
+    where
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = self.clone();
+        let operation = telemetry::operation_name::<F>();
+        let span = tracing::Span::current();
+        let queued = Instant::now();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let conn = db.conn()?;
+            let wait = queued.elapsed();
+            let start = Instant::now();
+            let result = f(&conn);
+            telemetry::observe_db("read", &operation, wait, start.elapsed());
+            Ok(result?)
+        })
+        .await
+        .map_err(|_| DbError::TaskFailed)?
+    }
 
-        -- Bookmarks
-        CREATE TABLE IF NOT EXISTS bookmarks (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            post_id INTEGER NOT NULL REFERENCES posts(id),
-            created_at TEXT NOT NULL DEFAULT (datetime('now')),
-            UNIQUE(user_id, post_id)
-        );
+    /// Run queries that modify data on the write connection off the async runtime
+    pub async fn write<F, T>(&self, f: F) -> std::result::Result<T, DbError>
+    where
+        F: FnOnce(&Connection) -> Result<T> + Send + 'static,
+        T: Send + 'static,
+    {
+        let db = self.clone();
+        let operation = telemetry::operation_name::<F>();
+        let span = tracing::Span::current();
+        let queued = Instant::now();
+        tokio::task::spawn_blocking(move || {
+            let _entered = span.enter();
+            let conn = db.write_conn();
+            let wait = queued.elapsed();
+            let start = Instant::now();
+            let result = f(&conn);
+            telemetry::observe_db("write", &operation, wait, start.elapsed());
+            Ok(result?)
+        })
+        .await
+        .map_err(|_| DbError::TaskFailed)?
+    }
+}
 
-        -- Notifications
-        CREATE TABLE IF NOT EXISTS notifications (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            notification_type TEXT NOT NULL,
-            content TEXT NOT NULL,
-            read INTEGER NOT NULL DEFAULT 0,
-            post_id INTEGER REFERENCES posts(id),
-            comment_id INTEGER REFERENCES comments(id),
-            from_user_id INTEGER REFERENCES users(id),
-            created_at TEXT NOT NULL DEFAULT (datetime('now'))
-        );
+pub fn init_db() -> std::result::Result<Db, DbError> {
+    Db::open(DEFAULT_DB_PATH, &DbOptions::default())
+}
 
-        -- Announcements
-        CREATE TABLE IF NOT EXISTS announcements (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            title TEXT NOT NULL,
-            content TEXT NOT NULL,
-            active INTEGER NOT NULL DEFAULT 1,
-            pinned INTEGER NOT NULL DEFAULT 0,
-            announcement_type TEXT NOT NULL DEFAULT 'info',
-            created_by INTEGER NOT NULL REFERENCES users(id),
-            created_at TEXT NOT NULL DEFAULT (datetime('now')),
-            expires_at TEXT
-        );
+pub fn init_db_with_path(path: &str) -> std::result::Result<Db, DbError> {
+    Db::open(path, &DbOptions::default())
+}
 
-        -- File uploads
-        CREATE TABLE IF NOT EXISTS uploads (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            filename TEXT NOT NULL,
-            original_name TEXT NOT NULL,
-            path TEXT NOT NULL,
-            mime_type TEXT NOT NULL,
-            size_bytes INTEGER NOT NULL,
-            created_at TEXT NOT NULL DEFAULT (datetime('now'))
-        );
+/// Open a database file without applying any migrations
+pub fn open_db(path: &str) -> Result<Connection> {
+    Connection::open(path)
+}
 
-        -- Post edit history
-        CREATE TABLE IF NOT EXISTS post_edits (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            post_id INTEGER NOT NULL REFERENCES posts(id),
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            old_title TEXT,
-            old_body TEXT NOT NULL,
-            edit_reason TEXT,
-            created_at TEXT NOT NULL DEFAULT (datetime('now'))
-        );
+/// Open an existing database file for inspection; nothing can be written
+pub fn open_db_read_only(path: &str) -> Result<Connection> {
+    Connection::open_with_flags(path, rusqlite::OpenFlags::SQLITE_OPEN_READ_ONLY | rusqlite::OpenFlags::SQLITE_OPEN_NO_MUTEX)
+}
 
-        -- Comment edit history
-        CREATE TABLE IF NOT EXISTS comment_edits (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            comment_id INTEGER NOT NULL REFERENCES comments(id),
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            old_body TEXT NOT NULL,
-            edit_reason TEXT,
-            created_at TEXT NOT NULL DEFAULT (datetime('now'))
-        );
+// ============ Schema Migrations ============
+
+/// A numbered schema change. Migrations are applied in version order and
+/// each one runs exactly once per database; never edit one that has shipped,
+/// add a new one instead.
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub sql: &'static str,
+    /// Data fix-ups that need Rust, run in the same transaction after `sql`
+    pub after: Option<fn(&Connection) -> Result<()>>,
+}
 
-        -- Activity logs for moderation
-        CREATE TABLE IF NOT EXISTS activity_logs (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            user_id INTEGER NOT NULL REFERENCES users(id),
-            action TEXT NOT NULL,
-            target_type TEXT,
-            target_id INTEGER,
-            details TEXT,
-            ip_address TEXT,
-            created_at TEXT NOT NULL DEFAULT (datetime('now'))
-        );
+pub const MIGRATIONS: &[Migration] = &[
+    // Everything up to and including the old `create_tables` batch. It keeps
+    // the IF NOT EXISTS guards so databases created before versioning adopt
+    // it cleanly.
+    Migration {
+        version: 1,
+        name: "baseline",
+        sql: r#"
+            -- Core user table
+            CREATE TABLE IF NOT EXISTS users (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                email TEXT UNIQUE NOT NULL,
+                password_hash TEXT NOT NULL,
+                username TEXT UNIQUE NOT NULL,
+                role TEXT NOT NULL DEFAULT 'unverified',
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                banned INTEGER NOT NULL DEFAULT 0,
+                karma INTEGER NOT NULL DEFAULT 0,
+                flair TEXT
+            );
+
+            -- User profiles (extended info)
+            CREATE TABLE IF NOT EXISTS user_profiles (
+                user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE,
+                avatar_path TEXT,
+                bio TEXT,
+                specialties TEXT,
+                location TEXT,
+                website TEXT
+            );
+
+            -- Verification requests
+            CREATE TABLE IF NOT EXISTS verification_requests (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                proof_text TEXT NOT NULL,
+                proof_type TEXT NOT NULL,
+                status TEXT NOT NULL DEFAULT 'pending',
+                reviewed_by INTEGER REFERENCES users(id),
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            -- Categories
+            CREATE TABLE IF NOT EXISTS categories (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                slug TEXT UNIQUE NOT NULL,
+                description TEXT NOT NULL DEFAULT '',
+                icon TEXT,
+                color TEXT DEFAULT '#6b7280'
+            );
+
+            -- Post tags/flair
+            CREATE TABLE IF NOT EXISTS post_tags (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL UNIQUE,
+                color TEXT NOT NULL DEFAULT '#6b7280',
+                description TEXT
+            );
+
+            -- Posts
+            CREATE TABLE IF NOT EXISTS posts (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                category_id INTEGER NOT NULL REFERENCES categories(id),
+                title TEXT NOT NULL,
+                body TEXT NOT NULL,
+                body_html TEXT,
+                score INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                edited_at TEXT,
+                removed INTEGER NOT NULL DEFAULT 0,
+                pinned INTEGER NOT NULL DEFAULT 0,
+                best_answer_id INTEGER REFERENCES comments(id)
+            );
+
+            -- Post to tag mapping
+            CREATE TABLE IF NOT EXISTS post_tag_map (
+                post_id INTEGER NOT NULL REFERENCES posts(id) ON DELETE CASCADE,
+                tag_id INTEGER NOT NULL REFERENCES post_tags(id) ON DELETE CASCADE,
+                PRIMARY KEY (post_id, tag_id)
+            );
+
+            -- Comments
+            CREATE TABLE IF NOT EXISTS comments (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                post_id INTEGER NOT NULL REFERENCES posts(id),
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                parent_id INTEGER REFERENCES comments(id),
+                body TEXT NOT NULL,
+                body_html TEXT,
+                score INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                edited_at TEXT,
+                removed INTEGER NOT NULL DEFAULT 0
+            );
+
+            -- Votes (for posts and comments)
+            CREATE TABLE IF NOT EXISTS votes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                post_id INTEGER REFERENCES posts(id),
+                comment_id INTEGER REFERENCES comments(id),
+                value INTEGER NOT NULL CHECK (value IN (-1, 1)),
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                UNIQUE(user_id, post_id, comment_id)
+            );
+
+            -- Stores
+            CREATE TABLE IF NOT EXISTS stores (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                name TEXT NOT NULL,
+                url TEXT NOT NULL,
+                description TEXT,
+                category TEXT NOT NULL,
+                submitted_by INTEGER NOT NULL REFERENCES users(id),
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            -- Store votes
+            CREATE TABLE IF NOT EXISTS store_votes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                store_id INTEGER NOT NULL REFERENCES stores(id),
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                positive INTEGER NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                UNIQUE(store_id, user_id)
+            );
+
+            -- Reports
+            CREATE TABLE IF NOT EXISTS reports (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                reporter_id INTEGER NOT NULL REFERENCES users(id),
+                post_id INTEGER REFERENCES posts(id),
+                comment_id INTEGER REFERENCES comments(id),
+                reason TEXT NOT NULL,
+                resolved INTEGER NOT NULL DEFAULT 0,
+                resolved_by INTEGER REFERENCES users(id),
+                resolution_note TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            -- Sessions
+            CREATE TABLE IF NOT EXISTS sessions (
+                token TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                expires_at TEXT NOT NULL,
+                ip_address TEXT,
+                user_agent TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            -- Bookmarks
+            CREATE TABLE IF NOT EXISTS bookmarks (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                post_id INTEGER NOT NULL REFERENCES posts(id),
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                UNIQUE(user_id, post_id)
+            );
+
+            -- Notifications
+            CREATE TABLE IF NOT EXISTS notifications (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                notification_type TEXT NOT NULL,
+                content TEXT NOT NULL,
+                read INTEGER NOT NULL DEFAULT 0,
+                post_id INTEGER REFERENCES posts(id),
+                comment_id INTEGER REFERENCES comments(id),
+                from_user_id INTEGER REFERENCES users(id),
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            -- Announcements
+            CREATE TABLE IF NOT EXISTS announcements (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                title TEXT NOT NULL,
+                content TEXT NOT NULL,
+                active INTEGER NOT NULL DEFAULT 1,
+                pinned INTEGER NOT NULL DEFAULT 0,
+                announcement_type TEXT NOT NULL DEFAULT 'info',
+                created_by INTEGER NOT NULL REFERENCES users(id),
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                expires_at TEXT
+            );
+
+            -- File uploads
+            CREATE TABLE IF NOT EXISTS uploads (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                filename TEXT NOT NULL,
+                original_name TEXT NOT NULL,
+                path TEXT NOT NULL,
+                mime_type TEXT NOT NULL,
+                size_bytes INTEGER NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            -- Post edit history
+            CREATE TABLE IF NOT EXISTS post_edits (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                post_id INTEGER NOT NULL REFERENCES posts(id),
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                old_title TEXT,
+                old_body TEXT NOT NULL,
+                edit_reason TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            -- Comment edit history
+            CREATE TABLE IF NOT EXISTS comment_edits (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                comment_id INTEGER NOT NULL REFERENCES comments(id),
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                old_body TEXT NOT NULL,
+                edit_reason TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            -- Activity logs for moderation
+            CREATE TABLE IF NOT EXISTS activity_logs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                action TEXT NOT NULL,
+                target_type TEXT,
+                target_id INTEGER,
+                details TEXT,
+                ip_address TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            -- Indexes for performance
+            CREATE INDEX IF NOT EXISTS idx_posts_category ON posts(category_id);
+            CREATE INDEX IF NOT EXISTS idx_posts_user ON posts(user_id);
+            CREATE INDEX IF NOT EXISTS idx_posts_created ON posts(created_at DESC);
+            CREATE INDEX IF NOT EXISTS idx_posts_score ON posts(score DESC);
+            CREATE INDEX IF NOT EXISTS idx_comments_post ON comments(post_id);
+            CREATE INDEX IF NOT EXISTS idx_comments_user ON comments(user_id);
+            CREATE INDEX IF NOT EXISTS idx_votes_post ON votes(post_id);
+            CREATE INDEX IF NOT EXISTS idx_votes_comment ON votes(comment_id);
+            CREATE INDEX IF NOT EXISTS idx_votes_user ON votes(user_id);
+            CREATE INDEX IF NOT EXISTS idx_bookmarks_user ON bookmarks(user_id);
+            CREATE INDEX IF NOT EXISTS idx_notifications_user ON notifications(user_id);
+            CREATE INDEX IF NOT EXISTS idx_notifications_read ON notifications(read);
+            CREATE INDEX IF NOT EXISTS idx_sessions_user ON sessions(user_id);
+            CREATE INDEX IF NOT EXISTS idx_activity_user ON activity_logs(user_id);
+            CREATE INDEX IF NOT EXISTS idx_activity_created ON activity_logs(created_at DESC);
+        "#,
+        after: None,
+    },
+    // Full-text index over posts and their comments. The rowid is the post
+    // id; removed posts and comments are kept out of the index by triggers.
+    Migration {
+        version: 2,
+        name: "posts_fts",
+        sql: r#"
+            CREATE VIRTUAL TABLE posts_fts USING fts5(
+                title,
+                body,
+                comments,
+                tokenize = 'unicode61 remove_diacritics 2'
+            );
+
+            INSERT INTO posts_fts (rowid, title, body, comments)
+            SELECT p.id, p.title, p.body,
+                   COALESCE((SELECT group_concat(c.body, ' ') FROM comments c WHERE c.post_id = p.id AND c.removed = 0), '')
+            FROM posts p WHERE p.removed = 0;
+
+            CREATE TRIGGER posts_fts_insert AFTER INSERT ON posts WHEN new.removed = 0 BEGIN
+                INSERT INTO posts_fts (rowid, title, body, comments) VALUES (new.id, new.title, new.body, '');
+            END;
+
+            CREATE TRIGGER posts_fts_update AFTER UPDATE OF title, body, removed ON posts BEGIN
+                DELETE FROM posts_fts WHERE rowid = old.id;
+                INSERT INTO posts_fts (rowid, title, body, comments)
+                SELECT new.id, new.title, new.body,
+                       COALESCE((SELECT group_concat(c.body, ' ') FROM comments c WHERE c.post_id = new.id AND c.removed = 0), '')
+                WHERE new.removed = 0;
+            END;
+
+            CREATE TRIGGER posts_fts_delete AFTER DELETE ON posts BEGIN
+                DELETE FROM posts_fts WHERE rowid = old.id;
+            END;
+
+            CREATE TRIGGER comments_fts_insert AFTER INSERT ON comments BEGIN
+                UPDATE posts_fts
+                SET comments = COALESCE((SELECT group_concat(c.body, ' ') FROM comments c WHERE c.post_id = new.post_id AND c.removed = 0), '')
+                WHERE rowid = new.post_id;
+            END;
+
+            CREATE TRIGGER comments_fts_update AFTER UPDATE OF body, removed ON comments BEGIN
+                UPDATE posts_fts
+                SET comments = COALESCE((SELECT group_concat(c.body, ' ') FROM comments c WHERE c.post_id = new.post_id AND c.removed = 0), '')
+                WHERE rowid = new.post_id;
+            END;
+
+            CREATE TRIGGER comments_fts_delete AFTER DELETE ON comments BEGIN
+                UPDATE posts_fts
+                SET comments = COALESCE((SELECT group_concat(c.body, ' ') FROM comments c WHERE c.post_id = old.post_id AND c.removed = 0), '')
+                WHERE rowid = old.post_id;
+            END;
+        "#,
+        after: None,
+    },
+    Migration {
+        version: 3,
+        name: "vehicles",
+        sql: r#"
+            CREATE TABLE vehicles (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                year INTEGER NOT NULL,
+                make TEXT NOT NULL,
+                model TEXT NOT NULL,
+                trim TEXT,
+                engine_code TEXT,
+                transmission TEXT,
+                mileage INTEGER,
+                in_garage INTEGER NOT NULL DEFAULT 1,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            ALTER TABLE posts ADD COLUMN vehicle_id INTEGER REFERENCES vehicles(id);
+
+            CREATE INDEX idx_vehicles_user ON vehicles(user_id);
+            CREATE INDEX idx_vehicles_make_model ON vehicles(make COLLATE NOCASE, model COLLATE NOCASE, year);
+            CREATE INDEX idx_posts_vehicle ON posts(vehicle_id);
+        "#,
+        after: None,
+    },
+    Migration {
+        version: 4,
+        name: "dtc_codes",
+        sql: concat!(
+            r#"
+            CREATE TABLE dtc_codes (
+                code TEXT PRIMARY KEY,
+                description TEXT NOT NULL
+            );
+
+            -- Causes aren't tied to dtc_codes so manufacturer-specific codes
+            -- can collect them too
+            CREATE TABLE dtc_causes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                code TEXT NOT NULL,
+                cause TEXT NOT NULL,
+                created_by INTEGER REFERENCES users(id),
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE TABLE dtc_cause_suggestions (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                code TEXT NOT NULL,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                action TEXT NOT NULL CHECK (action IN ('add', 'remove')),
+                cause TEXT NOT NULL,
+                cause_id INTEGER REFERENCES dtc_causes(id) ON DELETE SET NULL,
+                status TEXT NOT NULL DEFAULT 'pending',
+                reviewed_by INTEGER REFERENCES users(id),
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                reviewed_at TEXT
+            );
+
+            CREATE INDEX idx_dtc_causes_code ON dtc_causes(code);
+            CREATE INDEX idx_dtc_suggestions_status ON dtc_cause_suggestions(status, created_at);
+            "#,
+            include_str!("../data/dtc_codes.sql")
+        ),
+        // Existing posts and comments pick up DTC links from the re-render
+        // in migration 16
+        after: None,
+    },
+    // Repair procedures are posts with a row in `procedures`; the post body
+    // is the overview and the child tables hold the structured parts
+    Migration {
+        version: 5,
+        name: "procedures",
+        sql: r#"
+            CREATE TABLE procedures (
+                post_id INTEGER PRIMARY KEY REFERENCES posts(id),
+                difficulty TEXT NOT NULL CHECK (difficulty IN ('beginner', 'intermediate', 'advanced', 'expert')),
+                labor_minutes INTEGER
+            );
+
+            CREATE TABLE procedure_steps (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                post_id INTEGER NOT NULL REFERENCES procedures(post_id),
+                position INTEGER NOT NULL,
+                body TEXT NOT NULL,
+                body_html TEXT,
+                upload_id INTEGER REFERENCES uploads(id)
+            );
+
+            CREATE TABLE procedure_tools (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                post_id INTEGER NOT NULL REFERENCES procedures(post_id),
+                position INTEGER NOT NULL,
+                name TEXT NOT NULL
+            );
+
+            CREATE TABLE procedure_parts (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                post_id INTEGER NOT NULL REFERENCES procedures(post_id),
+                position INTEGER NOT NULL,
+                name TEXT NOT NULL,
+                part_number TEXT,
+                quantity INTEGER NOT NULL DEFAULT 1
+            );
+
+            CREATE TABLE procedure_torque_specs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                post_id INTEGER NOT NULL REFERENCES procedures(post_id),
+                position INTEGER NOT NULL,
+                fastener TEXT NOT NULL,
+                value REAL NOT NULL,
+                unit TEXT NOT NULL CHECK (unit IN ('Nm', 'ft-lb', 'in-lb'))
+            );
+
+            CREATE INDEX idx_procedure_steps_post ON procedure_steps(post_id, position);
+            CREATE INDEX idx_procedure_tools_post ON procedure_tools(post_id, position);
+            CREATE INDEX idx_procedure_parts_post ON procedure_parts(post_id, position);
+            CREATE INDEX idx_procedure_torque_specs_post ON procedure_torque_specs(post_id, position);
+        "#,
+        after: None,
+    },
+    // Community torque spec library. A spec covers one fastener on a range of
+    // model years; verified users confirm or dispute it like store votes.
+    Migration {
+        version: 6,
+        name: "torque_specs",
+        sql: r#"
+            CREATE TABLE torque_specs (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                make TEXT NOT NULL,
+                model TEXT NOT NULL,
+                year_from INTEGER NOT NULL,
+                year_to INTEGER NOT NULL,
+                engine_code TEXT,
+                fastener TEXT NOT NULL,
+                value REAL NOT NULL,
+                unit TEXT NOT NULL CHECK (unit IN ('Nm', 'ft-lb', 'in-lb')),
+                sequence TEXT,
+                angle_degrees INTEGER,
+                torque_to_yield INTEGER NOT NULL DEFAULT 0,
+                source TEXT,
+                submitted_by INTEGER NOT NULL REFERENCES users(id),
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE TABLE torque_spec_votes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                spec_id INTEGER NOT NULL REFERENCES torque_specs(id),
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                confirmed INTEGER NOT NULL,
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                UNIQUE(spec_id, user_id)
+            );
+
+            CREATE INDEX idx_torque_specs_vehicle ON torque_specs(make COLLATE NOCASE, model COLLATE NOCASE, year_from);
+        "#,
+        // Torque values in existing posts pick up their conversions from the
+        // re-render in migration 16
+        after: None,
+    },
+    // Only a SHA-256 of each token is stored, so the table can't be used to
+    // take over accounts if the database leaks
+    Migration {
+        version: 7,
+        name: "password_reset_tokens",
+        sql: r#"
+            CREATE TABLE password_reset_tokens (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                token_hash TEXT NOT NULL UNIQUE,
+                expires_at TEXT NOT NULL,
+                used_at TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE INDEX idx_password_reset_tokens_user ON password_reset_tokens(user_id);
+        "#,
+        after: None,
+    },
+    // New accounts start unconfirmed until they follow the emailed link
+    Migration {
+        version: 8,
+        name: "email_confirmation",
+        sql: r#"
+            ALTER TABLE users ADD COLUMN email_confirmed_at TEXT;
+
+            -- Accounts from before confirmation existed keep their access
+            UPDATE users SET email_confirmed_at = created_at;
+
+            CREATE TABLE email_confirmation_tokens (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                token_hash TEXT NOT NULL UNIQUE,
+                expires_at TEXT NOT NULL,
+                used_at TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE INDEX idx_email_confirmation_tokens_user ON email_confirmation_tokens(user_id);
+        "#,
+        after: None,
+    },
+    // TOTP second factor, the pending step between password and session, and
+    // a key/value table for the admin's policy on who must use it
+    Migration {
+        version: 9,
+        name: "two_factor",
+        sql: r#"
+            CREATE TABLE user_totp (
+                user_id INTEGER PRIMARY KEY REFERENCES users(id),
+                secret TEXT NOT NULL,
+                enabled_at TEXT,
+                last_used_step INTEGER,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE TABLE totp_recovery_codes (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                code_hash TEXT NOT NULL,
+                used_at TEXT
+            );
+
+            CREATE INDEX idx_totp_recovery_codes_user ON totp_recovery_codes(user_id);
+
+            CREATE TABLE login_challenges (
+                token_hash TEXT PRIMARY KEY,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                expires_at TEXT NOT NULL,
+                attempts INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE TABLE site_settings (
+                key TEXT PRIMARY KEY,
+                value TEXT NOT NULL,
+                updated_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+        "#,
+        after: None,
+    },
+    // Sign-in and registration attempts for rate limiting, and temporary
+    // lockouts after repeated failed logins
+    Migration {
+        version: 10,
+        name: "login_rate_limits",
+        sql: r#"
+            CREATE TABLE login_attempts (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                kind TEXT NOT NULL,
+                ip_address TEXT NOT NULL,
+                account TEXT,
+                success INTEGER NOT NULL,
+                created_at TEXT NOT NULL
+            );
+
+            CREATE INDEX idx_login_attempts_ip ON login_attempts(kind, ip_address, created_at);
+            CREATE INDEX idx_login_attempts_account ON login_attempts(account, created_at);
+
+            ALTER TABLE users ADD COLUMN locked_until TEXT;
+        "#,
+        after: None,
+    },
+    // Session management: a name the user can give each session, when it was
+    // last used, and a flag to issue it a new token after a role change
+    Migration {
+        version: 11,
+        name: "session_details",
+        sql: r#"
+            ALTER TABLE sessions ADD COLUMN name TEXT;
+            ALTER TABLE sessions ADD COLUMN last_seen_at TEXT;
+            ALTER TABLE sessions ADD COLUMN rotate_pending INTEGER NOT NULL DEFAULT 0;
+        "#,
+        after: None,
+    },
+    // Personal access tokens for the JSON API, stored hashed like the
+    // emailed tokens
+    Migration {
+        version: 12,
+        name: "api_tokens",
+        sql: r#"
+            CREATE TABLE api_tokens (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                name TEXT NOT NULL,
+                token_hash TEXT NOT NULL UNIQUE,
+                prefix TEXT NOT NULL,
+                scopes TEXT NOT NULL,
+                last_used_at TEXT,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE INDEX idx_api_tokens_user ON api_tokens(user_id);
+        "#,
+        after: None,
+    },
+    // Roles and what they may do move into the database so admins can add
+    // custom roles, plus moderators assigned to a single category
+    Migration {
+        version: 13,
+        name: "roles_and_permissions",
+        sql: r#"
+            CREATE TABLE roles (
+                name TEXT PRIMARY KEY,
+                label TEXT NOT NULL,
+                builtin INTEGER NOT NULL DEFAULT 0,
+                created_at TEXT NOT NULL DEFAULT (datetime('now'))
+            );
+
+            CREATE TABLE role_permissions (
+                role TEXT NOT NULL REFERENCES roles(name) ON DELETE CASCADE,
+                permission TEXT NOT NULL,
+                PRIMARY KEY (role, permission)
+            );
+
+            CREATE TABLE category_moderators (
+                user_id INTEGER NOT NULL REFERENCES users(id),
+                category_id INTEGER NOT NULL REFERENCES categories(id),
+                created_at TEXT NOT NULL DEFAULT (datetime('now')),
+                PRIMARY KEY (user_id, category_id)
+            );
+
+            INSERT INTO roles (name, label, builtin) VALUES
+                ('unverified', 'Unverified', 1),
+                ('verified_mechanic', 'Verified Mechanic', 1),
+                ('moderator', 'Moderator', 1),
+                ('admin', 'Admin', 1);
+
+            -- The same access the hard-coded role checks gave
+            INSERT INTO role_permissions (role, permission) VALUES
+                ('unverified', 'comment'),
+                ('verified_mechanic', 'comment'),
+                ('verified_mechanic', 'post'),
+                ('verified_mechanic', 'vote_store'),
+                ('moderator', 'comment'),
+                ('moderator', 'post'),
+                ('moderator', 'vote_store'),
+                ('moderator', 'remove_content'),
+                ('moderator', 'ban'),
+                ('admin', 'comment'),
+                ('admin', 'post'),
+                ('admin', 'vote_store'),
+                ('admin', 'remove_content'),
+                ('admin', 'ban'),
+                ('admin', 'review_verifications'),
+                ('admin', 'manage_roles'),
+                ('admin', 'manage_categories'),
+                ('admin', 'manage_site');
+        "#,
+        after: None,
+    },
+    Migration {
+        version: 14,
+        name: "backups",
+        sql: r#"
+            -- One row per backup run, successful or not
+            CREATE TABLE backups (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                started_at TEXT NOT NULL,
+                finished_at TEXT NOT NULL DEFAULT (datetime('now')),
+                path TEXT,
+                size_bytes INTEGER,
+                upload_files INTEGER,
+                error TEXT
+            );
+        "#,
+        after: None,
+    },
+    // The search snippet markers are private-use characters, which users
+    // can still type. Index text without them so a post containing one
+    // can't unbalance the `<mark>` tags in its snippet.
+    Migration {
+        version: 15,
+        name: "posts_fts_strip_markers",
+        sql: r#"
+            DROP TRIGGER posts_fts_insert;
+            DROP TRIGGER posts_fts_update;
+            DROP TRIGGER comments_fts_insert;
+            DROP TRIGGER comments_fts_update;
+            DROP TRIGGER comments_fts_delete;
+
+            DELETE FROM posts_fts;
+            INSERT INTO posts_fts (rowid, title, body, comments)
+            SELECT p.id,
+                   replace(replace(p.title, char(57344), ''), char(57345), ''),
+                   replace(replace(p.body, char(57344), ''), char(57345), ''),
+                   replace(replace(COALESCE((SELECT group_concat(c.body, ' ') FROM comments c WHERE c.post_id = p.id AND c.removed = 0), ''), char(57344), ''), char(57345), '')
+            FROM posts p WHERE p.removed = 0;
+
+            CREATE TRIGGER posts_fts_insert AFTER INSERT ON posts WHEN new.removed = 0 BEGIN
+                INSERT INTO posts_fts (rowid, title, body, comments)
+                VALUES (new.id,
+                        replace(replace(new.title, char(57344), ''), char(57345), ''),
+                        replace(replace(new.body, char(57344), ''), char(57345), ''),
+                        '');
+            END;
+
+            CREATE TRIGGER posts_fts_update AFTER UPDATE OF title, body, removed ON posts BEGIN
+                DELETE FROM posts_fts WHERE rowid = old.id;
+                INSERT INTO posts_fts (rowid, title, body, comments)
+                SELECT new.id,
+                       replace(replace(new.title, char(57344), ''), char(57345), ''),
+                       replace(replace(new.body, char(57344), ''), char(57345), ''),
+                       replace(replace(COALESCE((SELECT group_concat(c.body, ' ') FROM comments c WHERE c.post_id = new.id AND c.removed = 0), ''), char(57344), ''), char(57345), '')
+                WHERE new.removed = 0;
+            END;
+
+            CREATE TRIGGER comments_fts_insert AFTER INSERT ON comments BEGIN
+                UPDATE posts_fts
+                SET comments = replace(replace(COALESCE((SELECT group_concat(c.body, ' ') FROM comments c WHERE c.post_id = new.post_id AND c.removed = 0), ''), char(57344), ''), char(57345), '')
+                WHERE rowid = new.post_id;
+            END;
+
+            CREATE TRIGGER comments_fts_update AFTER UPDATE OF body, removed ON comments BEGIN
+                UPDATE posts_fts
+                SET comments = replace(replace(COALESCE((SELECT group_concat(c.body, ' ') FROM comments c WHERE c.post_id = new.post_id AND c.removed = 0), ''), char(57344), ''), char(57345), '')
+                WHERE rowid = new.post_id;
+            END;
+
+            CREATE TRIGGER comments_fts_delete AFTER DELETE ON comments BEGIN
+                UPDATE posts_fts
+                SET comments = replace(replace(COALESCE((SELECT group_concat(c.body, ' ') FROM comments c WHERE c.post_id = old.post_id AND c.removed = 0), ''), char(57344), ''), char(57345), '')
+                WHERE rowid = old.post_id;
+            END;
+        "#,
+        after: None,
+    },
+    // Stored HTML was rendered before `render_markdown` sanitized its output,
+    // so raw `<script>` and friends in old bodies are still there. This is
+    // the one re-render for every earlier change to `render_markdown`, so a
+    // fresh database only renders its bodies once.
+    Migration {
+        version: 16,
+        name: "sanitize_rendered_markdown",
+        sql: "",
+        after: Some(rerender_markdown),
+    },
+];
+
+/// Highest migration version this build knows about
+pub fn latest_schema_version() -> i64 {
+    MIGRATIONS.last().map(|m| m.version).unwrap_or(0)
+}
 
-        -- Indexes for performance
-        CREATE INDEX IF NOT EXISTS idx_posts_category ON posts(category_id);
-        CREATE INDEX IF NOT EXISTS idx_posts_user ON posts(user_id);
-        CREATE INDEX IF NOT EXISTS idx_posts_created ON posts(created_at DESC);
-        CREATE INDEX IF NOT EXISTS idx_posts_score ON posts(score DESC);
-        CREATE INDEX IF NOT EXISTS idx_comments_post ON comments(post_id);
-        CREATE INDEX IF NOT EXISTS idx_comments_user ON comments(user_id);
-        CREATE INDEX IF NOT EXISTS idx_votes_post ON votes(post_id);
-        CREATE INDEX IF NOT EXISTS idx_votes_comment ON votes(comment_id);
-        CREATE INDEX IF NOT EXISTS idx_votes_user ON votes(user_id);
-        CREATE INDEX IF NOT EXISTS idx_bookmarks_user ON bookmarks(user_id);
-        CREATE INDEX IF NOT EXISTS idx_notifications_user ON notifications(user_id);
-        CREATE INDEX IF NOT EXISTS idx_notifications_read ON notifications(read);
-        CREATE INDEX IF NOT EXISTS idx_sessions_user ON sessions(user_id);
-        CREATE INDEX IF NOT EXISTS idx_activity_user ON activity_logs(user_id);
-        CREATE INDEX IF NOT EXISTS idx_activity_created ON activity_logs(created_at DESC);
-
-        -- Full text search (optional, for SQLite FTS5)
-        -- CREATE VIRTUAL TABLE IF NOT EXISTS posts_fts USING fts5(title, body, content=posts, content_rowid=id);
-    "#)?;
-    Ok(())
+fn ensure_schema_version_table(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_version (
+            version INTEGER PRIMARY KEY,
+            name TEXT NOT NULL,
+            applied_at TEXT NOT NULL DEFAULT (datetime('now'))
+        );"
+    )
+}
+
+/// Current schema version of the database (0 for a fresh file)
+pub fn get_schema_version(conn: &Connection) -> Result<i64> {
+    ensure_schema_version_table(conn)?;
+    conn.query_row("SELECT COALESCE(MAX(version), 0) FROM schema_version", [], |r| r.get(0))
+}
+
+/// Apply every pending migration. Returns the versions that were applied.
+pub fn run_migrations(conn: &Connection) -> Result<Vec<i64>> {
+    migrate_to(conn, latest_schema_version())
+}
+
+/// Apply pending migrations up to and including `target`, each in its own
+/// transaction. Migrations are forward-only; a target at or below the
+/// current version is a no-op.
+pub fn migrate_to(conn: &Connection, target: i64) -> Result<Vec<i64>> {
+    let current = get_schema_version(conn)?;
+    let mut applied = Vec::new();
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current && m.version <= target) {
+        let tx = conn.unchecked_transaction()?;
+        tx.execute_batch(migration.sql)?;
+        if let Some(after) = migration.after {
+            after(&tx)?;
+        }
+        tx.execute(
+            "INSERT INTO schema_version (version, name) VALUES (?1, ?2)",
+            params![migration.version, migration.name],
+        )?;
+        tx.commit()?;
+        applied.push(migration.version);
+    }
+
+    Ok(applied)
+}
+
+/// Every known migration alongside when (if ever) it was applied, or None
+/// for a database that has never been migrated. Only reads, so it works on
+/// a read-only connection.
+pub fn get_migration_status(conn: &Connection) -> Result<Option<Vec<MigrationStatus>>> {
+    let versioned: bool = conn.query_row(
+        "SELECT COUNT(*) > 0 FROM sqlite_master WHERE type = 'table' AND name = 'schema_version'",
+        [],
+        |r| r.get(0),
+    )?;
+    if !versioned {
+        return Ok(None);
+    }
+    let mut stmt = conn.prepare("SELECT applied_at FROM schema_version WHERE version = ?1")?;
+    let mut statuses = Vec::new();
+    for migration in MIGRATIONS {
+        let applied_at: Option<String> = match stmt.query_row(params![migration.version], |r| r.get(0)) {
+            Ok(v) => Some(v),
+            Err(rusqlite::Error::QueryReturnedNoRows) => None,
+            Err(e) => return Err(e),
+        };
+        statuses.push(MigrationStatus {
+            version: migration.version,
+            name: migration.name.to_string(),
+            applied_at,
+        });
+    }
+    Ok(Some(statuses))
 }
 
 fn seed_defaults(conn: &Connection) -> Result<()> {
@@ -312,7 +1054,8 @@ pub fn create_user(conn: &Connection, email: &str, password_hash: &str, username
 
 pub fn get_user_by_email(conn: &Connection, email: &str) -> Result<Option<(User, String)>> {
     let mut stmt = conn.prepare(
-        "SELECT id, email, password_hash, username, role, created_at, banned, karma, flair FROM users WHERE email = ?1"
+        "SELECT id, email, password_hash, username, role, created_at, banned, karma, flair, email_confirmed_at IS NOT NULL,
+         EXISTS(SELECT 1 FROM user_totp t WHERE t.user_id = users.id AND t.enabled_at IS NOT NULL) FROM users WHERE email = ?1"
     )?;
     let mut rows = stmt.query(params![email])?;
     if let Some(row) = rows.next()? {
@@ -325,6 +1068,9 @@ pub fn get_user_by_email(conn: &Connection, email: &str) -> Result<Option<(User,
             banned: row.get::<_, i64>(6)? != 0,
             karma: row.get(7)?,
             flair: row.get(8)?,
+            email_confirmed: row.get(9)?,
+            two_factor_enabled: row.get(10)?,
+            permissions: Permissions::default(),
         }, row.get(2)?)))
     } else {
         Ok(None)
@@ -333,7 +1079,8 @@ pub fn get_user_by_email(conn: &Connection, email: &str) -> Result<Option<(User,
 
 pub fn get_user_by_id(conn: &Connection, id: i64) -> Result<Option<User>> {
     let mut stmt = conn.prepare(
-        "SELECT id, email, username, role, created_at, banned, karma, flair FROM users WHERE id = ?1"
+        "SELECT id, email, username, role, created_at, banned, karma, flair, email_confirmed_at IS NOT NULL,
+         EXISTS(SELECT 1 FROM user_totp t WHERE t.user_id = users.id AND t.enabled_at IS NOT NULL) FROM users WHERE id = ?1"
     )?;
     let mut rows = stmt.query(params![id])?;
     if let Some(row) = rows.next()? {
@@ -346,6 +1093,9 @@ pub fn get_user_by_id(conn: &Connection, id: i64) -> Result<Option<User>> {
             banned: row.get::<_, i64>(5)? != 0,
             karma: row.get(6)?,
             flair: row.get(7)?,
+            email_confirmed: row.get(8)?,
+            two_factor_enabled: row.get(9)?,
+            permissions: Permissions::default(),
         }))
     } else {
         Ok(None)
@@ -354,7 +1104,8 @@ pub fn get_user_by_id(conn: &Connection, id: i64) -> Result<Option<User>> {
 
 pub fn get_user_by_username(conn: &Connection, username: &str) -> Result<Option<User>> {
     let mut stmt = conn.prepare(
-        "SELECT id, email, username, role, created_at, banned, karma, flair FROM users WHERE username = ?1"
+        "SELECT id, email, username, role, created_at, banned, karma, flair, email_confirmed_at IS NOT NULL,
+         EXISTS(SELECT 1 FROM user_totp t WHERE t.user_id = users.id AND t.enabled_at IS NOT NULL) FROM users WHERE username = ?1"
     )?;
     let mut rows = stmt.query(params![username])?;
     if let Some(row) = rows.next()? {
@@ -367,6 +1118,9 @@ pub fn get_user_by_username(conn: &Connection, username: &str) -> Result<Option<
             banned: row.get::<_, i64>(5)? != 0,
             karma: row.get(6)?,
             flair: row.get(7)?,
+            email_confirmed: row.get(8)?,
+            two_factor_enabled: row.get(9)?,
+            permissions: Permissions::default(),
         }))
     } else {
         Ok(None)
@@ -375,7 +1129,7 @@ pub fn get_user_by_username(conn: &Connection, username: &str) -> Result<Option<
 
 pub fn update_user_role(conn: &Connection, user_id: i64, role: &str) -> Result<()> {
     conn.execute("UPDATE users SET role = ?1 WHERE id = ?2", params![role, user_id])?;
-    Ok(())
+    mark_sessions_for_rotation(conn, user_id)
 }
 
 pub fn update_user_flair(conn: &Connection, user_id: i64, flair: &str) -> Result<()> {
@@ -395,7 +1149,8 @@ pub fn set_user_banned(conn: &Connection, user_id: i64, banned: bool) -> Result<
 
 pub fn get_all_users(conn: &Connection) -> Result<Vec<User>> {
     let mut stmt = conn.prepare(
-        "SELECT id, email, username, role, created_at, banned, karma, flair FROM users ORDER BY created_at DESC"
+        "SELECT id, email, username, role, created_at, banned, karma, flair, email_confirmed_at IS NOT NULL,
+         EXISTS(SELECT 1 FROM user_totp t WHERE t.user_id = users.id AND t.enabled_at IS NOT NULL) FROM users ORDER BY created_at DESC"
     )?;
     let rows = stmt.query_map([], |row| {
         Ok(User {
@@ -407,6 +1162,9 @@ pub fn get_all_users(conn: &Connection) -> Result<Vec<User>> {
             banned: row.get::<_, i64>(5)? != 0,
             karma: row.get(6)?,
             flair: row.get(7)?,
+            email_confirmed: row.get(8)?,
+            two_factor_enabled: row.get(9)?,
+            permissions: Permissions::default(),
         })
     })?;
     rows.collect()
@@ -414,7 +1172,8 @@ pub fn get_all_users(conn: &Connection) -> Result<Vec<User>> {
 
 pub fn get_banned_users(conn: &Connection) -> Result<Vec<User>> {
     let mut stmt = conn.prepare(
-        "SELECT id, email, username, role, created_at, banned, karma, flair FROM users WHERE banned = 1 ORDER BY created_at DESC"
+        "SELECT id, email, username, role, created_at, banned, karma, flair, email_confirmed_at IS NOT NULL,
+         EXISTS(SELECT 1 FROM user_totp t WHERE t.user_id = users.id AND t.enabled_at IS NOT NULL) FROM users WHERE banned = 1 ORDER BY created_at DESC"
     )?;
     let rows = stmt.query_map([], |row| {
         Ok(User {
@@ -426,6 +1185,9 @@ pub fn get_banned_users(conn: &Connection) -> Result<Vec<User>> {
             banned: row.get::<_, i64>(5)? != 0,
             karma: row.get(6)?,
             flair: row.get(7)?,
+            email_confirmed: row.get(8)?,
+            two_factor_enabled: row.get(9)?,
+            permissions: Permissions::default(),
         })
     })?;
     rows.collect()
@@ -504,40 +1266,637 @@ pub fn get_user_stats(conn: &Connection, user_id: i64) -> Result<UserStats> {
     })
 }
 
+// ============ Role Functions ============
+
+pub fn get_role_permissions(conn: &Connection, role: &str) -> Result<Vec<Permission>> {
+    let mut stmt = conn.prepare("SELECT permission FROM role_permissions WHERE role = ?1")?;
+    let names = stmt.query_map(params![role], |row| row.get::<_, String>(0))?.collect::<Result<Vec<_>>>()?;
+    // Kept in the order of `Permission::ALL`; unknown names are skipped
+    Ok(Permission::ALL.into_iter().filter(|p| names.iter().any(|n| n == p.to_str())).collect())
+}
+
+/// The user's role permissions and category assignments, for the
+/// signed-in user
+pub fn get_user_permissions(conn: &Connection, user_id: i64, role: &UserRole) -> Result<Permissions> {
+    let mut stmt = conn.prepare("SELECT category_id FROM category_moderators WHERE user_id = ?1 ORDER BY category_id")?;
+    let categories = stmt.query_map(params![user_id], |row| row.get(0))?.collect::<Result<Vec<i64>>>()?;
+    Ok(Permissions {
+        granted: get_role_permissions(conn, role.to_str())?,
+        categories,
+    })
+}
+
+/// Built-in roles first, then custom roles by name
+pub fn get_roles(conn: &Connection) -> Result<Vec<RoleDetails>> {
+    let mut stmt = conn.prepare(
+        "SELECT r.name, r.label, r.builtin, (SELECT COUNT(*) FROM users u WHERE u.role = r.name)
+         FROM roles r
+         ORDER BY r.builtin DESC,
+                  CASE r.name WHEN 'unverified' THEN 0 WHEN 'verified_mechanic' THEN 1 WHEN 'moderator' THEN 2 ELSE 3 END,
+                  r.name"
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, i64>(2)? != 0, row.get::<_, i64>(3)?))
+    })?.collect::<Result<Vec<_>>>()?;
+    rows.into_iter()
+        .map(|(name, label, builtin, user_count)| {
+            Ok(RoleDetails {
+                permissions: get_role_permissions(conn, &name)?,
+                name,
+                label,
+                builtin,
+                user_count,
+            })
+        })
+        .collect()
+}
+
+pub fn get_role(conn: &Connection, name: &str) -> Result<Option<RoleDetails>> {
+    Ok(get_roles(conn)?.into_iter().find(|r| r.name == name))
+}
+
+pub fn create_role(conn: &Connection, name: &str, label: &str, permissions: &[Permission]) -> Result<()> {
+    conn.execute("INSERT INTO roles (name, label) VALUES (?1, ?2)", params![name, label])?;
+    set_role_permissions(conn, name, permissions)
+}
+
+/// Replace the role's permissions. Everyone with the role gets a new session
+/// token, as on a role change.
+pub fn set_role_permissions(conn: &Connection, name: &str, permissions: &[Permission]) -> Result<()> {
+    conn.execute("DELETE FROM role_permissions WHERE role = ?1", params![name])?;
+    for permission in permissions {
+        conn.execute(
+            "INSERT OR IGNORE INTO role_permissions (role, permission) VALUES (?1, ?2)",
+            params![name, permission.to_str()],
+        )?;
+    }
+    conn.execute(
+        "UPDATE sessions SET rotate_pending = 1 WHERE user_id IN (SELECT id FROM users WHERE role = ?1)",
+        params![name],
+    )?;
+    Ok(())
+}
+
+/// Delete a custom role. Callers check nobody still has it.
+pub fn delete_role(conn: &Connection, name: &str) -> Result<()> {
+    conn.execute("DELETE FROM role_permissions WHERE role = ?1", params![name])?;
+    conn.execute("DELETE FROM roles WHERE name = ?1 AND builtin = 0", params![name])?;
+    Ok(())
+}
+
 // ============ Session Functions ============
 
-pub fn create_session(conn: &Connection, token: &str, user_id: i64, expires_at: &str) -> Result<()> {
+pub fn create_session(conn: &Connection, token: &str, user_id: i64, expires_at: &str, ip_address: Option<&str>, user_agent: Option<&str>) -> Result<()> {
     conn.execute(
-        "INSERT INTO sessions (token, user_id, expires_at) VALUES (?1, ?2, ?3)",
-        params![token, user_id, expires_at],
+        "INSERT INTO sessions (token, user_id, expires_at, ip_address, user_agent, last_seen_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, datetime('now'))",
+        params![token, user_id, expires_at, ip_address, user_agent],
     )?;
     Ok(())
 }
 
+const SESSION_COLUMNS: &str = "token, user_id, expires_at, name, ip_address, user_agent, created_at, last_seen_at, rotate_pending";
+
+fn session_from_row(row: &rusqlite::Row) -> Result<Session> {
+    Ok(Session {
+        token: row.get(0)?,
+        user_id: row.get(1)?,
+        expires_at: row.get(2)?,
+        name: row.get(3)?,
+        ip_address: row.get(4)?,
+        user_agent: row.get(5)?,
+        created_at: row.get(6)?,
+        last_seen_at: row.get(7)?,
+        rotate_pending: row.get(8)?,
+    })
+}
+
 pub fn get_session(conn: &Connection, token: &str) -> Result<Option<Session>> {
-    let mut stmt = conn.prepare("SELECT token, user_id, expires_at FROM sessions WHERE token = ?1")?;
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM sessions WHERE token = ?1", SESSION_COLUMNS))?;
     let mut rows = stmt.query(params![token])?;
     if let Some(row) = rows.next()? {
-        Ok(Some(Session {
-            token: row.get(0)?,
-            user_id: row.get(1)?,
-            expires_at: row.get(2)?,
-        }))
+        Ok(Some(session_from_row(row)?))
     } else {
         Ok(None)
     }
 }
 
+/// A user's unexpired sessions, most recently used first
+pub fn get_user_sessions(conn: &Connection, user_id: i64, now: &str) -> Result<Vec<Session>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM sessions WHERE user_id = ?1 AND expires_at > ?2
+         ORDER BY COALESCE(last_seen_at, created_at) DESC",
+        SESSION_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![user_id, now], session_from_row)?;
+    rows.collect()
+}
+
+/// Record that a session was used at `now`
+pub fn touch_session(conn: &Connection, token: &str, now: &str) -> Result<()> {
+    conn.execute("UPDATE sessions SET last_seen_at = ?2 WHERE token = ?1", params![token, now])?;
+    Ok(())
+}
+
+/// Give a session marked for rotation its new token. Returns false if it
+/// wasn't marked, e.g. because a concurrent request already rotated it.
+pub fn rotate_session_token(conn: &Connection, token: &str, new_token: &str) -> Result<bool> {
+    let changed = conn.execute(
+        "UPDATE sessions SET token = ?2, rotate_pending = 0 WHERE token = ?1 AND rotate_pending = 1",
+        params![token, new_token],
+    )?;
+    Ok(changed == 1)
+}
+
+/// Have all of a user's sessions get new tokens on their next request
+pub fn mark_sessions_for_rotation(conn: &Connection, user_id: i64) -> Result<()> {
+    conn.execute("UPDATE sessions SET rotate_pending = 1 WHERE user_id = ?1", params![user_id])?;
+    Ok(())
+}
+
+/// Name a session, or clear its name with `None`
+pub fn rename_session(conn: &Connection, user_id: i64, token: &str, name: Option<&str>) -> Result<()> {
+    conn.execute(
+        "UPDATE sessions SET name = ?3 WHERE token = ?1 AND user_id = ?2",
+        params![token, user_id, name],
+    )?;
+    Ok(())
+}
+
 pub fn delete_session(conn: &Connection, token: &str) -> Result<()> {
     conn.execute("DELETE FROM sessions WHERE token = ?1", params![token])?;
     Ok(())
 }
 
+/// Delete all of a user's sessions except `keep_token`
+pub fn delete_other_sessions(conn: &Connection, user_id: i64, keep_token: &str) -> Result<usize> {
+    conn.execute(
+        "DELETE FROM sessions WHERE user_id = ?1 AND token != ?2",
+        params![user_id, keep_token],
+    )
+}
+
 pub fn delete_user_sessions(conn: &Connection, user_id: i64) -> Result<()> {
     conn.execute("DELETE FROM sessions WHERE user_id = ?1", params![user_id])?;
     Ok(())
 }
 
+// ============ API Token Functions ============
+
+pub fn create_api_token(conn: &Connection, user_id: i64, name: &str, token_hash: &str, prefix: &str, scopes: &[ApiScope]) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO api_tokens (user_id, name, token_hash, prefix, scopes) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![user_id, name, token_hash, prefix, ApiScope::join(scopes)],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+const API_TOKEN_COLUMNS: &str = "id, user_id, name, prefix, scopes, created_at, last_used_at";
+
+fn api_token_from_row(row: &rusqlite::Row) -> Result<ApiToken> {
+    Ok(ApiToken {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        name: row.get(2)?,
+        prefix: row.get(3)?,
+        scopes: ApiScope::parse_list(&row.get::<_, String>(4)?),
+        created_at: row.get(5)?,
+        last_used_at: row.get(6)?,
+    })
+}
+
+pub fn get_api_token_by_hash(conn: &Connection, token_hash: &str) -> Result<Option<ApiToken>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM api_tokens WHERE token_hash = ?1", API_TOKEN_COLUMNS))?;
+    let mut rows = stmt.query(params![token_hash])?;
+    if let Some(row) = rows.next()? {
+        Ok(Some(api_token_from_row(row)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// A user's tokens, newest first
+pub fn get_user_api_tokens(conn: &Connection, user_id: i64) -> Result<Vec<ApiToken>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM api_tokens WHERE user_id = ?1 ORDER BY id DESC",
+        API_TOKEN_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![user_id], api_token_from_row)?;
+    rows.collect()
+}
+
+/// Record that a token was used at `now`
+pub fn touch_api_token(conn: &Connection, token_id: i64, now: &str) -> Result<()> {
+    conn.execute("UPDATE api_tokens SET last_used_at = ?2 WHERE id = ?1", params![token_id, now])?;
+    Ok(())
+}
+
+/// Delete one of the user's tokens. Returns false if they have no such token.
+pub fn delete_api_token(conn: &Connection, user_id: i64, token_id: i64) -> Result<bool> {
+    let deleted = conn.execute(
+        "DELETE FROM api_tokens WHERE id = ?1 AND user_id = ?2",
+        params![token_id, user_id],
+    )?;
+    Ok(deleted == 1)
+}
+
+// ============ Password Reset Functions ============
+
+/// Store a new reset token for `user_id`. Earlier unused tokens for the user
+/// are dropped, so only the most recent link works.
+pub fn create_password_reset_token(conn: &Connection, user_id: i64, token_hash: &str, expires_at: &str) -> Result<()> {
+    conn.execute(
+        "DELETE FROM password_reset_tokens WHERE user_id = ?1 AND used_at IS NULL",
+        params![user_id],
+    )?;
+    conn.execute(
+        "INSERT INTO password_reset_tokens (user_id, token_hash, expires_at) VALUES (?1, ?2, ?3)",
+        params![user_id, token_hash, expires_at],
+    )?;
+    Ok(())
+}
+
+/// The user an unused, unexpired reset token belongs to
+pub fn get_password_reset_user(conn: &Connection, token_hash: &str, now: &str) -> Result<Option<i64>> {
+    match conn.query_row(
+        "SELECT user_id FROM password_reset_tokens WHERE token_hash = ?1 AND used_at IS NULL AND expires_at > ?2",
+        params![token_hash, now],
+        |row| row.get(0),
+    ) {
+        Ok(user_id) => Ok(Some(user_id)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Spend a reset token and set the new password, signing the user out
+/// everywhere. Returns the user id, or None if the token is unknown, used or
+/// expired.
+pub fn reset_password(conn: &Connection, token_hash: &str, password_hash: &str, now: &str) -> Result<Option<i64>> {
+    let tx = conn.unchecked_transaction()?;
+    let user_id: i64 = match tx.query_row(
+        "UPDATE password_reset_tokens SET used_at = ?2
+         WHERE token_hash = ?1 AND used_at IS NULL AND expires_at > ?2
+         RETURNING user_id",
+        params![token_hash, now],
+        |row| row.get(0),
+    ) {
+        Ok(user_id) => user_id,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    tx.execute("UPDATE users SET password_hash = ?1 WHERE id = ?2", params![password_hash, user_id])?;
+    tx.execute(
+        "DELETE FROM password_reset_tokens WHERE user_id = ?1 AND used_at IS NULL",
+        params![user_id],
+    )?;
+    delete_user_sessions(&tx, user_id)?;
+    tx.commit()?;
+    Ok(Some(user_id))
+}
+
+/// Set a new password directly, as an operator would, signing the user out
+/// everywhere and dropping any outstanding reset links
+pub fn set_user_password(conn: &Connection, user_id: i64, password_hash: &str) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("UPDATE users SET password_hash = ?1 WHERE id = ?2", params![password_hash, user_id])?;
+    tx.execute(
+        "DELETE FROM password_reset_tokens WHERE user_id = ?1 AND used_at IS NULL",
+        params![user_id],
+    )?;
+    delete_user_sessions(&tx, user_id)?;
+    tx.commit()
+}
+
+// ============ Email Confirmation Functions ============
+
+/// Store a new confirmation token for `user_id`, replacing any unused one so
+/// only the most recent email's link works
+pub fn create_email_confirmation_token(conn: &Connection, user_id: i64, token_hash: &str, expires_at: &str) -> Result<()> {
+    conn.execute(
+        "DELETE FROM email_confirmation_tokens WHERE user_id = ?1 AND used_at IS NULL",
+        params![user_id],
+    )?;
+    conn.execute(
+        "INSERT INTO email_confirmation_tokens (user_id, token_hash, expires_at) VALUES (?1, ?2, ?3)",
+        params![user_id, token_hash, expires_at],
+    )?;
+    Ok(())
+}
+
+/// When the user's most recent confirmation email was sent, for throttling
+/// resends
+pub fn get_last_email_confirmation_sent(conn: &Connection, user_id: i64) -> Result<Option<String>> {
+    conn.query_row(
+        "SELECT MAX(created_at) FROM email_confirmation_tokens WHERE user_id = ?1",
+        params![user_id],
+        |row| row.get(0),
+    )
+}
+
+/// Spend a confirmation token and mark the address confirmed. Returns the
+/// user id, or None if the token is unknown, used or expired.
+pub fn confirm_email(conn: &Connection, token_hash: &str, now: &str) -> Result<Option<i64>> {
+    let user_id: i64 = match conn.query_row(
+        "UPDATE email_confirmation_tokens SET used_at = ?2
+         WHERE token_hash = ?1 AND used_at IS NULL AND expires_at > ?2
+         RETURNING user_id",
+        params![token_hash, now],
+        |row| row.get(0),
+    ) {
+        Ok(user_id) => user_id,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    conn.execute(
+        "UPDATE users SET email_confirmed_at = ?2 WHERE id = ?1 AND email_confirmed_at IS NULL",
+        params![user_id, now],
+    )?;
+    Ok(Some(user_id))
+}
+
+/// Mark the address confirmed without a token, for accounts set up by an
+/// operator
+pub fn mark_email_confirmed(conn: &Connection, user_id: i64) -> Result<()> {
+    conn.execute(
+        "UPDATE users SET email_confirmed_at = datetime('now') WHERE id = ?1 AND email_confirmed_at IS NULL",
+        params![user_id],
+    )?;
+    Ok(())
+}
+
+// ============ Two-Factor Functions ============
+
+pub fn get_user_totp(conn: &Connection, user_id: i64) -> Result<Option<UserTotp>> {
+    match conn.query_row(
+        "SELECT user_id, secret, enabled_at IS NOT NULL, last_used_step FROM user_totp WHERE user_id = ?1",
+        params![user_id],
+        |row| Ok(UserTotp {
+            user_id: row.get(0)?,
+            secret: row.get(1)?,
+            enabled: row.get(2)?,
+            last_used_step: row.get(3)?,
+        }),
+    ) {
+        Ok(totp) => Ok(Some(totp)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Store a secret waiting for its first code. Does nothing if two-factor is
+/// already enabled, so a stale setup page can't swap out a working secret.
+pub fn start_totp_enrollment(conn: &Connection, user_id: i64, secret: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO user_totp (user_id, secret) VALUES (?1, ?2)
+         ON CONFLICT(user_id) DO UPDATE SET secret = excluded.secret, last_used_step = NULL,
+             created_at = datetime('now')
+         WHERE enabled_at IS NULL",
+        params![user_id, secret],
+    )?;
+    Ok(())
+}
+
+/// Turn on two-factor after the first code checked out, replacing any old
+/// recovery codes with `recovery_code_hashes`
+pub fn enable_totp(conn: &Connection, user_id: i64, step: i64, recovery_code_hashes: &[String], now: &str) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "UPDATE user_totp SET enabled_at = ?2, last_used_step = ?3 WHERE user_id = ?1",
+        params![user_id, now, step],
+    )?;
+    replace_recovery_codes(&tx, user_id, recovery_code_hashes)?;
+    tx.commit()
+}
+
+/// Remove the secret and recovery codes
+pub fn disable_totp(conn: &Connection, user_id: i64) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("DELETE FROM totp_recovery_codes WHERE user_id = ?1", params![user_id])?;
+    tx.execute("DELETE FROM user_totp WHERE user_id = ?1", params![user_id])?;
+    tx.commit()
+}
+
+/// Record that a code for `step` was accepted. Returns false if that step
+/// or a later one was already used, i.e. the code is being replayed.
+pub fn use_totp_step(conn: &Connection, user_id: i64, step: i64) -> Result<bool> {
+    let changed = conn.execute(
+        "UPDATE user_totp SET last_used_step = ?2
+         WHERE user_id = ?1 AND (last_used_step IS NULL OR last_used_step < ?2)",
+        params![user_id, step],
+    )?;
+    Ok(changed == 1)
+}
+
+pub fn replace_recovery_codes(conn: &Connection, user_id: i64, code_hashes: &[String]) -> Result<()> {
+    conn.execute("DELETE FROM totp_recovery_codes WHERE user_id = ?1", params![user_id])?;
+    let mut stmt = conn.prepare("INSERT INTO totp_recovery_codes (user_id, code_hash) VALUES (?1, ?2)")?;
+    for hash in code_hashes {
+        stmt.execute(params![user_id, hash])?;
+    }
+    Ok(())
+}
+
+/// Spend a recovery code. Returns false if it's unknown or already used.
+pub fn use_recovery_code(conn: &Connection, user_id: i64, code_hash: &str, now: &str) -> Result<bool> {
+    let changed = conn.execute(
+        "UPDATE totp_recovery_codes SET used_at = ?3 WHERE user_id = ?1 AND code_hash = ?2 AND used_at IS NULL",
+        params![user_id, code_hash, now],
+    )?;
+    Ok(changed == 1)
+}
+
+pub fn count_unused_recovery_codes(conn: &Connection, user_id: i64) -> Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM totp_recovery_codes WHERE user_id = ?1 AND used_at IS NULL",
+        params![user_id],
+        |row| row.get(0),
+    )
+}
+
+pub fn create_login_challenge(conn: &Connection, token_hash: &str, user_id: i64, expires_at: &str) -> Result<()> {
+    conn.execute("DELETE FROM login_challenges WHERE expires_at < datetime('now')", [])?;
+    conn.execute(
+        "INSERT INTO login_challenges (token_hash, user_id, expires_at) VALUES (?1, ?2, ?3)",
+        params![token_hash, user_id, expires_at],
+    )?;
+    Ok(())
+}
+
+/// The pending login for a challenge cookie, if it hasn't expired
+pub fn get_login_challenge(conn: &Connection, token_hash: &str, now: &str) -> Result<Option<LoginChallenge>> {
+    match conn.query_row(
+        "SELECT user_id, attempts FROM login_challenges WHERE token_hash = ?1 AND expires_at > ?2",
+        params![token_hash, now],
+        |row| Ok(LoginChallenge { user_id: row.get(0)?, attempts: row.get(1)? }),
+    ) {
+        Ok(challenge) => Ok(Some(challenge)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn record_login_challenge_failure(conn: &Connection, token_hash: &str) -> Result<()> {
+    conn.execute(
+        "UPDATE login_challenges SET attempts = attempts + 1 WHERE token_hash = ?1",
+        params![token_hash],
+    )?;
+    Ok(())
+}
+
+pub fn delete_login_challenge(conn: &Connection, token_hash: &str) -> Result<()> {
+    conn.execute("DELETE FROM login_challenges WHERE token_hash = ?1", params![token_hash])?;
+    Ok(())
+}
+
+// ============ Login Rate Limit Functions ============
+
+/// Record a login or registration attempt, returning its id. `account` is
+/// the normalized email for logins. Attempts older than a day are pruned on
+/// the way.
+pub fn record_auth_attempt(conn: &Connection, kind: &str, ip_address: &str, account: Option<&str>, success: bool, now: &str) -> Result<i64> {
+    conn.execute("DELETE FROM login_attempts WHERE created_at < datetime(?1, '-1 day')", params![now])?;
+    conn.execute(
+        "INSERT INTO login_attempts (kind, ip_address, account, success, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![kind, ip_address, account, success, now],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn delete_auth_attempt(conn: &Connection, id: i64) -> Result<()> {
+    conn.execute("DELETE FROM login_attempts WHERE id = ?1", params![id])?;
+    Ok(())
+}
+
+/// Failed logins from an IP since `since`
+pub fn count_failed_logins_from_ip(conn: &Connection, ip_address: &str, since: &str) -> Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM login_attempts
+         WHERE kind = 'login' AND ip_address = ?1 AND success = 0 AND created_at > ?2",
+        params![ip_address, since],
+        |row| row.get(0),
+    )
+}
+
+/// Failed logins for an account since `since` and after its last successful
+/// one, with the time of the latest
+pub fn get_account_login_failures(conn: &Connection, account: &str, since: &str) -> Result<(i64, Option<String>)> {
+    conn.query_row(
+        "SELECT COUNT(*), MAX(created_at) FROM login_attempts
+         WHERE kind = 'login' AND account = ?1 AND success = 0 AND created_at > ?2
+           AND created_at >= COALESCE(
+               (SELECT MAX(created_at) FROM login_attempts WHERE kind = 'login' AND account = ?1 AND success = 1), '')",
+        params![account, since],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+}
+
+/// Attempts of `kind` from an IP since `since`, successful or not
+pub fn count_attempts_from_ip(conn: &Connection, kind: &str, ip_address: &str, since: &str) -> Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM login_attempts WHERE kind = ?1 AND ip_address = ?2 AND created_at > ?3",
+        params![kind, ip_address, since],
+        |row| row.get(0),
+    )
+}
+
+/// Attempts of `kind` for an account since `since`, successful or not
+pub fn count_attempts_for_account(conn: &Connection, kind: &str, account: &str, since: &str) -> Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM login_attempts WHERE kind = ?1 AND account = ?2 AND created_at > ?3",
+        params![kind, account, since],
+        |row| row.get(0),
+    )
+}
+
+pub fn lock_user(conn: &Connection, user_id: i64, until: &str) -> Result<()> {
+    conn.execute("UPDATE users SET locked_until = ?2 WHERE id = ?1", params![user_id, until])?;
+    Ok(())
+}
+
+/// When the user's lockout ends, if they're locked out at `now`
+pub fn get_user_locked_until(conn: &Connection, user_id: i64, now: &str) -> Result<Option<String>> {
+    match conn.query_row(
+        "SELECT locked_until FROM users WHERE id = ?1 AND locked_until > ?2",
+        params![user_id, now],
+        |row| row.get(0),
+    ) {
+        Ok(until) => Ok(Some(until)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Lift a lockout early and forget the failures that caused it
+pub fn unlock_user(conn: &Connection, user_id: i64) -> Result<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "DELETE FROM login_attempts WHERE kind = 'login' AND success = 0
+         AND account = (SELECT lower(email) FROM users WHERE id = ?1)",
+        params![user_id],
+    )?;
+    tx.execute("UPDATE users SET locked_until = NULL WHERE id = ?1", params![user_id])?;
+    tx.commit()
+}
+
+pub fn get_locked_accounts(conn: &Connection, now: &str) -> Result<Vec<LockedAccount>> {
+    let mut stmt = conn.prepare(
+        "SELECT u.id, u.username, u.email, u.locked_until,
+         (SELECT COUNT(*) FROM login_attempts a WHERE a.kind = 'login' AND a.account = lower(u.email) AND a.success = 0),
+         (SELECT a.ip_address FROM login_attempts a WHERE a.kind = 'login' AND a.account = lower(u.email) AND a.success = 0
+          ORDER BY a.created_at DESC LIMIT 1)
+         FROM users u WHERE u.locked_until > ?1 ORDER BY u.locked_until DESC"
+    )?;
+    let rows = stmt.query_map(params![now], |row| {
+        Ok(LockedAccount {
+            user_id: row.get(0)?,
+            username: row.get(1)?,
+            email: row.get(2)?,
+            locked_until: row.get(3)?,
+            recent_failures: row.get(4)?,
+            last_ip_address: row.get(5)?,
+        })
+    })?;
+    rows.collect()
+}
+
+// ============ Site Setting Functions ============
+
+const TWO_FACTOR_ROLES_SETTING: &str = "two_factor_roles";
+
+pub fn get_setting(conn: &Connection, key: &str) -> Result<Option<String>> {
+    match conn.query_row("SELECT value FROM site_settings WHERE key = ?1", params![key], |row| row.get(0)) {
+        Ok(value) => Ok(Some(value)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn set_setting(conn: &Connection, key: &str, value: &str) -> Result<()> {
+    conn.execute(
+        "INSERT INTO site_settings (key, value) VALUES (?1, ?2)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = datetime('now')",
+        params![key, value],
+    )?;
+    Ok(())
+}
+
+/// Roles that must use two-factor authentication
+pub fn get_two_factor_roles(conn: &Connection) -> Result<Vec<UserRole>> {
+    Ok(get_setting(conn, TWO_FACTOR_ROLES_SETTING)?
+        .unwrap_or_default()
+        .split(',')
+        .filter(|role| !role.is_empty())
+        .map(UserRole::from_str)
+        .collect())
+}
+
+pub fn set_two_factor_roles(conn: &Connection, roles: &[UserRole]) -> Result<()> {
+    let value = roles.iter().map(UserRole::to_str).collect::<Vec<_>>().join(",");
+    set_setting(conn, TWO_FACTOR_ROLES_SETTING, &value)
+}
+
 // ============ Category Functions ============
 
 pub fn get_categories(conn: &Connection) -> Result<Vec<Category>> {
@@ -590,6 +1949,52 @@ pub fn create_category(conn: &Connection, name: &str, slug: &str, description: &
     Ok(conn.last_insert_rowid())
 }
 
+/// Every per-category moderator assignment, by category then username
+pub fn get_category_moderators(conn: &Connection) -> Result<Vec<CategoryModerator>> {
+    let mut stmt = conn.prepare(
+        "SELECT m.user_id, u.username, m.category_id, c.name, c.slug, m.created_at
+         FROM category_moderators m
+         JOIN users u ON m.user_id = u.id
+         JOIN categories c ON m.category_id = c.id
+         ORDER BY c.name, u.username"
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(CategoryModerator {
+            user_id: row.get(0)?,
+            username: row.get(1)?,
+            category_id: row.get(2)?,
+            category_name: row.get(3)?,
+            category_slug: row.get(4)?,
+            created_at: row.get(5)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Returns false if the user already moderated the category
+pub fn add_category_moderator(conn: &Connection, user_id: i64, category_id: i64) -> Result<bool> {
+    let added = conn.execute(
+        "INSERT OR IGNORE INTO category_moderators (user_id, category_id) VALUES (?1, ?2)",
+        params![user_id, category_id],
+    )? > 0;
+    if added {
+        mark_sessions_for_rotation(conn, user_id)?;
+    }
+    Ok(added)
+}
+
+/// Returns false if there was no such assignment
+pub fn remove_category_moderator(conn: &Connection, user_id: i64, category_id: i64) -> Result<bool> {
+    let removed = conn.execute(
+        "DELETE FROM category_moderators WHERE user_id = ?1 AND category_id = ?2",
+        params![user_id, category_id],
+    )? > 0;
+    if removed {
+        mark_sessions_for_rotation(conn, user_id)?;
+    }
+    Ok(removed)
+}
+
 // ============ Post Tag Functions ============
 
 pub fn get_all_tags(conn: &Connection) -> Result<Vec<PostTag>> {
@@ -650,6 +2055,109 @@ pub fn set_post_tags(conn: &Connection, post_id: i64, tag_ids: &[i64]) -> Result
     Ok(())
 }
 
+// ============ Vehicle Functions ============
+
+pub fn create_vehicle(conn: &Connection, user_id: i64, details: &VehicleDetails, in_garage: bool) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO vehicles (user_id, year, make, model, trim, engine_code, transmission, mileage, in_garage)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        params![
+            user_id, details.year, details.make, details.model, details.trim,
+            details.engine_code, details.transmission, details.mileage, in_garage as i64
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+fn map_vehicle(row: &rusqlite::Row) -> rusqlite::Result<Vehicle> {
+    Ok(Vehicle {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        year: row.get(2)?,
+        make: row.get(3)?,
+        model: row.get(4)?,
+        trim: row.get(5)?,
+        engine_code: row.get(6)?,
+        transmission: row.get(7)?,
+        mileage: row.get(8)?,
+        in_garage: row.get::<_, i64>(9)? != 0,
+        created_at: row.get(10)?,
+    })
+}
+
+const VEHICLE_COLUMNS: &str =
+    "v.id, v.user_id, v.year, v.make, v.model, v.trim, v.engine_code, v.transmission, v.mileage, v.in_garage, v.created_at";
+
+pub fn get_vehicle_by_id(conn: &Connection, id: i64) -> Result<Option<Vehicle>> {
+    let sql = format!("SELECT {} FROM vehicles v WHERE v.id = ?1", VEHICLE_COLUMNS);
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(params![id])?;
+    if let Some(row) = rows.next()? {
+        Ok(Some(map_vehicle(row)?))
+    } else {
+        Ok(None)
+    }
+}
+
+pub fn get_vehicle_for_post(conn: &Connection, post_id: i64) -> Result<Option<Vehicle>> {
+    let sql = format!(
+        "SELECT {} FROM vehicles v JOIN posts p ON p.vehicle_id = v.id WHERE p.id = ?1",
+        VEHICLE_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let mut rows = stmt.query(params![post_id])?;
+    if let Some(row) = rows.next()? {
+        Ok(Some(map_vehicle(row)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Vehicles a user has added to their garage, newest model year first
+pub fn get_user_garage(conn: &Connection, user_id: i64) -> Result<Vec<Vehicle>> {
+    let sql = format!(
+        "SELECT {} FROM vehicles v WHERE v.user_id = ?1 AND v.in_garage = 1 ORDER BY v.year DESC, v.id DESC",
+        VEHICLE_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params![user_id], map_vehicle)?;
+    rows.collect()
+}
+
+/// Take a vehicle out of its owner's garage. Posts that reference it keep
+/// their vehicle header, so the row is only deleted when nothing uses it.
+pub fn remove_vehicle_from_garage(conn: &Connection, vehicle_id: i64) -> Result<()> {
+    let in_use: bool = conn.query_row(
+        "SELECT EXISTS (SELECT 1 FROM posts WHERE vehicle_id = ?1)",
+        params![vehicle_id],
+        |r| r.get(0),
+    )?;
+    if in_use {
+        conn.execute("UPDATE vehicles SET in_garage = 0 WHERE id = ?1", params![vehicle_id])?;
+    } else {
+        conn.execute("DELETE FROM vehicles WHERE id = ?1", params![vehicle_id])?;
+    }
+    Ok(())
+}
+
+pub fn set_post_vehicle(conn: &Connection, post_id: i64, vehicle_id: Option<i64>) -> Result<()> {
+    conn.execute(
+        "UPDATE posts SET vehicle_id = ?1 WHERE id = ?2",
+        params![vehicle_id, post_id],
+    )?;
+    Ok(())
+}
+
+/// Makes that appear on at least one post, for the listing filter
+pub fn get_vehicle_makes(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT DISTINCT v.make FROM vehicles v JOIN posts p ON p.vehicle_id = v.id
+         WHERE p.removed = 0 ORDER BY v.make COLLATE NOCASE"
+    )?;
+    let rows = stmt.query_map([], |r| r.get(0))?;
+    rows.collect()
+}
+
 // ============ Post Functions ============
 
 pub fn create_post(conn: &Connection, user_id: i64, category_id: i64, title: &str, body: &str) -> Result<i64> {
@@ -669,65 +2177,142 @@ pub fn create_post(conn: &Connection, user_id: i64, category_id: i64, title: &st
     Ok(post_id)
 }
 
-pub fn create_post_with_tags(conn: &Connection, user_id: i64, category_id: i64, title: &str, body: &str, tag_ids: &[i64]) -> Result<i64> {
+pub fn create_post_with_tags(conn: &Connection, user_id: i64, category_id: i64, title: &str, body: &str, tag_ids: &[i64], vehicle_id: Option<i64>) -> Result<i64> {
     let post_id = create_post(conn, user_id, category_id, title, body)?;
     set_post_tags(conn, post_id, tag_ids)?;
+    if vehicle_id.is_some() {
+        set_post_vehicle(conn, post_id, vehicle_id)?;
+    }
     Ok(post_id)
 }
 
 pub fn get_posts(conn: &Connection, category_slug: Option<&str>, sort: &str, limit: i64, offset: i64) -> Result<Vec<Post>> {
+    get_posts_filtered(conn, category_slug, &VehicleFilter::default(), sort, limit, offset)
+}
+
+/// WHERE conditions shared by post listings and their counts
+fn post_listing_conditions(category_slug: Option<&str>, filter: &VehicleFilter, values: &mut Vec<rusqlite::types::Value>) -> String {
+    let mut conditions = vec!["p.removed = 0".to_string()];
+    if let Some(slug) = category_slug {
+        conditions.push(format!("c.slug = {}", bind(values, slug.to_string())));
+    }
+    if !filter.is_empty() {
+        let mut vehicle_conditions = vec!["v.id = p.vehicle_id".to_string()];
+        if let Some(make) = &filter.make {
+            vehicle_conditions.push(format!("v.make = {} COLLATE NOCASE", bind(values, make.clone())));
+        }
+        if let Some(model) = &filter.model {
+            vehicle_conditions.push(format!("v.model = {} COLLATE NOCASE", bind(values, model.clone())));
+        }
+        if let Some(year) = filter.year_min {
+            vehicle_conditions.push(format!("v.year >= {}", bind(values, year)));
+        }
+        if let Some(year) = filter.year_max {
+            vehicle_conditions.push(format!("v.year <= {}", bind(values, year)));
+        }
+        conditions.push(format!("EXISTS (SELECT 1 FROM vehicles v WHERE {})", vehicle_conditions.join(" AND ")));
+    }
+    conditions.join(" AND ")
+}
+
+pub fn get_posts_filtered(conn: &Connection, category_slug: Option<&str>, filter: &VehicleFilter, sort: &str, limit: i64, offset: i64) -> Result<Vec<Post>> {
     let order = match sort {
         "top" => "p.score DESC, p.created_at DESC",
-        "new" => "p.created_at DESC",
+        "new" => "p.created_at DESC, p.id DESC",
         "controversial" => "ABS(p.score) ASC, p.created_at DESC",
         _ => "p.score DESC, p.created_at DESC", // hot (default)
     };
 
+    let mut values = Vec::new();
+    let conditions = post_listing_conditions(category_slug, filter, &mut values);
     let sql = format!(
         r#"SELECT p.id, p.user_id, p.category_id, p.title, p.body, p.body_html, p.score, p.created_at, 
            p.edited_at, p.removed, p.pinned, p.best_answer_id,
            u.username, u.role, u.flair,
            (SELECT avatar_path FROM user_profiles WHERE user_id = u.id) as avatar,
            c.name, c.slug,
-           (SELECT COUNT(*) FROM comments WHERE post_id = p.id AND removed = 0) as comment_count
+           (SELECT COUNT(*) FROM comments WHERE post_id = p.id AND removed = 0) as comment_count,
+           p.vehicle_id
            FROM posts p
            JOIN users u ON p.user_id = u.id
            JOIN categories c ON p.category_id = c.id
-           WHERE p.removed = 0
-           {}
+           WHERE {}
            ORDER BY p.pinned DESC, {}
-           LIMIT ?1 OFFSET ?2"#,
-        if category_slug.is_some() { "AND c.slug = ?3" } else { "" },
-        order
+           LIMIT {} OFFSET {}"#,
+        conditions,
+        order,
+        bind(&mut values, limit),
+        bind(&mut values, offset)
     );
 
-    let mut stmt = conn.prepare(&sql)?;
+    query_posts_with_vehicles(conn, &sql, values)
+}
+
+/// Newest posts first, starting after the post with id `before_id`. Keyset
+/// paging for the API, so new posts don't shift later pages.
+pub fn get_posts_before(conn: &Connection, category_slug: Option<&str>, before_id: Option<i64>, limit: i64) -> Result<Vec<Post>> {
+    let mut values = Vec::new();
+    let mut conditions = post_listing_conditions(category_slug, &VehicleFilter::default(), &mut values);
+    if let Some(id) = before_id {
+        conditions.push_str(&format!(" AND p.id < {}", bind(&mut values, id)));
+    }
+    let sql = format!(
+        r#"SELECT p.id, p.user_id, p.category_id, p.title, p.body, p.body_html, p.score, p.created_at,
+           p.edited_at, p.removed, p.pinned, p.best_answer_id,
+           u.username, u.role, u.flair,
+           (SELECT avatar_path FROM user_profiles WHERE user_id = u.id) as avatar,
+           c.name, c.slug,
+           (SELECT COUNT(*) FROM comments WHERE post_id = p.id AND removed = 0) as comment_count,
+           p.vehicle_id
+           FROM posts p
+           JOIN users u ON p.user_id = u.id
+           JOIN categories c ON p.category_id = c.id
+           WHERE {}
+           ORDER BY p.id DESC
+           LIMIT {}"#,
+        conditions,
+        bind(&mut values, limit)
+    );
+
+    query_posts_with_vehicles(conn, &sql, values)
+}
+
+/// Runs a post listing query whose column 19 is `p.vehicle_id` and attaches
+/// each post's vehicle and procedure summary
+fn query_posts_with_vehicles(conn: &Connection, sql: &str, values: Vec<rusqlite::types::Value>) -> Result<Vec<Post>> {
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+        Ok((map_post(row)?, row.get::<_, Option<i64>>(19)?))
+    })?;
     
-    let rows = if let Some(slug) = category_slug {
-        stmt.query_map(params![limit, offset, slug], map_post)?
-    } else {
-        stmt.query_map(params![limit, offset], map_post)?
-    };
-    rows.collect()
+    let mut posts = Vec::new();
+    for row in rows {
+        let (mut post, vehicle_id) = row?;
+        if let Some(vehicle_id) = vehicle_id {
+            post.vehicle = get_vehicle_by_id(conn, vehicle_id)?;
+        }
+        post.procedure = get_procedure_summary(conn, post.id)?;
+        posts.push(post);
+    }
+    Ok(posts)
 }
 
 pub fn get_posts_paginated(conn: &Connection, category_slug: Option<&str>, sort: &str, page: i64, per_page: i64) -> Result<(Vec<Post>, PaginationInfo)> {
+    get_posts_paginated_filtered(conn, category_slug, &VehicleFilter::default(), sort, page, per_page)
+}
+
+pub fn get_posts_paginated_filtered(conn: &Connection, category_slug: Option<&str>, filter: &VehicleFilter, sort: &str, page: i64, per_page: i64) -> Result<(Vec<Post>, PaginationInfo)> {
     let offset = (page - 1) * per_page;
     
     // Count total
-    let count_sql = if category_slug.is_some() {
-        "SELECT COUNT(*) FROM posts p JOIN categories c ON p.category_id = c.id WHERE p.removed = 0 AND c.slug = ?1"
-    } else {
-        "SELECT COUNT(*) FROM posts WHERE removed = 0"
-    };
-    
-    let total: i64 = if let Some(slug) = category_slug {
-        conn.query_row(count_sql, params![slug], |r| r.get(0))?
-    } else {
-        conn.query_row(count_sql, [], |r| r.get(0))?
-    };
+    let mut values = Vec::new();
+    let count_sql = format!(
+        "SELECT COUNT(*) FROM posts p JOIN categories c ON p.category_id = c.id WHERE {}",
+        post_listing_conditions(category_slug, filter, &mut values)
+    );
+    let total: i64 = conn.query_row(&count_sql, rusqlite::params_from_iter(values), |r| r.get(0))?;
     
-    let posts = get_posts(conn, category_slug, sort, per_page, offset)?;
+    let posts = get_posts_filtered(conn, category_slug, filter, sort, per_page, offset)?;
     let pagination = PaginationInfo::new(page, per_page, total);
     
     Ok((posts, pagination))
@@ -754,7 +2339,10 @@ fn map_post(row: &rusqlite::Row) -> rusqlite::Result<Post> {
         category_name: row.get(16).ok(),
         category_slug: row.get(17).ok(),
         comment_count: row.get(18).ok(),
+        snippet: None,
         tags: None,
+        vehicle: None,
+        procedure: None,
         is_bookmarked: None,
         user_vote: None,
     })
@@ -777,6 +2365,8 @@ pub fn get_post_by_id(conn: &Connection, id: i64) -> Result<Option<Post>> {
     if let Some(row) = rows.next()? {
         let mut post = map_post(row)?;
         post.tags = Some(get_tags_for_post(conn, id).unwrap_or_default());
+        post.vehicle = get_vehicle_for_post(conn, id)?;
+        post.procedure = get_procedure(conn, id)?;
         Ok(Some(post))
     } else {
         Ok(None)
@@ -849,9 +2439,33 @@ pub fn pin_post(conn: &Connection, post_id: i64, pinned: bool) -> Result<()> {
     Ok(())
 }
 
-pub fn set_best_answer(conn: &Connection, post_id: i64, comment_id: Option<i64>) -> Result<()> {
-    conn.execute("UPDATE posts SET best_answer_id = ?1 WHERE id = ?2", params![comment_id, post_id])?;
-    Ok(())
+/// Mark `comment_id` as the post's best answer, or clear it with `None`.
+/// Returns false, changing nothing, if the comment isn't on this post.
+/// Writes the answer's notification too, so run it in a transaction.
+pub fn set_best_answer(conn: &Connection, post_id: i64, comment_id: Option<i64>) -> Result<bool> {
+    // The check and the update are one statement, so the comment can't move
+    // or the answer be set from another thread in between
+    let post_author: i64 = match conn.query_row(
+        "UPDATE posts SET best_answer_id = ?2
+         WHERE id = ?1 AND (?2 IS NULL OR EXISTS (SELECT 1 FROM comments WHERE id = ?2 AND post_id = ?1))
+         RETURNING user_id",
+        params![post_id, comment_id],
+        |r| r.get(0),
+    ) {
+        Ok(user_id) => user_id,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    
+    // Notify the answer's author on behalf of the post author
+    if let Some(cid) = comment_id {
+        let comment_author: i64 = conn.query_row("SELECT user_id FROM comments WHERE id = ?1", params![cid], |r| r.get(0))?;
+        if comment_author != post_author {
+            create_notification(conn, comment_author, "best_answer", "Your answer was marked as the best answer!", Some(post_id), Some(cid), Some(post_author))?;
+        }
+    }
+    
+    Ok(true)
 }
 
 pub fn get_trending_posts(conn: &Connection, limit: i64) -> Result<Vec<Post>> {
@@ -874,6 +2488,114 @@ pub fn get_trending_posts(conn: &Connection, limit: i64) -> Result<Vec<Post>> {
     rows.collect()
 }
 
+// ============ Procedure Functions ============
+
+/// Turn a freshly created post into a repair procedure. The post body is
+/// the overview; steps, tools, parts and torque specs are stored here.
+/// Writes several tables, so run it in the post's transaction.
+pub fn add_procedure(conn: &Connection, post_id: i64, details: &ProcedureDetails) -> Result<()> {
+    conn.execute(
+        "INSERT INTO procedures (post_id, difficulty, labor_minutes) VALUES (?1, ?2, ?3)",
+        params![post_id, details.difficulty, details.labor_minutes],
+    )?;
+    insert_procedure_items(conn, post_id, details)
+}
+
+/// Replace a procedure's structured details. Title and overview edits go
+/// through `update_post` so they keep their edit history. Run it in a
+/// transaction so a failed insert doesn't leave the steps deleted.
+pub fn update_procedure(conn: &Connection, post_id: i64, details: &ProcedureDetails) -> Result<()> {
+    conn.execute(
+        "UPDATE procedures SET difficulty = ?1, labor_minutes = ?2 WHERE post_id = ?3",
+        params![details.difficulty, details.labor_minutes, post_id],
+    )?;
+    for table in ["procedure_steps", "procedure_tools", "procedure_parts", "procedure_torque_specs"] {
+        conn.execute(&format!("DELETE FROM {} WHERE post_id = ?1", table), params![post_id])?;
+    }
+    insert_procedure_items(conn, post_id, details)
+}
+
+fn insert_procedure_items(conn: &Connection, post_id: i64, details: &ProcedureDetails) -> Result<()> {
+    for (position, step) in details.steps.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO procedure_steps (post_id, position, body, body_html, upload_id) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![post_id, position as i64, step.body, render_markdown(&step.body), step.upload_id],
+        )?;
+    }
+    for (position, tool) in details.tools.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO procedure_tools (post_id, position, name) VALUES (?1, ?2, ?3)",
+            params![post_id, position as i64, tool],
+        )?;
+    }
+    for (position, part) in details.parts.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO procedure_parts (post_id, position, name, part_number, quantity) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![post_id, position as i64, part.name, part.part_number, part.quantity],
+        )?;
+    }
+    for (position, spec) in details.torque_specs.iter().enumerate() {
+        conn.execute(
+            "INSERT INTO procedure_torque_specs (post_id, position, fastener, value, unit) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![post_id, position as i64, spec.fastener, spec.value, spec.unit],
+        )?;
+    }
+    Ok(())
+}
+
+/// Difficulty and labor time only, for post listings
+pub fn get_procedure_summary(conn: &Connection, post_id: i64) -> Result<Option<ProcedureDetails>> {
+    match conn.query_row(
+        "SELECT difficulty, labor_minutes FROM procedures WHERE post_id = ?1",
+        params![post_id],
+        |r| Ok(ProcedureDetails { difficulty: r.get(0)?, labor_minutes: r.get(1)?, ..Default::default() }),
+    ) {
+        Ok(details) => Ok(Some(details)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The full procedure for a post, or None if it's an ordinary post
+pub fn get_procedure(conn: &Connection, post_id: i64) -> Result<Option<ProcedureDetails>> {
+    let Some(mut details) = get_procedure_summary(conn, post_id)? else {
+        return Ok(None);
+    };
+
+    let mut stmt = conn.prepare(
+        "SELECT s.body, s.body_html, s.upload_id, '/' || up.path
+         FROM procedure_steps s LEFT JOIN uploads up ON up.id = s.upload_id
+         WHERE s.post_id = ?1 ORDER BY s.position"
+    )?;
+    details.steps = stmt.query_map(params![post_id], |row| {
+        Ok(ProcedureStep {
+            body: row.get(0)?,
+            body_html: row.get(1)?,
+            upload_id: row.get(2)?,
+            image_url: row.get(3)?,
+        })
+    })?.collect::<Result<_>>()?;
+
+    let mut stmt = conn.prepare("SELECT name FROM procedure_tools WHERE post_id = ?1 ORDER BY position")?;
+    details.tools = stmt.query_map(params![post_id], |row| row.get(0))?.collect::<Result<_>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT name, part_number, quantity FROM procedure_parts WHERE post_id = ?1 ORDER BY position"
+    )?;
+    details.parts = stmt.query_map(params![post_id], |row| {
+        Ok(ProcedurePart { name: row.get(0)?, part_number: row.get(1)?, quantity: row.get(2)? })
+    })?.collect::<Result<_>>()?;
+
+    let mut stmt = conn.prepare(
+        "SELECT fastener, value, unit FROM procedure_torque_specs WHERE post_id = ?1 ORDER BY position"
+    )?;
+    details.torque_specs = stmt.query_map(params![post_id], |row| {
+        Ok(ProcedureTorqueSpec { fastener: row.get(0)?, value: row.get(1)?, unit: row.get(2)? })
+    })?.collect::<Result<_>>()?;
+
+    Ok(Some(details))
+}
+
 // ============ Comment Functions ============
 
 pub fn create_comment(conn: &Connection, post_id: i64, user_id: i64, parent_id: Option<i64>, body: &str) -> Result<i64> {
@@ -916,7 +2638,7 @@ pub fn create_comment(conn: &Connection, post_id: i64, user_id: i64, parent_id:
     for mention in mentions {
         if let Ok(Some(mentioned_user)) = get_user_by_username(conn, &mention) {
             if mentioned_user.id != user_id {
-                create_notification(conn, mentioned_user.id, "mention", &format!("You were mentioned in a comment"), Some(post_id), Some(comment_id), Some(user_id))?;
+                create_notification(conn, mentioned_user.id, "mention", "You were mentioned in a comment", Some(post_id), Some(comment_id), Some(user_id))?;
             }
         }
     }
@@ -950,33 +2672,74 @@ pub fn get_comments_for_post_sorted(conn: &Connection, post_id: i64, sort: &str)
     );
     
     let mut stmt = conn.prepare(&sql)?;
-    let rows = stmt.query_map(params![post_id], |row| {
-        let best_id: Option<i64> = row.get(14)?;
-        let comment_id: i64 = row.get(0)?;
-        Ok(Comment {
-            id: comment_id,
-            post_id: row.get(1)?,
-            user_id: row.get(2)?,
-            parent_id: row.get(3)?,
-            body: row.get(4)?,
-            body_html: row.get(5)?,
-            score: row.get(6)?,
-            created_at: row.get(7)?,
-            edited_at: row.get(8)?,
-            removed: row.get::<_, i64>(9)? != 0,
-            username: row.get(10).ok(),
-            user_role: row.get(11).ok(),
-            user_flair: row.get(12).ok(),
-            user_avatar: row.get(13).ok(),
-            is_best_answer: best_id == Some(comment_id),
-            replies: vec![],
-            user_vote: None,
-            depth: 0,
-        })
-    })?;
+    let rows = stmt.query_map(params![post_id], map_comment)?;
+    rows.collect()
+}
+
+/// A post's comments in the order they were made, starting after the
+/// comment with id `after_id`
+pub fn get_comments_after(conn: &Connection, post_id: i64, after_id: Option<i64>, limit: i64) -> Result<Vec<Comment>> {
+    let mut stmt = conn.prepare(
+        r#"SELECT c.id, c.post_id, c.user_id, c.parent_id, c.body, c.body_html, c.score, c.created_at,
+           c.edited_at, c.removed,
+           u.username, u.role, u.flair,
+           (SELECT avatar_path FROM user_profiles WHERE user_id = u.id) as avatar,
+           (SELECT best_answer_id FROM posts WHERE id = c.post_id) as best_id
+           FROM comments c
+           JOIN users u ON c.user_id = u.id
+           WHERE c.post_id = ?1 AND c.removed = 0 AND c.id > ?2
+           ORDER BY c.id
+           LIMIT ?3"#
+    )?;
+    let rows = stmt.query_map(params![post_id, after_id.unwrap_or(0), limit], map_comment)?;
     rows.collect()
 }
 
+/// Maps a comment query whose column 14 is the post's best answer id
+fn map_comment(row: &rusqlite::Row) -> rusqlite::Result<Comment> {
+    let best_id: Option<i64> = row.get(14)?;
+    let comment_id: i64 = row.get(0)?;
+    Ok(Comment {
+        id: comment_id,
+        post_id: row.get(1)?,
+        user_id: row.get(2)?,
+        parent_id: row.get(3)?,
+        body: row.get(4)?,
+        body_html: row.get(5)?,
+        score: row.get(6)?,
+        created_at: row.get(7)?,
+        edited_at: row.get(8)?,
+        removed: row.get::<_, i64>(9)? != 0,
+        username: row.get(10).ok(),
+        user_role: row.get(11).ok(),
+        user_flair: row.get(12).ok(),
+        user_avatar: row.get(13).ok(),
+        is_best_answer: best_id == Some(comment_id),
+        replies: vec![],
+        user_vote: None,
+        depth: 0,
+    })
+}
+
+pub fn get_comment_by_id(conn: &Connection, comment_id: i64) -> Result<Option<Comment>> {
+    let mut stmt = conn.prepare(
+        r#"SELECT c.id, c.post_id, c.user_id, c.parent_id, c.body, c.body_html, c.score, c.created_at,
+           c.edited_at, c.removed,
+           u.username, u.role, u.flair,
+           (SELECT avatar_path FROM user_profiles WHERE user_id = u.id) as avatar,
+           (SELECT best_answer_id FROM posts WHERE id = c.post_id) as best_id
+           FROM comments c
+           JOIN users u ON c.user_id = u.id
+           WHERE c.id = ?1"#
+    )?;
+    let mut rows = stmt.query(params![comment_id])?;
+    if let Some(row) = rows.next()? {
+        Ok(Some(map_comment(row)?))
+    } else {
+        Ok(None)
+    }
+}
+
 pub fn get_comments_by_user(conn: &Connection, user_id: i64) -> Result<Vec<Comment>> {
     let mut stmt = conn.prepare(
         r#"SELECT c.id, c.post_id, c.user_id, c.parent_id, c.body, c.body_html, c.score, c.created_at,
@@ -1208,6 +2971,42 @@ pub fn get_stores(conn: &Connection, category: Option<&str>) -> Result<Vec<Store
     rows.collect()
 }
 
+/// Stores in the order they were submitted, starting after the store with
+/// id `after_id`
+pub fn get_stores_after(conn: &Connection, category: Option<&str>, after_id: Option<i64>, limit: i64) -> Result<Vec<Store>> {
+    let mut stmt = conn.prepare(
+        r#"SELECT s.id, s.name, s.url, s.description, s.category, s.submitted_by, s.created_at,
+           u.username,
+           (SELECT COUNT(*) FROM store_votes WHERE store_id = s.id AND positive = 1) as pos,
+           (SELECT COUNT(*) FROM store_votes WHERE store_id = s.id) as total
+           FROM stores s
+           JOIN users u ON s.submitted_by = u.id
+           WHERE s.id > ?1 AND (?2 IS NULL OR s.category = ?2)
+           ORDER BY s.id
+           LIMIT ?3"#
+    )?;
+    let rows = stmt.query_map(params![after_id.unwrap_or(0), category, limit], map_store)?;
+    rows.collect()
+}
+
+pub fn get_store_by_id(conn: &Connection, store_id: i64) -> Result<Option<Store>> {
+    let mut stmt = conn.prepare(
+        r#"SELECT s.id, s.name, s.url, s.description, s.category, s.submitted_by, s.created_at,
+           u.username,
+           (SELECT COUNT(*) FROM store_votes WHERE store_id = s.id AND positive = 1) as pos,
+           (SELECT COUNT(*) FROM store_votes WHERE store_id = s.id) as total
+           FROM stores s
+           JOIN users u ON s.submitted_by = u.id
+           WHERE s.id = ?1"#
+    )?;
+    let mut rows = stmt.query(params![store_id])?;
+    if let Some(row) = rows.next()? {
+        Ok(Some(map_store(row)?))
+    } else {
+        Ok(None)
+    }
+}
+
 fn map_store(row: &rusqlite::Row) -> rusqlite::Result<Store> {
     let pos: i64 = row.get(8)?;
     let total: i64 = row.get(9)?;
@@ -1226,18 +3025,192 @@ fn map_store(row: &rusqlite::Row) -> rusqlite::Result<Store> {
     })
 }
 
-pub fn vote_store(conn: &Connection, store_id: i64, user_id: i64, positive: bool) -> Result<()> {
+pub fn vote_store(conn: &Connection, store_id: i64, user_id: i64, positive: bool) -> Result<()> {
+    conn.execute(
+        "INSERT OR REPLACE INTO store_votes (store_id, user_id, positive) VALUES (?1, ?2, ?3)",
+        params![store_id, user_id, positive as i64],
+    )?;
+    Ok(())
+}
+
+pub fn get_user_store_vote(conn: &Connection, store_id: i64, user_id: i64) -> Result<Option<bool>> {
+    match conn.query_row(
+        "SELECT positive FROM store_votes WHERE store_id = ?1 AND user_id = ?2",
+        params![store_id, user_id],
+        |row| row.get::<_, i64>(0).map(|v| v != 0),
+    ) {
+        Ok(v) => Ok(Some(v)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn get_store_categories(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT DISTINCT category FROM stores ORDER BY category")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+pub fn search_stores(conn: &Connection, query: &str) -> Result<Vec<Store>> {
+    search_stores_sorted(conn, query, "relevance", "all")
+}
+
+/// Stores whose name or description contain every search word. Post-only
+/// operators such as `author:` or `is:solved` can't match a store, so a query
+/// using them finds none.
+pub fn search_stores_sorted(conn: &Connection, query: &str, sort: &str, time: &str) -> Result<Vec<Store>> {
+    let filters = parse_search_query(query);
+    let words: Vec<&String> = filters.terms.iter().chain(&filters.phrases).collect();
+    if words.is_empty() || filters.has_post_filters() {
+        return Ok(Vec::new());
+    }
+    
+    let mut values = Vec::new();
+    let mut conditions = Vec::new();
+    for word in words {
+        let placeholder = bind(&mut values, format!("%{}%", word));
+        conditions.push(format!("(s.name LIKE {0} OR s.description LIKE {0})", placeholder));
+    }
+    for word in &filters.excluded {
+        let placeholder = bind(&mut values, format!("%{}%", word));
+        conditions.push(format!("NOT (s.name LIKE {0} OR COALESCE(s.description, '') LIKE {0})", placeholder));
+    }
+    if let Some(modifier) = search_time_modifier(time) {
+        conditions.push(format!("s.created_at >= datetime('now', '{}')", modifier));
+    }
+    
+    let order = match sort {
+        "new" => "s.created_at DESC, s.id DESC",
+        "top" => "pos DESC, s.name",
+        _ => "s.name",
+    };
+    
+    let sql = format!(
+        r#"SELECT s.id, s.name, s.url, s.description, s.category, s.submitted_by, s.created_at,
+           u.username,
+           (SELECT COUNT(*) FROM store_votes WHERE store_id = s.id AND positive = 1) as pos,
+           (SELECT COUNT(*) FROM store_votes WHERE store_id = s.id) as total
+           FROM stores s
+           JOIN users u ON s.submitted_by = u.id
+           WHERE {}
+           ORDER BY {}"#,
+        conditions.join(" AND "),
+        order
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(values), map_store)?;
+    rows.collect()
+}
+
+// ============ Torque Spec Functions ============
+
+pub fn create_torque_spec(conn: &Connection, details: &TorqueSpecDetails, submitted_by: i64) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO torque_specs (make, model, year_from, year_to, engine_code, fastener, value, unit,
+                                   sequence, angle_degrees, torque_to_yield, source, submitted_by)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13)",
+        params![
+            details.make, details.model, details.year_from, details.year_to, details.engine_code,
+            details.fastener, details.value, details.unit, details.sequence, details.angle_degrees,
+            details.torque_to_yield as i64, details.source, submitted_by
+        ],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+const TORQUE_SPEC_COLUMNS: &str = r#"t.id, t.make, t.model, t.year_from, t.year_to, t.engine_code, t.fastener,
+    t.value, t.unit, t.sequence, t.angle_degrees, t.torque_to_yield, t.source, t.submitted_by, t.created_at,
+    u.username,
+    (SELECT COUNT(*) FROM torque_spec_votes WHERE spec_id = t.id AND confirmed = 1) as confirmations,
+    (SELECT COUNT(*) FROM torque_spec_votes WHERE spec_id = t.id AND confirmed = 0) as disputes"#;
+
+fn map_torque_spec(row: &rusqlite::Row) -> rusqlite::Result<TorqueSpec> {
+    let value: f64 = row.get(7)?;
+    let unit: String = row.get(8)?;
+    Ok(TorqueSpec {
+        id: row.get(0)?,
+        make: row.get(1)?,
+        model: row.get(2)?,
+        year_from: row.get(3)?,
+        year_to: row.get(4)?,
+        engine_code: row.get(5)?,
+        fastener: row.get(6)?,
+        values: crate::torque::TorqueValues::new(value, &unit),
+        value,
+        unit,
+        sequence: row.get(9)?,
+        angle_degrees: row.get(10)?,
+        torque_to_yield: row.get::<_, i64>(11)? != 0,
+        source: row.get(12)?,
+        submitted_by: row.get(13)?,
+        created_at: row.get(14)?,
+        submitter_name: row.get(15).ok(),
+        confirmations: row.get(16)?,
+        disputes: row.get(17)?,
+    })
+}
+
+/// Specs matching `filter`, grouped by vehicle and fastener with the best
+/// confirmed spec for each fastener first. `year` matches any spec whose
+/// range covers it; `fastener` is a substring match.
+pub fn get_torque_specs(conn: &Connection, filter: &TorqueSpecFilter, limit: i64) -> Result<Vec<TorqueSpec>> {
+    let mut values = Vec::new();
+    let mut conditions = Vec::new();
+    if let Some(make) = &filter.make {
+        conditions.push(format!("t.make = {} COLLATE NOCASE", bind(&mut values, make.clone())));
+    }
+    if let Some(model) = &filter.model {
+        conditions.push(format!("t.model = {} COLLATE NOCASE", bind(&mut values, model.clone())));
+    }
+    if let Some(year) = filter.year {
+        let placeholder = bind(&mut values, year);
+        conditions.push(format!("{0} BETWEEN t.year_from AND t.year_to", placeholder));
+    }
+    if let Some(fastener) = &filter.fastener {
+        conditions.push(format!("t.fastener LIKE {}", bind(&mut values, format!("%{}%", fastener))));
+    }
+    let limit = bind(&mut values, limit);
+
+    let sql = format!(
+        "SELECT {} FROM torque_specs t
+         JOIN users u ON t.submitted_by = u.id
+         {}
+         ORDER BY t.make COLLATE NOCASE, t.model COLLATE NOCASE, t.fastener COLLATE NOCASE,
+                  confirmations - disputes DESC, t.created_at DESC
+         LIMIT {}",
+        TORQUE_SPEC_COLUMNS,
+        if conditions.is_empty() { String::new() } else { format!("WHERE {}", conditions.join(" AND ")) },
+        limit
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(values), map_torque_spec)?;
+    rows.collect()
+}
+
+pub fn get_torque_spec(conn: &Connection, spec_id: i64) -> Result<Option<TorqueSpec>> {
+    let sql = format!(
+        "SELECT {} FROM torque_specs t JOIN users u ON t.submitted_by = u.id WHERE t.id = ?1",
+        TORQUE_SPEC_COLUMNS
+    );
+    match conn.query_row(&sql, params![spec_id], map_torque_spec) {
+        Ok(spec) => Ok(Some(spec)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+pub fn vote_torque_spec(conn: &Connection, spec_id: i64, user_id: i64, confirmed: bool) -> Result<()> {
     conn.execute(
-        "INSERT OR REPLACE INTO store_votes (store_id, user_id, positive) VALUES (?1, ?2, ?3)",
-        params![store_id, user_id, positive as i64],
+        "INSERT OR REPLACE INTO torque_spec_votes (spec_id, user_id, confirmed) VALUES (?1, ?2, ?3)",
+        params![spec_id, user_id, confirmed as i64],
     )?;
     Ok(())
 }
 
-pub fn get_user_store_vote(conn: &Connection, store_id: i64, user_id: i64) -> Result<Option<bool>> {
+pub fn get_user_torque_spec_vote(conn: &Connection, spec_id: i64, user_id: i64) -> Result<Option<bool>> {
     match conn.query_row(
-        "SELECT positive FROM store_votes WHERE store_id = ?1 AND user_id = ?2",
-        params![store_id, user_id],
+        "SELECT confirmed FROM torque_spec_votes WHERE spec_id = ?1 AND user_id = ?2",
+        params![spec_id, user_id],
         |row| row.get::<_, i64>(0).map(|v| v != 0),
     ) {
         Ok(v) => Ok(Some(v)),
@@ -1246,28 +3219,13 @@ pub fn get_user_store_vote(conn: &Connection, store_id: i64, user_id: i64) -> Re
     }
 }
 
-pub fn get_store_categories(conn: &Connection) -> Result<Vec<String>> {
-    let mut stmt = conn.prepare("SELECT DISTINCT category FROM stores ORDER BY category")?;
+/// Makes with at least one spec, for the lookup form
+pub fn get_torque_spec_makes(conn: &Connection) -> Result<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT make FROM torque_specs GROUP BY make COLLATE NOCASE ORDER BY make COLLATE NOCASE")?;
     let rows = stmt.query_map([], |row| row.get(0))?;
     rows.collect()
 }
 
-pub fn search_stores(conn: &Connection, query: &str) -> Result<Vec<Store>> {
-    let search_pattern = format!("%{}%", query);
-    let mut stmt = conn.prepare(
-        r#"SELECT s.id, s.name, s.url, s.description, s.category, s.submitted_by, s.created_at,
-           u.username,
-           (SELECT COUNT(*) FROM store_votes WHERE store_id = s.id AND positive = 1) as pos,
-           (SELECT COUNT(*) FROM store_votes WHERE store_id = s.id) as total
-           FROM stores s
-           JOIN users u ON s.submitted_by = u.id
-           WHERE s.name LIKE ?1 OR s.description LIKE ?1
-           ORDER BY s.name"#
-    )?;
-    let rows = stmt.query_map(params![search_pattern], map_store)?;
-    rows.collect()
-}
-
 // ============ Verification Functions ============
 
 pub fn create_verification_request(conn: &Connection, user_id: i64, proof_text: &str, proof_type: &str) -> Result<i64> {
@@ -1318,6 +3276,7 @@ pub fn approve_verification(conn: &Connection, request_id: i64, reviewer_id: i64
         "UPDATE users SET role = 'verified_mechanic' WHERE id = ?1",
         params![user_id],
     )?;
+    mark_sessions_for_rotation(conn, user_id)?;
     
     create_notification(conn, user_id, "system", "Your verification has been approved! You can now create posts.", None, None, None)?;
     
@@ -1365,11 +3324,13 @@ pub fn get_unresolved_reports(conn: &Connection) -> Result<Vec<Report>> {
         r#"SELECT r.id, r.reporter_id, r.post_id, r.comment_id, r.reason, r.resolved, r.created_at,
            u.username,
            p.title,
-           c.body
+           c.body,
+           COALESCE(p.category_id, cp.category_id)
            FROM reports r
            JOIN users u ON r.reporter_id = u.id
            LEFT JOIN posts p ON r.post_id = p.id
            LEFT JOIN comments c ON r.comment_id = c.id
+           LEFT JOIN posts cp ON c.post_id = cp.id
            WHERE r.resolved = 0
            ORDER BY r.created_at ASC"#
     )?;
@@ -1385,6 +3346,7 @@ pub fn get_unresolved_reports(conn: &Connection) -> Result<Vec<Report>> {
             reporter_name: row.get(7).ok(),
             post_title: row.get(8).ok(),
             comment_body: row.get(9).ok(),
+            category_id: row.get(10)?,
         })
     })?;
     rows.collect()
@@ -1463,24 +3425,44 @@ pub fn get_user_notifications(conn: &Connection, user_id: i64, limit: i64) -> Re
            ORDER BY n.created_at DESC
            LIMIT ?2"#
     )?;
-    let rows = stmt.query_map(params![user_id, limit], |row| {
-        Ok(Notification {
-            id: row.get(0)?,
-            user_id: row.get(1)?,
-            notification_type: NotificationType::from_str(&row.get::<_, String>(2)?),
-            content: row.get(3)?,
-            read: row.get::<_, i64>(4)? != 0,
-            post_id: row.get(5)?,
-            comment_id: row.get(6)?,
-            from_user_id: row.get(7)?,
-            created_at: row.get(8)?,
-            from_username: row.get(9).ok(),
-            post_title: row.get(10).ok(),
-        })
-    })?;
+    let rows = stmt.query_map(params![user_id, limit], map_notification)?;
+    rows.collect()
+}
+
+/// A user's notifications, newest first, starting after the notification
+/// with id `before_id`
+pub fn get_notifications_before(conn: &Connection, user_id: i64, unread_only: bool, before_id: Option<i64>, limit: i64) -> Result<Vec<Notification>> {
+    let mut stmt = conn.prepare(
+        r#"SELECT n.id, n.user_id, n.notification_type, n.content, n.read, n.post_id, n.comment_id, n.from_user_id, n.created_at,
+           u.username,
+           p.title
+           FROM notifications n
+           LEFT JOIN users u ON n.from_user_id = u.id
+           LEFT JOIN posts p ON n.post_id = p.id
+           WHERE n.user_id = ?1 AND (?2 = 0 OR n.read = 0) AND (?3 IS NULL OR n.id < ?3)
+           ORDER BY n.id DESC
+           LIMIT ?4"#
+    )?;
+    let rows = stmt.query_map(params![user_id, unread_only, before_id, limit], map_notification)?;
     rows.collect()
 }
 
+fn map_notification(row: &rusqlite::Row) -> rusqlite::Result<Notification> {
+    Ok(Notification {
+        id: row.get(0)?,
+        user_id: row.get(1)?,
+        notification_type: NotificationType::from_str(&row.get::<_, String>(2)?),
+        content: row.get(3)?,
+        read: row.get::<_, i64>(4)? != 0,
+        post_id: row.get(5)?,
+        comment_id: row.get(6)?,
+        from_user_id: row.get(7)?,
+        created_at: row.get(8)?,
+        from_username: row.get(9).ok(),
+        post_title: row.get(10).ok(),
+    })
+}
+
 pub fn get_unread_notification_count(conn: &Connection, user_id: i64) -> Result<i64> {
     conn.query_row(
         "SELECT COUNT(*) FROM notifications WHERE user_id = ?1 AND read = 0",
@@ -1497,6 +3479,16 @@ pub fn mark_notification_read(conn: &Connection, notification_id: i64) -> Result
     Ok(())
 }
 
+/// Mark one of the user's notifications read. Returns false if they have no
+/// such notification.
+pub fn mark_user_notification_read(conn: &Connection, user_id: i64, notification_id: i64) -> Result<bool> {
+    let changed = conn.execute(
+        "UPDATE notifications SET read = 1 WHERE id = ?1 AND user_id = ?2",
+        params![notification_id, user_id],
+    )?;
+    Ok(changed == 1)
+}
+
 pub fn mark_all_notifications_read(conn: &Connection, user_id: i64) -> Result<()> {
     conn.execute(
         "UPDATE notifications SET read = 1 WHERE user_id = ?1",
@@ -1643,8 +3635,217 @@ pub fn get_recent_activity(conn: &Connection, limit: i64) -> Result<Vec<Activity
 
 // ============ Search Functions ============
 
+/// Column weights for bm25: a hit in the title counts for more than one in
+/// the body, which counts for more than one buried in the comments
+const FTS_WEIGHTS: &str = "10.0, 4.0, 1.0";
+
+/// Markers `snippet()` wraps matched terms in. They are private-use code
+/// points so they survive HTML escaping; the index strips them from user
+/// text (migration 15) so every marker in a snippet is one of ours.
+const HIGHLIGHT_START: &str = "\u{E000}";
+const HIGHLIGHT_END: &str = "\u{E001}";
+
+/// Split search box input into free text and operators. Supports
+/// `author:name`, `tag:name`, `category:slug`, `is:solved`, `"quoted
+/// phrases"` and `-exclusions`; operator values may be quoted too.
+/// Anything that isn't a recognised operator is searched as plain text.
+pub fn parse_search_query(input: &str) -> SearchFilters {
+    let mut filters = SearchFilters::default();
+    let mut chars = input.chars().peekable();
+    
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+        
+        let negated = c == '-';
+        if negated {
+            chars.next();
+        }
+        
+        // Read one token, letting a quoted section swallow whitespace
+        let mut token = String::new();
+        let mut quoted = false;
+        let mut in_quotes = false;
+        while let Some(&c) = chars.peek() {
+            if c == '"' {
+                quoted = true;
+                in_quotes = !in_quotes;
+                chars.next();
+                continue;
+            }
+            if c.is_whitespace() && !in_quotes {
+                break;
+            }
+            token.push(c);
+            chars.next();
+        }
+        
+        if token.is_empty() {
+            continue;
+        }
+        
+        if !negated {
+            if let Some((key, value)) = token.split_once(':') {
+                let value = value.trim();
+                let handled = match key.to_lowercase().as_str() {
+                    "author" if !value.is_empty() => { filters.author = Some(value.to_string()); true }
+                    "tag" if !value.is_empty() => { filters.tags.push(value.to_string()); true }
+                    "category" if !value.is_empty() => { filters.category = Some(value.to_string()); true }
+                    "is" if value.eq_ignore_ascii_case("solved") => { filters.solved = true; true }
+                    _ => false,
+                };
+                if handled {
+                    continue;
+                }
+            }
+        }
+        
+        if negated {
+            filters.excluded.push(token);
+        } else if quoted {
+            filters.phrases.push(token);
+        } else {
+            filters.terms.push(token);
+        }
+    }
+    
+    filters
+}
+
+/// Quote a term or phrase so FTS5 reads it as text, never as query syntax.
+/// Returns `None` for input with nothing searchable in it.
+fn fts_quote(text: &str) -> Option<String> {
+    if !text.chars().any(|c| c.is_alphanumeric()) {
+        return None;
+    }
+    Some(format!("\"{}\"", text.replace('"', "\"\"")))
+}
+
+/// Build the FTS5 MATCH expression for the positive part of a search. The
+/// last bare term matches as a prefix so partially typed words still find
+/// results.
+fn fts_match_expr(filters: &SearchFilters) -> Option<String> {
+    let mut parts: Vec<String> = filters.phrases.iter().filter_map(|p| fts_quote(p)).collect();
+    let terms: Vec<String> = filters.terms.iter().filter_map(|t| fts_quote(t)).collect();
+    if let Some((last, rest)) = terms.split_last() {
+        parts.extend(rest.iter().cloned());
+        parts.push(format!("{}*", last));
+    }
+    
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" "))
+    }
+}
+
+/// MATCH expression for everything the search excludes
+fn fts_exclude_expr(filters: &SearchFilters) -> Option<String> {
+    let parts: Vec<String> = filters.excluded.iter().filter_map(|t| fts_quote(t)).collect();
+    if parts.is_empty() {
+        None
+    } else {
+        Some(parts.join(" OR "))
+    }
+}
+
+/// SQLite datetime modifier for a search time window, `None` for all time
+fn search_time_modifier(time: &str) -> Option<&'static str> {
+    match time {
+        "day" => Some("-1 day"),
+        "week" => Some("-7 days"),
+        "month" => Some("-1 month"),
+        "year" => Some("-1 year"),
+        _ => None,
+    }
+}
+
+/// Escape a `snippet()` result for HTML and turn its markers into `<mark>` tags
+fn highlight_snippet(raw: &str) -> String {
+    escape_html(raw)
+        .replace(HIGHLIGHT_START, "<mark>")
+        .replace(HIGHLIGHT_END, "</mark>")
+}
+
+/// Push a bound value and return its numbered placeholder
+fn bind(values: &mut Vec<rusqlite::types::Value>, value: impl Into<rusqlite::types::Value>) -> String {
+    values.push(value.into());
+    format!("?{}", values.len())
+}
+
 pub fn search_posts(conn: &Connection, query: &str, category_slug: Option<&str>, limit: i64) -> Result<Vec<Post>> {
-    let search_pattern = format!("%{}%", query);
+    search_posts_sorted(conn, query, category_slug, "relevance", "all", limit)
+}
+
+/// Search posts with advanced query syntax (see [`parse_search_query`]).
+/// `sort` is one of relevance/new/top/comments and `time` one of
+/// day/week/month/year/all; unknown values fall back to relevance and all.
+pub fn search_posts_sorted(conn: &Connection, query: &str, category_slug: Option<&str>, sort: &str, time: &str, limit: i64) -> Result<Vec<Post>> {
+    let filters = parse_search_query(query);
+    let match_expr = fts_match_expr(&filters);
+    
+    // Exclusions alone would list the whole forum
+    if match_expr.is_none() && !filters.has_post_filters() {
+        return Ok(Vec::new());
+    }
+    
+    let mut values = Vec::new();
+    let mut conditions = vec!["p.removed = 0".to_string()];
+    
+    let (from, snippet) = match &match_expr {
+        Some(expr) => {
+            conditions.push(format!("posts_fts MATCH {}", bind(&mut values, expr.clone())));
+            let snippet = format!(
+                "snippet(posts_fts, -1, {}, {}, '…', 24)",
+                bind(&mut values, HIGHLIGHT_START.to_string()),
+                bind(&mut values, HIGHLIGHT_END.to_string())
+            );
+            ("posts_fts JOIN posts p ON p.id = posts_fts.rowid", snippet)
+        }
+        None => ("posts p", "NULL".to_string()),
+    };
+    
+    if let Some(expr) = fts_exclude_expr(&filters) {
+        conditions.push(format!(
+            "p.id NOT IN (SELECT rowid FROM posts_fts WHERE posts_fts MATCH {})",
+            bind(&mut values, expr)
+        ));
+    }
+    if let Some(author) = &filters.author {
+        conditions.push(format!("u.username = {} COLLATE NOCASE", bind(&mut values, author.clone())));
+    }
+    for tag in &filters.tags {
+        conditions.push(format!(
+            "EXISTS (SELECT 1 FROM post_tag_map m JOIN post_tags t ON t.id = m.tag_id
+                     WHERE m.post_id = p.id AND t.name = {} COLLATE NOCASE)",
+            bind(&mut values, tag.clone())
+        ));
+    }
+    for category in filters.category.as_deref().into_iter().chain(category_slug) {
+        let placeholder = bind(&mut values, category.to_string());
+        conditions.push(format!("(c.slug = {0} OR c.name = {0} COLLATE NOCASE)", placeholder));
+    }
+    if filters.solved {
+        conditions.push("p.best_answer_id IS NOT NULL".to_string());
+    }
+    if let Some(modifier) = search_time_modifier(time) {
+        conditions.push(format!("p.created_at >= datetime('now', '{}')", modifier));
+    }
+    
+    // bm25 is negative with lower meaning more relevant; well-voted posts get
+    // up to double weight so accepted community answers float up
+    let order = match sort {
+        "new" => "p.created_at DESC, p.id DESC".to_string(),
+        "top" => "p.score DESC, p.created_at DESC".to_string(),
+        "comments" => "comment_count DESC, p.score DESC".to_string(),
+        _ if match_expr.is_some() => format!(
+            "bm25(posts_fts, {}) * (1.0 + MIN(MAX(p.score, 0), 100) / 100.0), p.id DESC",
+            FTS_WEIGHTS
+        ),
+        _ => "p.score DESC, p.created_at DESC".to_string(),
+    };
     
     let sql = format!(
         r#"SELECT p.id, p.user_id, p.category_id, p.title, p.body, p.body_html, p.score, p.created_at,
@@ -1652,73 +3853,244 @@ pub fn search_posts(conn: &Connection, query: &str, category_slug: Option<&str>,
            u.username, u.role, u.flair,
            (SELECT avatar_path FROM user_profiles WHERE user_id = u.id) as avatar,
            c.name, c.slug,
-           (SELECT COUNT(*) FROM comments WHERE post_id = p.id AND removed = 0) as comment_count
-           FROM posts p
+           (SELECT COUNT(*) FROM comments WHERE post_id = p.id AND removed = 0) as comment_count,
+           {} as snippet, p.vehicle_id
+           FROM {}
            JOIN users u ON p.user_id = u.id
            JOIN categories c ON p.category_id = c.id
-           WHERE p.removed = 0 AND (p.title LIKE ?1 OR p.body LIKE ?1)
-           {}
-           ORDER BY p.score DESC
-           LIMIT ?2"#,
-        if category_slug.is_some() { "AND c.slug = ?3" } else { "" }
+           WHERE {}
+           ORDER BY {}
+           LIMIT {}"#,
+        snippet,
+        from,
+        conditions.join(" AND "),
+        order,
+        bind(&mut values, limit)
     );
     
     let mut stmt = conn.prepare(&sql)?;
-    let rows = if let Some(slug) = category_slug {
-        stmt.query_map(params![search_pattern, limit, slug], map_post)?
-    } else {
-        stmt.query_map(params![search_pattern, limit], map_post)?
-    };
-    rows.collect()
+    let rows = stmt.query_map(rusqlite::params_from_iter(values), |row| {
+        let mut post = map_post(row)?;
+        post.snippet = row.get::<_, Option<String>>(19)?.map(|s| highlight_snippet(&s));
+        Ok((post, row.get::<_, Option<i64>>(20)?))
+    })?;
+    
+    let mut posts = Vec::new();
+    for row in rows {
+        let (mut post, vehicle_id) = row?;
+        if let Some(vehicle_id) = vehicle_id {
+            post.vehicle = get_vehicle_by_id(conn, vehicle_id)?;
+        }
+        posts.push(post);
+    }
+    Ok(posts)
 }
 
 pub fn global_search(conn: &Connection, query: &str, limit: i64) -> Result<Vec<SearchResult>> {
-    let search_pattern = format!("%{}%", query);
+    global_search_sorted(conn, query, "relevance", "all", limit)
+}
+
+/// Posts and stores matching a query, with the same syntax, sorts and time
+/// windows as [`search_posts_sorted`]
+pub fn global_search_sorted(conn: &Connection, query: &str, sort: &str, time: &str, limit: i64) -> Result<Vec<SearchResult>> {
     let mut results = Vec::new();
     
     // Search posts
-    let mut stmt = conn.prepare(
-        r#"SELECT 'post' as type, id, title, substr(body, 1, 200) as snippet, '/post/' || id as url, score, created_at
-           FROM posts WHERE removed = 0 AND (title LIKE ?1 OR body LIKE ?1)
-           ORDER BY score DESC LIMIT ?2"#
-    )?;
-    let post_rows = stmt.query_map(params![&search_pattern, limit / 2], |row| {
-        Ok(SearchResult {
-            result_type: row.get(0)?,
-            id: row.get(1)?,
-            title: row.get(2)?,
-            snippet: row.get(3)?,
-            url: row.get(4)?,
-            score: row.get(5)?,
-            created_at: row.get(6)?,
-        })
-    })?;
-    for row in post_rows {
-        results.push(row?);
+    for post in search_posts_sorted(conn, query, None, sort, time, limit / 2)? {
+        let snippet = post.snippet.unwrap_or_else(|| {
+            escape_html(&post.body.chars().take(200).collect::<String>())
+        });
+        results.push(SearchResult {
+            result_type: "post".to_string(),
+            id: post.id,
+            title: post.title,
+            snippet,
+            url: format!("/post/{}", post.id),
+            score: Some(post.score),
+            created_at: post.created_at,
+        });
     }
     
     // Search stores
+    for store in search_stores_sorted(conn, query, sort, time)?.into_iter().take((limit / 2) as usize) {
+        results.push(SearchResult {
+            result_type: "store".to_string(),
+            id: store.id,
+            title: store.name,
+            snippet: escape_html(store.description.as_deref().unwrap_or("")),
+            url: format!("/stores#store-{}", store.id),
+            score: None,
+            created_at: store.created_at,
+        });
+    }
+    
+    Ok(results)
+}
+
+// ============ DTC Functions ============
+
+static DTC_CODE: LazyLock<regex::Regex> = LazyLock::new(|| regex::Regex::new(r"^[PBCU][0-3][0-9A-F]{3}$").unwrap());
+
+/// Trouble codes inside running text
+static DTC_IN_TEXT: LazyLock<regex::Regex> = LazyLock::new(|| regex::Regex::new(r"\b[PBCU][0-3][0-9A-F]{3}\b").unwrap());
+
+/// True for a well-formed OBD-II code such as `P0300` or `U0100`
+pub fn is_dtc_code(code: &str) -> bool {
+    DTC_CODE.is_match(code)
+}
+
+/// Looks up a code's definition. Codes missing from the seeded list still
+/// come back, just without a description.
+pub fn get_dtc_code(conn: &Connection, code: &str) -> Result<DtcCode> {
+    let description: Option<String> = match conn.query_row(
+        "SELECT description FROM dtc_codes WHERE code = ?1",
+        params![code],
+        |r| r.get(0),
+    ) {
+        Ok(d) => Some(d),
+        Err(rusqlite::Error::QueryReturnedNoRows) => None,
+        Err(e) => return Err(e),
+    };
+    Ok(DtcCode::new(code, description))
+}
+
+/// Codes whose code starts with, or whose description contains, `query`
+pub fn search_dtc_codes(conn: &Connection, query: &str, limit: i64) -> Result<Vec<DtcCode>> {
     let mut stmt = conn.prepare(
-        r#"SELECT 'store' as type, id, name, COALESCE(description, '') as snippet, '/stores#store-' || id as url, NULL as score, created_at
-           FROM stores WHERE name LIKE ?1 OR description LIKE ?1
-           ORDER BY name LIMIT ?2"#
-    )?;
-    let store_rows = stmt.query_map(params![&search_pattern, limit / 2], |row| {
-        Ok(SearchResult {
-            result_type: row.get(0)?,
-            id: row.get(1)?,
-            title: row.get(2)?,
-            snippet: row.get(3)?,
-            url: row.get(4)?,
-            score: row.get(5)?,
-            created_at: row.get(6)?,
+        "SELECT code, description FROM dtc_codes
+         WHERE code LIKE ?1 || '%' OR description LIKE '%' || ?1 || '%'
+         ORDER BY code LIMIT ?2"
+    )?;
+    let rows = stmt.query_map(params![query.trim().to_uppercase(), limit], |row| {
+        let code: String = row.get(0)?;
+        Ok(DtcCode::new(&code, row.get(1)?))
+    })?;
+    rows.collect()
+}
+
+pub fn get_dtc_causes(conn: &Connection, code: &str) -> Result<Vec<DtcCause>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, code, cause, created_at FROM dtc_causes WHERE code = ?1 ORDER BY id"
+    )?;
+    let rows = stmt.query_map(params![code], |row| {
+        Ok(DtcCause {
+            id: row.get(0)?,
+            code: row.get(1)?,
+            cause: row.get(2)?,
+            created_at: row.get(3)?,
         })
     })?;
-    for row in store_rows {
-        results.push(row?);
+    rows.collect()
+}
+
+/// Posts whose title, body or comments mention `code`, solved threads first
+pub fn get_posts_mentioning_dtc(conn: &Connection, code: &str, limit: i64) -> Result<Vec<Post>> {
+    let Some(term) = fts_quote(code) else {
+        return Ok(Vec::new());
+    };
+    let mut values = Vec::new();
+    let sql = format!(
+        r#"SELECT p.id, p.user_id, p.category_id, p.title, p.body, p.body_html, p.score, p.created_at, 
+           p.edited_at, p.removed, p.pinned, p.best_answer_id,
+           u.username, u.role, u.flair,
+           (SELECT avatar_path FROM user_profiles WHERE user_id = u.id) as avatar,
+           c.name, c.slug,
+           (SELECT COUNT(*) FROM comments WHERE post_id = p.id AND removed = 0) as comment_count,
+           p.vehicle_id
+           FROM posts_fts
+           JOIN posts p ON p.id = posts_fts.rowid
+           JOIN users u ON p.user_id = u.id
+           JOIN categories c ON p.category_id = c.id
+           WHERE posts_fts MATCH {} AND p.removed = 0
+           ORDER BY p.best_answer_id IS NOT NULL DESC, p.score DESC, p.created_at DESC
+           LIMIT {}"#,
+        bind(&mut values, term),
+        bind(&mut values, limit)
+    );
+    query_posts_with_vehicles(conn, &sql, values)
+}
+
+/// Queue a proposed change to a code's causes list for moderator review.
+/// `cause_id` names the cause to drop when `action` is "remove".
+pub fn create_dtc_suggestion(conn: &Connection, code: &str, user_id: i64, action: &str, cause: &str, cause_id: Option<i64>) -> Result<i64> {
+    conn.execute(
+        "INSERT INTO dtc_cause_suggestions (code, user_id, action, cause, cause_id) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![code, user_id, action, cause, cause_id],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+fn map_dtc_suggestion(row: &rusqlite::Row) -> rusqlite::Result<DtcCauseSuggestion> {
+    Ok(DtcCauseSuggestion {
+        id: row.get(0)?,
+        code: row.get(1)?,
+        user_id: row.get(2)?,
+        action: row.get(3)?,
+        cause: row.get(4)?,
+        cause_id: row.get(5)?,
+        status: row.get(6)?,
+        created_at: row.get(7)?,
+        username: row.get(8).ok(),
+    })
+}
+
+const DTC_SUGGESTION_COLUMNS: &str =
+    "s.id, s.code, s.user_id, s.action, s.cause, s.cause_id, s.status, s.created_at, u.username";
+
+pub fn get_pending_dtc_suggestions(conn: &Connection) -> Result<Vec<DtcCauseSuggestion>> {
+    let sql = format!(
+        "SELECT {} FROM dtc_cause_suggestions s JOIN users u ON s.user_id = u.id
+         WHERE s.status = 'pending' ORDER BY s.created_at, s.id",
+        DTC_SUGGESTION_COLUMNS
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map([], map_dtc_suggestion)?;
+    rows.collect()
+}
+
+pub fn count_pending_dtc_suggestions(conn: &Connection, code: &str) -> Result<i64> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM dtc_cause_suggestions WHERE code = ?1 AND status = 'pending'",
+        params![code],
+        |r| r.get(0),
+    )
+}
+
+/// Approve or reject a pending suggestion, applying it to the causes list on
+/// approval. Returns None if the suggestion doesn't exist or was already
+/// reviewed.
+pub fn review_dtc_suggestion(conn: &Connection, suggestion_id: i64, reviewer_id: i64, approve: bool) -> Result<Option<DtcCauseSuggestion>> {
+    let sql = format!(
+        "SELECT {} FROM dtc_cause_suggestions s JOIN users u ON s.user_id = u.id
+         WHERE s.id = ?1 AND s.status = 'pending'",
+        DTC_SUGGESTION_COLUMNS
+    );
+    let suggestion = match conn.query_row(&sql, params![suggestion_id], map_dtc_suggestion) {
+        Ok(s) => s,
+        Err(rusqlite::Error::QueryReturnedNoRows) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    if approve {
+        match (suggestion.action.as_str(), suggestion.cause_id) {
+            ("add", _) => {
+                conn.execute(
+                    "INSERT INTO dtc_causes (code, cause, created_by) VALUES (?1, ?2, ?3)",
+                    params![suggestion.code, suggestion.cause, suggestion.user_id],
+                )?;
+            }
+            ("remove", Some(cause_id)) => {
+                conn.execute("DELETE FROM dtc_causes WHERE id = ?1", params![cause_id])?;
+            }
+            _ => {}
+        }
     }
-    
-    Ok(results)
+
+    conn.execute(
+        "UPDATE dtc_cause_suggestions SET status = ?1, reviewed_by = ?2, reviewed_at = datetime('now') WHERE id = ?3",
+        params![if approve { "approved" } else { "rejected" }, reviewer_id, suggestion_id],
+    )?;
+    Ok(Some(suggestion))
 }
 
 // ============ Stats Functions ============
@@ -1744,24 +4116,170 @@ pub fn get_forum_stats(conn: &Connection) -> Result<ForumStats> {
     })
 }
 
+// ============ Maintenance Functions ============
+
+/// Record a finished backup run: `Ok` with where it went, or `Err` with why
+/// it failed
+pub fn record_backup(conn: &Connection, started_at: &str, result: std::result::Result<&BackupSnapshot, &str>) -> Result<i64> {
+    match result {
+        Ok(snapshot) => conn.execute(
+            "INSERT INTO backups (started_at, path, size_bytes, upload_files) VALUES (?1, ?2, ?3, ?4)",
+            params![started_at, snapshot.path, snapshot.size_bytes, snapshot.upload_files],
+        )?,
+        Err(error) => conn.execute(
+            "INSERT INTO backups (started_at, error) VALUES (?1, ?2)",
+            params![started_at, error],
+        )?,
+    };
+    Ok(conn.last_insert_rowid())
+}
+
+fn map_backup_record(row: &rusqlite::Row) -> rusqlite::Result<BackupRecord> {
+    Ok(BackupRecord {
+        id: row.get(0)?,
+        started_at: row.get(1)?,
+        finished_at: row.get(2)?,
+        path: row.get(3)?,
+        size_bytes: row.get(4)?,
+        upload_files: row.get(5)?,
+        error: row.get(6)?,
+    })
+}
+
+const BACKUP_COLUMNS: &str = "id, started_at, finished_at, path, size_bytes, upload_files, error";
+
+pub fn get_last_successful_backup(conn: &Connection) -> Result<Option<BackupRecord>> {
+    match conn.query_row(
+        &format!("SELECT {} FROM backups WHERE error IS NULL ORDER BY id DESC LIMIT 1", BACKUP_COLUMNS),
+        [],
+        map_backup_record,
+    ) {
+        Ok(record) => Ok(Some(record)),
+        Err(rusqlite::Error::QueryReturnedNoRows) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The most recent backup runs, newest first, failures included
+pub fn get_recent_backups(conn: &Connection, limit: i64) -> Result<Vec<BackupRecord>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM backups ORDER BY id DESC LIMIT ?1", BACKUP_COLUMNS))?;
+    let rows = stmt.query_map(params![limit], map_backup_record)?;
+    rows.collect()
+}
+
+/// Rebuild the database file to reclaim free pages, fold the WAL back into
+/// it and refresh the query planner's statistics
+pub fn vacuum(conn: &Connection) -> Result<()> {
+    // In WAL mode the rebuilt pages land in the WAL, so checkpoint after
+    conn.execute_batch("VACUUM;")?;
+    conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+    conn.execute_batch("PRAGMA optimize;")
+}
+
 // ============ Helper Functions ============
 
+/// Raw HTML in a body is passed through by the markdown parser; this strips
+/// scripts, event handlers and the like, keeping the classes we add ourselves
+static MARKDOWN_SANITIZER: LazyLock<ammonia::Builder<'static>> = LazyLock::new(|| {
+    let mut builder = ammonia::Builder::default();
+    builder
+        .add_allowed_classes("a", ["dtc-link"])
+        .add_allowed_classes("span", ["torque-conversion"]);
+    builder
+});
+
+/// Render a post, comment or step body to HTML. The output is sanitized, so
+/// it is safe to emit unescaped.
 fn render_markdown(text: &str) -> String {
-    use pulldown_cmark::{Parser, Options, html};
+    use pulldown_cmark::{Parser, Options, Event, Tag, TagEnd, html};
     
     let mut options = Options::empty();
     options.insert(Options::ENABLE_STRIKETHROUGH);
     
-    let parser = Parser::new_ext(text, options);
+    // Trouble codes in plain text link to their /dtc page and torque values
+    // get their conversion appended; text that is already a link, image alt
+    // text or a code block is left alone
+    let mut skip_depth = 0;
+    let mut events = Vec::new();
+    for event in Parser::new_ext(text, options) {
+        match event {
+            Event::Start(Tag::Link { .. } | Tag::Image { .. } | Tag::CodeBlock(_)) => {
+                skip_depth += 1;
+                events.push(event);
+            }
+            Event::End(TagEnd::Link | TagEnd::Image | TagEnd::CodeBlock) => {
+                skip_depth -= 1;
+                events.push(event);
+            }
+            Event::Text(text) if skip_depth == 0 => {
+                let mut replacements: Vec<(std::ops::Range<usize>, String)> = DTC_IN_TEXT
+                    .find_iter(&text)
+                    .map(|m| (m.range(), format!(r#"<a href="/dtc/{0}" class="dtc-link">{0}</a>"#, m.as_str())))
+                    .collect();
+                for (range, converted) in crate::torque::find_in_text(&text) {
+                    let html = format!(
+                        r#"{} <span class="torque-conversion">({})</span>"#,
+                        escape_html(&text[range.clone()]),
+                        converted
+                    );
+                    replacements.push((range, html));
+                }
+                replacements.sort_by_key(|(range, _)| range.start);
+
+                let mut last = 0;
+                for (range, html) in replacements {
+                    events.push(Event::Text(text[last..range.start].to_string().into()));
+                    events.push(Event::InlineHtml(html.into()));
+                    last = range.end;
+                }
+                events.push(Event::Text(text[last..].to_string().into()));
+            }
+            _ => events.push(event),
+        }
+    }
+    
     let mut html_output = String::new();
-    html::push_html(&mut html_output, parser);
+    html::push_html(&mut html_output, events.into_iter());
     
-    html_output
+    MARKDOWN_SANITIZER.clean(&html_output).to_string()
+}
+
+/// Re-render stored post, comment and procedure step HTML after a change
+/// to `render_markdown`
+fn rerender_markdown(conn: &Connection) -> Result<()> {
+    for table in ["posts", "comments", "procedure_steps"] {
+        let rows: Vec<(i64, String)> = {
+            let mut stmt = conn.prepare(&format!("SELECT id, body FROM {}", table))?;
+            let rows = stmt.query_map([], |r| Ok((r.get(0)?, r.get(1)?)))?;
+            rows.collect::<Result<_>>()?
+        };
+        let mut update = conn.prepare(&format!("UPDATE {} SET body_html = ?1 WHERE id = ?2", table))?;
+        for (id, body) in rows {
+            update.execute(params![render_markdown(&body), id])?;
+        }
+    }
+    Ok(())
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
 }
 
+static MENTION: LazyLock<regex::Regex> = LazyLock::new(|| regex::Regex::new(r"@(\w+)").unwrap());
+
 fn extract_mentions(text: &str) -> Vec<String> {
-    let re = regex::Regex::new(r"@(\w+)").unwrap();
-    re.captures_iter(text)
+    MENTION.captures_iter(text)
         .filter_map(|cap| cap.get(1).map(|m| m.as_str().to_string()))
         .collect()
 }
@@ -1778,6 +4296,40 @@ mod tests {
         assert!(output.contains("<em>italic</em>"));
     }
     
+    #[test]
+    fn test_render_markdown_links_trouble_codes() {
+        let output = render_markdown("Threw P0300 and U0100, not P0300X");
+        assert!(output.contains(r#"<a href="/dtc/P0300" class="dtc-link" rel="noopener noreferrer">P0300</a>"#));
+        assert!(output.contains(r#"<a href="/dtc/U0100" class="dtc-link" rel="noopener noreferrer">U0100</a>"#));
+        assert!(output.contains("P0300X"));
+
+        // Code spans, code blocks and existing links are left alone
+        let output = render_markdown("`P0171`\n\n[P0420](https://example.com)\n\n```\nP0128\n```");
+        assert!(!output.contains("dtc-link"));
+    }
+    
+    #[test]
+    fn test_render_markdown_converts_torque_values() {
+        let output = render_markdown("Lug nuts to 80 ft-lbs, caliper bolts 25 Nm.");
+        assert!(output.contains(r#"80 ft-lbs <span class="torque-conversion">(108.5 Nm)</span>"#));
+        assert!(output.contains(r#"25 Nm <span class="torque-conversion">(18.4 ft-lb)</span>"#));
+
+        // Values already given in both units and code spans are left alone
+        let output = render_markdown("Torque to 80 ft-lb (108 Nm), then `25 Nm`");
+        assert!(!output.contains("torque-conversion"));
+    }
+    
+    #[test]
+    fn test_render_markdown_strips_unsafe_html() {
+        let output = render_markdown("Hi <script>alert(1)</script><img src=\"x.png\" onerror=\"alert(2)\"> [x](javascript:alert(3)) <b class=\"dtc-link\">ok</b>");
+        assert!(!output.contains("<script"));
+        assert!(!output.contains("onerror"));
+        assert!(!output.contains("javascript:"));
+        assert!(output.contains(r#"<img src="x.png">"#));
+        // Only the classes we add ourselves survive, and only where we add them
+        assert!(output.contains("<b>ok</b>"));
+    }
+    
     #[test]
     fn test_extract_mentions() {
         let text = "Hello @john and @jane, what do you think?";
@@ -1786,4 +4338,31 @@ mod tests {
         assert!(mentions.contains(&"john".to_string()));
         assert!(mentions.contains(&"jane".to_string()));
     }
+    
+    #[test]
+    fn test_parse_search_query() {
+        let filters = parse_search_query(r#"misfire "coil pack" -plugs author:Dave tag:"check engine" category:engine is:solved P0300:"#);
+        assert_eq!(filters.terms, vec!["misfire", "P0300:"]);
+        assert_eq!(filters.phrases, vec!["coil pack"]);
+        assert_eq!(filters.excluded, vec!["plugs"]);
+        assert_eq!(filters.author.as_deref(), Some("Dave"));
+        assert_eq!(filters.tags, vec!["check engine"]);
+        assert_eq!(filters.category.as_deref(), Some("engine"));
+        assert!(filters.solved);
+        
+        assert_eq!(
+            fts_match_expr(&filters).as_deref(),
+            Some(r#""coil pack" "misfire" "P0300:"*"#)
+        );
+        assert_eq!(fts_exclude_expr(&filters).as_deref(), Some(r#""plugs""#));
+    }
+    
+    #[test]
+    fn test_parse_search_query_plain_text() {
+        let filters = parse_search_query(r#"  is:open -"  " "unterminated"#);
+        assert_eq!(filters.terms, vec!["is:open"]);
+        assert_eq!(filters.phrases, vec!["unterminated"]);
+        assert!(!filters.has_post_filters());
+        assert_eq!(fts_exclude_expr(&filters), None);
+    }
 }
@@ -0,0 +1,219 @@
+//! Errors from the HTML side of the site.
+//!
+//! Handlers return [`AppError`] with `?`. Its response carries the right
+//! status, and the [`render_errors`] middleware fills in the body: the
+//! `error.html` page for full page loads (boosted links and forms
+//! included), or a toast for other htmx requests. The toast response sets
+//! `HX-Reswap: none` so the request's target is left alone; `base.html`
+//! tells htmx to process error responses at all.
+//!
+//! [`AppError::Unauthorized`] is the exception: it sends the browser to the
+//! login page, with a `303 See Other` for plain requests and `HX-Redirect`
+//! for htmx ones.
+
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+use axum_extra::extract::CookieJar;
+use std::sync::Arc;
+use tera::{Context, Tera};
+
+use crate::auth::ensure_session;
+use crate::db::{self, Db, DbError};
+use crate::telemetry;
+
+/// What most page and fragment handlers return
+pub type HtmlResult = Result<Html<String>, AppError>;
+
+#[derive(Debug)]
+pub enum AppError {
+    /// Not signed in
+    Unauthorized,
+    /// Signed in, but not allowed to do this
+    Forbidden(String),
+    NotFound(String),
+    /// The change clashes with what's already there
+    Conflict(String),
+    /// The submitted values weren't acceptable
+    Validation(String),
+    /// Something failed on our side; the details are logged, not shown
+    Internal,
+}
+
+impl AppError {
+    pub fn forbidden(message: impl Into<String>) -> Self {
+        AppError::Forbidden(message.into())
+    }
+
+    pub fn not_found(message: impl Into<String>) -> Self {
+        AppError::NotFound(message.into())
+    }
+
+    pub fn conflict(message: impl Into<String>) -> Self {
+        AppError::Conflict(message.into())
+    }
+
+    pub fn validation(message: impl Into<String>) -> Self {
+        AppError::Validation(message.into())
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            AppError::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) => StatusCode::CONFLICT,
+            AppError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            AppError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// The message shown to the user
+    pub fn message(&self) -> &str {
+        match self {
+            AppError::Unauthorized => "Please log in to continue",
+            AppError::Forbidden(m) | AppError::NotFound(m) | AppError::Conflict(m) | AppError::Validation(m) => m,
+            AppError::Internal => "Something went wrong on our end. Please try again.",
+        }
+    }
+}
+
+impl std::fmt::Display for AppError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.status().as_u16(), self.message())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl From<DbError> for AppError {
+    fn from(e: DbError) -> Self {
+        // A lookup by id from the URL that found nothing
+        if let DbError::Sqlite(rusqlite::Error::QueryReturnedNoRows) = e {
+            return AppError::not_found("Not found");
+        }
+        tracing::error!(error = %e, "request failed");
+        AppError::Internal
+    }
+}
+
+impl From<tera::Error> for AppError {
+    fn from(e: tera::Error) -> Self {
+        // The cause, e.g. a missing variable, is in the source chain
+        let mut message = e.to_string();
+        let mut source = std::error::Error::source(&e);
+        while let Some(cause) = source {
+            message = format!("{}: {}", message, cause);
+            source = cause.source();
+        }
+        tracing::error!(error = %message, "template error");
+        AppError::Internal
+    }
+}
+
+impl From<argon2::password_hash::Error> for AppError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        tracing::error!(error = %e, "failed to hash password");
+        AppError::Internal
+    }
+}
+
+/// Left on an error response for [`render_errors`] to give it a body
+#[derive(Clone)]
+struct ErrorPage {
+    message: String,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let message = self.message().to_string();
+        // Plain text until `render_errors` replaces it
+        let mut response = (self.status(), message.clone()).into_response();
+        response.extensions_mut().insert(ErrorPage { message });
+        response
+    }
+}
+
+/// Whether a request came from htmx and will swap the response into part of
+/// the page, rather than replacing the whole page
+pub fn is_fragment_request(headers: &HeaderMap) -> bool {
+    headers.contains_key("hx-request") && !headers.contains_key("hx-boosted")
+}
+
+/// Send the browser to `location`, typically after a form has done its work:
+/// a `303 See Other` for plain requests, and `HX-Redirect` for htmx ones so
+/// the whole page is loaded rather than swapped into the target
+pub fn redirect(headers: &HeaderMap, location: &str) -> Response {
+    if headers.contains_key("hx-request") {
+        [("HX-Redirect", location)].into_response()
+    } else {
+        (StatusCode::SEE_OTHER, [(header::LOCATION, location)]).into_response()
+    }
+}
+
+/// An out-of-band toast for an htmx response. `kind` is a CSS class such
+/// as `success` or `error`; `message` is escaped.
+pub fn toast(kind: &str, message: &str) -> String {
+    format!(
+        r#"<div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast {}">{}</div>
+        </div>"#,
+        kind,
+        tera::escape_html(message)
+    )
+}
+
+/// Middleware for the whole router: gives [`AppError`] responses their body
+pub async fn render_errors(
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    jar: CookieJar,
+    request: Request,
+    next: Next,
+) -> Response {
+    let fragment = is_fragment_request(request.headers());
+    let htmx = request.headers().contains_key("hx-request");
+    let mut response = next.run(request).await;
+    let Some(ErrorPage { message }) = response.extensions_mut().remove::<ErrorPage>() else {
+        return response;
+    };
+    let status = response.status();
+
+    if status == StatusCode::UNAUTHORIZED {
+        // htmx won't follow a 303 with the whole page, so it gets told to
+        return if htmx {
+            (status, [("HX-Redirect", "/login")]).into_response()
+        } else {
+            (StatusCode::SEE_OTHER, [(header::LOCATION, "/login")]).into_response()
+        };
+    }
+
+    if fragment {
+        let mut ctx = Context::new();
+        ctx.insert("message", &message);
+        let html = telemetry::render(&tera, "partials/error_toast.html", &ctx)
+            .unwrap_or_else(|_| tera::escape_html(&message));
+        let mut response = (status, Html(html)).into_response();
+        response.headers_mut().insert("HX-Reswap", HeaderValue::from_static("none"));
+        return response;
+    }
+
+    let mut ctx = Context::new();
+    if let Some((user, _)) = ensure_session(jar, &db).await {
+        let user_id = user.id;
+        let unread_count = db.read(move |conn| db::get_unread_notification_count(conn, user_id)).await.unwrap_or(0);
+        ctx.insert("user", &user);
+        ctx.insert("unread_notifications", &unread_count);
+    }
+    ctx.insert("status", &status.as_u16());
+    ctx.insert("error", &message);
+    match telemetry::render(&tera, "error.html", &ctx) {
+        Ok(html) => (status, Html(html)).into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, "failed to render error page");
+            (status, message).into_response()
+        }
+    }
+}
@@ -1,7 +1,20 @@
+pub mod api;
 pub mod auth;
+pub mod backup;
+pub mod cli;
+pub mod config;
+pub mod csrf;
 pub mod db;
+pub mod error;
+pub mod mail;
 pub mod models;
+pub mod rate_limit;
 pub mod routes;
+pub mod shutdown;
+pub mod telemetry;
+pub mod torque;
+pub mod totp;
+pub mod vin;
 
 // Re-export commonly used items
 pub use db::Db;
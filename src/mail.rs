@@ -0,0 +1,216 @@
+//! Outgoing email.
+//!
+//! Handlers build an [`Email`] and hand it to [`Mail::send`], which runs the
+//! configured [`Mailer`] on tokio's blocking pool. Production uses
+//! [`SmtpMailer`]; local setups and tests use [`FileMailer`], which writes
+//! messages to stdout or appends them to a file instead of sending them.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+
+use crate::config::MailConfig;
+
+pub const DEFAULT_FROM: &str = "Wrench Forum <noreply@localhost>";
+pub const DEFAULT_BASE_URL: &str = "http://localhost:3000";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    /// Plain text body
+    pub body: String,
+}
+
+#[derive(Debug)]
+pub enum MailError {
+    /// A from or to address that doesn't parse
+    Address(String),
+    Smtp(lettre::transport::smtp::Error),
+    Io(std::io::Error),
+    /// The blocking task sending the message panicked or was cancelled
+    TaskFailed,
+}
+
+impl std::fmt::Display for MailError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MailError::Address(a) => write!(f, "invalid email address: {}", a),
+            MailError::Smtp(e) => write!(f, "SMTP error: {}", e),
+            MailError::Io(e) => write!(f, "mail file error: {}", e),
+            MailError::TaskFailed => write!(f, "mail task failed"),
+        }
+    }
+}
+
+impl std::error::Error for MailError {}
+
+impl From<lettre::transport::smtp::Error> for MailError {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        MailError::Smtp(e)
+    }
+}
+
+impl From<std::io::Error> for MailError {
+    fn from(e: std::io::Error) -> Self {
+        MailError::Io(e)
+    }
+}
+
+/// Delivers email. Implementations may block; [`Mail::send`] keeps them off
+/// the async runtime.
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> Result<(), MailError>;
+}
+
+/// Sends through an SMTP relay, using STARTTLS unless `tls` is off (for
+/// local catch-all servers like MailHog)
+pub struct SmtpMailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(host: &str, port: u16, credentials: Option<(String, String)>, from: &str, tls: bool) -> Result<Self, MailError> {
+        let from = from.parse().map_err(|_| MailError::Address(from.to_string()))?;
+        let mut builder = if tls {
+            SmtpTransport::starttls_relay(host)?
+        } else {
+            SmtpTransport::builder_dangerous(host)
+        };
+        builder = builder.port(port);
+        if let Some((username, password)) = credentials {
+            builder = builder.credentials(Credentials::new(username, password));
+        }
+        Ok(SmtpMailer { transport: builder.build(), from })
+    }
+}
+
+impl Mailer for SmtpMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        let to: Mailbox = email.to.parse().map_err(|_| MailError::Address(email.to.clone()))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(&email.subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(email.body.clone())
+            .map_err(|_| MailError::Address(email.to.clone()))?;
+        self.transport.send(&message)?;
+        Ok(())
+    }
+}
+
+/// Writes each message as plain text to stdout or appends it to a file, for
+/// development and tests
+pub struct FileMailer {
+    path: Option<PathBuf>,
+    // Keeps messages from concurrent requests from interleaving
+    lock: Mutex<()>,
+}
+
+impl FileMailer {
+    pub fn stdout() -> Self {
+        FileMailer { path: None, lock: Mutex::new(()) }
+    }
+
+    pub fn file(path: impl Into<PathBuf>) -> Self {
+        FileMailer { path: Some(path.into()), lock: Mutex::new(()) }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        let text = format!(
+            "To: {}\nSubject: {}\n\n{}\n----------------------------------------\n",
+            email.to, email.subject, email.body
+        );
+        let _guard = self.lock.lock().unwrap_or_else(std::sync::PoisonError::into_inner);
+        match &self.path {
+            Some(path) => OpenOptions::new().create(true).append(true).open(path)?.write_all(text.as_bytes())?,
+            None => std::io::stdout().write_all(text.as_bytes())?,
+        }
+        Ok(())
+    }
+}
+
+/// The mailer plus what handlers need to write links into messages. Shared
+/// with handlers as an axum `Extension`.
+#[derive(Clone)]
+pub struct Mail {
+    pub mailer: Arc<dyn Mailer>,
+    /// Public URL of the site without a trailing slash, e.g. `https://wrench.example`
+    pub base_url: String,
+}
+
+impl Mail {
+    pub fn new(mailer: Arc<dyn Mailer>, base_url: &str) -> Self {
+        Mail { mailer, base_url: base_url.trim_end_matches('/').to_string() }
+    }
+
+    /// The mailer the `[mail]` config asks for: SMTP if `smtp_host` is set,
+    /// otherwise `file` or stdout
+    pub fn from_config(config: &MailConfig) -> Result<Self, MailError> {
+        let mailer: Arc<dyn Mailer> = if let Some(host) = &config.smtp_host {
+            let credentials = config.smtp_username.clone().map(|u| (u, config.smtp_password.clone().unwrap_or_default()));
+            Arc::new(SmtpMailer::new(host, config.smtp_port, credentials, &config.from, config.smtp_tls)?)
+        } else if let Some(path) = &config.file {
+            Arc::new(FileMailer::file(path))
+        } else {
+            Arc::new(FileMailer::stdout())
+        };
+
+        Ok(Mail::new(mailer, &config.base_url))
+    }
+
+    /// Absolute URL for a site path like `/reset-password/abc`
+    pub fn link(&self, path: &str) -> String {
+        format!("{}{}", self.base_url, path)
+    }
+
+    pub async fn send(&self, email: Email) -> Result<(), MailError> {
+        let mailer = self.mailer.clone();
+        tokio::task::spawn_blocking(move || mailer.send(&email))
+            .await
+            .map_err(|_| MailError::TaskFailed)?
+    }
+}
+
+/// The message carrying a password reset link
+pub fn password_reset_email(to: &str, username: &str, link: &str, valid_minutes: i64) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: "Reset your Wrench Forum password".to_string(),
+        body: format!(
+            "Hi {},\n\n\
+             Someone asked to reset the password for your Wrench Forum account. \
+             To choose a new one, open this link within {} minutes:\n\n\
+             {}\n\n\
+             The link works once. If you didn't ask for this, ignore this email; \
+             your password hasn't changed.\n",
+            username, valid_minutes, link
+        ),
+    }
+}
+
+/// The message asking a new member to confirm their address
+pub fn email_confirmation_email(to: &str, username: &str, link: &str, valid_hours: i64) -> Email {
+    Email {
+        to: to.to_string(),
+        subject: "Confirm your Wrench Forum email".to_string(),
+        body: format!(
+            "Welcome to Wrench Forum, {}!\n\n\
+             Please confirm this is your email address by opening this link \
+             within {} hours:\n\n\
+             {}\n\n\
+             Until you do, you can read the forum but not comment or report \
+             posts. If you didn't create an account, ignore this email.\n",
+            username, valid_hours, link
+        ),
+    }
+}
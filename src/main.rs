@@ -1,37 +1,223 @@
 use axum::{
-    routing::{get, post, delete},
-    Router,
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{get, post},
+    Extension, Router,
 };
+use clap::{Parser, Subcommand};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::future::IntoFuture;
 use std::sync::Arc;
+use std::time::Duration;
 use tera::Tera;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
 use tower_http::services::ServeDir;
 
-mod auth;
-mod db;
-mod models;
-mod routes;
+use wrench_forum::config::{Config, Overrides, UPLOADS_URL_PREFIX};
+use wrench_forum::shutdown::{self, Shutdown};
+use wrench_forum::{auth, backup, cli, csrf, db, error, mail::Mail, routes, telemetry, torque};
+
+#[derive(Parser)]
+#[command(name = "wrench-forum", version, about = "The Wrench Forum server")]
+struct Cli {
+    #[command(flatten)]
+    overrides: Overrides,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the web server (the default)
+    Serve,
+    /// Apply pending schema migrations
+    Migrate {
+        /// List migrations and whether each has been applied
+        #[arg(long, conflicts_with = "to")]
+        status: bool,
+        /// Stop after this schema version
+        #[arg(long, value_name = "VERSION")]
+        to: Option<i64>,
+    },
+    /// Check the configuration and print the settings in effect
+    Config,
+    /// Create a confirmed admin account
+    CreateAdmin {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        username: String,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Give a user a built-in or custom role
+    SetRole {
+        username: String,
+        role: String,
+    },
+    /// Set a new password, lift any lockout and sign the user out everywhere
+    ResetPassword {
+        username: String,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Ban a user and sign them out, or lift a ban
+    Ban {
+        username: String,
+        /// Lift the ban instead
+        #[arg(long)]
+        unban: bool,
+    },
+    /// Fill an empty database with sample data
+    Seed {
+        /// Users, posts, comments, votes, stores and torque specs that look
+        /// like a forum in use
+        #[arg(long, required = true)]
+        demo: bool,
+        #[command(flatten)]
+        password: PasswordArgs,
+    },
+    /// Checkpoint the WAL, compact the database file and refresh statistics
+    Vacuum,
+    /// Print forum and database statistics
+    Stats,
+    /// Take a snapshot of the database and uploads now, safe while the
+    /// server is running
+    Backup {
+        /// Write the snapshot to this new directory instead of the backup
+        /// directory, leaving it out of rotation
+        #[arg(long, value_name = "DIR")]
+        output: Option<PathBuf>,
+    },
+    /// Replace the database with a snapshot and restore its uploads. Stop
+    /// the server first.
+    Restore {
+        /// The snapshot directory, as written by `backup`
+        snapshot: PathBuf,
+    },
+}
+
+#[derive(clap::Args)]
+struct PasswordArgs {
+    /// Read the password from the first line of stdin instead of generating
+    /// one
+    #[arg(long)]
+    password_stdin: bool,
+}
+
+impl PasswordArgs {
+    /// The password to use, and whether it was generated and so needs
+    /// showing to the operator
+    fn read(&self) -> (String, bool) {
+        if !self.password_stdin {
+            return (cli::generate_password(), true);
+        }
+        let mut line = String::new();
+        if let Err(e) = std::io::stdin().read_line(&mut line) {
+            eprintln!("Failed to read password: {}", e);
+            std::process::exit(1);
+        }
+        (line.trim_end_matches(['\r', '\n']).to_string(), false)
+    }
+}
 
 #[tokio::main]
 async fn main() {
+    let cli = Cli::parse();
+    let config = Config::load(&cli.overrides).unwrap_or_else(|e| {
+        eprintln!("Configuration error: {}", e);
+        std::process::exit(1);
+    });
+    telemetry::init_logging(&config.logging);
+    
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(config).await,
+        Command::Migrate { status, to } => run_migrate_command(&config, status, to),
+        Command::Config => print!("{}", toml::to_string(&config).expect("config serializes")),
+        Command::Restore { snapshot } => run_restore_command(&config, &snapshot),
+        command => run_admin_command(&config, command),
+    }
+}
+
+async fn serve(config: Config) {
     // Create uploads directory if it doesn't exist
-    std::fs::create_dir_all("static/uploads").ok();
+    if let Err(e) = std::fs::create_dir_all(&config.uploads.dir) {
+        tracing::error!(dir = %config.uploads.dir, error = %e, "failed to create uploads directory");
+        std::process::exit(1);
+    }
     
     // Initialize database
-    let db = db::init_db().expect("Failed to initialize database");
+    let db = db::Db::open(&config.database.path, &config.database.options()).unwrap_or_else(|e| {
+        tracing::error!(path = %config.database.path, error = %e, "failed to open database");
+        std::process::exit(1);
+    });
     
     // Initialize templates
-    let tera = match Tera::new("templates/**/*.html") {
-        Ok(t) => Arc::new(t),
+    let tera = match Tera::new(&config.server.templates) {
+        Ok(mut t) => {
+            t.register_filter("torque_alternate", torque::tera_filter);
+            Arc::new(t)
+        }
         Err(e) => {
-            eprintln!("Template parsing error: {}", e);
+            tracing::error!(error = %e, "failed to parse templates");
             std::process::exit(1);
         }
     };
     
+    let mail = Mail::from_config(&config.mail).unwrap_or_else(|e| {
+        tracing::error!(error = %e, "invalid mail configuration");
+        std::process::exit(1);
+    });
+    
+    let limits = config.rate_limits();
+    
+    let config = Arc::new(config);
+    let shutdown = Shutdown::new();
+    let backups = tokio::spawn(backup::run_schedule(db.clone(), config.clone(), shutdown.clone()));
     let state = (db, tera);
     
+    // Admin and moderation tools, behind the two-factor policy
+    let staff = Router::new()
+        // ============ Admin ============
+        .route("/admin", get(routes::admin::admin_panel))
+        .route("/admin/verify/{id}/approve", post(routes::admin::approve_verification))
+        .route("/admin/verify/{id}/deny", post(routes::admin::deny_verification))
+        .route("/admin/user/{id}/role", post(routes::admin::update_user_role))
+        .route("/admin/user/{id}/flair", post(routes::admin::update_user_flair))
+        .route("/admin/announcement", post(routes::admin::create_announcement))
+        .route("/admin/announcement/{id}/deactivate", post(routes::admin::deactivate_announcement))
+        .route("/admin/stats", get(routes::admin::forum_stats))
+        .route("/admin/activity", get(routes::admin::activity_logs))
+        .route("/admin/two-factor", post(routes::admin::update_two_factor_policy))
+        .route("/admin/user/{id}/unlock", post(routes::admin::unlock_account))
+        .route("/admin/backup", post(routes::admin::backup_now))
+        .route("/admin/roles", post(routes::admin::create_role))
+        .route("/admin/roles/{name}", post(routes::admin::update_role_permissions))
+        .route("/admin/roles/{name}/delete", post(routes::admin::delete_role))
+        .route("/admin/category-moderators", post(routes::admin::add_category_moderator))
+        .route("/admin/category-moderators/{user_id}/{category_id}/remove", post(routes::admin::remove_category_moderator))
+        
+        // ============ Moderation ============
+        .route("/mod", get(routes::moderation::mod_queue))
+        .route("/mod/post/{id}/remove", post(routes::moderation::remove_post))
+        .route("/mod/post/{id}/restore", post(routes::moderation::restore_post))
+        .route("/mod/post/{id}/pin", post(routes::moderation::pin_post))
+        .route("/mod/comment/{id}/remove", post(routes::moderation::remove_comment))
+        .route("/mod/user/{id}/ban", post(routes::moderation::ban_user))
+        .route("/mod/user/{id}/unban", post(routes::moderation::unban_user))
+        .route("/mod/report/{id}/resolve", post(routes::moderation::resolve_report))
+        .route("/mod/dtc/{id}/approve", post(routes::moderation::approve_dtc_suggestion))
+        .route("/mod/dtc/{id}/reject", post(routes::moderation::reject_dtc_suggestion))
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_two_factor));
+    
     // Build router
     let app = Router::new()
+        // ============ Health ============
+        .route("/healthz", get(routes::health::healthz))
+        .route("/readyz", get(routes::health::readyz))
+        .route("/metrics", get(routes::metrics::metrics))
+        
         // ============ Home ============
         .route("/", get(routes::home::index))
         
@@ -40,7 +226,15 @@ async fn main() {
         .route("/register", post(routes::auth::register_submit))
         .route("/login", get(routes::auth::login_page))
         .route("/login", post(routes::auth::login_submit))
-        .route("/logout", get(routes::auth::logout))
+        .route("/logout", post(routes::auth::logout))
+        .route("/forgot-password", get(routes::auth::forgot_password_page))
+        .route("/forgot-password", post(routes::auth::forgot_password_submit))
+        .route("/reset-password/{token}", get(routes::auth::reset_password_page))
+        .route("/reset-password/{token}", post(routes::auth::reset_password_submit))
+        .route("/confirm-email/resend", post(routes::auth::resend_confirmation))
+        .route("/confirm-email/{token}", get(routes::auth::confirm_email))
+        .route("/login/two-factor", get(routes::auth::login_two_factor_page))
+        .route("/login/two-factor", post(routes::auth::login_two_factor_submit))
         
         // ============ Forum ============
         .route("/category/{slug}", get(routes::forum::category_posts))
@@ -58,12 +252,28 @@ async fn main() {
         .route("/comment/{id}/delete", post(routes::forum::delete_comment))
         .route("/post/{id}/report", post(routes::forum::report_post))
         .route("/comment/{id}/report", post(routes::forum::report_comment))
+        .route("/procedure/new", get(routes::procedures::new_procedure_page))
+        .route("/procedure/new", post(routes::procedures::create_procedure))
+        .route("/post/{id}/procedure/edit", get(routes::procedures::edit_procedure_page))
+        .route("/post/{id}/procedure/edit", post(routes::procedures::edit_procedure_submit))
+        .route("/post/{id}/print", get(routes::procedures::print_procedure))
         
         // ============ Search ============
         .route("/search", get(routes::search::search_page))
         .route("/api/search", get(routes::search::search_api))
         .route("/api/search/suggestions", get(routes::search::search_suggestions))
         
+        // ============ Trouble Codes ============
+        .route("/dtc", get(routes::dtc::dtc_index))
+        .route("/dtc/{code}", get(routes::dtc::dtc_page))
+        .route("/dtc/{code}/suggest", post(routes::dtc::suggest_cause))
+        
+        // ============ Torque Specs ============
+        .route("/torque", get(routes::torque::torque_page))
+        .route("/torque", post(routes::torque::submit_torque_spec))
+        .route("/torque/{id}/vote", post(routes::torque::vote_torque_spec))
+        .route("/api/torque", get(routes::torque::torque_api))
+        
         // ============ Stores ============
         .route("/stores", get(routes::stores::list_stores))
         .route("/stores/submit", post(routes::stores::submit_store))
@@ -76,6 +286,20 @@ async fn main() {
         .route("/user/{username}", get(routes::profile::view_profile))
         .route("/user/{username}/posts", get(routes::profile::user_posts))
         .route("/user/{username}/comments", get(routes::profile::user_comments))
+        .route("/garage", post(routes::garage::add_vehicle))
+        .route("/garage/{id}/delete", post(routes::garage::remove_vehicle))
+        .route("/api/vin/decode", post(routes::vin::decode_vin))
+        .route("/profile/two-factor", get(routes::two_factor::two_factor_page))
+        .route("/profile/two-factor/enable", post(routes::two_factor::enable_two_factor))
+        .route("/profile/two-factor/disable", post(routes::two_factor::disable_two_factor))
+        .route("/profile/two-factor/recovery-codes", post(routes::two_factor::regenerate_recovery_codes))
+        .route("/settings/sessions", get(routes::sessions::sessions_page))
+        .route("/settings/sessions/revoke-others", post(routes::sessions::revoke_other_sessions))
+        .route("/settings/sessions/{id}/name", post(routes::sessions::rename_session))
+        .route("/settings/sessions/{id}/revoke", post(routes::sessions::revoke_session))
+        .route("/settings/tokens", get(routes::tokens::tokens_page))
+        .route("/settings/tokens", post(routes::tokens::create_token))
+        .route("/settings/tokens/{id}/revoke", post(routes::tokens::revoke_token))
         
         // ============ Bookmarks ============
         .route("/bookmarks", get(routes::bookmarks::list_bookmarks))
@@ -91,38 +315,260 @@ async fn main() {
         .route("/verification", get(routes::verification::verification_page))
         .route("/verification", post(routes::verification::submit_verification))
         
-        // ============ Admin ============
-        .route("/admin", get(routes::admin::admin_panel))
-        .route("/admin/verify/{id}/approve", post(routes::admin::approve_verification))
-        .route("/admin/verify/{id}/deny", post(routes::admin::deny_verification))
-        .route("/admin/user/{id}/role", post(routes::admin::update_user_role))
-        .route("/admin/user/{id}/flair", post(routes::admin::update_user_flair))
-        .route("/admin/announcement", post(routes::admin::create_announcement))
-        .route("/admin/announcement/{id}/deactivate", post(routes::admin::deactivate_announcement))
-        .route("/admin/stats", get(routes::admin::forum_stats))
-        .route("/admin/activity", get(routes::admin::activity_logs))
+        // ============ Uploads ============
+        .route("/upload", post(routes::uploads::upload_file).layer(DefaultBodyLimit::max(config.uploads.body_limit())))
+        .route("/upload/avatar", post(routes::uploads::upload_avatar).layer(DefaultBodyLimit::max(config.uploads.body_limit())))
         
-        // ============ Moderation ============
-        .route("/mod", get(routes::moderation::mod_queue))
-        .route("/mod/post/{id}/remove", post(routes::moderation::remove_post))
-        .route("/mod/post/{id}/restore", post(routes::moderation::restore_post))
-        .route("/mod/post/{id}/pin", post(routes::moderation::pin_post))
-        .route("/mod/comment/{id}/remove", post(routes::moderation::remove_comment))
-        .route("/mod/user/{id}/ban", post(routes::moderation::ban_user))
-        .route("/mod/user/{id}/unban", post(routes::moderation::unban_user))
-        .route("/mod/report/{id}/resolve", post(routes::moderation::resolve_report))
+        .merge(staff)
         
-        // ============ Uploads ============
-        .route("/upload", post(routes::uploads::upload_file))
-        .route("/upload/avatar", post(routes::uploads::upload_avatar))
+        // ============ JSON API ============
+        .nest("/api/v1", routes::api::router())
+        .route("/api/openapi.json", get(routes::api::openapi_json))
         
         // ============ Static Files ============
-        .nest_service("/static", ServeDir::new("static"))
+        .nest_service(UPLOADS_URL_PREFIX, ServeDir::new(&config.uploads.dir))
+        .nest_service("/static", ServeDir::new(&config.server.static_dir))
         
+        .layer(middleware::from_fn_with_state(state.clone(), auth::track_session))
+        .layer(middleware::from_fn(csrf::protect))
+        .layer(Extension(mail))
+        .layer(Extension(limits))
+        .layer(Extension(config.clone()))
+        .layer(Extension(shutdown.clone()))
+        // Outside the layers above, so errors from them get a page too
+        .layer(middleware::from_fn_with_state(state.clone(), error::render_errors))
+        // Outermost, so the timing and status cover everything
+        .layer(middleware::from_fn(telemetry::track_requests))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
         .with_state(state);
     
-    println!("🔧 Wrench Forum running at http://localhost:3000");
+    let listener = tokio::net::TcpListener::bind(config.server.bind).await.unwrap_or_else(|e| {
+        tracing::error!(bind = %config.server.bind, error = %e, "failed to listen");
+        std::process::exit(1);
+    });
+    tracing::info!("🔧 Wrench Forum running at http://{}", config.server.bind);
+    
+    tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown::signal().await;
+            tracing::info!("shutting down: finishing in-flight requests");
+            shutdown.trigger();
+        }
+    });
+    
+    let mut server = tokio::spawn(
+        axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+            .with_graceful_shutdown({
+                let shutdown = shutdown.clone();
+                async move { shutdown.wait().await }
+            })
+            .into_future(),
+    );
+    // Once shutdown starts the server returns by itself and can win the race
+    let finished = tokio::select! {
+        result = &mut server => Some(result),
+        _ = shutdown.wait() => None,
+    };
+    if let Some(result) = &finished {
+        if !shutdown.is_triggered() {
+            tracing::error!(?result, "server stopped unexpectedly");
+            std::process::exit(1);
+        }
+    }
+    
+    // New connections are refused from here; wait for the open ones, for
+    // emails they left sending and for a backup that's under way, but not
+    // forever
+    let timeout = Duration::from_secs(config.server.shutdown_timeout_seconds);
+    let drained = tokio::time::timeout(timeout, async {
+        if finished.is_none() {
+            let _ = server.await;
+        }
+        shutdown.tasks_finished().await;
+        let _ = backups.await;
+    });
+    if drained.await.is_err() {
+        tracing::warn!(timeout_seconds = timeout.as_secs(), "gave up waiting; exiting with work still in progress");
+        std::process::exit(1);
+    }
+    tracing::info!("stopped");
+}
+
+/// The operator subcommands, run against the configured database with
+/// migrations applied
+fn run_admin_command(config: &Config, command: Command) {
+    let db = db::Db::open(&config.database.path, &config.database.options()).unwrap_or_else(|e| {
+        eprintln!("Failed to open database {}: {}", config.database.path, e);
+        std::process::exit(1);
+    });
+    let conn = db.write_conn();
     
-    let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    axum::serve(listener, app).await.unwrap();
+    let result = match command {
+        Command::CreateAdmin { email, username, password } => {
+            let (password, generated) = password.read();
+            cli::create_admin(&conn, &email, &username, &password).map(|_| {
+                println!("Created admin {}", username);
+                if generated {
+                    println!("Password: {}", password);
+                }
+            })
+        }
+        Command::SetRole { username, role } => {
+            cli::set_role(&conn, &username, &role).map(|_| println!("{} is now {}", username, role))
+        }
+        Command::ResetPassword { username, password } => {
+            let (password, generated) = password.read();
+            cli::reset_password(&conn, &username, &password).map(|_| {
+                println!("Password reset for {}; their sessions have been signed out", username);
+                if generated {
+                    println!("New password: {}", password);
+                }
+            })
+        }
+        Command::Ban { username, unban } => cli::set_banned(&conn, &username, !unban).map(|_| {
+            println!("{} {}", if unban { "Unbanned" } else { "Banned" }, username);
+        }),
+        Command::Seed { password, .. } => {
+            let (password, generated) = password.read();
+            cli::seed_demo(&conn, &password).map(|summary| {
+                println!(
+                    "Seeded {} posts, {} comments, {} stores and {} torque specs",
+                    summary.posts, summary.comments, summary.stores, summary.torque_specs
+                );
+                println!("Accounts:");
+                for (username, role) in summary.users {
+                    println!("  {:<20} {}", username, role);
+                }
+                if generated {
+                    println!("Password for all of them: {}", password);
+                }
+            })
+        }
+        Command::Vacuum => {
+            let before = database_size(&config.database.path);
+            db::vacuum(&conn).map_err(cli::CliError::from).map(|_| {
+                let after = database_size(&config.database.path);
+                println!("Vacuumed {}: {} KB -> {} KB", config.database.path, before / 1024, after / 1024);
+            })
+        }
+        Command::Stats => print_stats(&conn, config),
+        Command::Backup { output } => cli::backup(&conn, config, output.as_deref()).map(|snapshot| {
+            println!(
+                "Backed up schema version {} and {} uploads to {} ({} KB)",
+                snapshot.schema_version, snapshot.upload_files, snapshot.path, snapshot.size_bytes / 1024
+            );
+        }),
+        Command::Serve | Command::Migrate { .. } | Command::Config | Command::Restore { .. } => unreachable!("handled in main"),
+    };
+    
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Size of the database file plus its WAL, in bytes
+fn database_size(path: &str) -> u64 {
+    [path.to_string(), format!("{}-wal", path)]
+        .iter()
+        .filter_map(|p| std::fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn print_stats(conn: &rusqlite::Connection, config: &Config) -> Result<(), cli::CliError> {
+    let stats = db::get_forum_stats(conn)?;
+    let roles = db::get_roles(conn)?;
+    
+    println!("Database     {} ({} KB, schema version {})", config.database.path, database_size(&config.database.path) / 1024, db::get_schema_version(conn)?);
+    println!("Users        {} ({} verified mechanics)", stats.total_users, stats.verified_users);
+    println!("Posts        {} ({} today)", stats.total_posts, stats.posts_today);
+    println!("Comments     {}", stats.total_comments);
+    println!("Stores       {}", stats.total_stores);
+    println!("Roles:");
+    for role in roles {
+        println!("  {:<20} {}", role.name, role.user_count);
+    }
+    Ok(())
+}
+
+/// `wrench-forum restore SNAPSHOT`
+fn run_restore_command(config: &Config, snapshot: &std::path::Path) {
+    match backup::restore(config, snapshot) {
+        Ok(summary) => {
+            if let Some(previous) = summary.previous {
+                println!("Saved the replaced database to {}", previous.display());
+            }
+            println!("Restored schema version {} from {}", summary.schema_version, snapshot.display());
+            for version in summary.migrations_applied {
+                println!("Applied migration {}", version);
+            }
+            println!("Restored {} missing uploads", summary.upload_files);
+        }
+        Err(e) => {
+            eprintln!("Restore failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// `wrench-forum migrate [--status | --to N]`
+fn run_migrate_command(config: &Config, status: bool, to: Option<i64>) {
+    if status {
+        let conn = db::open_db_read_only(&config.database.path).unwrap_or_else(|e| {
+            eprintln!("Failed to open database: {}", e);
+            std::process::exit(1);
+        });
+        let statuses = db::get_migration_status(&conn).unwrap_or_else(|e| {
+            eprintln!("Failed to read schema version: {}", e);
+            std::process::exit(1);
+        });
+        let Some(statuses) = statuses else {
+            println!("Database is unversioned: no migrations have been applied");
+            return;
+        };
+        for status in statuses {
+            match status.applied_at {
+                Some(at) => println!("  [x] {:>4}  {:<30} applied {}", status.version, status.name, at),
+                None => println!("  [ ] {:>4}  {:<30} pending", status.version, status.name),
+            }
+        }
+        return;
+    }
+    
+    let conn = db::open_db(&config.database.path).unwrap_or_else(|e| {
+        eprintln!("Failed to open database: {}", e);
+        std::process::exit(1);
+    });
+    let Some(target) = to else {
+        apply_migrations(&conn, db::latest_schema_version());
+        return;
+    };
+    let current = db::get_schema_version(&conn).unwrap_or(0);
+    if target < current {
+        eprintln!("Database is already at version {}; migrations are forward-only", current);
+        std::process::exit(1);
+    }
+    if target > db::latest_schema_version() {
+        eprintln!("Unknown version {} (latest is {})", target, db::latest_schema_version());
+        std::process::exit(1);
+    }
+    apply_migrations(&conn, target);
+}
+
+fn apply_migrations(conn: &rusqlite::Connection, target: i64) {
+    match db::migrate_to(conn, target) {
+        Ok(applied) if applied.is_empty() => println!("Schema is up to date (version {})", db::get_schema_version(conn).unwrap_or(0)),
+        Ok(applied) => {
+            for version in applied {
+                println!("Applied migration {}", version);
+            }
+        }
+        Err(e) => {
+            eprintln!("Migration failed: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
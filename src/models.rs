@@ -1,58 +1,200 @@
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Serialize, Serializer};
+use utoipa::ToSchema;
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+/// A user's role. The four built-in roles always exist; admins can add
+/// custom ones, which keep the name they were created with. What a role may
+/// do is stored in the database, see [`Permission`].
+#[derive(Debug, Clone, PartialEq)]
 pub enum UserRole {
     Unverified,
     VerifiedMechanic,
     Moderator,
     Admin,
+    Custom(String),
 }
 
 impl UserRole {
+    pub const BUILTIN: [UserRole; 4] = [UserRole::Unverified, UserRole::VerifiedMechanic, UserRole::Moderator, UserRole::Admin];
+
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s {
+            "unverified" => UserRole::Unverified,
             "verified_mechanic" => UserRole::VerifiedMechanic,
             "moderator" => UserRole::Moderator,
             "admin" => UserRole::Admin,
-            _ => UserRole::Unverified,
+            other => UserRole::Custom(other.to_string()),
         }
     }
 
-    pub fn to_str(&self) -> &'static str {
+    pub fn to_str(&self) -> &str {
         match self {
             UserRole::Unverified => "unverified",
             UserRole::VerifiedMechanic => "verified_mechanic",
             UserRole::Moderator => "moderator",
             UserRole::Admin => "admin",
+            UserRole::Custom(name) => name,
         }
     }
 
-    pub fn display_name(&self) -> &'static str {
+    /// Built-in names; custom roles have their label in the roles table
+    pub fn display_name(&self) -> &str {
         match self {
             UserRole::Unverified => "Unverified",
             UserRole::VerifiedMechanic => "Verified Mechanic",
             UserRole::Moderator => "Moderator",
             UserRole::Admin => "Admin",
+            UserRole::Custom(name) => name,
+        }
+    }
+}
+
+/// Serialized by name, e.g. `"verified_mechanic"`, as stored
+impl Serialize for UserRole {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.to_str())
+    }
+}
+
+/// Something a role can be allowed to do. Adding one needs a migration that
+/// grants it to the admin role.
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Permission {
+    Post,
+    Comment,
+    VoteStore,
+    RemoveContent,
+    Ban,
+    ReviewVerifications,
+    ManageRoles,
+    ManageCategories,
+    ManageSite,
+}
+
+impl Permission {
+    pub const ALL: [Permission; 9] = [
+        Permission::Post,
+        Permission::Comment,
+        Permission::VoteStore,
+        Permission::RemoveContent,
+        Permission::Ban,
+        Permission::ReviewVerifications,
+        Permission::ManageRoles,
+        Permission::ManageCategories,
+        Permission::ManageSite,
+    ];
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        Permission::ALL.into_iter().find(|p| p.to_str() == s)
+    }
+
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            Permission::Post => "post",
+            Permission::Comment => "comment",
+            Permission::VoteStore => "vote_store",
+            Permission::RemoveContent => "remove_content",
+            Permission::Ban => "ban",
+            Permission::ReviewVerifications => "review_verifications",
+            Permission::ManageRoles => "manage_roles",
+            Permission::ManageCategories => "manage_categories",
+            Permission::ManageSite => "manage_site",
         }
     }
 
-    pub fn can_post(&self) -> bool {
-        matches!(self, UserRole::VerifiedMechanic | UserRole::Moderator | UserRole::Admin)
+    /// Shown next to the checkbox in the admin panel
+    pub fn description(&self) -> &'static str {
+        match self {
+            Permission::Post => "Start threads, write procedures and add torque specs",
+            Permission::Comment => "Reply to posts",
+            Permission::VoteStore => "Submit and rate parts stores",
+            Permission::RemoveContent => "Remove, restore and pin anything, and review reports and DTC suggestions",
+            Permission::Ban => "Ban and unban users",
+            Permission::ReviewVerifications => "Approve or deny mechanic verification requests",
+            Permission::ManageRoles => "Create roles, change their permissions and assign them",
+            Permission::ManageCategories => "Assign category moderators",
+            Permission::ManageSite => "Announcements, flair, two-factor policy, lockouts and logs",
+        }
     }
+}
 
+/// What a signed-in user may do: their role's permissions, plus the
+/// categories they moderate
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Permissions {
+    pub granted: Vec<Permission>,
+    /// Category ids the user was made a moderator of
+    pub categories: Vec<i64>,
+}
+
+impl Permissions {
+    pub fn has(&self, permission: Permission) -> bool {
+        self.granted.contains(&permission)
+    }
+
+    /// Site-wide content removal, or a moderator assignment to the category
+    pub fn can_moderate_category(&self, category_id: i64) -> bool {
+        self.has(Permission::RemoveContent) || self.categories.contains(&category_id)
+    }
+
+    /// Anything that uses the moderation queue
     pub fn can_moderate(&self) -> bool {
-        matches!(self, UserRole::Moderator | UserRole::Admin)
+        self.has(Permission::RemoveContent) || self.has(Permission::Ban) || !self.categories.is_empty()
     }
 
-    pub fn is_admin(&self) -> bool {
-        matches!(self, UserRole::Admin)
+    /// Anything that uses the admin panel
+    pub fn can_administer(&self) -> bool {
+        [Permission::ReviewVerifications, Permission::ManageRoles, Permission::ManageCategories, Permission::ManageSite]
+            .into_iter()
+            .any(|p| self.has(p))
     }
 
-    pub fn can_vote_stores(&self) -> bool {
-        matches!(self, UserRole::VerifiedMechanic | UserRole::Moderator | UserRole::Admin)
+    /// Holds every one of `permissions`
+    pub fn covers(&self, permissions: &[Permission]) -> bool {
+        permissions.iter().all(|p| self.has(*p))
     }
 }
 
+/// For templates: a flag per permission name, plus `moderate`, `administer`
+/// and `categories`, e.g. `{% if user.permissions.post %}`
+impl Serialize for Permissions {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(Some(Permission::ALL.len() + 3))?;
+        for permission in Permission::ALL {
+            map.serialize_entry(permission.to_str(), &self.has(permission))?;
+        }
+        map.serialize_entry("moderate", &self.can_moderate())?;
+        map.serialize_entry("administer", &self.can_administer())?;
+        map.serialize_entry("categories", &self.categories)?;
+        map.end()
+    }
+}
+
+/// A role as listed in the admin panel
+#[derive(Debug, Clone, Serialize)]
+pub struct RoleDetails {
+    pub name: String,
+    pub label: String,
+    /// Built-in roles can't be deleted
+    pub builtin: bool,
+    pub permissions: Vec<Permission>,
+    pub user_count: i64,
+}
+
+/// A user who moderates one category without site-wide moderator rights
+#[derive(Debug, Clone, Serialize)]
+pub struct CategoryModerator {
+    pub user_id: i64,
+    pub username: String,
+    pub category_id: i64,
+    pub category_name: String,
+    pub category_slug: String,
+    pub created_at: String,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct User {
     pub id: i64,
@@ -63,6 +205,13 @@ pub struct User {
     pub banned: bool,
     pub karma: i64,
     pub flair: Option<String>,
+    /// Followed the link in the confirmation email. Unconfirmed accounts
+    /// can't comment or file reports.
+    pub email_confirmed: bool,
+    /// Signs in with an authenticator code as well as the password
+    pub two_factor_enabled: bool,
+    /// Only loaded for the signed-in user; empty on users loaded for display
+    pub permissions: Permissions,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -116,11 +265,248 @@ pub struct Post {
     pub category_name: Option<String>,
     pub category_slug: Option<String>,
     pub comment_count: Option<i64>,
+    /// Search excerpt, HTML-escaped with matched terms in `<mark>`
+    pub snippet: Option<String>,
     pub tags: Option<Vec<PostTag>>,
+    pub vehicle: Option<Vehicle>,
+    /// Set for repair procedure posts
+    pub procedure: Option<ProcedureDetails>,
     pub is_bookmarked: Option<bool>,
     pub user_vote: Option<i64>,
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct Vehicle {
+    pub id: i64,
+    pub user_id: i64,
+    pub year: i64,
+    pub make: String,
+    pub model: String,
+    pub trim: Option<String>,
+    pub engine_code: Option<String>,
+    pub transmission: Option<String>,
+    pub mileage: Option<i64>,
+    /// Listed in the owner's garage; vehicles entered only for a post are not
+    pub in_garage: bool,
+    pub created_at: String,
+}
+
+/// The user-editable fields of a vehicle
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct VehicleDetails {
+    pub year: i64,
+    pub make: String,
+    pub model: String,
+    pub trim: Option<String>,
+    pub engine_code: Option<String>,
+    pub transmission: Option<String>,
+    pub mileage: Option<i64>,
+}
+
+/// Narrow a post listing to posts about matching vehicles
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct VehicleFilter {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub year_min: Option<i64>,
+    pub year_max: Option<i64>,
+}
+
+impl VehicleFilter {
+    pub fn is_empty(&self) -> bool {
+        self.make.is_none() && self.model.is_none() && self.year_min.is_none() && self.year_max.is_none()
+    }
+
+    /// The filter as `&key=value` pairs to append to listing links
+    pub fn query_string(&self) -> String {
+        let mut query = String::new();
+        if let Some(make) = &self.make {
+            query.push_str(&format!("&make={}", url_encode(make)));
+        }
+        if let Some(model) = &self.model {
+            query.push_str(&format!("&model={}", url_encode(model)));
+        }
+        if let Some(year) = self.year_min {
+            query.push_str(&format!("&year_min={}", year));
+        }
+        if let Some(year) = self.year_max {
+            query.push_str(&format!("&year_max={}", year));
+        }
+        query
+    }
+}
+
+fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// An OBD-II trouble code. `description` is None for codes that aren't in
+/// the seeded SAE list, typically manufacturer-specific ones.
+#[derive(Debug, Clone, Serialize)]
+pub struct DtcCode {
+    pub code: String,
+    pub description: Option<String>,
+    pub system: &'static str,
+    pub generic: bool,
+}
+
+impl DtcCode {
+    pub fn new(code: &str, description: Option<String>) -> Self {
+        let mut chars = code.chars();
+        let system = match chars.next() {
+            Some('P') => "Powertrain",
+            Some('B') => "Body",
+            Some('C') => "Chassis",
+            Some('U') => "Network",
+            _ => "Unknown",
+        };
+        // SAE J2012 reserves P0xxx, P2xxx and P34xx-P3Fxx for generic codes;
+        // B/C/U codes are only generic in the 0 range
+        let generic = match (code.as_bytes().first(), chars.next(), chars.next()) {
+            (_, Some('0'), _) => true,
+            (Some(b'P'), Some('2'), _) => true,
+            (Some(b'P'), Some('3'), Some(c)) => c >= '4',
+            _ => false,
+        };
+        DtcCode { code: code.to_string(), description, system, generic }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DtcCause {
+    pub id: i64,
+    pub code: String,
+    pub cause: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DtcCauseSuggestion {
+    pub id: i64,
+    pub code: String,
+    pub user_id: i64,
+    /// "add" or "remove"
+    pub action: String,
+    pub cause: String,
+    pub cause_id: Option<i64>,
+    pub status: String,
+    pub created_at: String,
+    // Joined
+    pub username: Option<String>,
+}
+
+/// Difficulty ratings for repair procedures, easiest first
+pub const PROCEDURE_DIFFICULTIES: &[&str] = &["beginner", "intermediate", "advanced", "expert"];
+
+/// Units a torque spec can be given in
+pub const TORQUE_UNITS: &[&str] = &["Nm", "ft-lb", "in-lb"];
+
+/// The structured part of a repair procedure post. The post body holds the
+/// overview; listings only load `difficulty` and `labor_minutes`.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProcedureDetails {
+    pub difficulty: String,
+    pub labor_minutes: Option<i64>,
+    pub steps: Vec<ProcedureStep>,
+    pub tools: Vec<String>,
+    pub parts: Vec<ProcedurePart>,
+    pub torque_specs: Vec<ProcedureTorqueSpec>,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ProcedureStep {
+    pub body: String,
+    pub body_html: Option<String>,
+    /// Image from `uploads`
+    pub upload_id: Option<i64>,
+    // Joined
+    pub image_url: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProcedurePart {
+    pub name: String,
+    pub part_number: Option<String>,
+    pub quantity: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProcedureTorqueSpec {
+    pub fastener: String,
+    pub value: f64,
+    /// One of `TORQUE_UNITS`
+    pub unit: String,
+}
+
+/// A spec from the community torque library, covering one fastener on a
+/// range of model years
+#[derive(Debug, Clone, Serialize)]
+pub struct TorqueSpec {
+    pub id: i64,
+    pub make: String,
+    pub model: String,
+    pub year_from: i64,
+    pub year_to: i64,
+    pub engine_code: Option<String>,
+    pub fastener: String,
+    pub value: f64,
+    /// One of `TORQUE_UNITS`
+    pub unit: String,
+    /// Tightening order, e.g. "spiral out from the center"
+    pub sequence: Option<String>,
+    /// Further rotation after reaching `value`, for torque-angle specs
+    pub angle_degrees: Option<i64>,
+    /// The bolt stretches and must be replaced, not reused
+    pub torque_to_yield: bool,
+    /// Where the number came from, e.g. a service manual section
+    pub source: Option<String>,
+    pub submitted_by: i64,
+    pub created_at: String,
+    // Computed
+    pub values: Option<crate::torque::TorqueValues>,
+    pub confirmations: i64,
+    pub disputes: i64,
+    pub submitter_name: Option<String>,
+}
+
+/// The user-editable fields of a torque spec
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TorqueSpecDetails {
+    pub make: String,
+    pub model: String,
+    pub year_from: i64,
+    pub year_to: i64,
+    pub engine_code: Option<String>,
+    pub fastener: String,
+    pub value: f64,
+    pub unit: String,
+    pub sequence: Option<String>,
+    pub angle_degrees: Option<i64>,
+    pub torque_to_yield: bool,
+    pub source: Option<String>,
+}
+
+/// Narrow the torque library to one vehicle and/or fastener
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TorqueSpecFilter {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub year: Option<i64>,
+    pub fastener: Option<String>,
+}
+
+impl TorqueSpecFilter {
+    pub fn is_empty(&self) -> bool {
+        self.make.is_none() && self.model.is_none() && self.year.is_none() && self.fastener.is_none()
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct Comment {
     pub id: i64,
@@ -184,6 +570,8 @@ pub struct Report {
     pub resolved: bool,
     pub created_at: String,
     // Joined
+    /// Category of the reported post, or of the reported comment's post
+    pub category_id: Option<i64>,
     pub reporter_name: Option<String>,
     pub post_title: Option<String>,
     pub comment_body: Option<String>,
@@ -194,6 +582,29 @@ pub struct Session {
     pub token: String,
     pub user_id: i64,
     pub expires_at: String,
+    /// Set by the user on the sessions page, e.g. "Shop PC"
+    pub name: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: String,
+    pub last_seen_at: Option<String>,
+    /// The user's role changed; the next request gets a new token
+    pub rotate_pending: bool,
+}
+
+/// A session as shown on the sessions page. Identified by a hash of its
+/// token so the token itself never appears in the page.
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveSession {
+    pub id: String,
+    pub name: Option<String>,
+    /// Browser and OS from the user agent, e.g. "Firefox on Windows"
+    pub device: String,
+    pub ip_address: Option<String>,
+    pub created_at: String,
+    pub last_seen_at: String,
+    /// The session this page was loaded with
+    pub current: bool,
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -218,6 +629,7 @@ pub enum NotificationType {
 }
 
 impl NotificationType {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Self {
         match s {
             "reply" => NotificationType::Reply,
@@ -324,12 +736,32 @@ pub struct SearchResult {
     pub result_type: String, // "post" or "store"
     pub id: i64,
     pub title: String,
+    /// HTML-escaped excerpt; post matches have their terms in `<mark>`
     pub snippet: String,
     pub url: String,
     pub score: Option<i64>,
     pub created_at: String,
 }
 
+/// A search box query split into free text and operators
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchFilters {
+    pub terms: Vec<String>,
+    pub phrases: Vec<String>,
+    pub excluded: Vec<String>,
+    pub author: Option<String>,
+    pub tags: Vec<String>,
+    pub category: Option<String>,
+    pub solved: bool,
+}
+
+impl SearchFilters {
+    /// Whether any operator that only applies to posts is set
+    pub fn has_post_filters(&self) -> bool {
+        self.author.is_some() || !self.tags.is_empty() || self.category.is_some() || self.solved
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Default)]
 pub struct PaginationInfo {
     pub page: i64,
@@ -372,3 +804,126 @@ pub struct ForumStats {
     pub total_stores: i64,
     pub posts_today: i64,
 }
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: String,
+    pub applied_at: Option<String>,
+}
+
+/// A user's authenticator secret. Not `enabled` until the first code from
+/// the app has been checked.
+#[derive(Debug, Clone)]
+pub struct UserTotp {
+    pub user_id: i64,
+    /// Base32, the form it's shown to the user in
+    pub secret: String,
+    pub enabled: bool,
+    /// Last time step a code was accepted for, so codes can't be replayed
+    pub last_used_step: Option<i64>,
+}
+
+/// A login that passed the password check and is waiting for the second
+/// factor
+#[derive(Debug, Clone)]
+pub struct LoginChallenge {
+    pub user_id: i64,
+    pub attempts: i64,
+}
+
+/// An account locked out after too many failed logins, for the admin panel
+#[derive(Debug, Clone, Serialize)]
+pub struct LockedAccount {
+    pub user_id: i64,
+    pub username: String,
+    pub email: String,
+    pub locked_until: String,
+    /// Failed logins still on record, i.e. from the last day
+    pub recent_failures: i64,
+    pub last_ip_address: Option<String>,
+}
+
+/// A backup just written: a directory holding a copy of the database and
+/// the uploads
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupSnapshot {
+    pub path: String,
+    pub schema_version: i64,
+    /// Database file plus uploads
+    pub size_bytes: i64,
+    pub upload_files: i64,
+}
+
+/// A recorded backup run, for the admin panel. `error` is set when it failed.
+#[derive(Debug, Clone, Serialize)]
+pub struct BackupRecord {
+    pub id: i64,
+    pub started_at: String,
+    pub finished_at: String,
+    pub path: Option<String>,
+    pub size_bytes: Option<i64>,
+    pub upload_files: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// What a personal API token may be used for
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ApiScope {
+    /// Read posts, comments, categories, stores and search
+    Read,
+    /// Post, comment, vote and submit stores
+    Write,
+    /// Read the user's notifications and mark them read
+    Notifications,
+    /// Scrape `/metrics`; only works for site managers' tokens
+    Metrics,
+}
+
+impl ApiScope {
+    pub const ALL: [ApiScope; 4] = [ApiScope::Read, ApiScope::Write, ApiScope::Notifications, ApiScope::Metrics];
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "read" => Some(ApiScope::Read),
+            "write" => Some(ApiScope::Write),
+            "notifications" => Some(ApiScope::Notifications),
+            "metrics" => Some(ApiScope::Metrics),
+            _ => None,
+        }
+    }
+
+    pub fn to_str(&self) -> &'static str {
+        match self {
+            ApiScope::Read => "read",
+            ApiScope::Write => "write",
+            ApiScope::Notifications => "notifications",
+            ApiScope::Metrics => "metrics",
+        }
+    }
+
+    /// Space-separated, the form scopes are stored in
+    pub fn join(scopes: &[ApiScope]) -> String {
+        scopes.iter().map(ApiScope::to_str).collect::<Vec<_>>().join(" ")
+    }
+
+    /// Unknown names are skipped
+    pub fn parse_list(s: &str) -> Vec<ApiScope> {
+        s.split_whitespace().filter_map(ApiScope::from_str).collect()
+    }
+}
+
+/// A personal access token for the JSON API. Only a hash of the token is
+/// stored; `prefix` is enough of it for the owner to tell tokens apart.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiToken {
+    pub id: i64,
+    pub user_id: i64,
+    pub name: String,
+    pub prefix: String,
+    pub scopes: Vec<ApiScope>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
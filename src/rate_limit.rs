@@ -0,0 +1,233 @@
+//! Throttling for login, registration and password reset emails.
+//!
+//! Attempts are recorded in `login_attempts` and counted over a sliding
+//! window, both per client IP and per account. After a few failed logins an
+//! account has to wait between attempts, doubling each time, and after
+//! [`RateLimits::lockout_threshold`] failures it is locked for a while.
+//! Checks run before the password is verified, so a flood of guesses can't
+//! keep Argon2 busy. A login that passes them is counted as a failure in the
+//! same write, and taken back if the password turns out to be right, so
+//! guesses sent in parallel can't all get past the check before any of them
+//! is counted.
+
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::request::Parts;
+use chrono::{DateTime, Duration, NaiveDateTime, Utc};
+use rusqlite::Connection;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+
+use crate::db;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+/// Limits, from the `[rate_limit]` config section; see [`crate::config::Config::rate_limits`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RateLimits {
+    /// Length of the sliding window failures are counted over
+    pub window_minutes: i64,
+    /// Failed logins allowed from one IP per window, across all accounts
+    pub max_failures_per_ip: i64,
+    /// Failed logins for an account before it has to wait between attempts
+    pub free_failures: i64,
+    /// First wait, doubled for each further failure
+    pub base_delay_seconds: i64,
+    pub max_delay_seconds: i64,
+    /// Failed logins for an account per window that lock it
+    pub lockout_threshold: i64,
+    pub lockout_minutes: i64,
+    /// Accounts that can be registered from one IP per hour
+    pub max_registrations_per_ip: i64,
+    /// Password reset emails that can be asked for from one IP per hour
+    pub max_password_resets_per_ip: i64,
+    /// Password reset emails that can be asked for one address per hour
+    pub max_password_resets_per_account: i64,
+    /// Take the client IP from `X-Forwarded-For`. Only safe behind a proxy
+    /// that sets it.
+    pub trust_proxy: bool,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        RateLimits {
+            window_minutes: 15,
+            max_failures_per_ip: 50,
+            free_failures: 3,
+            base_delay_seconds: 2,
+            max_delay_seconds: 60,
+            lockout_threshold: 10,
+            lockout_minutes: 15,
+            max_registrations_per_ip: 5,
+            max_password_resets_per_ip: 10,
+            max_password_resets_per_account: 3,
+            trust_proxy: false,
+        }
+    }
+}
+
+impl RateLimits {
+    /// Seconds an account has to wait after its `failures`th failed login
+    pub fn delay_after(&self, failures: i64) -> i64 {
+        if failures < self.free_failures {
+            return 0;
+        }
+        let doublings = (failures - self.free_failures).min(30) as u32;
+        self.base_delay_seconds.saturating_mul(1 << doublings).min(self.max_delay_seconds)
+    }
+}
+
+/// Whether a login may go ahead
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoginCheck {
+    Allowed,
+    /// Too many failures from this IP
+    IpLimited,
+    /// The account is locked until this time
+    Locked(String),
+    /// The account has to wait this many more seconds
+    Delayed(i64),
+}
+
+/// Emails as typed, in the form attempts are keyed by
+pub fn normalize_account(email: &str) -> String {
+    email.trim().to_lowercase()
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.format(TIMESTAMP_FORMAT).to_string()
+}
+
+fn window_start(limits: &RateLimits, now: DateTime<Utc>) -> String {
+    format_time(now - Duration::minutes(limits.window_minutes))
+}
+
+/// Checks the IP and account limits before a password is verified.
+/// `user_id` is the account's user, if the email belongs to one.
+pub fn check_login(conn: &Connection, limits: &RateLimits, ip: &str, account: &str, user_id: Option<i64>, now: DateTime<Utc>) -> rusqlite::Result<LoginCheck> {
+    let since = window_start(limits, now);
+    if db::count_failed_logins_from_ip(conn, ip, &since)? >= limits.max_failures_per_ip {
+        return Ok(LoginCheck::IpLimited);
+    }
+
+    if let Some(user_id) = user_id {
+        if let Some(until) = db::get_user_locked_until(conn, user_id, &format_time(now))? {
+            return Ok(LoginCheck::Locked(until));
+        }
+    }
+
+    let (failures, last_failure) = db::get_account_login_failures(conn, account, &since)?;
+    let last_failure = last_failure.and_then(|t| NaiveDateTime::parse_from_str(&t, TIMESTAMP_FORMAT).ok());
+    if let Some(last_failure) = last_failure {
+        let ready_at = last_failure.and_utc() + Duration::seconds(limits.delay_after(failures));
+        if ready_at > now {
+            return Ok(LoginCheck::Delayed((ready_at - now).num_seconds().max(1)));
+        }
+    }
+
+    Ok(LoginCheck::Allowed)
+}
+
+/// Runs [`check_login`] and, if the login may go ahead, records it as a
+/// failure straight away. Call it with the write connection so the check
+/// and the record can't be split by another attempt. Returns the attempt's
+/// id, for [`withdraw_login_attempt`] if the password is right or
+/// [`finish_failed_login`] if it isn't, or the reason the login is refused.
+pub fn start_login(conn: &Connection, limits: &RateLimits, ip: &str, account: &str, user_id: Option<i64>, now: DateTime<Utc>) -> rusqlite::Result<Result<i64, LoginCheck>> {
+    match check_login(conn, limits, ip, account, user_id, now)? {
+        LoginCheck::Allowed => Ok(Ok(db::record_auth_attempt(conn, "login", ip, Some(account), false, &format_time(now))?)),
+        refused => Ok(Err(refused)),
+    }
+}
+
+/// Takes back the failure [`start_login`] recorded, once the password has
+/// turned out to be right
+pub fn withdraw_login_attempt(conn: &Connection, attempt_id: i64) -> rusqlite::Result<()> {
+    db::delete_auth_attempt(conn, attempt_id)
+}
+
+/// Records a failed login, locking the account if it has now failed too
+/// often. Returns whether it was locked.
+pub fn record_login_failure(conn: &Connection, limits: &RateLimits, ip: &str, account: &str, user_id: Option<i64>, now: DateTime<Utc>) -> rusqlite::Result<bool> {
+    db::record_auth_attempt(conn, "login", ip, Some(account), false, &format_time(now))?;
+    finish_failed_login(conn, limits, ip, account, user_id, now)
+}
+
+/// Follows up a login [`start_login`] already counted as a failure, whose
+/// password was wrong: logs it and locks the account if it has now failed
+/// too often. Returns whether it was locked.
+pub fn finish_failed_login(conn: &Connection, limits: &RateLimits, ip: &str, account: &str, user_id: Option<i64>, now: DateTime<Utc>) -> rusqlite::Result<bool> {
+    let Some(user_id) = user_id else {
+        return Ok(false);
+    };
+
+    db::log_activity(conn, user_id, "login_failed", Some("user"), Some(user_id), None, Some(ip))?;
+    let (failures, _) = db::get_account_login_failures(conn, account, &window_start(limits, now))?;
+    if failures < limits.lockout_threshold {
+        return Ok(false);
+    }
+
+    let until = format_time(now + Duration::minutes(limits.lockout_minutes));
+    db::lock_user(conn, user_id, &until)?;
+    db::log_activity(conn, user_id, "account_locked", Some("user"), Some(user_id), Some(&until), Some(ip))?;
+    Ok(true)
+}
+
+/// Records a successful login, which clears the account's failure count
+pub fn record_login_success(conn: &Connection, ip: &str, account: &str, now: DateTime<Utc>) -> rusqlite::Result<()> {
+    db::record_auth_attempt(conn, "login", ip, Some(account), true, &format_time(now))?;
+    Ok(())
+}
+
+/// Records a registration attempt from `ip` if it's under the hourly limit.
+/// Returns false if it isn't.
+pub fn allow_registration(conn: &Connection, limits: &RateLimits, ip: &str, now: DateTime<Utc>) -> rusqlite::Result<bool> {
+    let since = format_time(now - Duration::hours(1));
+    if db::count_attempts_from_ip(conn, "register", ip, &since)? >= limits.max_registrations_per_ip {
+        return Ok(false);
+    }
+    db::record_auth_attempt(conn, "register", ip, None, true, &format_time(now))?;
+    Ok(true)
+}
+
+/// Records a password reset request for `account` from `ip` if both are
+/// under their hourly limits, whether or not the address has an account.
+/// Returns false if either isn't.
+pub fn allow_password_reset(conn: &Connection, limits: &RateLimits, ip: &str, account: &str, now: DateTime<Utc>) -> rusqlite::Result<bool> {
+    let since = format_time(now - Duration::hours(1));
+    if db::count_attempts_from_ip(conn, "password_reset", ip, &since)? >= limits.max_password_resets_per_ip
+        || db::count_attempts_for_account(conn, "password_reset", account, &since)? >= limits.max_password_resets_per_account
+    {
+        return Ok(false);
+    }
+    db::record_auth_attempt(conn, "password_reset", ip, Some(account), true, &format_time(now))?;
+    Ok(true)
+}
+
+/// "3 minutes", "45 seconds"
+pub fn describe_wait(seconds: i64) -> String {
+    let (amount, unit) = if seconds >= 60 { ((seconds + 59) / 60, "minute") } else { (seconds, "second") };
+    format!("{} {}{}", amount, unit, if amount == 1 { "" } else { "s" })
+}
+
+/// The client's IP: the connection's peer address, or with
+/// [`RateLimits::trust_proxy`] the address the proxy appended to
+/// `X-Forwarded-For`
+pub struct ClientIp(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let trust_proxy = parts.extensions.get::<RateLimits>().is_some_and(|l| l.trust_proxy);
+        let forwarded = parts
+            .headers
+            .get("x-forwarded-for")
+            .filter(|_| trust_proxy)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.rsplit(',').next())
+            .map(|ip| ip.trim().to_string())
+            .filter(|ip| !ip.is_empty());
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|c| c.0.ip().to_string());
+        Ok(ClientIp(forwarded.or(peer).unwrap_or_else(|| "unknown".to_string())))
+    }
+}
@@ -1,21 +1,34 @@
 use axum::{
     extract::{Path, Query, State},
     response::Html,
-    Form,
+    Extension, Form,
 };
-use axum_extra::extract::CookieJar;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tera::{Context, Tera};
 
-use crate::auth::ensure_session;
+use crate::auth::{now_timestamp, Admin, CategoryManager, PageContext, RequireRole, RoleManager, SiteManager, VerificationReviewer};
+use crate::backup;
+use crate::config::Config;
 use crate::db::{self, Db};
+use crate::error::{toast, AppError, HtmlResult};
+use crate::models::{Category, CategoryModerator, Permission, RoleDetails, User, UserRole};
+use crate::telemetry;
+
+/// Longest label a custom role can have
+const ROLE_LABEL_MAX: usize = 40;
 
 #[derive(Deserialize)]
 pub struct RoleForm {
     pub role: String,
 }
 
+#[derive(Deserialize)]
+pub struct CategoryModeratorForm {
+    pub username: String,
+    pub category_id: i64,
+}
+
 #[derive(Deserialize)]
 pub struct FlairForm {
     pub flair: String,
@@ -34,268 +47,515 @@ pub struct PaginationQuery {
     pub page: Option<i64>,
 }
 
+/// Checks the user exists, so acting on a stale link is a 404
+async fn require_user(db: &Db, user_id: i64) -> Result<User, AppError> {
+    db.read(move |conn| db::get_user_by_id(conn, user_id))
+        .await?
+        .ok_or_else(|| AppError::not_found("User not found"))
+}
+
+/// Checks the role exists, so acting on a stale link is a 404
+async fn require_role(db: &Db, name: String) -> Result<RoleDetails, AppError> {
+    db.read(move |conn| db::get_role(conn, &name))
+        .await?
+        .ok_or_else(|| AppError::not_found("Role not found"))
+}
+
+/// Role managers can only hand out permissions they hold themselves
+fn check_grantable(user: &User, permissions: &[Permission]) -> Result<(), AppError> {
+    if user.permissions.covers(permissions) {
+        Ok(())
+    } else {
+        Err(AppError::forbidden("You can only manage roles with permissions you have yourself"))
+    }
+}
+
+/// Every value sent for a repeated form field, e.g. a group of checkboxes
+fn form_values<'a>(fields: &'a [(String, String)], name: &str) -> Vec<&'a str> {
+    fields.iter().filter(|(k, _)| k == name).map(|(_, v)| v.as_str()).collect()
+}
+
+fn form_value<'a>(fields: &'a [(String, String)], name: &str) -> &'a str {
+    form_values(fields, name).first().map_or("", |v| v.trim())
+}
+
+/// The ticked `permission` checkboxes; unknown names are skipped
+fn form_permissions(fields: &[(String, String)]) -> Vec<Permission> {
+    let names = form_values(fields, "permission");
+    Permission::ALL.into_iter().filter(|p| names.contains(&p.to_str())).collect()
+}
+
+/// A permission checkbox in the roles section
+#[derive(Serialize)]
+struct PermissionOption {
+    name: &'static str,
+    description: &'static str,
+}
+
+fn permission_options() -> Vec<PermissionOption> {
+    Permission::ALL
+        .into_iter()
+        .map(|p| PermissionOption { name: p.to_str(), description: p.description() })
+        .collect()
+}
+
+fn render_roles(tera: &Tera, user: &User, roles: &[RoleDetails], message: &str) -> HtmlResult {
+    let mut ctx = Context::new();
+    ctx.insert("roles", roles);
+    ctx.insert("permission_options", &permission_options());
+    ctx.insert("user", user);
+    let html = telemetry::render(tera, "partials/role_list.html", &ctx)?;
+    Ok(Html(format!("{}\n{}", html, toast("success", message))))
+}
+
+fn render_category_moderators(tera: &Tera, category_moderators: &[CategoryModerator], categories: &[Category], message: &str) -> HtmlResult {
+    let mut ctx = Context::new();
+    ctx.insert("category_moderators", category_moderators);
+    ctx.insert("categories", categories);
+    let html = telemetry::render(tera, "partials/category_moderators.html", &ctx)?;
+    Ok(Html(format!("{}\n{}", html, toast("success", message))))
+}
+
 pub async fn admin_panel(
-    jar: CookieJar,
+    _: RequireRole<Admin>,
+    PageContext(mut ctx): PageContext,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
+) -> HtmlResult {
+    let (users, pending_verifications, announcements, stats, recent_activity, two_factor_roles, locked_accounts) = db.read(move |conn| {
+        Ok((
+            db::get_all_users(conn)?,
+            db::get_pending_verification_requests(conn)?,
+            db::get_active_announcements(conn)?,
+            db::get_forum_stats(conn)?,
+            db::get_recent_activity(conn, 20)?,
+            db::get_two_factor_roles(conn)?,
+            db::get_locked_accounts(conn, &now_timestamp())?,
+        ))
+    }).await?;
+    let (roles, categories, category_moderators, last_backup, recent_backups) = db.read(move |conn| {
+        Ok((
+            db::get_roles(conn)?,
+            db::get_categories(conn)?,
+            db::get_category_moderators(conn)?,
+            db::get_last_successful_backup(conn)?,
+            db::get_recent_backups(conn, 5)?,
+        ))
+    }).await?;
     
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.is_admin() {
-            ctx.insert("error", "Admin access required");
-            let html = tera.render("error.html", &ctx).unwrap();
-            return (jar, Html(html));
-        }
-        
-        let conn = db.lock().unwrap();
-        
-        let users = db::get_all_users(&conn).unwrap_or_default();
-        let pending_verifications = db::get_pending_verification_requests(&conn).unwrap_or_default();
-        let announcements = db::get_active_announcements(&conn).unwrap_or_default();
-        let stats = db::get_forum_stats(&conn).unwrap_or_default();
-        let recent_activity = db::get_recent_activity(&conn, 20).unwrap_or_default();
-        let unread_count = db::get_unread_notification_count(&conn, user.id).unwrap_or(0);
-        
-        ctx.insert("user", &user);
-        ctx.insert("users", &users);
-        ctx.insert("pending_verifications", &pending_verifications);
-        ctx.insert("announcements", &announcements);
-        ctx.insert("stats", &stats);
-        ctx.insert("recent_activity", &recent_activity);
-        ctx.insert("unread_notifications", &unread_count);
-        ctx.insert("current_page", &"admin");
-        
-        let html = tera.render("admin.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-        return (jar, Html(html));
-    }
+    ctx.insert("users", &users);
+    ctx.insert("pending_verifications", &pending_verifications);
+    ctx.insert("announcements", &announcements);
+    ctx.insert("stats", &stats);
+    ctx.insert("recent_activity", &recent_activity);
+    ctx.insert("two_factor_roles", &two_factor_roles);
+    ctx.insert("locked_accounts", &locked_accounts);
+    ctx.insert("roles", &roles);
+    ctx.insert("permission_options", &permission_options());
+    ctx.insert("categories", &categories);
+    ctx.insert("category_moderators", &category_moderators);
+    ctx.insert("last_backup", &last_backup);
+    ctx.insert("recent_backups", &recent_backups);
+    ctx.insert("current_page", &"admin");
     
-    let html = r#"<script>window.location.href = "/login";</script>"#.to_string();
-    (jar, Html(html))
+    Ok(Html(telemetry::render(&tera, "admin.html", &ctx)?))
 }
 
 pub async fn approve_verification(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<VerificationReviewer>,
     Path(id): Path<i64>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.is_admin() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let _ = db::approve_verification(&conn, id, user.id);
-        let _ = db::log_activity(&conn, user.id, "approve_verification", Some("verification"), Some(id), None, None);
+) -> HtmlResult {
+    let admin_id = user.id;
+    let pending = db.write(move |conn| {
+        db::approve_verification(conn, id, admin_id)?;
+        db::log_activity(conn, admin_id, "approve_verification", Some("verification"), Some(id), None, None)?;
         
         // Return updated verification queue
-        let pending = db::get_pending_verification_requests(&conn).unwrap_or_default();
-        let mut ctx = Context::new();
-        ctx.insert("pending_verifications", &pending);
-        
-        let html = tera.render("partials/verification_queue.html", &ctx).unwrap_or_default();
-        return (jar, Html(format!(
-            r#"{}
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast success">Verification approved!</div>
-            </div>"#,
-            html
-        )));
-    }
+        db::get_pending_verification_requests(conn)
+    }).await?;
+    let mut ctx = Context::new();
+    ctx.insert("pending_verifications", &pending);
     
-    (jar, Html("Unauthorized".to_string()))
+    let html = telemetry::render(&tera, "partials/verification_queue.html", &ctx)?;
+    Ok(Html(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Verification approved!</div>
+        </div>"#,
+        html
+    )))
 }
 
 pub async fn deny_verification(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<VerificationReviewer>,
     Path(id): Path<i64>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.is_admin() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let _ = db::deny_verification(&conn, id, user.id);
-        let _ = db::log_activity(&conn, user.id, "deny_verification", Some("verification"), Some(id), None, None);
-        
-        let pending = db::get_pending_verification_requests(&conn).unwrap_or_default();
-        let mut ctx = Context::new();
-        ctx.insert("pending_verifications", &pending);
-        
-        let html = tera.render("partials/verification_queue.html", &ctx).unwrap_or_default();
-        return (jar, Html(format!(
-            r#"{}
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast">Verification denied</div>
-            </div>"#,
-            html
-        )));
-    }
+) -> HtmlResult {
+    let admin_id = user.id;
+    let pending = db.write(move |conn| {
+        db::deny_verification(conn, id, admin_id)?;
+        db::log_activity(conn, admin_id, "deny_verification", Some("verification"), Some(id), None, None)?;
+        db::get_pending_verification_requests(conn)
+    }).await?;
+    let mut ctx = Context::new();
+    ctx.insert("pending_verifications", &pending);
     
-    (jar, Html("Unauthorized".to_string()))
+    let html = telemetry::render(&tera, "partials/verification_queue.html", &ctx)?;
+    Ok(Html(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast">Verification denied</div>
+        </div>"#,
+        html
+    )))
 }
 
 pub async fn update_user_role(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<RoleManager>,
     Path(user_id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
     Form(form): Form<RoleForm>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.is_admin() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        // Prevent changing own role
-        if user_id == user.id {
-            return (jar, Html("<div class=\"toast error\">Cannot change your own role</div>".to_string()));
+) -> HtmlResult {
+    // Prevent changing own role
+    if user_id == user.id {
+        return Err(AppError::validation("Cannot change your own role"));
+    }
+    let role = db.read(move |conn| db::get_role(conn, &form.role))
+        .await?
+        .ok_or_else(|| AppError::validation("Unknown role"))?;
+    let target = require_user(&db, user_id).await?;
+    let current = require_role(&db, target.role.to_str().to_string()).await?;
+    check_grantable(&user, &role.permissions)?;
+    check_grantable(&user, &current.permissions)?;
+    
+    let admin_id = user.id;
+    db.write(move |conn| {
+        db::update_user_role(conn, user_id, &role.name)?;
+        db::log_activity(conn, admin_id, "change_role", Some("user"), Some(user_id), Some(&role.name), None)
+    }).await?;
+    
+    Ok(Html("<div class=\"toast success\">Role updated!</div>".to_string()))
+}
+
+/// Create a custom role from the `name`, `label` and `permission` fields
+pub async fn create_role(
+    RequireRole(user, _): RequireRole<RoleManager>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> HtmlResult {
+    let name = form_value(&fields, "name").to_lowercase();
+    let label = form_value(&fields, "label").to_string();
+    let permissions = form_permissions(&fields);
+    
+    let valid_name = (2..=30).contains(&name.len())
+        && name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+    if !valid_name {
+        return Err(AppError::validation("Role names are 2 to 30 lowercase letters, digits or underscores, starting with a letter"));
+    }
+    if label.is_empty() || label.chars().count() > ROLE_LABEL_MAX {
+        return Err(AppError::validation(format!("Give the role a label of at most {} characters", ROLE_LABEL_MAX)));
+    }
+    check_grantable(&user, &permissions)?;
+    
+    let admin_id = user.id;
+    let roles = db.write(move |conn| {
+        if db::get_role(conn, &name)?.is_some() {
+            return Ok(None);
         }
-        
-        let conn = db.lock().unwrap();
-        let _ = db::update_user_role(&conn, user_id, &form.role);
-        let _ = db::log_activity(&conn, user.id, "change_role", Some("user"), Some(user_id), Some(&form.role), None);
-        
-        return (jar, Html("<div class=\"toast success\">Role updated!</div>".to_string()));
+        db::create_role(conn, &name, &label, &permissions)?;
+        db::log_activity(conn, admin_id, "create_role", Some("role"), None, Some(&name), None)?;
+        db::get_roles(conn).map(Some)
+    }).await?;
+    let roles = roles.ok_or_else(|| AppError::conflict("A role with that name already exists"))?;
+    
+    render_roles(&tera, &user, &roles, "Role created")
+}
+
+/// Replace a role's permissions with the ticked `permission` boxes
+pub async fn update_role_permissions(
+    RequireRole(user, _): RequireRole<RoleManager>,
+    Path(name): Path<String>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> HtmlResult {
+    let role = require_role(&db, name).await?;
+    // Keeps someone from locking every admin out
+    if role.name == UserRole::Admin.to_str() {
+        return Err(AppError::validation("The admin role always has every permission"));
+    }
+    if role.name == user.role.to_str() {
+        return Err(AppError::validation("Cannot change your own role's permissions"));
     }
+    let permissions = form_permissions(&fields);
+    check_grantable(&user, &role.permissions)?;
+    check_grantable(&user, &permissions)?;
     
-    (jar, Html("Unauthorized".to_string()))
+    let admin_id = user.id;
+    let roles = db.write(move |conn| {
+        db::set_role_permissions(conn, &role.name, &permissions)?;
+        let summary = permissions.iter().map(Permission::to_str).collect::<Vec<_>>().join(",");
+        db::log_activity(conn, admin_id, "update_role", Some("role"), None, Some(&format!("{}: {}", role.name, summary)), None)?;
+        db::get_roles(conn)
+    }).await?;
+    
+    render_roles(&tera, &user, &roles, "Role updated")
+}
+
+/// Delete a custom role once nobody has it
+pub async fn delete_role(
+    RequireRole(user, _): RequireRole<RoleManager>,
+    Path(name): Path<String>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let role = require_role(&db, name).await?;
+    if role.builtin {
+        return Err(AppError::validation("Built-in roles can't be deleted"));
+    }
+    if role.user_count > 0 {
+        return Err(AppError::conflict(format!("Move the role's {} members to another role first", role.user_count)));
+    }
+    check_grantable(&user, &role.permissions)?;
+    
+    let admin_id = user.id;
+    let roles = db.write(move |conn| {
+        db::delete_role(conn, &role.name)?;
+        let two_factor_roles: Vec<UserRole> = db::get_two_factor_roles(conn)?
+            .into_iter()
+            .filter(|r| r.to_str() != role.name)
+            .collect();
+        db::set_two_factor_roles(conn, &two_factor_roles)?;
+        db::log_activity(conn, admin_id, "delete_role", Some("role"), None, Some(&role.name), None)?;
+        db::get_roles(conn)
+    }).await?;
+    
+    render_roles(&tera, &user, &roles, "Role deleted")
+}
+
+/// The ticked `role` boxes must set up two-factor
+pub async fn update_two_factor_policy(
+    RequireRole(user, _): RequireRole<SiteManager>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> HtmlResult {
+    let names = form_values(&fields, "role").into_iter().map(String::from).collect::<Vec<_>>();
+    
+    let admin_id = user.id;
+    db.write(move |conn| {
+        let roles: Vec<UserRole> = db::get_roles(conn)?
+            .into_iter()
+            .filter(|r| names.contains(&r.name))
+            .map(|r| UserRole::from_str(&r.name))
+            .collect();
+        let summary = roles.iter().map(UserRole::to_str).collect::<Vec<_>>().join(",");
+        db::set_two_factor_roles(conn, &roles)?;
+        db::log_activity(conn, admin_id, "update_two_factor_policy", None, None, Some(&summary), None)
+    }).await?;
+    
+    Ok(Html("<div class=\"toast success\">Two-factor policy saved</div>".to_string()))
+}
+
+/// Make a user a moderator of one category
+pub async fn add_category_moderator(
+    RequireRole(user, _): RequireRole<CategoryManager>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Form(form): Form<CategoryModeratorForm>,
+) -> HtmlResult {
+    let username = form.username.trim().to_string();
+    let category_id = form.category_id;
+    let admin_id = user.id;
+    let result = db.write(move |conn| {
+        let Some(target) = db::get_user_by_username(conn, &username)? else {
+            return Ok(Err(AppError::not_found("User not found")));
+        };
+        let categories = db::get_categories(conn)?;
+        let Some(category) = categories.iter().find(|c| c.id == category_id) else {
+            return Ok(Err(AppError::not_found("Category not found")));
+        };
+        if !db::add_category_moderator(conn, target.id, category_id)? {
+            return Ok(Err(AppError::conflict(format!("{} already moderates {}", target.username, category.name))));
+        }
+        db::log_activity(conn, admin_id, "add_category_moderator", Some("user"), Some(target.id), Some(&category.slug), None)?;
+        Ok(Ok((db::get_category_moderators(conn)?, categories)))
+    }).await??;
+    let (category_moderators, categories) = result;
+    
+    render_category_moderators(&tera, &category_moderators, &categories, "Category moderator added")
+}
+
+pub async fn remove_category_moderator(
+    RequireRole(user, _): RequireRole<CategoryManager>,
+    Path((user_id, category_id)): Path<(i64, i64)>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let admin_id = user.id;
+    let result = db.write(move |conn| {
+        if !db::remove_category_moderator(conn, user_id, category_id)? {
+            return Ok(None);
+        }
+        db::log_activity(conn, admin_id, "remove_category_moderator", Some("user"), Some(user_id), Some(&category_id.to_string()), None)?;
+        Ok(Some((db::get_category_moderators(conn)?, db::get_categories(conn)?)))
+    }).await?;
+    let (category_moderators, categories) = result.ok_or_else(|| AppError::not_found("That assignment has already been removed"))?;
+    
+    render_category_moderators(&tera, &category_moderators, &categories, "Category moderator removed")
+}
+
+/// Lift a login lockout early
+pub async fn unlock_account(
+    RequireRole(user, _): RequireRole<SiteManager>,
+    Path(user_id): Path<i64>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let admin_id = user.id;
+    let locked_accounts = db.write(move |conn| {
+        db::unlock_user(conn, user_id)?;
+        db::log_activity(conn, admin_id, "unlock_account", Some("user"), Some(user_id), None, None)?;
+        db::get_locked_accounts(conn, &now_timestamp())
+    }).await?;
+    let mut ctx = Context::new();
+    ctx.insert("locked_accounts", &locked_accounts);
+    
+    let html = telemetry::render(&tera, "partials/locked_accounts.html", &ctx)?;
+    Ok(Html(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Account unlocked</div>
+        </div>"#,
+        html
+    )))
+}
+
+/// Take a backup now, on top of the scheduled ones
+pub async fn backup_now(
+    RequireRole(user, _): RequireRole<SiteManager>,
+    Extension(config): Extension<Arc<Config>>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let result = backup::run_and_record(&db, {
+        let config = config.clone();
+        move || backup::backup_to_dir(&config)
+    }).await;
+    
+    let admin_id = user.id;
+    let (last_backup, recent_backups) = db.write(move |conn| {
+        db::log_activity(conn, admin_id, "backup", None, None, None, None)?;
+        Ok((db::get_last_successful_backup(conn)?, db::get_recent_backups(conn, 5)?))
+    }).await?;
+    let mut ctx = Context::new();
+    ctx.insert("last_backup", &last_backup);
+    ctx.insert("recent_backups", &recent_backups);
+    
+    let html = telemetry::render(&tera, "partials/backup_status.html", &ctx)?;
+    let toast = match result {
+        Ok(_) => r#"<div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Backup complete</div>
+        </div>"#.to_string(),
+        Err(e) => {
+            let mut ctx = Context::new();
+            ctx.insert("message", &format!("Backup failed: {}", e));
+            telemetry::render(&tera, "partials/error_toast.html", &ctx)?
+        }
+    };
+    Ok(Html(format!("{}\n{}", html, toast)))
 }
 
 pub async fn update_user_flair(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<SiteManager>,
     Path(user_id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
     Form(form): Form<FlairForm>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.is_admin() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let _ = db::update_user_flair(&conn, user_id, &form.flair);
-        let _ = db::log_activity(&conn, user.id, "change_flair", Some("user"), Some(user_id), Some(&form.flair), None);
-        
-        return (jar, Html("<div class=\"toast success\">Flair updated!</div>".to_string()));
-    }
+) -> HtmlResult {
+    require_user(&db, user_id).await?;
+    
+    let admin_id = user.id;
+    db.write(move |conn| {
+        db::update_user_flair(conn, user_id, &form.flair)?;
+        db::log_activity(conn, admin_id, "change_flair", Some("user"), Some(user_id), Some(&form.flair), None)
+    }).await?;
     
-    (jar, Html("Unauthorized".to_string()))
+    Ok(Html("<div class=\"toast success\">Flair updated!</div>".to_string()))
 }
 
 pub async fn create_announcement(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<SiteManager>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
     Form(form): Form<AnnouncementForm>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.is_admin() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        if form.title.trim().is_empty() || form.content.trim().is_empty() {
-            return (jar, Html("<div class=\"toast error\">Title and content are required</div>".to_string()));
-        }
-        
-        let expires_at = form.expires_days.map(|days| {
-            (chrono::Utc::now() + chrono::Duration::days(days)).format("%Y-%m-%d %H:%M:%S").to_string()
-        });
-        
-        let conn = db.lock().unwrap();
-        let announcement_type = form.announcement_type.unwrap_or_else(|| "info".to_string());
-        let _ = db::create_announcement(&conn, &form.title, &form.content, &announcement_type, user.id, expires_at.as_deref());
-        let _ = db::log_activity(&conn, user.id, "create_announcement", None, None, Some(&form.title), None);
-        
-        let announcements = db::get_active_announcements(&conn).unwrap_or_default();
-        let mut ctx = Context::new();
-        ctx.insert("announcements", &announcements);
-        
-        let html = tera.render("partials/announcement_list.html", &ctx).unwrap_or_default();
-        return (jar, Html(format!(
-            r#"{}
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast success">Announcement created!</div>
-            </div>"#,
-            html
-        )));
+) -> HtmlResult {
+    if form.title.trim().is_empty() || form.content.trim().is_empty() {
+        return Err(AppError::validation("Title and content are required"));
     }
     
-    (jar, Html("Unauthorized".to_string()))
+    let expires_at = form.expires_days.map(|days| {
+        (chrono::Utc::now() + chrono::Duration::days(days)).format("%Y-%m-%d %H:%M:%S").to_string()
+    });
+    
+    let admin_id = user.id;
+    let announcement_type = form.announcement_type.unwrap_or_else(|| "info".to_string());
+    let announcements = db.write(move |conn| {
+        db::create_announcement(conn, &form.title, &form.content, &announcement_type, admin_id, expires_at.as_deref())?;
+        db::log_activity(conn, admin_id, "create_announcement", None, None, Some(&form.title), None)?;
+        db::get_active_announcements(conn)
+    }).await?;
+    let mut ctx = Context::new();
+    ctx.insert("announcements", &announcements);
+    
+    let html = telemetry::render(&tera, "partials/announcement_list.html", &ctx)?;
+    Ok(Html(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Announcement created!</div>
+        </div>"#,
+        html
+    )))
 }
 
 pub async fn deactivate_announcement(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<SiteManager>,
     Path(id): Path<i64>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.is_admin() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let _ = db::deactivate_announcement(&conn, id);
-        let _ = db::log_activity(&conn, user.id, "deactivate_announcement", Some("announcement"), Some(id), None, None);
-        
-        let announcements = db::get_active_announcements(&conn).unwrap_or_default();
-        let mut ctx = Context::new();
-        ctx.insert("announcements", &announcements);
-        
-        let html = tera.render("partials/announcement_list.html", &ctx).unwrap_or_default();
-        return (jar, Html(format!(
-            r#"{}
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast">Announcement deactivated</div>
-            </div>"#,
-            html
-        )));
-    }
+) -> HtmlResult {
+    let admin_id = user.id;
+    let announcements = db.write(move |conn| {
+        db::deactivate_announcement(conn, id)?;
+        db::log_activity(conn, admin_id, "deactivate_announcement", Some("announcement"), Some(id), None, None)?;
+        db::get_active_announcements(conn)
+    }).await?;
+    let mut ctx = Context::new();
+    ctx.insert("announcements", &announcements);
     
-    (jar, Html("Unauthorized".to_string()))
+    let html = telemetry::render(&tera, "partials/announcement_list.html", &ctx)?;
+    Ok(Html(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast">Announcement deactivated</div>
+        </div>"#,
+        html
+    )))
 }
 
 pub async fn forum_stats(
-    jar: CookieJar,
+    _: RequireRole<SiteManager>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.is_admin() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let stats = db::get_forum_stats(&conn).unwrap_or_default();
-        
-        let mut ctx = Context::new();
-        ctx.insert("stats", &stats);
-        
-        let html = tera.render("partials/forum_stats.html", &ctx).unwrap_or_default();
-        return (jar, Html(html));
-    }
+) -> HtmlResult {
+    let stats = db.read(db::get_forum_stats).await?;
+    
+    let mut ctx = Context::new();
+    ctx.insert("stats", &stats);
     
-    (jar, Html("Unauthorized".to_string()))
+    Ok(Html(telemetry::render(&tera, "partials/forum_stats.html", &ctx)?))
 }
 
 pub async fn activity_logs(
-    jar: CookieJar,
-    Query(query): Query<PaginationQuery>,
+    _: RequireRole<SiteManager>,
+    Query(_query): Query<PaginationQuery>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.is_admin() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let limit = 50;
-        let activity = db::get_recent_activity(&conn, limit).unwrap_or_default();
-        
-        let mut ctx = Context::new();
-        ctx.insert("activity", &activity);
-        
-        let html = tera.render("partials/activity_logs.html", &ctx).unwrap_or_default();
-        return (jar, Html(html));
-    }
+) -> HtmlResult {
+    let limit = 50;
+    let activity = db.read(move |conn| db::get_recent_activity(conn, limit)).await?;
+    
+    let mut ctx = Context::new();
+    ctx.insert("activity", &activity);
     
-    (jar, Html("Unauthorized".to_string()))
+    Ok(Html(telemetry::render(&tera, "partials/activity_logs.html", &ctx)?))
 }
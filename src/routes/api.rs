@@ -0,0 +1,549 @@
+use axum::{
+    extract::{
+        rejection::{JsonRejection, PathRejection, QueryRejection},
+        Path, Query, State,
+    },
+    http::StatusCode,
+    response::Json,
+    routing::{get, post},
+    Router,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tera::Tera;
+use utoipa::{
+    openapi::{
+        security::{HttpAuthScheme, HttpBuilder, SecurityScheme},
+        Content, Ref, RefOr, ResponseBuilder,
+    },
+    IntoParams, Modify, OpenApi,
+};
+
+use crate::api::{
+    ApiCategory, ApiComment, ApiError, ApiMe, ApiNotification, ApiPost, ApiScore, ApiSearchResult, ApiStore, ApiUser,
+    ErrorBody, NewComment, NewPost, NewStore, NewStoreVote, NewVote, Page, PageQuery,
+};
+use crate::auth::{Commenter, Poster, StoreVoter};
+use crate::db::{self, Db};
+use crate::models::{ApiScope, Post};
+
+type ApiResult<T> = Result<Json<T>, ApiError>;
+type Created<T> = Result<(StatusCode, Json<T>), ApiError>;
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct PostListQuery {
+    /// Category slug
+    pub category: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct StoreListQuery {
+    /// Store category
+    pub category: Option<String>,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct NotificationQuery {
+    /// Only unread notifications
+    #[serde(default)]
+    pub unread: bool,
+}
+
+#[derive(Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+pub struct SearchQuery {
+    /// Search terms; supports the search page's operators
+    pub q: Option<String>,
+    pub category: Option<String>,
+    /// relevance, new or top
+    pub sort: Option<String>,
+    /// all, day, week, month or year
+    pub time: Option<String>,
+}
+
+/// The API's routes, nested under `/api/v1`
+pub fn router() -> Router<(Db, Arc<Tera>)> {
+    Router::new()
+        .route("/me", get(me))
+        .route("/categories", get(list_categories))
+        .route("/posts", get(list_posts).post(create_post))
+        .route("/posts/{id}", get(get_post))
+        .route("/posts/{id}/comments", get(list_comments).post(create_comment))
+        .route("/posts/{id}/vote", post(vote_post))
+        .route("/comments/{id}/vote", post(vote_comment))
+        .route("/stores", get(list_stores).post(create_store))
+        .route("/stores/{id}/vote", post(vote_store))
+        .route("/notifications", get(list_notifications))
+        .route("/notifications/read-all", post(mark_all_notifications_read))
+        .route("/notifications/{id}/read", post(mark_notification_read))
+        .route("/search", get(search))
+        .fallback(not_found)
+}
+
+/// The API's OpenAPI 3 document, generated from the handlers' `utoipa::path`
+/// attributes and the request/response types in [`crate::api`]. Served at
+/// `/api/openapi.json`. A handler added to [`router`] needs adding to
+/// `paths` too; `tests/api_tests.rs` checks every documented operation is
+/// routed and that real responses match their schemas.
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Wrench Forum API", description = "Personal-token JSON API. Create a token under Settings → API tokens."),
+    servers((url = "/api/v1")),
+    paths(
+        me,
+        list_categories,
+        list_posts,
+        create_post,
+        get_post,
+        list_comments,
+        create_comment,
+        vote_post,
+        vote_comment,
+        list_stores,
+        create_store,
+        vote_store,
+        list_notifications,
+        mark_all_notifications_read,
+        mark_notification_read,
+        search,
+    ),
+    security(("bearer" = [])),
+    modifiers(&BearerAuth),
+)]
+pub struct ApiDoc;
+
+/// Declares the bearer token scheme and the 401 every operation can return,
+/// and gives undescribed responses their status's reason phrase
+struct BearerAuth;
+
+impl Modify for BearerAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        let components = openapi.components.get_or_insert_with(Default::default);
+        components.add_security_scheme(
+            "bearer",
+            SecurityScheme::Http(
+                HttpBuilder::new()
+                    .scheme(HttpAuthScheme::Bearer)
+                    .description(Some("A personal access token, `wf_...`"))
+                    .build(),
+            ),
+        );
+
+        let unauthorized = ResponseBuilder::new()
+            .description("Missing, invalid or revoked token")
+            .content("application/json", Content::new(Some(Ref::from_schema_name("ErrorBody"))))
+            .build();
+        for item in openapi.paths.paths.values_mut() {
+            for operation in [&mut item.get, &mut item.post].into_iter().flatten() {
+                operation.responses.responses.insert("401".to_string(), unauthorized.clone().into());
+                for (status, response) in operation.responses.responses.iter_mut() {
+                    if let RefOr::T(response) = response {
+                        if response.description.is_empty() {
+                            let reason = status.parse::<u16>().ok().and_then(|s| StatusCode::from_u16(s).ok()).and_then(|s| s.canonical_reason());
+                            response.description = reason.unwrap_or_default().to_string();
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// `GET /api/openapi.json`
+pub async fn openapi_json() -> Json<utoipa::openapi::OpenApi> {
+    Json(ApiDoc::openapi())
+}
+
+/// Any path under `/api/v1` without a handler
+pub async fn not_found() -> ApiError {
+    ApiError::not_found("No such endpoint")
+}
+
+#[utoipa::path(
+    get, path = "/me", tag = "account", summary = "The token's owner",
+    responses((status = 200, body = ApiMe)),
+)]
+pub async fn me(api_user: ApiUser) -> ApiResult<ApiMe> {
+    Ok(Json(ApiMe::from(&api_user)))
+}
+
+#[utoipa::path(
+    get, path = "/categories", tag = "posts", summary = "List categories",
+    responses((status = 200, body = Page<ApiCategory>), (status = 403, body = ErrorBody)),
+)]
+pub async fn list_categories(
+    api_user: ApiUser,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+) -> ApiResult<Page<ApiCategory>> {
+    api_user.require(ApiScope::Read)?;
+    let categories = db.read(db::get_categories).await?;
+    Ok(Json(Page::all(categories.into_iter().map(ApiCategory::from).collect())))
+}
+
+/// Newest first
+#[utoipa::path(
+    get, path = "/posts", tag = "posts", summary = "List posts",
+    params(PageQuery, PostListQuery),
+    responses((status = 200, body = Page<ApiPost>), (status = 400, body = ErrorBody), (status = 403, body = ErrorBody)),
+)]
+pub async fn list_posts(
+    api_user: ApiUser,
+    page: Result<Query<PageQuery>, QueryRejection>,
+    filter: Result<Query<PostListQuery>, QueryRejection>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+) -> ApiResult<Page<ApiPost>> {
+    api_user.require(ApiScope::Read)?;
+    let (Query(page), Query(filter)) = (page?, filter?);
+    let (before, limit) = (page.position()?, page.limit());
+    let category = filter.category.filter(|c| !c.is_empty());
+    let posts = db.read(move |conn| db::get_posts_before(conn, category.as_deref(), before, limit + 1)).await?;
+    Ok(Json(Page::from_overfetch(posts, limit, |p| p.id, ApiPost::from)))
+}
+
+#[utoipa::path(
+    get, path = "/posts/{id}", tag = "posts", summary = "Get a post",
+    params(("id" = i64, Path)),
+    responses((status = 200, body = ApiPost), (status = 403, body = ErrorBody), (status = 404, body = ErrorBody)),
+)]
+pub async fn get_post(
+    api_user: ApiUser,
+    id: Result<Path<i64>, PathRejection>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+) -> ApiResult<ApiPost> {
+    api_user.require(ApiScope::Read)?;
+    let Path(id) = id?;
+    let post = visible_post(&db, id, api_user.user.id).await?;
+    Ok(Json(ApiPost::from(post)))
+}
+
+/// A post that exists and hasn't been removed, or was removed but belongs to
+/// `user_id`, matching what the post page shows
+async fn visible_post(db: &Db, post_id: i64, user_id: i64) -> Result<Post, ApiError> {
+    db.read(move |conn| db::get_post_by_id(conn, post_id))
+        .await?
+        .filter(|p| !p.removed || p.user_id == user_id)
+        .ok_or_else(|| ApiError::not_found("Post not found"))
+}
+
+#[utoipa::path(
+    post, path = "/posts", tag = "posts", summary = "Create a post",
+    request_body = NewPost,
+    responses((status = 201, body = ApiPost), (status = 403, body = ErrorBody), (status = 422, body = ErrorBody)),
+)]
+pub async fn create_post(
+    api_user: ApiUser,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    payload: Result<Json<NewPost>, JsonRejection>,
+) -> Created<ApiPost> {
+    api_user.require(ApiScope::Write)?;
+    let Json(new_post) = payload?;
+    api_user.require_role::<Poster>()?;
+    if new_post.title.trim().is_empty() || new_post.title.len() > 300 {
+        return Err(ApiError::validation("Title must be between 1 and 300 characters"));
+    }
+    if new_post.body.trim().is_empty() {
+        return Err(ApiError::validation("Post body cannot be empty"));
+    }
+
+    let user_id = api_user.user.id;
+    let post = db.write(move |conn| {
+        if !db::get_categories(conn)?.iter().any(|c| c.id == new_post.category_id) {
+            return Ok(None);
+        }
+        let known_tags = db::get_all_tags(conn)?;
+        let tags: Vec<i64> = new_post.tags.iter().copied().filter(|id| known_tags.iter().any(|t| t.id == *id)).collect();
+        let post_id = db::create_post_with_tags(conn, user_id, new_post.category_id, &new_post.title, &new_post.body, &tags, None)?;
+        db::log_activity(conn, user_id, "create_post", Some("post"), Some(post_id), Some("api"), None)?;
+        db::get_post_by_id(conn, post_id)
+    }).await?;
+
+    let post = post.ok_or_else(|| ApiError::validation("Unknown category"))?;
+    Ok((StatusCode::CREATED, Json(ApiPost::from(post))))
+}
+
+/// Oldest first, flat; replies point at their parent with `parent_id`
+#[utoipa::path(
+    get, path = "/posts/{id}/comments", tag = "comments", summary = "List a post's comments",
+    params(("id" = i64, Path), PageQuery),
+    responses((status = 200, body = Page<ApiComment>), (status = 400, body = ErrorBody), (status = 403, body = ErrorBody), (status = 404, body = ErrorBody)),
+)]
+pub async fn list_comments(
+    api_user: ApiUser,
+    post_id: Result<Path<i64>, PathRejection>,
+    page: Result<Query<PageQuery>, QueryRejection>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+) -> ApiResult<Page<ApiComment>> {
+    api_user.require(ApiScope::Read)?;
+    let (Path(post_id), Query(page)) = (post_id?, page?);
+    let (after, limit) = (page.position()?, page.limit());
+    visible_post(&db, post_id, api_user.user.id).await?;
+    let comments = db.read(move |conn| db::get_comments_after(conn, post_id, after, limit + 1)).await?;
+    Ok(Json(Page::from_overfetch(comments, limit, |c| c.id, ApiComment::from)))
+}
+
+#[utoipa::path(
+    post, path = "/posts/{id}/comments", tag = "comments", summary = "Comment on a post",
+    params(("id" = i64, Path)),
+    request_body = NewComment,
+    responses((status = 201, body = ApiComment), (status = 403, body = ErrorBody), (status = 404, body = ErrorBody), (status = 422, body = ErrorBody)),
+)]
+pub async fn create_comment(
+    api_user: ApiUser,
+    post_id: Result<Path<i64>, PathRejection>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    payload: Result<Json<NewComment>, JsonRejection>,
+) -> Created<ApiComment> {
+    api_user.require(ApiScope::Write)?;
+    api_user.require_role::<Commenter>()?;
+    let (Path(post_id), Json(new_comment)) = (post_id?, payload?);
+    if !api_user.user.email_confirmed {
+        return Err(ApiError::forbidden("Confirm your email address to comment"));
+    }
+    if new_comment.body.trim().is_empty() {
+        return Err(ApiError::validation("Comment cannot be empty"));
+    }
+    let post = visible_post(&db, post_id, api_user.user.id).await?;
+    if post.removed {
+        return Err(ApiError::forbidden("This post has been removed"));
+    }
+
+    let user_id = api_user.user.id;
+    let comment = db.write(move |conn| {
+        if let Some(parent_id) = new_comment.parent_id {
+            if db::get_comment_by_id(conn, parent_id)?.filter(|c| c.post_id == post_id && !c.removed).is_none() {
+                return Ok(None);
+            }
+        }
+        let comment_id = db::create_comment(conn, post_id, user_id, new_comment.parent_id, &new_comment.body)?;
+        db::get_comment_by_id(conn, comment_id)
+    }).await?;
+
+    let comment = comment.ok_or_else(|| ApiError::validation("parent_id isn't a comment on this post"))?;
+    Ok((StatusCode::CREATED, Json(ApiComment::from(comment))))
+}
+
+/// Voting the same way twice takes the vote back, as on the site
+#[utoipa::path(
+    post, path = "/posts/{id}/vote", tag = "posts", summary = "Vote on a post",
+    params(("id" = i64, Path)),
+    request_body = NewVote,
+    responses((status = 200, body = ApiScore), (status = 403, body = ErrorBody), (status = 404, body = ErrorBody), (status = 422, body = ErrorBody)),
+)]
+pub async fn vote_post(
+    api_user: ApiUser,
+    post_id: Result<Path<i64>, PathRejection>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    payload: Result<Json<NewVote>, JsonRejection>,
+) -> ApiResult<ApiScore> {
+    api_user.require(ApiScope::Write)?;
+    let (Path(post_id), Json(vote)) = (post_id?, payload?);
+    let value = vote_value(&vote)?;
+    let post = visible_post(&db, post_id, api_user.user.id).await?;
+    let user_id = api_user.user.id;
+    let score = db.write(move |conn| db::vote_post(conn, user_id, post.id, value)).await?;
+    Ok(Json(ApiScore { score }))
+}
+
+#[utoipa::path(
+    post, path = "/comments/{id}/vote", tag = "comments", summary = "Vote on a comment",
+    params(("id" = i64, Path)),
+    request_body = NewVote,
+    responses((status = 200, body = ApiScore), (status = 403, body = ErrorBody), (status = 404, body = ErrorBody), (status = 422, body = ErrorBody)),
+)]
+pub async fn vote_comment(
+    api_user: ApiUser,
+    comment_id: Result<Path<i64>, PathRejection>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    payload: Result<Json<NewVote>, JsonRejection>,
+) -> ApiResult<ApiScore> {
+    api_user.require(ApiScope::Write)?;
+    let (Path(comment_id), Json(vote)) = (comment_id?, payload?);
+    let value = vote_value(&vote)?;
+    db.read(move |conn| db::get_comment_by_id(conn, comment_id))
+        .await?
+        .filter(|c| !c.removed)
+        .ok_or_else(|| ApiError::not_found("Comment not found"))?;
+    let user_id = api_user.user.id;
+    let score = db.write(move |conn| db::vote_comment(conn, user_id, comment_id, value)).await?;
+    Ok(Json(ApiScore { score }))
+}
+
+fn vote_value(vote: &NewVote) -> Result<i64, ApiError> {
+    match vote.value {
+        1 | -1 => Ok(vote.value),
+        _ => Err(ApiError::validation("value must be 1 or -1")),
+    }
+}
+
+/// In the order they were submitted
+#[utoipa::path(
+    get, path = "/stores", tag = "stores", summary = "List parts stores",
+    params(PageQuery, StoreListQuery),
+    responses((status = 200, body = Page<ApiStore>), (status = 400, body = ErrorBody), (status = 403, body = ErrorBody)),
+)]
+pub async fn list_stores(
+    api_user: ApiUser,
+    page: Result<Query<PageQuery>, QueryRejection>,
+    filter: Result<Query<StoreListQuery>, QueryRejection>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+) -> ApiResult<Page<ApiStore>> {
+    api_user.require(ApiScope::Read)?;
+    let (Query(page), Query(filter)) = (page?, filter?);
+    let (after, limit) = (page.position()?, page.limit());
+    let category = filter.category.filter(|c| !c.is_empty());
+    let stores = db.read(move |conn| db::get_stores_after(conn, category.as_deref(), after, limit + 1)).await?;
+    Ok(Json(Page::from_overfetch(stores, limit, |s| s.id, ApiStore::from)))
+}
+
+#[utoipa::path(
+    post, path = "/stores", tag = "stores", summary = "Submit a parts store",
+    request_body = NewStore,
+    responses((status = 201, body = ApiStore), (status = 403, body = ErrorBody), (status = 422, body = ErrorBody)),
+)]
+pub async fn create_store(
+    api_user: ApiUser,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    payload: Result<Json<NewStore>, JsonRejection>,
+) -> Created<ApiStore> {
+    api_user.require(ApiScope::Write)?;
+    let Json(new_store) = payload?;
+    api_user.require_role::<StoreVoter>()?;
+    if new_store.name.trim().is_empty() {
+        return Err(ApiError::validation("Store name is required"));
+    }
+    if !new_store.url.starts_with("http://") && !new_store.url.starts_with("https://") {
+        return Err(ApiError::validation("URL must start with http:// or https://"));
+    }
+    if new_store.category.trim().is_empty() {
+        return Err(ApiError::validation("Store category is required"));
+    }
+
+    let user_id = api_user.user.id;
+    let store = db.write(move |conn| {
+        let store_id = db::create_store(conn, &new_store.name, &new_store.url, new_store.description.as_deref(), &new_store.category, user_id)?;
+        db::get_store_by_id(conn, store_id)
+    }).await?;
+
+    let store = store.ok_or_else(ApiError::internal)?;
+    Ok((StatusCode::CREATED, Json(ApiStore::from(store))))
+}
+
+#[utoipa::path(
+    post, path = "/stores/{id}/vote", tag = "stores", summary = "Rate a parts store",
+    params(("id" = i64, Path)),
+    request_body = NewStoreVote,
+    responses((status = 200, body = ApiStore), (status = 403, body = ErrorBody), (status = 404, body = ErrorBody)),
+)]
+pub async fn vote_store(
+    api_user: ApiUser,
+    store_id: Result<Path<i64>, PathRejection>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    payload: Result<Json<NewStoreVote>, JsonRejection>,
+) -> ApiResult<ApiStore> {
+    api_user.require(ApiScope::Write)?;
+    let (Path(store_id), Json(vote)) = (store_id?, payload?);
+    api_user.require_role::<StoreVoter>()?;
+
+    let user_id = api_user.user.id;
+    let store = db.write(move |conn| {
+        if db::get_store_by_id(conn, store_id)?.is_none() {
+            return Ok(None);
+        }
+        db::vote_store(conn, store_id, user_id, vote.positive)?;
+        db::get_store_by_id(conn, store_id)
+    }).await?;
+
+    let store = store.ok_or_else(|| ApiError::not_found("Store not found"))?;
+    Ok(Json(ApiStore::from(store)))
+}
+
+/// Newest first; `?unread=true` for only unread ones
+#[utoipa::path(
+    get, path = "/notifications", tag = "notifications", summary = "List notifications",
+    params(PageQuery, NotificationQuery),
+    responses((status = 200, body = Page<ApiNotification>), (status = 400, body = ErrorBody), (status = 403, body = ErrorBody)),
+)]
+pub async fn list_notifications(
+    api_user: ApiUser,
+    page: Result<Query<PageQuery>, QueryRejection>,
+    filter: Result<Query<NotificationQuery>, QueryRejection>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+) -> ApiResult<Page<ApiNotification>> {
+    api_user.require(ApiScope::Notifications)?;
+    let (Query(page), Query(filter)) = (page?, filter?);
+    let (before, limit) = (page.position()?, page.limit());
+    let user_id = api_user.user.id;
+    let notifications = db.read(move |conn| {
+        db::get_notifications_before(conn, user_id, filter.unread, before, limit + 1)
+    }).await?;
+    Ok(Json(Page::from_overfetch(notifications, limit, |n| n.id, ApiNotification::from)))
+}
+
+#[utoipa::path(
+    post, path = "/notifications/{id}/read", tag = "notifications", summary = "Mark a notification read",
+    params(("id" = i64, Path)),
+    responses((status = 204), (status = 403, body = ErrorBody), (status = 404, body = ErrorBody)),
+)]
+pub async fn mark_notification_read(
+    api_user: ApiUser,
+    id: Result<Path<i64>, PathRejection>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+) -> Result<StatusCode, ApiError> {
+    api_user.require(ApiScope::Notifications)?;
+    let Path(id) = id?;
+    let user_id = api_user.user.id;
+    if db.write(move |conn| db::mark_user_notification_read(conn, user_id, id)).await? {
+        Ok(StatusCode::NO_CONTENT)
+    } else {
+        Err(ApiError::not_found("Notification not found"))
+    }
+}
+
+#[utoipa::path(
+    post, path = "/notifications/read-all", tag = "notifications", summary = "Mark every notification read",
+    responses((status = 204), (status = 403, body = ErrorBody)),
+)]
+pub async fn mark_all_notifications_read(
+    api_user: ApiUser,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+) -> Result<StatusCode, ApiError> {
+    api_user.require(ApiScope::Notifications)?;
+    let user_id = api_user.user.id;
+    db.write(move |conn| db::mark_all_notifications_read(conn, user_id)).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+/// Post search with the same operators as the search page
+#[utoipa::path(
+    get, path = "/search", tag = "search", summary = "Search posts",
+    params(PageQuery, SearchQuery),
+    responses((status = 200, body = Page<ApiSearchResult>), (status = 400, body = ErrorBody), (status = 403, body = ErrorBody), (status = 422, body = ErrorBody)),
+)]
+pub async fn search(
+    api_user: ApiUser,
+    page: Result<Query<PageQuery>, QueryRejection>,
+    query: Result<Query<SearchQuery>, QueryRejection>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+) -> ApiResult<Page<ApiSearchResult>> {
+    api_user.require(ApiScope::Read)?;
+    let (Query(page), Query(query)) = (page?, query?);
+    let Some(q) = query.q.filter(|q| !q.trim().is_empty()) else {
+        return Err(ApiError::validation("q is required"));
+    };
+    // Ranked results have no stable key, so the cursor is an offset
+    let (offset, limit) = (page.position()?.unwrap_or(0), page.limit());
+    let category = query.category.filter(|c| !c.is_empty());
+    let sort = query.sort.unwrap_or_else(|| "relevance".to_string());
+    let time = query.time.unwrap_or_else(|| "all".to_string());
+    let posts = db.read(move |conn| {
+        db::search_posts_sorted(conn, &q, category.as_deref(), &sort, &time, offset + limit + 1)
+    }).await?;
+
+    let mut results: Vec<_> = posts.into_iter().skip(offset as usize).collect();
+    let next_cursor = (results.len() as i64 > limit).then(|| (offset + limit).to_string());
+    results.truncate(limit as usize);
+    Ok(Json(Page { data: results.into_iter().map(ApiSearchResult::from).collect(), next_cursor }))
+}
@@ -1,19 +1,36 @@
 use axum::{
-    extract::State,
-    response::Html,
-    Form,
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Extension, Form,
 };
 use axum_extra::extract::CookieJar;
+use chrono::Utc;
 use serde::Deserialize;
 use std::sync::Arc;
 use tera::{Context, Tera};
+use tracing::Instrument;
 
 use crate::auth::{
-    ensure_session, hash_password, verify_password, 
+    hash_password, verify_password, CurrentUser, MaybeUser,
     create_session_token, session_expiry, set_session_cookie, clear_session_cookie,
     is_valid_email, is_valid_username, is_valid_password,
+    create_email_token, hash_token, now_timestamp, password_reset_expiry, PASSWORD_RESET_MINUTES,
+    email_confirmation_expiry, email_resend_wait, EMAIL_CONFIRMATION_HOURS,
+    check_second_factor, login_challenge_expiry, set_login_challenge_cookie, clear_login_challenge_cookie,
+    two_factor_setup_required, LOGIN_CHALLENGE_ATTEMPTS, user_agent,
 };
+use crate::config::Config;
 use crate::db::{self, Db};
+use crate::error::{redirect, toast, AppError, HtmlResult};
+use crate::mail::{self, Mail};
+use crate::rate_limit::{
+    allow_password_reset, allow_registration, describe_wait, finish_failed_login, normalize_account, record_login_failure,
+    record_login_success, start_login, withdraw_login_attempt,
+    ClientIp, LoginCheck, RateLimits,
+};
+use crate::shutdown::Shutdown;
+use crate::telemetry;
 
 #[derive(Deserialize)]
 pub struct RegisterForm {
@@ -29,26 +46,47 @@ pub struct LoginForm {
     pub password: String,
 }
 
+#[derive(Deserialize)]
+pub struct TwoFactorLoginForm {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordForm {
+    pub email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordForm {
+    pub password: String,
+    pub password_confirm: String,
+}
+
 pub async fn register_page(
-    jar: CookieJar,
-    State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
+    MaybeUser(user): MaybeUser,
+    State((_, tera)): State<(Db, Arc<Tera>)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     // Redirect if already logged in
-    if let Some((_, jar)) = ensure_session(jar.clone(), &db) {
-        let html = r#"<script>window.location.href = "/";</script>"#.to_string();
-        return (jar, Html(html));
+    if user.is_some() {
+        return Ok(redirect(&headers, "/"));
     }
     
     let ctx = Context::new();
-    let html = tera.render("register.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-    (jar, Html(html))
+    Ok(Html(telemetry::render(&tera, "register.html", &ctx)?).into_response())
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn register_submit(
     jar: CookieJar,
     State((db, tera)): State<(Db, Arc<Tera>)>,
+    Extension(mail): Extension<Mail>,
+    Extension(limits): Extension<RateLimits>,
+    Extension(config): Extension<Arc<Config>>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
     Form(form): Form<RegisterForm>,
-) -> (CookieJar, Html<String>) {
+) -> Result<Response, AppError> {
     let mut ctx = Context::new();
     
     // Validation
@@ -74,135 +112,512 @@ pub async fn register_submit(
         ctx.insert("errors", &errors);
         ctx.insert("email", &form.email);
         ctx.insert("username", &form.username);
-        let html = tera.render("register.html", &ctx).unwrap();
-        return (jar, Html(html));
+        let html = telemetry::render(&tera, "register.html", &ctx)?;
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, jar, Html(html)).into_response());
     }
     
-    let conn = db.lock().unwrap();
-    
-    // Check if email exists
-    if db::get_user_by_email(&conn, &form.email).ok().flatten().is_some() {
-        ctx.insert("errors", &vec!["Email already registered"]);
+    let register_ip = ip.clone();
+    let allowed = db.write(move |conn| allow_registration(conn, &limits, &register_ip, Utc::now())).await?;
+    if !allowed {
+        ctx.insert("errors", &vec!["Too many accounts created from your network. Try again in an hour."]);
+        ctx.insert("email", &form.email);
         ctx.insert("username", &form.username);
-        let html = tera.render("register.html", &ctx).unwrap();
-        return (jar, Html(html));
+        let html = telemetry::render(&tera, "register.html", &ctx)?;
+        return Ok((StatusCode::TOO_MANY_REQUESTS, jar, Html(html)).into_response());
     }
     
-    // Check if username exists
-    if db::get_user_by_username(&conn, &form.username).ok().flatten().is_some() {
-        ctx.insert("errors", &vec!["Username already taken"]);
-        ctx.insert("email", &form.email);
-        let html = tera.render("register.html", &ctx).unwrap();
-        return (jar, Html(html));
-    }
+    // Hash before touching the database; Argon2 is deliberately slow
+    let password_hash = hash_password(&form.password)?;
     
-    // Create user
-    let password_hash = match hash_password(&form.password) {
-        Ok(h) => h,
-        Err(_) => {
-            ctx.insert("errors", &vec!["Failed to create account"]);
-            let html = tera.render("register.html", &ctx).unwrap();
-            return (jar, Html(html));
+    let email = form.email.clone();
+    let username = form.username.clone();
+    let confirmation_token = create_email_token();
+    let confirmation_hash = hash_token(&confirmation_token);
+    let agent = user_agent(&headers);
+    let lifetime_days = config.sessions.lifetime_days;
+    let result = db.write(move |conn| {
+        // Check if email exists
+        if db::get_user_by_email(conn, &email)?.is_some() {
+            return Ok(Err("Email already registered"));
         }
-    };
+        
+        // Check if username exists
+        if db::get_user_by_username(conn, &username)?.is_some() {
+            return Ok(Err("Username already taken"));
+        }
+        
+        // Create user and session
+        let user_id = db::create_user(conn, &email, &password_hash, &username)?;
+        let token = create_session_token();
+        let expiry = session_expiry(lifetime_days);
+        db::create_session(conn, &token, user_id, &expiry, Some(&ip), agent.as_deref())?;
+        db::create_email_confirmation_token(conn, user_id, &confirmation_hash, &email_confirmation_expiry())?;
+        Ok(Ok(token))
+    }).await?;
     
-    let user_id = match db::create_user(&conn, &form.email, &password_hash, &form.username) {
-        Ok(id) => id,
-        Err(_) => {
-            ctx.insert("errors", &vec!["Failed to create account"]);
-            let html = tera.render("register.html", &ctx).unwrap();
-            return (jar, Html(html));
+    let token = match result {
+        Ok(token) => token,
+        Err(message) => {
+            ctx.insert("errors", &vec![message]);
+            if message.starts_with("Email") {
+                ctx.insert("username", &form.username);
+            } else {
+                ctx.insert("email", &form.email);
+            }
+            let html = telemetry::render(&tera, "register.html", &ctx)?;
+            return Ok((StatusCode::CONFLICT, jar, Html(html)).into_response());
         }
     };
     
-    // Create session
-    let token = create_session_token();
-    let expiry = session_expiry();
-    let _ = db::create_session(&conn, &token, user_id, &expiry);
+    let link = mail.link(&format!("/confirm-email/{}", confirmation_token));
+    let message = mail::email_confirmation_email(&form.email, &form.username, &link, EMAIL_CONFIRMATION_HOURS);
+    if let Err(e) = mail.send(message).await {
+        tracing::error!(username = %form.username, error = %e, "failed to send confirmation email");
+    }
     
-    let jar = set_session_cookie(jar, &token);
+    let jar = set_session_cookie(jar, &token, lifetime_days, config.server.secure_cookies);
     
-    let html = r#"<script>window.location.href = "/verification";</script>"#.to_string();
-    (jar, Html(html))
+    Ok((jar, redirect(&headers, "/verification")).into_response())
 }
 
 pub async fn login_page(
-    jar: CookieJar,
-    State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
+    MaybeUser(user): MaybeUser,
+    State((_, tera)): State<(Db, Arc<Tera>)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     // Redirect if already logged in
-    if let Some((_, jar)) = ensure_session(jar.clone(), &db) {
-        let html = r#"<script>window.location.href = "/";</script>"#.to_string();
-        return (jar, Html(html));
+    if user.is_some() {
+        return Ok(redirect(&headers, "/"));
     }
     
     let ctx = Context::new();
-    let html = tera.render("login.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-    (jar, Html(html))
+    Ok(Html(telemetry::render(&tera, "login.html", &ctx)?).into_response())
+}
+
+/// The login form again, with a message above it
+fn login_error(tera: &Tera, jar: CookieJar, status: StatusCode, message: &str, email: &str) -> Result<Response, AppError> {
+    let mut ctx = Context::new();
+    ctx.insert("error", message);
+    ctx.insert("email", email);
+    let html = telemetry::render(tera, "login.html", &ctx)?;
+    Ok((status, jar, Html(html)).into_response())
 }
 
 pub async fn login_submit(
     jar: CookieJar,
     State((db, tera)): State<(Db, Arc<Tera>)>,
+    Extension(limits): Extension<RateLimits>,
+    Extension(config): Extension<Arc<Config>>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
     Form(form): Form<LoginForm>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
-    
-    let conn = db.lock().unwrap();
-    
+) -> Result<Response, AppError> {
     // Find user
-    let (user, password_hash) = match db::get_user_by_email(&conn, &form.email) {
-        Ok(Some(data)) => data,
-        _ => {
-            ctx.insert("error", "Invalid email or password");
-            ctx.insert("email", &form.email);
-            let html = tera.render("login.html", &ctx).unwrap();
-            return (jar, Html(html));
+    let email = form.email.clone();
+    let found = db.read(move |conn| db::get_user_by_email(conn, &email)).await?;
+    
+    // Throttle before spending time on the password hash. The attempt is
+    // counted as it passes, on the write connection, so parallel guesses
+    // are counted one after another.
+    let account = normalize_account(&form.email);
+    let user_id = found.as_ref().map(|(user, _)| user.id);
+    let (check_limits, check_ip, check_account) = (limits.clone(), ip.clone(), account.clone());
+    let started = db.write(move |conn| {
+        start_login(conn, &check_limits, &check_ip, &check_account, user_id, Utc::now())
+    }).await?;
+    let attempt_id = match started {
+        Ok(attempt_id) => attempt_id,
+        Err(LoginCheck::Allowed) => unreachable!("start_login only refuses with a reason"),
+        Err(LoginCheck::IpLimited) => {
+            let message = format!("Too many failed sign-ins from your network. Try again in {}.", describe_wait(limits.window_minutes * 60));
+            return login_error(&tera, jar, StatusCode::TOO_MANY_REQUESTS, &message, &form.email);
+        }
+        Err(LoginCheck::Locked(until)) => {
+            let message = format!("This account is locked after too many failed sign-ins until {} UTC. Reset your password to get back in sooner.", until);
+            return login_error(&tera, jar, StatusCode::TOO_MANY_REQUESTS, &message, &form.email);
+        }
+        Err(LoginCheck::Delayed(seconds)) => {
+            let message = format!("Too many failed sign-ins. Wait {} before trying again.", describe_wait(seconds));
+            return login_error(&tera, jar, StatusCode::TOO_MANY_REQUESTS, &message, &form.email);
         }
     };
     
     // Verify password
-    if !verify_password(&form.password, &password_hash) {
-        ctx.insert("error", "Invalid email or password");
-        ctx.insert("email", &form.email);
-        let html = tera.render("login.html", &ctx).unwrap();
-        return (jar, Html(html));
-    }
+    let user = match found {
+        Some((user, hash)) if verify_password(&form.password, &hash) => user,
+        _ => {
+            let failure_limits = limits.clone();
+            let locked = db.write(move |conn| {
+                finish_failed_login(conn, &failure_limits, &ip, &account, user_id, Utc::now())
+            }).await?;
+            if locked {
+                let message = format!("Too many failed sign-ins. This account is locked for {}.", describe_wait(limits.lockout_minutes * 60));
+                return login_error(&tera, jar, StatusCode::TOO_MANY_REQUESTS, &message, &form.email);
+            }
+            return login_error(&tera, jar, StatusCode::UNAUTHORIZED, "Invalid email or password", &form.email);
+        }
+    };
     
     // Check if banned
     if user.banned {
-        ctx.insert("error", "Your account has been banned");
-        let html = tera.render("login.html", &ctx).unwrap();
-        return (jar, Html(html));
+        db.write(move |conn| withdraw_login_attempt(conn, attempt_id)).await?;
+        return login_error(&tera, jar, StatusCode::FORBIDDEN, "Your account has been banned", "");
+    }
+    
+    // Accounts with two-factor get a session only after the second step,
+    // which records the attempt's outcome
+    if user.two_factor_enabled {
+        let challenge = create_email_token();
+        let challenge_hash = hash_token(&challenge);
+        let user_id = user.id;
+        db.write(move |conn| {
+            withdraw_login_attempt(conn, attempt_id)?;
+            db::create_login_challenge(conn, &challenge_hash, user_id, &login_challenge_expiry())
+        }).await?;
+        
+        let jar = set_login_challenge_cookie(jar, &challenge, config.server.secure_cookies);
+        return Ok((jar, redirect(&headers, "/login/two-factor")).into_response());
     }
     
     // Create session
     let token = create_session_token();
-    let expiry = session_expiry();
-    let _ = db::create_session(&conn, &token, user.id, &expiry);
+    let lifetime_days = config.sessions.lifetime_days;
+    let expiry = session_expiry(lifetime_days);
+    let session_token = token.clone();
+    let old_token = jar.get("session").map(|c| c.value().to_string());
+    let agent = user_agent(&headers);
+    let setup_required = db.write(move |conn| {
+        // A fresh token on every login; drop whatever session the browser had
+        if let Some(old_token) = old_token {
+            db::delete_session(conn, &old_token)?;
+        }
+        db::create_session(conn, &session_token, user.id, &expiry, Some(&ip), agent.as_deref())?;
+        withdraw_login_attempt(conn, attempt_id)?;
+        record_login_success(conn, &ip, &account, Utc::now())?;
+        // Log activity
+        db::log_activity(conn, user.id, "login", None, None, None, Some(&ip))?;
+        two_factor_setup_required(conn, &user)
+    }).await?;
+    
+    let jar = set_session_cookie(jar, &token, lifetime_days, config.server.secure_cookies);
+    
+    let location = if setup_required { "/profile/two-factor" } else { "/" };
+    Ok((jar, redirect(&headers, location)).into_response())
+}
+
+pub async fn login_two_factor_page(
+    jar: CookieJar,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let pending = match jar.get("login_challenge") {
+        Some(cookie) => {
+            let challenge_hash = hash_token(cookie.value());
+            db.read(move |conn| db::get_login_challenge(conn, &challenge_hash, &now_timestamp())).await?
+        }
+        None => None,
+    };
+    
+    if pending.is_none() {
+        let jar = clear_login_challenge_cookie(jar);
+        return Ok((jar, redirect(&headers, "/login")).into_response());
+    }
+    
+    let ctx = Context::new();
+    Ok((jar, Html(telemetry::render(&tera, "login_two_factor.html", &ctx)?)).into_response())
+}
+
+/// Second login step: an authenticator code or a recovery code. After
+/// `LOGIN_CHALLENGE_ATTEMPTS` wrong codes the password has to be entered
+/// again.
+pub async fn login_two_factor_submit(
+    jar: CookieJar,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Extension(limits): Extension<RateLimits>,
+    Extension(config): Extension<Arc<Config>>,
+    ClientIp(ip): ClientIp,
+    headers: HeaderMap,
+    Form(form): Form<TwoFactorLoginForm>,
+) -> Result<Response, AppError> {
+    let mut ctx = Context::new();
     
-    // Log activity
-    let _ = db::log_activity(&conn, user.id, "login", None, None, None, None);
+    let Some(challenge_hash) = jar.get("login_challenge").map(|c| hash_token(c.value())) else {
+        return Ok((jar, redirect(&headers, "/login")).into_response());
+    };
     
-    let jar = set_session_cookie(jar, &token);
+    // Err(true): the challenge is gone and sign-in starts over; Err(false):
+    // wrong code, try again
+    let code = form.code;
+    let old_token = jar.get("session").map(|c| c.value().to_string());
+    let agent = user_agent(&headers);
+    let lifetime_days = config.sessions.lifetime_days;
+    let result = db.write(move |conn| {
+        let pending = match db::get_login_challenge(conn, &challenge_hash, &now_timestamp())? {
+            Some(pending) if pending.attempts < LOGIN_CHALLENGE_ATTEMPTS => pending,
+            _ => {
+                db::delete_login_challenge(conn, &challenge_hash)?;
+                return Ok(Err(true));
+            }
+        };
+        
+        let user = match db::get_user_by_id(conn, pending.user_id)? {
+            Some(user) if !user.banned => user,
+            _ => {
+                db::delete_login_challenge(conn, &challenge_hash)?;
+                return Ok(Err(true));
+            }
+        };
+        let account = normalize_account(&user.email);
+        let now = Utc::now();
+        
+        // Wrong codes count against the account like wrong passwords
+        if !check_second_factor(conn, user.id, &code)? {
+            db::record_login_challenge_failure(conn, &challenge_hash)?;
+            if record_login_failure(conn, &limits, &ip, &account, Some(user.id), now)? {
+                db::delete_login_challenge(conn, &challenge_hash)?;
+                return Ok(Err(true));
+            }
+            return Ok(Err(false));
+        }
+        
+        db::delete_login_challenge(conn, &challenge_hash)?;
+        if db::get_user_locked_until(conn, user.id, &now_timestamp())?.is_some() {
+            return Ok(Err(true));
+        }
+        if let Some(old_token) = old_token {
+            db::delete_session(conn, &old_token)?;
+        }
+        let token = create_session_token();
+        db::create_session(conn, &token, user.id, &session_expiry(lifetime_days), Some(&ip), agent.as_deref())?;
+        record_login_success(conn, &ip, &account, now)?;
+        db::log_activity(conn, user.id, "login", None, None, None, Some(&ip))?;
+        Ok(Ok(token))
+    }).await?;
     
-    let html = r#"<script>window.location.href = "/";</script>"#.to_string();
-    (jar, Html(html))
+    match result {
+        Ok(token) => {
+            let jar = set_session_cookie(clear_login_challenge_cookie(jar), &token, lifetime_days, config.server.secure_cookies);
+            Ok((jar, redirect(&headers, "/")).into_response())
+        }
+        Err(false) => {
+            ctx.insert("error", "That code didn't work. Check your authenticator app and try again.");
+            let html = telemetry::render(&tera, "login_two_factor.html", &ctx)?;
+            Ok((StatusCode::UNAUTHORIZED, jar, Html(html)).into_response())
+        }
+        Err(true) => {
+            ctx.insert("error", "Your sign-in timed out or was locked. Please enter your password again.");
+            let html = telemetry::render(&tera, "login.html", &ctx)?;
+            Ok((StatusCode::UNAUTHORIZED, clear_login_challenge_cookie(jar), Html(html)).into_response())
+        }
+    }
 }
 
 pub async fn logout(
     jar: CookieJar,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
     if let Some(cookie) = jar.get("session") {
         let token = cookie.value().to_string();
-        if let Ok(conn) = db.lock() {
-            let _ = db::delete_session(&conn, &token);
-        }
+        db.write(move |conn| db::delete_session(conn, &token)).await?;
     }
     
     let jar = clear_session_cookie(jar);
     
-    let html = r#"<script>window.location.href = "/";</script>"#.to_string();
-    (jar, Html(html))
+    Ok((jar, redirect(&headers, "/")).into_response())
+}
+
+pub async fn forgot_password_page(
+    State((_, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let ctx = Context::new();
+    Ok(Html(telemetry::render(&tera, "forgot_password.html", &ctx)?))
+}
+
+/// Emails a reset link if the address belongs to an account. The response
+/// is the same either way so the form can't be used to probe for accounts.
+pub async fn forgot_password_submit(
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Extension(mail): Extension<Mail>,
+    Extension(limits): Extension<RateLimits>,
+    Extension(shutdown): Extension<Shutdown>,
+    ClientIp(ip): ClientIp,
+    Form(form): Form<ForgotPasswordForm>,
+) -> Result<Response, AppError> {
+    let mut ctx = Context::new();
+    let email = form.email.trim().to_string();
+
+    if !is_valid_email(&email) {
+        ctx.insert("error", "Invalid email address");
+        ctx.insert("email", &email);
+        let html = telemetry::render(&tera, "forgot_password.html", &ctx)?;
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, Html(html)).into_response());
+    }
+
+    let token = create_email_token();
+    let token_hash = hash_token(&token);
+    let lookup = email.clone();
+    // `None` when throttled, then the user if the address has an account
+    let outcome = db.write(move |conn| {
+        if !allow_password_reset(conn, &limits, &ip, &normalize_account(&lookup), Utc::now())? {
+            return Ok(None);
+        }
+        let user = match db::get_user_by_email(conn, &lookup)? {
+            Some((user, _)) if !user.banned => user,
+            _ => return Ok(Some(None)),
+        };
+        db::create_password_reset_token(conn, user.id, &token_hash, &password_reset_expiry())?;
+        Ok(Some(Some(user)))
+    }).await?;
+
+    let Some(user) = outcome else {
+        ctx.insert("error", "Too many reset requests. Try again in an hour.");
+        ctx.insert("email", &email);
+        let html = telemetry::render(&tera, "forgot_password.html", &ctx)?;
+        return Ok((StatusCode::TOO_MANY_REQUESTS, Html(html)).into_response());
+    };
+
+    // Sent in the background so the response takes as long whether or not
+    // the address has an account; shutdown waits for it
+    if let Some(user) = user {
+        let link = mail.link(&format!("/reset-password/{}", token));
+        let message = mail::password_reset_email(&user.email, &user.username, &link, PASSWORD_RESET_MINUTES);
+        shutdown.spawn(
+            async move {
+                if let Err(e) = mail.send(message).await {
+                    tracing::error!(user_id = user.id, error = %e, "failed to send password reset email");
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+    }
+
+    ctx.insert("sent", &true);
+    ctx.insert("email", &email);
+    let html = telemetry::render(&tera, "forgot_password.html", &ctx)?;
+    Ok(Html(html).into_response())
+}
+
+pub async fn reset_password_page(
+    Path(token): Path<String>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let mut ctx = Context::new();
+    let token_hash = hash_token(&token);
+    let valid = db.read(move |conn| db::get_password_reset_user(conn, &token_hash, &now_timestamp()))
+        .await?
+        .is_some();
+
+    ctx.insert("token", &token);
+    ctx.insert("invalid", &!valid);
+    Ok(Html(telemetry::render(&tera, "reset_password.html", &ctx)?))
+}
+
+pub async fn reset_password_submit(
+    jar: CookieJar,
+    Path(token): Path<String>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Form(form): Form<ResetPasswordForm>,
+) -> Result<Response, AppError> {
+    let mut ctx = Context::new();
+    ctx.insert("token", &token);
+
+    let error = if !is_valid_password(&form.password) {
+        Some("Password must be at least 8 characters")
+    } else if form.password != form.password_confirm {
+        Some("Passwords do not match")
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        ctx.insert("error", error);
+        let html = telemetry::render(&tera, "reset_password.html", &ctx)?;
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, jar, Html(html)).into_response());
+    }
+
+    let password_hash = hash_password(&form.password)?;
+
+    let token_hash = hash_token(&token);
+    let user_id = db.write(move |conn| {
+        let user_id = db::reset_password(conn, &token_hash, &password_hash, &now_timestamp())?;
+        if let Some(user_id) = user_id {
+            db::log_activity(conn, user_id, "password_reset", None, None, None, None)?;
+        }
+        Ok(user_id)
+    }).await?;
+
+    if user_id.is_none() {
+        ctx.insert("invalid", &true);
+        let html = telemetry::render(&tera, "reset_password.html", &ctx)?;
+        return Ok((StatusCode::NOT_FOUND, jar, Html(html)).into_response());
+    }
+
+    // Every session was revoked, including this browser's
+    let jar = clear_session_cookie(jar);
+    ctx.insert("success", "Your password has been updated. Sign in with your new password.");
+    let html = telemetry::render(&tera, "login.html", &ctx)?;
+    Ok((jar, Html(html)).into_response())
+}
+
+/// Follows the link from the confirmation email. Works whether or not the
+/// user is signed in on this browser.
+pub async fn confirm_email(
+    MaybeUser(user): MaybeUser,
+    Path(token): Path<String>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let mut ctx = Context::new();
+    let token_hash = hash_token(&token);
+    let signed_in = user.map(|u| u.id);
+    let (confirmed, user) = db.write(move |conn| {
+        let confirmed = db::confirm_email(conn, &token_hash, &now_timestamp())?.is_some();
+        // Loaded after confirming so the page reflects the new state
+        let user = match signed_in {
+            Some(user_id) => db::get_user_by_id(conn, user_id)?,
+            None => None,
+        };
+        Ok((confirmed, user))
+    }).await?;
+
+    if let Some(user) = user {
+        ctx.insert("user", &user);
+    }
+    ctx.insert("confirmed", &confirmed);
+    Ok(Html(telemetry::render(&tera, "confirm_email.html", &ctx)?))
+}
+
+/// Sends a fresh confirmation link, replacing the previous one
+pub async fn resend_confirmation(
+    CurrentUser(user): CurrentUser,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    Extension(mail): Extension<Mail>,
+) -> Result<Response, AppError> {
+    if user.email_confirmed {
+        return Ok(Html(toast("success", "Your email is already confirmed")).into_response());
+    }
+
+    let user_id = user.id;
+    let token = create_email_token();
+    let token_hash = hash_token(&token);
+    let result = db.write(move |conn| {
+        let wait = email_resend_wait(db::get_last_email_confirmation_sent(conn, user_id)?.as_deref());
+        if wait > 0 {
+            return Ok(Err(wait));
+        }
+        db::create_email_confirmation_token(conn, user_id, &token_hash, &email_confirmation_expiry())?;
+        Ok(Ok(()))
+    }).await?;
+
+    if let Err(wait) = result {
+        let html = toast("error", &format!("Please wait {} seconds before asking again", wait));
+        return Ok((StatusCode::TOO_MANY_REQUESTS, Html(html)).into_response());
+    }
+
+    let link = mail.link(&format!("/confirm-email/{}", token));
+    let message = mail::email_confirmation_email(&user.email, &user.username, &link, EMAIL_CONFIRMATION_HOURS);
+    if let Err(e) = mail.send(message).await {
+        tracing::error!(user_id = user.id, error = %e, "failed to send confirmation email");
+        return Err(AppError::Internal);
+    }
+    Ok(Html(toast("success", &format!("Confirmation email sent to {}", user.email))).into_response())
 }
@@ -2,77 +2,73 @@ use axum::{
     extract::{Path, State},
     response::Html,
 };
-use axum_extra::extract::CookieJar;
 use std::sync::Arc;
-use tera::{Context, Tera};
+use tera::Tera;
 
-use crate::auth::ensure_session;
+use crate::auth::{CurrentUser, PageContext};
 use crate::db::{self, Db};
+use crate::error::{AppError, HtmlResult};
+use crate::telemetry;
 
 pub async fn list_bookmarks(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
+    PageContext(mut ctx): PageContext,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
+) -> HtmlResult {
+    let user_id = user.id;
+    let bookmarks = db.read(move |conn| db::get_user_bookmarks(conn, user_id)).await?;
     
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        let unread_count = db::get_unread_notification_count(&conn, user.id).unwrap_or(0);
-        ctx.insert("unread_notifications", &unread_count);
-        
-        let bookmarks = db::get_user_bookmarks(&conn, user.id).unwrap_or_default();
-        ctx.insert("user", &user);
-        ctx.insert("posts", &bookmarks);
-        ctx.insert("current_page", &"bookmarks");
-        
-        let html = tera.render("bookmarks.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-        return (jar, Html(html));
-    }
+    ctx.insert("posts", &bookmarks);
+    ctx.insert("current_page", &"bookmarks");
     
-    let html = r#"<script>window.location.href = "/login";</script>"#.to_string();
-    (jar, Html(html))
+    Ok(Html(telemetry::render(&tera, "bookmarks.html", &ctx)?))
 }
 
 pub async fn toggle_bookmark(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     Path(post_id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        
-        let is_bookmarked = db::is_post_bookmarked(&conn, user.id, post_id).unwrap_or(false);
-        
+) -> HtmlResult {
+    let user_id = user.id;
+    let was_bookmarked = db.write(move |conn| {
+        if db::get_post_by_id(conn, post_id)?.is_none() {
+            return Ok(None);
+        }
+        let is_bookmarked = db::is_post_bookmarked(conn, user_id, post_id)?;
         if is_bookmarked {
-            let _ = db::remove_bookmark(&conn, user.id, post_id);
-            return (jar, Html(format!(
-                r#"<button class="btn-icon bookmark-btn" 
-                           hx-post="/post/{}/bookmark" 
-                           hx-swap="outerHTML"
-                           title="Add to bookmarks">
-                    <span class="icon">🔖</span>
-                </button>
-                <div id="toast-container" hx-swap-oob="beforeend">
-                    <div class="toast">Removed from bookmarks</div>
-                </div>"#,
-                post_id
-            )));
+            db::remove_bookmark(conn, user_id, post_id)?;
         } else {
-            let _ = db::add_bookmark(&conn, user.id, post_id);
-            return (jar, Html(format!(
-                r#"<button class="btn-icon bookmark-btn bookmarked" 
-                           hx-post="/post/{}/bookmark" 
-                           hx-swap="outerHTML"
-                           title="Remove from bookmarks">
-                    <span class="icon">🔖</span>
-                </button>
-                <div id="toast-container" hx-swap-oob="beforeend">
-                    <div class="toast success">Added to bookmarks</div>
-                </div>"#,
-                post_id
-            )));
+            db::add_bookmark(conn, user_id, post_id)?;
         }
-    }
+        Ok(Some(is_bookmarked))
+    }).await?;
+    let was_bookmarked = was_bookmarked.ok_or_else(|| AppError::not_found("Post not found"))?;
     
-    (jar, Html("<div class=\"toast error\">Please log in</div>".to_string()))
+    if was_bookmarked {
+        Ok(Html(format!(
+            r#"<button class="btn-icon bookmark-btn" 
+                       hx-post="/post/{}/bookmark" 
+                       hx-swap="outerHTML"
+                       title="Add to bookmarks">
+                <span class="icon">🔖</span>
+            </button>
+            <div id="toast-container" hx-swap-oob="beforeend">
+                <div class="toast">Removed from bookmarks</div>
+            </div>"#,
+            post_id
+        )))
+    } else {
+        Ok(Html(format!(
+            r#"<button class="btn-icon bookmark-btn bookmarked" 
+                       hx-post="/post/{}/bookmark" 
+                       hx-swap="outerHTML"
+                       title="Remove from bookmarks">
+                <span class="icon">🔖</span>
+            </button>
+            <div id="toast-container" hx-swap-oob="beforeend">
+                <div class="toast success">Added to bookmarks</div>
+            </div>"#,
+            post_id
+        )))
+    }
 }
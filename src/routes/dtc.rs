@@ -0,0 +1,126 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::HeaderMap,
+    response::{Html, IntoResponse, Response},
+    Form,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tera::Tera;
+
+use crate::auth::{MaybeUser, PageContext, Poster, RequireRole, Role};
+use crate::db::{self, Db};
+use crate::error::{redirect, toast, AppError, HtmlResult};
+use crate::telemetry;
+
+const MAX_CAUSE_LEN: usize = 200;
+
+#[derive(Deserialize)]
+pub struct DtcQuery {
+    pub q: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct SuggestionForm {
+    pub action: String,
+    #[serde(default)]
+    pub cause: String,
+    #[serde(default)]
+    pub cause_id: String,
+}
+
+pub async fn dtc_index(
+    PageContext(mut ctx): PageContext,
+    Query(query): Query<DtcQuery>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let q = query.q.unwrap_or_default().trim().to_uppercase();
+    if db::is_dtc_code(&q) {
+        return Ok(redirect(&headers, &format!("/dtc/{}", q)));
+    }
+
+    let search = q.clone();
+    let codes = db.read(move |conn| db::search_dtc_codes(conn, &search, 200)).await?;
+    ctx.insert("codes", &codes);
+    ctx.insert("query", &q);
+    ctx.insert("current_page", &"dtc");
+
+    Ok(Html(telemetry::render(&tera, "dtc_index.html", &ctx)?).into_response())
+}
+
+pub async fn dtc_page(
+    MaybeUser(user): MaybeUser,
+    PageContext(mut ctx): PageContext,
+    Path(code): Path<String>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let code = code.to_uppercase();
+    if !db::is_dtc_code(&code) {
+        return Err(AppError::not_found(
+            "Not a valid trouble code. OBD-II codes look like P0300: a letter (P, B, C or U) followed by four digits.",
+        ));
+    }
+
+    ctx.insert("can_suggest", &user.is_some_and(|u| Poster::allows(&u.permissions)));
+
+    let lookup = code.clone();
+    let (dtc, causes, posts, pending) = db.read(move |conn| {
+        Ok((
+            db::get_dtc_code(conn, &lookup)?,
+            db::get_dtc_causes(conn, &lookup)?,
+            db::get_posts_mentioning_dtc(conn, &lookup, 50)?,
+            db::count_pending_dtc_suggestions(conn, &lookup)?,
+        ))
+    }).await?;
+
+    ctx.insert("dtc", &dtc);
+    ctx.insert("causes", &causes);
+    ctx.insert("posts", &posts);
+    ctx.insert("pending_suggestions", &pending);
+    ctx.insert("current_page", &"dtc");
+    Ok(Html(telemetry::render(&tera, "dtc.html", &ctx)?))
+}
+
+/// Verified mechanics propose adding or removing a common cause; moderators
+/// review the suggestion from the mod queue
+pub async fn suggest_cause(
+    RequireRole(user, _): RequireRole<Poster>,
+    Path(code): Path<String>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    Form(form): Form<SuggestionForm>,
+) -> HtmlResult {
+    let code = code.to_uppercase();
+    if !db::is_dtc_code(&code) {
+        return Err(AppError::not_found("Not a valid trouble code"));
+    }
+
+    let user_id = user.id;
+    match form.action.as_str() {
+        "add" => {
+            let cause = form.cause.trim().to_string();
+            if cause.chars().count() < 3 || cause.chars().count() > MAX_CAUSE_LEN {
+                return Err(AppError::validation(format!("Causes must be 3 to {} characters", MAX_CAUSE_LEN)));
+            }
+            db.write(move |conn| db::create_dtc_suggestion(conn, &code, user_id, "add", &cause, None)).await?;
+        }
+        "remove" => {
+            let Ok(cause_id) = form.cause_id.parse::<i64>() else {
+                return Err(AppError::validation("Pick a cause to remove"));
+            };
+            let created = db.write(move |conn| {
+                let causes = db::get_dtc_causes(conn, &code)?;
+                match causes.into_iter().find(|c| c.id == cause_id) {
+                    Some(cause) => db::create_dtc_suggestion(conn, &code, user_id, "remove", &cause.cause, Some(cause_id)).map(Some),
+                    None => Ok(None),
+                }
+            }).await?;
+            if created.is_none() {
+                return Err(AppError::not_found("That cause no longer exists"));
+            }
+        }
+        _ => return Err(AppError::validation("Unknown suggestion type")),
+    }
+
+    Ok(Html(toast("success", "Thanks! A moderator will review your suggestion")))
+}
@@ -1,16 +1,19 @@
 use axum::{
     extract::{Path, Query, State},
-    response::Html,
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
     Form,
 };
-use axum_extra::extract::CookieJar;
 use serde::Deserialize;
 use std::sync::Arc;
 use tera::{Context, Tera};
 
-use crate::auth::ensure_session;
+use crate::auth::{Commenter, CurrentUser, MaybeUser, PageContext, Poster, RequireRole};
 use crate::db::{self, Db};
-use crate::models::Comment;
+use crate::error::{redirect, AppError, HtmlResult};
+use crate::models::{Comment, Post, User, VehicleDetails, VehicleFilter};
+use crate::routes::garage::VehicleForm;
+use crate::telemetry;
 
 #[derive(Deserialize)]
 pub struct PostForm {
@@ -19,6 +22,38 @@ pub struct PostForm {
     pub body: String,
     #[serde(default)]
     pub tags: Vec<i64>,
+    /// A vehicle from the author's garage, or empty to use the fields below
+    #[serde(default)]
+    pub vehicle_id: String,
+    pub save_to_garage: Option<String>,
+    #[serde(default)]
+    pub year: String,
+    #[serde(default)]
+    pub make: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub trim: String,
+    #[serde(default)]
+    pub engine_code: String,
+    #[serde(default)]
+    pub transmission: String,
+    #[serde(default)]
+    pub mileage: String,
+}
+
+impl PostForm {
+    fn vehicle(&self) -> VehicleForm {
+        VehicleForm {
+            year: self.year.clone(),
+            make: self.make.clone(),
+            model: self.model.clone(),
+            trim: self.trim.clone(),
+            engine_code: self.engine_code.clone(),
+            transmission: self.transmission.clone(),
+            mileage: self.mileage.clone(),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -47,6 +82,23 @@ pub struct VoteForm {
 pub struct ListQuery {
     pub sort: Option<String>,
     pub page: Option<i64>,
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub year_min: Option<String>,
+    pub year_max: Option<String>,
+}
+
+impl ListQuery {
+    fn vehicle_filter(&self) -> VehicleFilter {
+        let text = |v: &Option<String>| v.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(String::from);
+        let year = |v: &Option<String>| v.as_deref().and_then(|v| v.trim().parse().ok());
+        VehicleFilter {
+            make: text(&self.make),
+            model: text(&self.model),
+            year_min: year(&self.year_min),
+            year_max: year(&self.year_max),
+        }
+    }
 }
 
 #[derive(Deserialize)]
@@ -55,190 +107,185 @@ pub struct ReportForm {
 }
 
 pub async fn category_posts(
-    jar: CookieJar,
+    PageContext(mut ctx): PageContext,
     Path(slug): Path<String>,
     Query(query): Query<ListQuery>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
+) -> HtmlResult {
+    let filter = query.vehicle_filter();
     let sort = query.sort.unwrap_or_else(|| "hot".to_string());
     let page = query.page.unwrap_or(1);
     
-    let jar = if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        let unread_count = db::get_unread_notification_count(&conn, user.id).unwrap_or(0);
-        ctx.insert("user", &user);
-        ctx.insert("unread_notifications", &unread_count);
-        jar
-    } else {
-        jar
-    };
-    
-    let conn = db.lock().unwrap();
-    let category = db::get_category_by_slug(&conn, &slug);
-    let categories = db::get_categories(&conn).unwrap_or_default();
-    let (posts, pagination) = db::get_posts_paginated(&conn, Some(&slug), &sort, page, 25).unwrap_or_default();
+    let (category_slug, sort_key, post_filter) = (slug.clone(), sort.clone(), filter.clone());
+    let listing = db.read(move |conn| {
+        let Some(category) = db::get_category_by_slug(conn, &category_slug)? else {
+            return Ok(None);
+        };
+        Ok(Some((
+            category,
+            db::get_categories(conn)?,
+            db::get_posts_paginated_filtered(conn, Some(&category_slug), &post_filter, &sort_key, page, 25)?,
+            db::get_vehicle_makes(conn)?,
+        )))
+    }).await?;
+    let (category, categories, (posts, pagination), makes) = listing.ok_or_else(|| AppError::not_found("Category not found"))?;
     
     ctx.insert("categories", &categories);
-    ctx.insert("category", &category.ok().flatten());
+    ctx.insert("category", &category);
     ctx.insert("posts", &posts);
     ctx.insert("pagination", &pagination);
     ctx.insert("sort", &sort);
     ctx.insert("current_slug", &slug);
+    ctx.insert("filter", &filter);
+    ctx.insert("filter_query", &filter.query_string());
+    ctx.insert("vehicle_makes", &makes);
     
-    let html = tera.render("category.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-    (jar, Html(html))
+    Ok(Html(telemetry::render(&tera, "category.html", &ctx)?))
 }
 
 pub async fn new_post_page(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<Poster>,
+    PageContext(mut ctx): PageContext,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
-    
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.can_post() {
-            ctx.insert("error", "Only verified mechanics can create posts");
-            ctx.insert("error_details", "Please submit your credentials for verification first.");
-            let html = tera.render("error.html", &ctx).unwrap();
-            return (jar, Html(html));
-        }
-        
-        ctx.insert("user", &user);
-        let conn = db.lock().unwrap();
-        let categories = db::get_categories(&conn).unwrap_or_default();
-        let tags = db::get_all_tags(&conn).unwrap_or_default();
-        ctx.insert("categories", &categories);
-        ctx.insert("tags", &tags);
-        
-        let html = tera.render("new_post.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-        return (jar, Html(html));
-    }
-    
-    let html = r#"<script>window.location.href = "/login";</script>"#.to_string();
-    (jar, Html(html))
+) -> HtmlResult {
+    insert_post_form_options(&db, &mut ctx, user.id).await?;
+    Ok(Html(telemetry::render(&tera, "new_post.html", &ctx)?))
+}
+
+/// Categories, tags and the author's garage for the new post form
+async fn insert_post_form_options(db: &Db, ctx: &mut Context, user_id: i64) -> Result<(), AppError> {
+    let (categories, tags, garage) = db.read(move |conn| {
+        Ok((
+            db::get_categories(conn)?,
+            db::get_all_tags(conn)?,
+            db::get_user_garage(conn, user_id)?,
+        ))
+    }).await?;
+    ctx.insert("categories", &categories);
+    ctx.insert("tags", &tags);
+    ctx.insert("garage", &garage);
+    Ok(())
 }
 
 pub async fn create_post(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<Poster>,
+    PageContext(mut ctx): PageContext,
     State((db, tera)): State<(Db, Arc<Tera>)>,
+    headers: HeaderMap,
     Form(form): Form<PostForm>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
+) -> Result<Response, AppError> {
+    // Validation
+    let garage_vehicle = form.vehicle_id.trim().parse::<i64>().ok();
+    let vehicle_form = form.vehicle();
+    let mut new_vehicle = None;
     
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.can_post() {
-            ctx.insert("error", "Only verified mechanics can create posts");
-            let html = tera.render("error.html", &ctx).unwrap();
-            return (jar, Html(html));
-        }
-        
-        // Validation
-        if form.title.trim().is_empty() || form.title.len() > 300 {
-            ctx.insert("error", "Title must be between 1 and 300 characters");
-            ctx.insert("user", &user);
-            let conn = db.lock().unwrap();
-            let categories = db::get_categories(&conn).unwrap_or_default();
-            ctx.insert("categories", &categories);
-            let html = tera.render("new_post.html", &ctx).unwrap();
-            return (jar, Html(html));
-        }
-        
-        if form.body.trim().is_empty() {
-            ctx.insert("error", "Post body cannot be empty");
-            ctx.insert("user", &user);
-            let conn = db.lock().unwrap();
-            let categories = db::get_categories(&conn).unwrap_or_default();
-            ctx.insert("categories", &categories);
-            let html = tera.render("new_post.html", &ctx).unwrap();
-            return (jar, Html(html));
-        }
-        
-        let conn = db.lock().unwrap();
-        match db::create_post_with_tags(&conn, user.id, form.category_id, &form.title, &form.body, &form.tags) {
-            Ok(post_id) => {
-                let _ = db::log_activity(&conn, user.id, "create_post", Some("post"), Some(post_id), None, None);
-                let html = format!(r#"<script>window.location.href = "/post/{}";</script>"#, post_id);
-                return (jar, Html(html));
-            }
-            Err(_) => {
-                ctx.insert("error", "Failed to create post");
-                ctx.insert("user", &user);
-                let categories = db::get_categories(&conn).unwrap_or_default();
-                ctx.insert("categories", &categories);
-                let html = tera.render("new_post.html", &ctx).unwrap();
-                return (jar, Html(html));
+    let error = if form.title.trim().is_empty() || form.title.len() > 300 {
+        Some("Title must be between 1 and 300 characters".to_string())
+    } else if form.body.trim().is_empty() {
+        Some("Post body cannot be empty".to_string())
+    } else if garage_vehicle.is_none() && !vehicle_form.is_blank() {
+        match vehicle_form.to_details() {
+            Ok(details) => {
+                new_vehicle = Some(details);
+                None
             }
+            Err(e) => Some(e),
         }
+    } else {
+        None
+    };
+    
+    if let Some(error) = error {
+        insert_post_form_options(&db, &mut ctx, user.id).await?;
+        ctx.insert("error", &error);
+        let html = telemetry::render(&tera, "new_post.html", &ctx)?;
+        return Ok((StatusCode::UNPROCESSABLE_ENTITY, Html(html)).into_response());
     }
     
-    let html = r#"<script>window.location.href = "/login";</script>"#.to_string();
-    (jar, Html(html))
+    let user_id = user.id;
+    let save_to_garage = form.save_to_garage.is_some();
+    let post_id = db.write(move |conn| {
+        let vehicle_id = post_vehicle_id(conn, user_id, garage_vehicle, new_vehicle, save_to_garage)?;
+        let post_id = db::create_post_with_tags(conn, user_id, form.category_id, &form.title, &form.body, &form.tags, vehicle_id)?;
+        db::log_activity(conn, user_id, "create_post", Some("post"), Some(post_id), None, None)?;
+        Ok(post_id)
+    }).await?;
+    
+    Ok(redirect(&headers, &format!("/post/{}", post_id)))
+}
+
+/// The vehicle to attach to a new post: one of the author's garage
+/// vehicles, or a new vehicle entered on the form
+pub(crate) fn post_vehicle_id(
+    conn: &rusqlite::Connection,
+    user_id: i64,
+    garage_vehicle: Option<i64>,
+    new_vehicle: Option<VehicleDetails>,
+    save_to_garage: bool,
+) -> rusqlite::Result<Option<i64>> {
+    Ok(match (garage_vehicle, new_vehicle) {
+        // Only the author's own vehicles can be attached
+        (Some(id), _) => db::get_vehicle_by_id(conn, id)?
+            .filter(|v| v.user_id == user_id)
+            .map(|v| v.id),
+        (None, Some(details)) => Some(db::create_vehicle(conn, user_id, &details, save_to_garage)?),
+        (None, None) => None,
+    })
 }
 
 pub async fn view_post(
-    jar: CookieJar,
+    MaybeUser(user): MaybeUser,
+    PageContext(mut ctx): PageContext,
     Path(id): Path<i64>,
     Query(query): Query<ListQuery>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
+) -> HtmlResult {
     let comment_sort = query.sort.unwrap_or_else(|| "best".to_string());
+    let user_id = user.as_ref().map(|u| u.id);
+    ctx.insert("user_id", &user_id);
+    ctx.insert("post_id", &id);
     
-    let (jar, user_id) = if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        let unread_count = db::get_unread_notification_count(&conn, user.id).unwrap_or(0);
-        ctx.insert("user", &user);
-        ctx.insert("user_id", &user.id);
-        ctx.insert("unread_notifications", &unread_count);
-        (jar, Some(user.id))
-    } else {
-        (jar, None)
-    };
-    
-    let conn = db.lock().unwrap();
-    
-    match db::get_post_by_id(&conn, id) {
-        Ok(Some(mut post)) => {
-            if post.removed && user_id.map_or(true, |uid| uid != post.user_id) {
-                ctx.insert("error", "This post has been removed");
-                let html = tera.render("error.html", &ctx).unwrap();
-                return (jar, Html(html));
-            }
-            
-            // Add user context if logged in
-            if let Some(uid) = user_id {
-                post.user_vote = db::get_user_vote_for_post(&conn, uid, id).ok().flatten();
-                post.is_bookmarked = Some(db::is_post_bookmarked(&conn, uid, id).unwrap_or(false));
-            }
-            
-            let comments = db::get_comments_for_post_sorted(&conn, id, &comment_sort).unwrap_or_default();
-            let threaded = thread_comments(comments, user_id, &conn);
-            
-            ctx.insert("post", &post);
-            ctx.insert("comments", &threaded);
-            ctx.insert("comment_sort", &comment_sort);
-            ctx.insert("comment_count", &threaded.len());
-            
-            let html = tera.render("post.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-            (jar, Html(html))
+    let sort_key = comment_sort.clone();
+    let result = db.read(move |conn| {
+        let Some(mut post) = db::get_post_by_id(conn, id)? else {
+            return Ok(None);
+        };
+        
+        // Removed posts are only shown to their author
+        if post.removed && user_id != Some(post.user_id) {
+            return Ok(None);
         }
-        _ => {
-            ctx.insert("error", "Post not found");
-            let html = tera.render("error.html", &ctx).unwrap();
-            (jar, Html(html))
+        
+        // Add user context if logged in
+        if let Some(uid) = user_id {
+            post.user_vote = db::get_user_vote_for_post(conn, uid, id)?;
+            post.is_bookmarked = Some(db::is_post_bookmarked(conn, uid, id)?);
         }
-    }
+        
+        let comments = db::get_comments_for_post_sorted(conn, id, &sort_key)?;
+        let threaded = thread_comments(comments, user_id, conn)?;
+        Ok(Some((post, threaded)))
+    }).await?;
+    let (post, threaded) = result.ok_or_else(|| AppError::not_found("Post not found"))?;
+    let can_moderate = user.is_some_and(|u| u.permissions.can_moderate_category(post.category_id));
+    
+    ctx.insert("post", &post);
+    ctx.insert("can_moderate", &can_moderate);
+    ctx.insert("comments", &threaded);
+    ctx.insert("comment_sort", &comment_sort);
+    ctx.insert("comment_count", &threaded.len());
+    
+    Ok(Html(telemetry::render(&tera, "post.html", &ctx)?))
 }
 
-fn thread_comments(comments: Vec<Comment>, user_id: Option<i64>, conn: &rusqlite::Connection) -> Vec<Comment> {
+fn thread_comments(comments: Vec<Comment>, user_id: Option<i64>, conn: &rusqlite::Connection) -> rusqlite::Result<Vec<Comment>> {
     let mut top_level: Vec<Comment> = vec![];
     let mut by_parent: std::collections::HashMap<i64, Vec<Comment>> = std::collections::HashMap::new();
     
     for mut c in comments {
         // Add user vote if logged in
         if let Some(uid) = user_id {
-            c.user_vote = db::get_user_vote_for_comment(conn, uid, c.id).ok().flatten();
+            c.user_vote = db::get_user_vote_for_comment(conn, uid, c.id)?;
         }
         
         if let Some(parent_id) = c.parent_id {
@@ -262,346 +309,276 @@ fn thread_comments(comments: Vec<Comment>, user_id: Option<i64>, conn: &rusqlite
         attach_replies(c, &by_parent, 0);
     }
     
-    top_level
+    Ok(top_level)
+}
+
+/// The post, if `user` may edit it: its author or a moderator of its category
+pub(crate) async fn editable_post(db: &Db, post_id: i64, user: &User) -> Result<Post, AppError> {
+    let post = db.read(move |conn| db::get_post_by_id(conn, post_id))
+        .await?
+        .ok_or_else(|| AppError::not_found("Post not found"))?;
+    if post.user_id != user.id && !user.permissions.can_moderate_category(post.category_id) {
+        return Err(AppError::forbidden("You don't have permission to edit this post"));
+    }
+    Ok(post)
 }
 
 pub async fn edit_post_page(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
+    PageContext(mut ctx): PageContext,
     Path(id): Path<i64>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
-    
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        
-        match db::get_post_by_id(&conn, id) {
-            Ok(Some(post)) => {
-                // Check ownership or mod status
-                if post.user_id != user.id && !user.role.can_moderate() {
-                    ctx.insert("error", "You don't have permission to edit this post");
-                    let html = tera.render("error.html", &ctx).unwrap();
-                    return (jar, Html(html));
-                }
-                
-                ctx.insert("user", &user);
-                ctx.insert("post", &post);
-                let tags = db::get_all_tags(&conn).unwrap_or_default();
-                ctx.insert("tags", &tags);
-                
-                let html = tera.render("edit_post.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-                return (jar, Html(html));
-            }
-            _ => {
-                ctx.insert("error", "Post not found");
-                let html = tera.render("error.html", &ctx).unwrap();
-                return (jar, Html(html));
-            }
-        }
-    }
+) -> HtmlResult {
+    let post = editable_post(&db, id, &user).await?;
+    let tags = db.read(db::get_all_tags).await?;
     
-    let html = r#"<script>window.location.href = "/login";</script>"#.to_string();
-    (jar, Html(html))
+    ctx.insert("post", &post);
+    ctx.insert("tags", &tags);
+    Ok(Html(telemetry::render(&tera, "edit_post.html", &ctx)?))
 }
 
 pub async fn edit_post_submit(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     Path(id): Path<i64>,
-    State((db, tera)): State<(Db, Arc<Tera>)>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    headers: HeaderMap,
     Form(form): Form<EditPostForm>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
-    
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        
-        match db::get_post_by_id(&conn, id) {
-            Ok(Some(post)) => {
-                if post.user_id != user.id && !user.role.can_moderate() {
-                    ctx.insert("error", "You don't have permission to edit this post");
-                    let html = tera.render("error.html", &ctx).unwrap();
-                    return (jar, Html(html));
-                }
-                
-                if let Err(_) = db::update_post(&conn, id, user.id, &form.title, &form.body) {
-                    ctx.insert("error", "Failed to update post");
-                    let html = tera.render("error.html", &ctx).unwrap();
-                    return (jar, Html(html));
-                }
-                
-                let _ = db::log_activity(&conn, user.id, "edit_post", Some("post"), Some(id), None, None);
-                
-                let html = format!(r#"<script>window.location.href = "/post/{}";</script>"#, id);
-                return (jar, Html(html));
-            }
-            _ => {
-                ctx.insert("error", "Post not found");
-                let html = tera.render("error.html", &ctx).unwrap();
-                return (jar, Html(html));
-            }
-        }
+) -> Result<Response, AppError> {
+    editable_post(&db, id, &user).await?;
+    if form.title.trim().is_empty() || form.title.len() > 300 {
+        return Err(AppError::validation("Title must be between 1 and 300 characters"));
+    }
+    if form.body.trim().is_empty() {
+        return Err(AppError::validation("Post body cannot be empty"));
     }
     
-    let html = r#"<script>window.location.href = "/login";</script>"#.to_string();
-    (jar, Html(html))
+    let user_id = user.id;
+    db.write(move |conn| {
+        db::update_post(conn, id, user_id, &form.title, &form.body)?;
+        db::log_activity(conn, user_id, "edit_post", Some("post"), Some(id), None, None)
+    }).await?;
+    
+    Ok(redirect(&headers, &format!("/post/{}", id)))
 }
 
 pub async fn delete_post(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     Path(id): Path<i64>,
-    State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
-    
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        
-        match db::get_post_by_id(&conn, id) {
-            Ok(Some(post)) => {
-                if post.user_id != user.id && !user.role.can_moderate() {
-                    return (jar, Html("Unauthorized".to_string()));
-                }
-                
-                let _ = db::remove_post(&conn, id);
-                let _ = db::log_activity(&conn, user.id, "delete_post", Some("post"), Some(id), None, None);
-                
-                let html = r#"<script>window.location.href = "/";</script>"#.to_string();
-                return (jar, Html(html));
-            }
-            _ => {
-                return (jar, Html("Post not found".to_string()));
-            }
-        }
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let post = db.read(move |conn| db::get_post_by_id(conn, id))
+        .await?
+        .ok_or_else(|| AppError::not_found("Post not found"))?;
+    if post.user_id != user.id && !user.permissions.can_moderate_category(post.category_id) {
+        return Err(AppError::forbidden("You don't have permission to delete this post"));
     }
     
-    (jar, Html("Unauthorized".to_string()))
+    let user_id = user.id;
+    db.write(move |conn| {
+        db::remove_post(conn, id)?;
+        db::log_activity(conn, user_id, "delete_post", Some("post"), Some(id), None, None)
+    }).await?;
+    
+    Ok(redirect(&headers, "/"))
 }
 
 pub async fn add_comment(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<Commenter>,
     Path(post_id): Path<i64>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
     Form(form): Form<CommentForm>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if form.body.trim().is_empty() {
-            return (jar, Html("<div class=\"toast error\">Comment cannot be empty</div>".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let _ = db::create_comment(&conn, post_id, user.id, form.parent_id, &form.body);
+) -> HtmlResult {
+    if !user.email_confirmed {
+        return Err(AppError::forbidden("Confirm your email address to comment"));
+    }
+    if form.body.trim().is_empty() {
+        return Err(AppError::validation("Comment cannot be empty"));
+    }
+    
+    let user_id = user.id;
+    let result = db.write(move |conn| {
+        let Some(post) = db::get_post_by_id(conn, post_id)?.filter(|p| !p.removed) else {
+            return Ok(None);
+        };
+        db::create_comment(conn, post_id, user_id, form.parent_id, &form.body)?;
         
         // Return updated comments partial
-        let comment_sort = "best";
-        let comments = db::get_comments_for_post_sorted(&conn, post_id, comment_sort).unwrap_or_default();
-        let threaded = thread_comments(comments, Some(user.id), &conn);
-        
-        let mut ctx = Context::new();
-        ctx.insert("comments", &threaded);
-        ctx.insert("post_id", &post_id);
-        ctx.insert("user", &user);
-        ctx.insert("user_id", &user.id);
-        
-        // Also return a toast notification
-        let comments_html = tera.render("partials/comments.html", &ctx).unwrap_or_default();
-        let html = format!(r#"
-            {}
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast success">Comment posted!</div>
-            </div>
-        "#, comments_html);
-        
-        return (jar, Html(html));
-    }
+        let comments = db::get_comments_for_post_sorted(conn, post_id, "best")?;
+        Ok(Some((post.category_id, thread_comments(comments, Some(user_id), conn)?)))
+    }).await?;
+    let (category_id, threaded) = result.ok_or_else(|| AppError::not_found("Post not found"))?;
+    
+    let mut ctx = Context::new();
+    ctx.insert("comments", &threaded);
+    ctx.insert("post_id", &post_id);
+    ctx.insert("can_moderate", &user.permissions.can_moderate_category(category_id));
+    ctx.insert("user", &user);
+    ctx.insert("user_id", &user.id);
     
-    (jar, Html("<div class=\"toast error\">Please log in to comment</div>".to_string()))
+    // Also return a toast notification
+    let comments_html = telemetry::render(&tera, "partials/comments.html", &ctx)?;
+    let html = format!(r#"
+        {}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Comment posted!</div>
+        </div>
+    "#, comments_html);
+    
+    Ok(Html(html))
+}
+
+/// Checks `user` may change the comment: its author or a moderator of the
+/// post's category
+async fn check_comment_owner(db: &Db, comment_id: i64, user: &User) -> Result<(), AppError> {
+    let found = db.read(move |conn| {
+        let Some(comment) = db::get_comment_by_id(conn, comment_id)? else {
+            return Ok(None);
+        };
+        let category_id = db::get_post_by_id(conn, comment.post_id)?.map(|p| p.category_id);
+        Ok(Some((comment, category_id)))
+    }).await?;
+    let (comment, category_id) = found.ok_or_else(|| AppError::not_found("Comment not found"))?;
+    let moderates = category_id.is_some_and(|id| user.permissions.can_moderate_category(id));
+    if comment.user_id != user.id && !moderates {
+        return Err(AppError::forbidden("You can only change your own comments"));
+    }
+    Ok(())
 }
 
 pub async fn edit_comment(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     Path(comment_id): Path<i64>,
-    State((db, tera)): State<(Db, Arc<Tera>)>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
     Form(form): Form<EditCommentForm>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        
-        // Verify ownership
-        let comment_author: Option<i64> = conn.query_row(
-            "SELECT user_id FROM comments WHERE id = ?1",
-            rusqlite::params![comment_id],
-            |r| r.get(0)
-        ).ok();
-        
-        if comment_author != Some(user.id) && !user.role.can_moderate() {
-            return (jar, Html("<div class=\"toast error\">Unauthorized</div>".to_string()));
-        }
-        
-        let _ = db::update_comment(&conn, comment_id, user.id, &form.body);
-        
-        return (jar, Html("<div class=\"toast success\">Comment updated!</div>".to_string()));
+) -> HtmlResult {
+    check_comment_owner(&db, comment_id, &user).await?;
+    if form.body.trim().is_empty() {
+        return Err(AppError::validation("Comment cannot be empty"));
     }
     
-    (jar, Html("<div class=\"toast error\">Please log in</div>".to_string()))
+    let user_id = user.id;
+    db.write(move |conn| db::update_comment(conn, comment_id, user_id, &form.body)).await?;
+    
+    Ok(Html("<div class=\"toast success\">Comment updated!</div>".to_string()))
 }
 
 pub async fn delete_comment(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     Path(comment_id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        
-        let comment_author: Option<i64> = conn.query_row(
-            "SELECT user_id FROM comments WHERE id = ?1",
-            rusqlite::params![comment_id],
-            |r| r.get(0)
-        ).ok();
-        
-        if comment_author != Some(user.id) && !user.role.can_moderate() {
-            return (jar, Html("<div class=\"toast error\">Unauthorized</div>".to_string()));
-        }
-        
-        let _ = db::remove_comment(&conn, comment_id);
-        let _ = db::log_activity(&conn, user.id, "delete_comment", Some("comment"), Some(comment_id), None, None);
-        
-        return (jar, Html("<div class=\"toast success comment-deleted\">Comment deleted</div>".to_string()));
-    }
+) -> HtmlResult {
+    check_comment_owner(&db, comment_id, &user).await?;
+    
+    let user_id = user.id;
+    db.write(move |conn| {
+        db::remove_comment(conn, comment_id)?;
+        db::log_activity(conn, user_id, "delete_comment", Some("comment"), Some(comment_id), None, None)
+    }).await?;
     
-    (jar, Html("<div class=\"toast error\">Please log in</div>".to_string()))
+    Ok(Html("<div class=\"toast success comment-deleted\">Comment deleted</div>".to_string()))
 }
 
 pub async fn vote_post(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     Path(post_id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
     Form(form): Form<VoteForm>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        let value = if form.value > 0 { 1 } else { -1 };
-        match db::vote_post(&conn, user.id, post_id, value) {
-            Ok(new_score) => {
-                let user_vote = db::get_user_vote_for_post(&conn, user.id, post_id).ok().flatten();
-                let up_class = if user_vote == Some(1) { "voted" } else { "" };
-                let down_class = if user_vote == Some(-1) { "voted" } else { "" };
-                
-                let html = format!(
-                    "<span class=\"score\" id=\"score-{post_id}\">{new_score}</span>",
-                    post_id = post_id, new_score = new_score
-                );
-                return (jar, Html(html));
-            }
-            Err(_) => {
-                return (jar, Html("Error".to_string()));
-            }
+) -> HtmlResult {
+    let user_id = user.id;
+    let value = if form.value > 0 { 1 } else { -1 };
+    let new_score = db.write(move |conn| {
+        if db::get_post_by_id(conn, post_id)?.filter(|p| !p.removed).is_none() {
+            return Ok(None);
         }
-    }
+        db::vote_post(conn, user_id, post_id, value).map(Some)
+    }).await?;
+    let new_score = new_score.ok_or_else(|| AppError::not_found("Post not found"))?;
     
-    (jar, Html("<span class=\"toast error\">Please log in to vote</span>".to_string()))
+    let html = format!(
+        "<span class=\"score\" id=\"score-{post_id}\">{new_score}</span>",
+        post_id = post_id, new_score = new_score
+    );
+    Ok(Html(html))
 }
 
 pub async fn vote_comment(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     Path(comment_id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
     Form(form): Form<VoteForm>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        let value = if form.value > 0 { 1 } else { -1 };
-        match db::vote_comment(&conn, user.id, comment_id, value) {
-            Ok(new_score) => {
-                return (jar, Html(format!(r#"<span class="score">{}</span>"#, new_score)));
-            }
-            Err(_) => {
-                return (jar, Html("Error".to_string()));
-            }
+) -> HtmlResult {
+    let user_id = user.id;
+    let value = if form.value > 0 { 1 } else { -1 };
+    let new_score = db.write(move |conn| {
+        if db::get_comment_by_id(conn, comment_id)?.filter(|c| !c.removed).is_none() {
+            return Ok(None);
         }
-    }
+        db::vote_comment(conn, user_id, comment_id, value).map(Some)
+    }).await?;
+    let new_score = new_score.ok_or_else(|| AppError::not_found("Comment not found"))?;
     
-    (jar, Html("Login required".to_string()))
+    Ok(Html(format!(r#"<span class="score">{}</span>"#, new_score)))
 }
 
 pub async fn set_best_answer(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     Path((post_id, comment_id)): Path<(i64, i64)>,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        
-        // Verify post ownership
-        let post_author: Option<i64> = conn.query_row(
-            "SELECT user_id FROM posts WHERE id = ?1",
-            rusqlite::params![post_id],
-            |r| r.get(0)
-        ).ok();
-        
-        if post_author != Some(user.id) && !user.role.can_moderate() {
-            return (jar, Html("<div class=\"toast error\">Only the post author can mark best answer</div>".to_string()));
-        }
-        
-        // Toggle best answer
-        let current_best: Option<i64> = conn.query_row(
-            "SELECT best_answer_id FROM posts WHERE id = ?1",
-            rusqlite::params![post_id],
-            |r| r.get(0)
-        ).ok().flatten();
-        
-        if current_best == Some(comment_id) {
-            let _ = db::set_best_answer(&conn, post_id, None);
-        } else {
-            let _ = db::set_best_answer(&conn, post_id, Some(comment_id));
-            
-            // Notify comment author
-            let comment_author: Option<i64> = conn.query_row(
-                "SELECT user_id FROM comments WHERE id = ?1",
-                rusqlite::params![comment_id],
-                |r| r.get(0)
-            ).ok();
-            
-            if let Some(author_id) = comment_author {
-                if author_id != user.id {
-                    let _ = db::create_notification(&conn, author_id, "best_answer", "Your answer was marked as the best answer!", Some(post_id), Some(comment_id), Some(user.id));
-                }
-            }
-        }
-        
-        let html = format!(r#"<script>window.location.href = "/post/{}";</script>"#, post_id);
-        return (jar, Html(html));
+    headers: HeaderMap,
+) -> Result<Response, AppError> {
+    let (post, comment) = db.read(move |conn| {
+        Ok((db::get_post_by_id(conn, post_id)?, db::get_comment_by_id(conn, comment_id)?))
+    }).await?;
+    let post = post.ok_or_else(|| AppError::not_found("Post not found"))?;
+    if comment.filter(|c| c.post_id == post_id).is_none() {
+        return Err(AppError::not_found("Comment not found"));
+    }
+    if post.user_id != user.id && !user.permissions.can_moderate_category(post.category_id) {
+        return Err(AppError::forbidden("Only the post author can mark best answer"));
+    }
+    
+    // Toggle best answer
+    let new_best = if post.best_answer_id == Some(comment_id) { None } else { Some(comment_id) };
+    let set = db.write(move |conn| {
+        let tx = conn.unchecked_transaction()?;
+        let set = db::set_best_answer(&tx, post_id, new_best)?;
+        tx.commit()?;
+        Ok(set)
+    }).await?;
+    if !set {
+        return Err(AppError::not_found("Comment not found"));
     }
     
-    (jar, Html("Login required".to_string()))
+    Ok(redirect(&headers, &format!("/post/{}", post_id)))
 }
 
 pub async fn report_post(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     Path(post_id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
     Form(form): Form<ReportForm>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        let _ = db::create_report(&conn, user.id, Some(post_id), None, &form.reason);
-        return (jar, Html(r#"<span class="reported">✓ Reported</span>"#.to_string()));
+) -> HtmlResult {
+    if !user.email_confirmed {
+        return Err(AppError::forbidden("Confirm your email address to report"));
     }
     
-    (jar, Html("Login required".to_string()))
+    let user_id = user.id;
+    db.write(move |conn| db::create_report(conn, user_id, Some(post_id), None, &form.reason)).await?;
+    Ok(Html(r#"<span class="reported">✓ Reported</span>"#.to_string()))
 }
 
 pub async fn report_comment(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     Path(comment_id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
     Form(form): Form<ReportForm>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        let _ = db::create_report(&conn, user.id, None, Some(comment_id), &form.reason);
-        return (jar, Html(r#"<span class="reported">✓ Reported</span>"#.to_string()));
+) -> HtmlResult {
+    if !user.email_confirmed {
+        return Err(AppError::forbidden("Confirm your email address to report"));
     }
     
-    (jar, Html("Login required".to_string()))
+    let user_id = user.id;
+    db.write(move |conn| db::create_report(conn, user_id, None, Some(comment_id), &form.reason)).await?;
+    Ok(Html(r#"<span class="reported">✓ Reported</span>"#.to_string()))
 }
@@ -0,0 +1,141 @@
+use axum::{
+    extract::{Path, State},
+    response::Html,
+    Form,
+};
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tera::{Context, Tera};
+
+use crate::auth::CurrentUser;
+use crate::db::{self, Db};
+use crate::error::{AppError, HtmlResult};
+use crate::models::{Vehicle, VehicleDetails};
+use crate::telemetry;
+
+const MAX_FIELD_LEN: usize = 40;
+const MAX_MILEAGE: i64 = 2_000_000;
+
+/// Vehicle fields as submitted by the garage and new post forms. Everything
+/// arrives as text so blank optional inputs don't fail to deserialize.
+#[derive(Deserialize, Serialize, Default)]
+pub struct VehicleForm {
+    #[serde(default)]
+    pub year: String,
+    #[serde(default)]
+    pub make: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub trim: String,
+    #[serde(default)]
+    pub engine_code: String,
+    #[serde(default)]
+    pub transmission: String,
+    #[serde(default)]
+    pub mileage: String,
+}
+
+fn optional_field(value: &str, name: &str) -> Result<Option<String>, String> {
+    let value = value.trim();
+    if value.chars().count() > MAX_FIELD_LEN {
+        return Err(format!("{} must be at most {} characters", name, MAX_FIELD_LEN));
+    }
+    Ok(if value.is_empty() { None } else { Some(value.to_string()) })
+}
+
+impl VehicleForm {
+    /// True when the user left every vehicle field empty
+    pub fn is_blank(&self) -> bool {
+        [&self.year, &self.make, &self.model, &self.trim, &self.engine_code, &self.transmission, &self.mileage]
+            .iter()
+            .all(|f| f.trim().is_empty())
+    }
+
+    pub fn to_details(&self) -> Result<VehicleDetails, String> {
+        let max_year = chrono::Utc::now().year() as i64 + 2;
+        let year = match self.year.trim().parse::<i64>() {
+            Ok(year) if (1886..=max_year).contains(&year) => year,
+            _ => return Err(format!("Enter a model year between 1886 and {}", max_year)),
+        };
+
+        let make = optional_field(&self.make, "Make")?.ok_or("Make is required")?;
+        let model = optional_field(&self.model, "Model")?.ok_or("Model is required")?;
+
+        let mileage_text: String = self.mileage.chars().filter(|c| !matches!(c, ',' | '_' | ' ')).collect();
+        let mileage = if mileage_text.is_empty() {
+            None
+        } else {
+            match mileage_text.parse::<i64>() {
+                Ok(miles) if (0..=MAX_MILEAGE).contains(&miles) => Some(miles),
+                _ => return Err("Mileage must be a whole number".to_string()),
+            }
+        };
+
+        Ok(VehicleDetails {
+            year,
+            make,
+            model,
+            trim: optional_field(&self.trim, "Trim")?,
+            engine_code: optional_field(&self.engine_code, "Engine code")?.map(|e| e.to_uppercase()),
+            transmission: optional_field(&self.transmission, "Transmission")?,
+            mileage,
+        })
+    }
+}
+
+/// Renders the garage list along with an out-of-band toast
+fn render_garage(tera: &Tera, garage: &[Vehicle], message: &str) -> tera::Result<String> {
+    let mut ctx = Context::new();
+    ctx.insert("garage", garage);
+    ctx.insert("is_own_profile", &true);
+
+    let html = telemetry::render(tera, "partials/garage.html", &ctx)?;
+    Ok(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">{}</div>
+        </div>"#,
+        html, message
+    ))
+}
+
+/// A rejected submission is an error toast; the list is left in place
+pub async fn add_vehicle(
+    CurrentUser(user): CurrentUser,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Form(form): Form<VehicleForm>,
+) -> HtmlResult {
+    let details = form.to_details().map_err(AppError::validation)?;
+
+    let user_id = user.id;
+    let garage = db.write(move |conn| {
+        db::create_vehicle(conn, user_id, &details, true)?;
+        db::get_user_garage(conn, user_id)
+    }).await?;
+
+    Ok(Html(render_garage(&tera, &garage, "Vehicle added to your garage")?))
+}
+
+pub async fn remove_vehicle(
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<i64>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+
+    let user_id = user.id;
+    let garage = db.write(move |conn| {
+        match db::get_vehicle_by_id(conn, id)? {
+            Some(vehicle) if vehicle.user_id == user_id => {
+                db::remove_vehicle_from_garage(conn, id)?;
+                db::get_user_garage(conn, user_id).map(Some)
+            }
+            _ => Ok(None),
+        }
+    }).await?;
+    // Someone else's vehicle is as good as missing
+    let garage = garage.ok_or_else(|| AppError::not_found("Vehicle not found"))?;
+
+    Ok(Html(render_garage(&tera, &garage, "Vehicle removed")?))
+}
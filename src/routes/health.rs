@@ -0,0 +1,64 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::Json,
+    Extension,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use tera::Tera;
+
+use crate::db::{self, Db};
+use crate::shutdown::Shutdown;
+
+/// Templates the error page and the home page need. An empty or wrong
+/// `server.templates` glob still starts, so this is what shows it.
+pub const REQUIRED_TEMPLATES: &[&str] = &["base.html", "error.html", "home.html"];
+
+const OK: &str = "ok";
+
+/// `/readyz` body: each check is `"ok"` or what's wrong
+#[derive(Serialize)]
+pub struct Readiness {
+    pub ready: bool,
+    pub database: String,
+    pub migrations: String,
+    pub templates: String,
+    pub shutdown: String,
+}
+
+/// The process is up and serving requests. Deliberately checks nothing
+/// else, so a supervisor only restarts a process that's truly stuck.
+pub async fn healthz() -> &'static str {
+    OK
+}
+
+/// Whether this process should be sent traffic: the database answers, its
+/// schema is the one this build expects, the templates are loaded and it
+/// isn't shutting down. 503 if not.
+pub async fn readyz(
+    Extension(shutdown): Extension<Shutdown>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> (StatusCode, Json<Readiness>) {
+    let latest = db::latest_schema_version();
+    let (database, migrations) = match db.read(db::get_schema_version).await {
+        Ok(version) if version == latest => (OK.to_string(), OK.to_string()),
+        Ok(version) if version < latest => (OK.to_string(), format!("schema version {}, {} expected", version, latest)),
+        Ok(version) => (OK.to_string(), format!("schema version {} is newer than this build ({})", version, latest)),
+        Err(e) => (e.to_string(), "not checked".to_string()),
+    };
+
+    let loaded: Vec<&str> = tera.get_template_names().collect();
+    let missing: Vec<&str> = REQUIRED_TEMPLATES.iter().copied().filter(|name| !loaded.contains(name)).collect();
+    let templates = if missing.is_empty() {
+        OK.to_string()
+    } else {
+        format!("missing {}", missing.join(", "))
+    };
+
+    let shutdown = if shutdown.is_triggered() { "shutting down" } else { OK }.to_string();
+
+    let ready = [&database, &migrations, &templates, &shutdown].iter().all(|check| *check == OK);
+    let status = if ready { StatusCode::OK } else { StatusCode::SERVICE_UNAVAILABLE };
+    (status, Json(Readiness { ready, database, migrations, templates, shutdown }))
+}
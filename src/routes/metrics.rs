@@ -0,0 +1,29 @@
+use axum::{
+    http::header,
+    response::IntoResponse,
+};
+
+use crate::api::{ApiError, ApiUser};
+use crate::auth::{MaybeUser, Role, SiteManager};
+use crate::models::ApiScope;
+use crate::telemetry;
+
+/// Prometheus metrics, for site managers. Browsers use their session;
+/// scrapers send a token with the `metrics` scope as
+/// `Authorization: Bearer <token>`.
+pub async fn metrics(
+    MaybeUser(session_user): MaybeUser,
+    api_user: Result<ApiUser, ApiError>,
+) -> Result<impl IntoResponse, ApiError> {
+    let user = match api_user {
+        Ok(api_user) => {
+            api_user.require(ApiScope::Metrics)?;
+            api_user.user
+        }
+        Err(e) => session_user.ok_or(e)?,
+    };
+    if !SiteManager::allows(&user.permissions) {
+        return Err(ApiError::forbidden(SiteManager::DENIED));
+    }
+    Ok(([(header::CONTENT_TYPE, telemetry::METRICS_CONTENT_TYPE)], telemetry::gather()))
+}
@@ -10,3 +10,14 @@ pub mod search;
 pub mod bookmarks;
 pub mod notifications;
 pub mod uploads;
+pub mod garage;
+pub mod vin;
+pub mod dtc;
+pub mod procedures;
+pub mod torque;
+pub mod two_factor;
+pub mod sessions;
+pub mod tokens;
+pub mod api;
+pub mod health;
+pub mod metrics;
@@ -3,266 +3,299 @@ use axum::{
     response::Html,
     Form,
 };
-use axum_extra::extract::CookieJar;
 use serde::Deserialize;
 use std::sync::Arc;
 use tera::{Context, Tera};
 
-use crate::auth::ensure_session;
+use crate::auth::{BanManager, ContentModerator, Moderator, PageContext, RequireRole};
 use crate::db::{self, Db};
+use crate::error::{AppError, HtmlResult};
+use crate::models::{Permission, Post, Report, User};
+use crate::telemetry;
 
 #[derive(Deserialize)]
 pub struct BanForm {
     pub reason: Option<String>,
 }
 
+/// The reports `user` can act on: everything for site-wide moderators, or
+/// those in the categories they moderate
+fn visible_reports(reports: Vec<Report>, user: &User) -> Vec<Report> {
+    reports
+        .into_iter()
+        .filter(|r| r.category_id.map_or(user.permissions.has(Permission::RemoveContent), |id| user.permissions.can_moderate_category(id)))
+        .collect()
+}
+
 pub async fn mod_queue(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<Moderator>,
+    PageContext(mut ctx): PageContext,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
+) -> HtmlResult {
+    let (reports, banned_users, dtc_suggestions) = db.read(move |conn| {
+        Ok((
+            db::get_unresolved_reports(conn)?,
+            db::get_banned_users(conn)?,
+            db::get_pending_dtc_suggestions(conn)?,
+        ))
+    }).await?;
     
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.can_moderate() {
-            ctx.insert("error", "Moderator access required");
-            let html = tera.render("error.html", &ctx).unwrap();
-            return (jar, Html(html));
-        }
-        
-        let conn = db.lock().unwrap();
-        
-        let reports = db::get_unresolved_reports(&conn).unwrap_or_default();
-        let banned_users = db::get_banned_users(&conn).unwrap_or_default();
-        let unread_count = db::get_unread_notification_count(&conn, user.id).unwrap_or(0);
-        
-        ctx.insert("user", &user);
-        ctx.insert("reports", &reports);
-        ctx.insert("banned_users", &banned_users);
-        ctx.insert("unread_notifications", &unread_count);
-        ctx.insert("current_page", &"mod");
-        
-        let html = tera.render("mod_queue.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-        return (jar, Html(html));
-    }
+    ctx.insert("reports", &visible_reports(reports, &user));
+    ctx.insert("banned_users", &banned_users);
+    ctx.insert("dtc_suggestions", &dtc_suggestions);
+    ctx.insert("current_page", &"mod");
     
-    let html = r#"<script>window.location.href = "/login";</script>"#.to_string();
-    (jar, Html(html))
+    Ok(Html(telemetry::render(&tera, "mod_queue.html", &ctx)?))
+}
+
+/// Checks the post exists and `user` moderates its category, so acting on a
+/// stale link is a 404
+async fn require_post(db: &Db, id: i64, user: &User) -> Result<Post, AppError> {
+    let post = db.read(move |conn| db::get_post_by_id(conn, id))
+        .await?
+        .ok_or_else(|| AppError::not_found("Post not found"))?;
+    if !user.permissions.can_moderate_category(post.category_id) {
+        return Err(AppError::forbidden("You don't moderate this category"));
+    }
+    Ok(post)
 }
 
 pub async fn remove_post(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<Moderator>,
     Path(id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.can_moderate() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let _ = db::remove_post(&conn, id);
-        let _ = db::log_activity(&conn, user.id, "remove_post", Some("post"), Some(id), None, None);
-        
-        return (jar, Html(r#"
-            <span class="removed-badge">Removed</span>
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast success">Post removed</div>
-            </div>
-        "#.to_string()));
-    }
+) -> HtmlResult {
+    require_post(&db, id, &user).await?;
     
-    (jar, Html("Unauthorized".to_string()))
+    let user_id = user.id;
+    db.write(move |conn| {
+        db::remove_post(conn, id)?;
+        db::log_activity(conn, user_id, "remove_post", Some("post"), Some(id), None, None)
+    }).await?;
+    
+    Ok(Html(r#"
+        <span class="removed-badge">Removed</span>
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Post removed</div>
+        </div>
+    "#.to_string()))
 }
 
 pub async fn restore_post(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<Moderator>,
     Path(id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.can_moderate() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let _ = db::restore_post(&conn, id);
-        let _ = db::log_activity(&conn, user.id, "restore_post", Some("post"), Some(id), None, None);
-        
-        return (jar, Html(r#"
-            <span class="restored-badge">Restored</span>
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast success">Post restored</div>
-            </div>
-        "#.to_string()));
-    }
+) -> HtmlResult {
+    require_post(&db, id, &user).await?;
     
-    (jar, Html("Unauthorized".to_string()))
+    let user_id = user.id;
+    db.write(move |conn| {
+        db::restore_post(conn, id)?;
+        db::log_activity(conn, user_id, "restore_post", Some("post"), Some(id), None, None)
+    }).await?;
+    
+    Ok(Html(r#"
+        <span class="restored-badge">Restored</span>
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Post restored</div>
+        </div>
+    "#.to_string()))
 }
 
 pub async fn pin_post(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<Moderator>,
     Path(id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.can_moderate() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        
-        // Toggle pin status
-        let current_pinned: Option<i64> = conn.query_row(
-            "SELECT pinned FROM posts WHERE id = ?1",
-            rusqlite::params![id],
-            |r| r.get(0)
-        ).ok();
-        
-        let new_pinned = current_pinned != Some(1);
-        let _ = db::pin_post(&conn, id, new_pinned);
-        let _ = db::log_activity(&conn, user.id, if new_pinned { "pin_post" } else { "unpin_post" }, Some("post"), Some(id), None, None);
-        
-        let message = if new_pinned { "Post pinned" } else { "Post unpinned" };
-        return (jar, Html(format!(r#"
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast success">{}</div>
-            </div>
-        "#, message)));
-    }
+) -> HtmlResult {
+    let post = require_post(&db, id, &user).await?;
+    
+    // Toggle pin status
+    let user_id = user.id;
+    let new_pinned = !post.pinned;
+    db.write(move |conn| {
+        db::pin_post(conn, id, new_pinned)?;
+        db::log_activity(conn, user_id, if new_pinned { "pin_post" } else { "unpin_post" }, Some("post"), Some(id), None, None)
+    }).await?;
     
-    (jar, Html("Unauthorized".to_string()))
+    let message = if new_pinned { "Post pinned" } else { "Post unpinned" };
+    Ok(Html(format!(r#"
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">{}</div>
+        </div>
+    "#, message)))
 }
 
 pub async fn remove_comment(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<Moderator>,
     Path(id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.can_moderate() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let _ = db::remove_comment(&conn, id);
-        let _ = db::log_activity(&conn, user.id, "remove_comment", Some("comment"), Some(id), None, None);
-        
-        return (jar, Html(r#"
-            <span class="removed-badge">Comment removed</span>
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast success">Comment removed</div>
-            </div>
-        "#.to_string()));
-    }
+) -> HtmlResult {
+    let post_id = db.read(move |conn| db::get_comment_by_id(conn, id))
+        .await?
+        .ok_or_else(|| AppError::not_found("Comment not found"))?
+        .post_id;
+    require_post(&db, post_id, &user).await?;
+    
+    let user_id = user.id;
+    db.write(move |conn| {
+        db::remove_comment(conn, id)?;
+        db::log_activity(conn, user_id, "remove_comment", Some("comment"), Some(id), None, None)
+    }).await?;
     
-    (jar, Html("Unauthorized".to_string()))
+    Ok(Html(r#"
+        <span class="removed-badge">Comment removed</span>
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Comment removed</div>
+        </div>
+    "#.to_string()))
 }
 
 pub async fn ban_user(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<BanManager>,
     Path(id): Path<i64>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
     Form(form): Form<BanForm>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.can_moderate() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        // Prevent self-ban
-        if id == user.id {
-            return (jar, Html("<div class=\"toast error\">Cannot ban yourself</div>".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        
-        // Check if target is admin (can't ban admins)
-        if let Ok(Some(target)) = db::get_user_by_id(&conn, id) {
-            if target.role.is_admin() && !user.role.is_admin() {
-                return (jar, Html("<div class=\"toast error\">Cannot ban an admin</div>".to_string()));
-            }
+) -> HtmlResult {
+    // Prevent self-ban
+    if id == user.id {
+        return Err(AppError::validation("Cannot ban yourself"));
+    }
+    
+    // Staff can only be banned by someone with at least their permissions,
+    // so moderators can't ban admins
+    let target_permissions = db.read(move |conn| {
+        match db::get_user_by_id(conn, id)? {
+            Some(target) => db::get_user_permissions(conn, target.id, &target.role).map(Some),
+            None => Ok(None),
         }
-        
-        let _ = db::set_user_banned(&conn, id, true);
-        let _ = db::delete_user_sessions(&conn, id); // Force logout
-        let _ = db::log_activity(&conn, user.id, "ban_user", Some("user"), Some(id), form.reason.as_deref(), None);
+    }).await?.ok_or_else(|| AppError::not_found("User not found"))?;
+    if !user.permissions.covers(&target_permissions.granted) {
+        return Err(AppError::forbidden("You can't ban someone with permissions you don't have"));
+    }
+    
+    let user_id = user.id;
+    let banned_users = db.write(move |conn| {
+        db::set_user_banned(conn, id, true)?;
+        db::delete_user_sessions(conn, id)?; // Force logout
+        db::log_activity(conn, user_id, "ban_user", Some("user"), Some(id), form.reason.as_deref(), None)?;
         
         // Return updated ban list
-        let banned_users = db::get_banned_users(&conn).unwrap_or_default();
-        let mut ctx = Context::new();
-        ctx.insert("banned_users", &banned_users);
-        
-        let html = tera.render("partials/ban_list.html", &ctx).unwrap_or_default();
-        return (jar, Html(format!(
-            r#"{}
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast success">User banned</div>
-            </div>"#,
-            html
-        )));
-    }
+        db::get_banned_users(conn)
+    }).await?;
+    let mut ctx = Context::new();
+    ctx.insert("banned_users", &banned_users);
     
-    (jar, Html("Unauthorized".to_string()))
+    let html = telemetry::render(&tera, "partials/ban_list.html", &ctx)?;
+    Ok(Html(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">User banned</div>
+        </div>"#,
+        html
+    )))
 }
 
 pub async fn unban_user(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<BanManager>,
     Path(id): Path<i64>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.can_moderate() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let _ = db::set_user_banned(&conn, id, false);
-        let _ = db::log_activity(&conn, user.id, "unban_user", Some("user"), Some(id), None, None);
-        
-        let banned_users = db::get_banned_users(&conn).unwrap_or_default();
-        let mut ctx = Context::new();
-        ctx.insert("banned_users", &banned_users);
-        
-        let html = tera.render("partials/ban_list.html", &ctx).unwrap_or_default();
-        return (jar, Html(format!(
-            r#"{}
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast success">User unbanned</div>
-            </div>"#,
-            html
-        )));
-    }
+) -> HtmlResult {
+    let user_id = user.id;
+    let banned_users = db.write(move |conn| {
+        db::set_user_banned(conn, id, false)?;
+        db::log_activity(conn, user_id, "unban_user", Some("user"), Some(id), None, None)?;
+        db::get_banned_users(conn)
+    }).await?;
+    let mut ctx = Context::new();
+    ctx.insert("banned_users", &banned_users);
     
-    (jar, Html("Unauthorized".to_string()))
+    let html = telemetry::render(&tera, "partials/ban_list.html", &ctx)?;
+    Ok(Html(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">User unbanned</div>
+        </div>"#,
+        html
+    )))
 }
 
 pub async fn resolve_report(
-    jar: CookieJar,
+    RequireRole(user, _): RequireRole<Moderator>,
     Path(id): Path<i64>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        if !user.role.can_moderate() {
-            return (jar, Html("Unauthorized".to_string()));
-        }
-        
-        let conn = db.lock().unwrap();
-        let _ = db::resolve_report(&conn, id);
-        let _ = db::log_activity(&conn, user.id, "resolve_report", Some("report"), Some(id), None, None);
-        
-        let reports = db::get_unresolved_reports(&conn).unwrap_or_default();
-        let mut ctx = Context::new();
-        ctx.insert("reports", &reports);
-        
-        let html = tera.render("partials/report_queue.html", &ctx).unwrap_or_default();
-        return (jar, Html(format!(
-            r#"{}
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast success">Report resolved</div>
-            </div>"#,
-            html
-        )));
+) -> HtmlResult {
+    let reports = db.read(db::get_unresolved_reports).await?;
+    let report = reports
+        .into_iter()
+        .find(|r| r.id == id)
+        .ok_or_else(|| AppError::not_found("That report has already been resolved"))?;
+    if visible_reports(vec![report], &user).is_empty() {
+        return Err(AppError::forbidden("You don't moderate this category"));
     }
     
-    (jar, Html("Unauthorized".to_string()))
+    let user_id = user.id;
+    let reports = db.write(move |conn| {
+        db::resolve_report(conn, id)?;
+        db::log_activity(conn, user_id, "resolve_report", Some("report"), Some(id), None, None)?;
+        db::get_unresolved_reports(conn)
+    }).await?;
+    let mut ctx = Context::new();
+    ctx.insert("reports", &visible_reports(reports, &user));
+    
+    let html = telemetry::render(&tera, "partials/report_queue.html", &ctx)?;
+    Ok(Html(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Report resolved</div>
+        </div>"#,
+        html
+    )))
+}
+
+pub async fn approve_dtc_suggestion(
+    RequireRole(user, _): RequireRole<ContentModerator>,
+    Path(id): Path<i64>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    review_dtc_suggestion(user, id, true, db, tera).await
+}
+
+pub async fn reject_dtc_suggestion(
+    RequireRole(user, _): RequireRole<ContentModerator>,
+    Path(id): Path<i64>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    review_dtc_suggestion(user, id, false, db, tera).await
+}
+
+async fn review_dtc_suggestion(
+    user: User,
+    id: i64,
+    approve: bool,
+    db: Db,
+    tera: Arc<Tera>,
+) -> HtmlResult {
+    let user_id = user.id;
+    let suggestions = db.write(move |conn| {
+        let Some(suggestion) = db::review_dtc_suggestion(conn, id, user_id, approve)? else {
+            return Ok(None);
+        };
+        let action = if approve { "approve_dtc_suggestion" } else { "reject_dtc_suggestion" };
+        db::log_activity(conn, user_id, action, Some("dtc"), Some(id), Some(&suggestion.code), None)?;
+        db::get_pending_dtc_suggestions(conn).map(Some)
+    }).await?;
+    // Missing, or another moderator got to it first
+    let suggestions = suggestions.ok_or_else(|| AppError::not_found("That suggestion isn't awaiting review"))?;
+    let mut ctx = Context::new();
+    ctx.insert("dtc_suggestions", &suggestions);
+    
+    let html = telemetry::render(&tera, "partials/dtc_suggestions.html", &ctx)?;
+    Ok(Html(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Suggestion {}</div>
+        </div>"#,
+        html,
+        if approve { "approved" } else { "rejected" }
+    )))
 }
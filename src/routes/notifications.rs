@@ -2,100 +2,87 @@ use axum::{
     extract::{Path, State},
     response::Html,
 };
-use axum_extra::extract::CookieJar;
 use std::sync::Arc;
-use tera::{Context, Tera};
+use tera::Tera;
 
-use crate::auth::ensure_session;
+use crate::auth::{CurrentUser, MaybeUser, PageContext};
 use crate::db::{self, Db};
+use crate::error::{AppError, HtmlResult};
+use crate::telemetry;
 
 pub async fn list_notifications(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
+    PageContext(mut ctx): PageContext,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
+) -> HtmlResult {
+    let user_id = user.id;
+    let notifications = db.read(move |conn| db::get_user_notifications(conn, user_id, 50)).await?;
     
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        
-        let notifications = db::get_user_notifications(&conn, user.id, 50).unwrap_or_default();
-        let unread_count = db::get_unread_notification_count(&conn, user.id).unwrap_or(0);
-        
-        ctx.insert("user", &user);
-        ctx.insert("notifications", &notifications);
-        ctx.insert("unread_notifications", &unread_count);
-        ctx.insert("current_page", &"notifications");
-        
-        let html = tera.render("notifications.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-        return (jar, Html(html));
-    }
+    ctx.insert("notifications", &notifications);
+    ctx.insert("current_page", &"notifications");
     
-    let html = r#"<script>window.location.href = "/login";</script>"#.to_string();
-    (jar, Html(html))
+    Ok(Html(telemetry::render(&tera, "notifications.html", &ctx)?))
 }
 
+/// Polled by the nav badge. Signed-out pages just get an empty badge.
 pub async fn notification_count(
-    jar: CookieJar,
+    MaybeUser(user): MaybeUser,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        let count = db::get_unread_notification_count(&conn, user.id).unwrap_or(0);
-        
-        if count > 0 {
-            return (jar, Html(format!(
-                r#"<span class="notification-badge">{}</span>"#,
-                if count > 99 { "99+".to_string() } else { count.to_string() }
-            )));
-        } else {
-            return (jar, Html(String::new()));
-        }
-    }
+) -> HtmlResult {
+    let Some(user) = user else {
+        return Ok(Html(String::new()));
+    };
+    
+    let user_id = user.id;
+    let count = db.read(move |conn| db::get_unread_notification_count(conn, user_id)).await?;
     
-    (jar, Html(String::new()))
+    if count > 0 {
+        Ok(Html(format!(
+            r#"<span class="notification-badge">{}</span>"#,
+            if count > 99 { "99+".to_string() } else { count.to_string() }
+        )))
+    } else {
+        Ok(Html(String::new()))
+    }
 }
 
 pub async fn mark_read(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     Path(id): Path<i64>,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        let _ = db::mark_notification_read(&conn, id);
-        
-        let count = db::get_unread_notification_count(&conn, user.id).unwrap_or(0);
-        
-        return (jar, Html(format!(
-            r#"<span id="notification-count" hx-swap-oob="true">
-                {}
-            </span>"#,
-            if count > 0 {
-                format!(r#"<span class="notification-badge">{}</span>"#, count)
-            } else {
-                String::new()
-            }
-        )));
-    }
+) -> HtmlResult {
+    let user_id = user.id;
+    let count = db.write(move |conn| {
+        if !db::mark_user_notification_read(conn, user_id, id)? {
+            return Ok(None);
+        }
+        db::get_unread_notification_count(conn, user_id).map(Some)
+    }).await?;
+    let count = count.ok_or_else(|| AppError::not_found("Notification not found"))?;
     
-    (jar, Html(String::new()))
+    Ok(Html(format!(
+        r#"<span id="notification-count" hx-swap-oob="true">
+            {}
+        </span>"#,
+        if count > 0 {
+            format!(r#"<span class="notification-badge">{}</span>"#, count)
+        } else {
+            String::new()
+        }
+    )))
 }
 
 pub async fn mark_all_read(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        let _ = db::mark_all_notifications_read(&conn, user.id);
-        
-        return (jar, Html(r#"
-            <span id="notification-count" hx-swap-oob="true"></span>
-            <div id="toast-container" hx-swap-oob="beforeend">
-                <div class="toast success">All notifications marked as read</div>
-            </div>
-        "#.to_string()));
-    }
+) -> HtmlResult {
+    let user_id = user.id;
+    db.write(move |conn| db::mark_all_notifications_read(conn, user_id)).await?;
     
-    (jar, Html(String::new()))
+    Ok(Html(r#"
+        <span id="notification-count" hx-swap-oob="true"></span>
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">All notifications marked as read</div>
+        </div>
+    "#.to_string()))
 }
@@ -0,0 +1,391 @@
+use axum::{
+    extract::{Path, State},
+    http::{HeaderMap, StatusCode},
+    response::{Html, IntoResponse, Response},
+    Form,
+};
+use serde::Serialize;
+use std::sync::Arc;
+use tera::{Context, Tera};
+
+use crate::auth::{CurrentUser, MaybeUser, PageContext, Poster, RequireRole};
+use crate::db::{self, Db};
+use crate::error::{redirect, AppError, HtmlResult};
+use crate::models::{
+    Post, ProcedureDetails, ProcedurePart, ProcedureStep, ProcedureTorqueSpec, User, PROCEDURE_DIFFICULTIES, TORQUE_UNITS,
+};
+use crate::routes::forum::{editable_post, post_vehicle_id};
+use crate::routes::garage::VehicleForm;
+use crate::telemetry;
+
+const MAX_STEPS: usize = 100;
+const MAX_STEP_LEN: usize = 5000;
+const MAX_ITEMS: usize = 50;
+const MAX_ITEM_LEN: usize = 120;
+const MAX_PART_NUMBER_LEN: usize = 60;
+const MAX_LABOR_HOURS: f64 = 200.0;
+
+/// The procedure form as submitted. Steps, tools, parts and torque specs
+/// arrive as repeated fields (one value per row, in order), so the body is
+/// read as raw pairs rather than through a derived struct.
+#[derive(Default, Serialize)]
+pub struct ProcedureForm {
+    pub category_id: String,
+    pub title: String,
+    pub body: String,
+    pub tags: Vec<i64>,
+    pub vehicle_id: String,
+    pub save_to_garage: bool,
+    pub vehicle: VehicleForm,
+    pub difficulty: String,
+    pub labor_hours: String,
+    pub step_body: Vec<String>,
+    pub step_image: Vec<String>,
+    /// Only used to redisplay image previews when the form is re-rendered
+    pub step_image_url: Vec<String>,
+    pub tool: Vec<String>,
+    pub part_name: Vec<String>,
+    pub part_number: Vec<String>,
+    pub part_quantity: Vec<String>,
+    pub torque_fastener: Vec<String>,
+    pub torque_value: Vec<String>,
+    pub torque_unit: Vec<String>,
+}
+
+fn row(values: &[String], index: usize) -> &str {
+    values.get(index).map(|v| v.trim()).unwrap_or("")
+}
+
+fn check_len(value: &str, max: usize, name: &str) -> Result<(), String> {
+    if value.chars().count() > max {
+        return Err(format!("{} must be at most {} characters", name, max));
+    }
+    Ok(())
+}
+
+impl ProcedureForm {
+    pub fn from_fields(fields: Vec<(String, String)>) -> Self {
+        let mut form = ProcedureForm::default();
+        for (key, value) in fields {
+            match key.as_str() {
+                "category_id" => form.category_id = value,
+                "title" => form.title = value,
+                "body" => form.body = value,
+                "tags" => form.tags.extend(value.parse::<i64>().ok()),
+                "vehicle_id" => form.vehicle_id = value,
+                "save_to_garage" => form.save_to_garage = true,
+                "year" => form.vehicle.year = value,
+                "make" => form.vehicle.make = value,
+                "model" => form.vehicle.model = value,
+                "trim" => form.vehicle.trim = value,
+                "engine_code" => form.vehicle.engine_code = value,
+                "transmission" => form.vehicle.transmission = value,
+                "mileage" => form.vehicle.mileage = value,
+                "difficulty" => form.difficulty = value,
+                "labor_hours" => form.labor_hours = value,
+                "step_body" => form.step_body.push(value),
+                "step_image" => form.step_image.push(value),
+                "step_image_url" => form.step_image_url.push(value),
+                "tool" => form.tool.push(value),
+                "part_name" => form.part_name.push(value),
+                "part_number" => form.part_number.push(value),
+                "part_quantity" => form.part_quantity.push(value),
+                "torque_fastener" => form.torque_fastener.push(value),
+                "torque_value" => form.torque_value.push(value),
+                "torque_unit" => form.torque_unit.push(value),
+                _ => {}
+            }
+        }
+        form
+    }
+
+    /// Validates the structured fields. Blank rows are dropped.
+    pub fn details(&self) -> Result<ProcedureDetails, String> {
+        if !PROCEDURE_DIFFICULTIES.contains(&self.difficulty.as_str()) {
+            return Err("Pick a difficulty".to_string());
+        }
+
+        let labor_minutes = match self.labor_hours.trim() {
+            "" => None,
+            hours => match hours.parse::<f64>() {
+                Ok(h) if h > 0.0 && h <= MAX_LABOR_HOURS => Some((h * 60.0).round() as i64),
+                _ => return Err(format!("Labor time must be between 0 and {} hours", MAX_LABOR_HOURS)),
+            },
+        };
+
+        let mut steps = Vec::new();
+        for i in 0..self.step_body.len() {
+            let (body, image) = (row(&self.step_body, i), row(&self.step_image, i));
+            if body.is_empty() && image.is_empty() {
+                continue;
+            }
+            if body.is_empty() {
+                return Err(format!("Step {} needs instructions", steps.len() + 1));
+            }
+            check_len(body, MAX_STEP_LEN, &format!("Step {}", steps.len() + 1))?;
+            steps.push(ProcedureStep {
+                body: body.to_string(),
+                upload_id: image.parse().ok(),
+                ..Default::default()
+            });
+        }
+        if steps.is_empty() {
+            return Err("Add at least one step".to_string());
+        }
+        if steps.len() > MAX_STEPS {
+            return Err(format!("Procedures can have at most {} steps", MAX_STEPS));
+        }
+
+        let mut tools = Vec::new();
+        for tool in self.tool.iter().map(|t| t.trim()).filter(|t| !t.is_empty()) {
+            check_len(tool, MAX_ITEM_LEN, "Tool names")?;
+            tools.push(tool.to_string());
+        }
+
+        let mut parts = Vec::new();
+        for i in 0..self.part_name.len() {
+            let (name, number, quantity) = (row(&self.part_name, i), row(&self.part_number, i), row(&self.part_quantity, i));
+            if name.is_empty() && number.is_empty() {
+                continue;
+            }
+            if name.is_empty() {
+                return Err(format!("Part {} needs a name", number));
+            }
+            check_len(name, MAX_ITEM_LEN, "Part names")?;
+            check_len(number, MAX_PART_NUMBER_LEN, "Part numbers")?;
+            let quantity = match quantity {
+                "" => 1,
+                q => match q.parse::<i64>() {
+                    Ok(q) if (1..=999).contains(&q) => q,
+                    _ => return Err(format!("Quantity for {} must be a whole number from 1 to 999", name)),
+                },
+            };
+            parts.push(ProcedurePart {
+                name: name.to_string(),
+                part_number: (!number.is_empty()).then(|| number.to_string()),
+                quantity,
+            });
+        }
+
+        let mut torque_specs = Vec::new();
+        for i in 0..self.torque_fastener.len() {
+            let (fastener, value, unit) = (row(&self.torque_fastener, i), row(&self.torque_value, i), row(&self.torque_unit, i));
+            if fastener.is_empty() && value.is_empty() {
+                continue;
+            }
+            if fastener.is_empty() {
+                return Err("Every torque spec needs a fastener".to_string());
+            }
+            check_len(fastener, MAX_ITEM_LEN, "Fastener names")?;
+            let value = match value.parse::<f64>() {
+                Ok(v) if v > 0.0 && v < 10_000.0 => v,
+                _ => return Err(format!("Enter a torque value for {}", fastener)),
+            };
+            if !TORQUE_UNITS.contains(&unit) {
+                return Err(format!("Pick a unit for {}", fastener));
+            }
+            torque_specs.push(ProcedureTorqueSpec { fastener: fastener.to_string(), value, unit: unit.to_string() });
+        }
+
+        if tools.len() > MAX_ITEMS || parts.len() > MAX_ITEMS || torque_specs.len() > MAX_ITEMS {
+            return Err(format!("Tool, part and torque lists can have at most {} entries each", MAX_ITEMS));
+        }
+
+        Ok(ProcedureDetails { difficulty: self.difficulty.clone(), labor_minutes, steps, tools, parts, torque_specs })
+    }
+}
+
+fn validate_post_fields(form: &ProcedureForm) -> Result<(), String> {
+    if form.title.trim().is_empty() || form.title.len() > 300 {
+        return Err("Title must be between 1 and 300 characters".to_string());
+    }
+    if form.body.trim().is_empty() {
+        return Err("Add a short overview of the job".to_string());
+    }
+    Ok(())
+}
+
+/// Step images must be the procedure author's own uploads
+fn keep_own_images(conn: &rusqlite::Connection, author_id: i64, details: &mut ProcedureDetails) -> rusqlite::Result<()> {
+    for step in &mut details.steps {
+        if let Some(upload_id) = step.upload_id {
+            let owned = db::get_upload(conn, upload_id)?.is_some_and(|u| u.user_id == author_id);
+            if !owned {
+                step.upload_id = None;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn render_form(db: &Db, tera: &Tera, mut ctx: Context, user_id: i64, form: &ProcedureForm, post_id: Option<i64>, error: Option<&str>) -> Result<String, AppError> {
+    let (categories, tags, garage) = db.read(move |conn| {
+        Ok((
+            db::get_categories(conn)?,
+            db::get_all_tags(conn)?,
+            db::get_user_garage(conn, user_id)?,
+        ))
+    }).await?;
+
+    ctx.insert("categories", &categories);
+    ctx.insert("tags", &tags);
+    ctx.insert("garage", &garage);
+    ctx.insert("form", form);
+    ctx.insert("post_id", &post_id);
+    ctx.insert("difficulties", PROCEDURE_DIFFICULTIES);
+    ctx.insert("torque_units", TORQUE_UNITS);
+    if let Some(error) = error {
+        ctx.insert("error", error);
+    }
+    Ok(telemetry::render(tera, "procedure_form.html", &ctx)?)
+}
+
+/// The procedure post, if `user` may edit it
+async fn editable_procedure(db: &Db, post_id: i64, user: &User) -> Result<Post, AppError> {
+    let post = editable_post(db, post_id, user).await?;
+    if post.procedure.is_none() {
+        return Err(AppError::not_found("Procedure not found"));
+    }
+    Ok(post)
+}
+
+/// Pre-fill the form from a saved procedure for editing
+fn form_from_details(title: &str, body: &str, details: &ProcedureDetails) -> ProcedureForm {
+    ProcedureForm {
+        title: title.to_string(),
+        body: body.to_string(),
+        difficulty: details.difficulty.clone(),
+        labor_hours: details.labor_minutes.map(|m| format!("{}", m as f64 / 60.0)).unwrap_or_default(),
+        step_body: details.steps.iter().map(|s| s.body.clone()).collect(),
+        step_image: details.steps.iter().map(|s| s.upload_id.map(|id| id.to_string()).unwrap_or_default()).collect(),
+        step_image_url: details.steps.iter().map(|s| s.image_url.clone().unwrap_or_default()).collect(),
+        tool: details.tools.clone(),
+        part_name: details.parts.iter().map(|p| p.name.clone()).collect(),
+        part_number: details.parts.iter().map(|p| p.part_number.clone().unwrap_or_default()).collect(),
+        part_quantity: details.parts.iter().map(|p| p.quantity.to_string()).collect(),
+        torque_fastener: details.torque_specs.iter().map(|t| t.fastener.clone()).collect(),
+        torque_value: details.torque_specs.iter().map(|t| t.value.to_string()).collect(),
+        torque_unit: details.torque_specs.iter().map(|t| t.unit.clone()).collect(),
+        ..Default::default()
+    }
+}
+
+pub async fn new_procedure_page(
+    RequireRole(user, _): RequireRole<Poster>,
+    PageContext(ctx): PageContext,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let form = ProcedureForm { difficulty: "intermediate".to_string(), ..Default::default() };
+    let html = render_form(&db, &tera, ctx, user.id, &form, None, None).await?;
+    Ok(Html(html))
+}
+
+pub async fn create_procedure(
+    RequireRole(user, _): RequireRole<Poster>,
+    PageContext(ctx): PageContext,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    headers: HeaderMap,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<Response, AppError> {
+    let form = ProcedureForm::from_fields(fields);
+    let garage_vehicle = form.vehicle_id.trim().parse::<i64>().ok();
+    let validated = validate_post_fields(&form)
+        .and_then(|_| form.category_id.parse::<i64>().map_err(|_| "Pick a category".to_string()))
+        .and_then(|category_id| Ok((category_id, form.details()?)))
+        .and_then(|(category_id, details)| {
+            let vehicle = if garage_vehicle.is_none() && !form.vehicle.is_blank() {
+                Some(form.vehicle.to_details()?)
+            } else {
+                None
+            };
+            Ok((category_id, details, vehicle))
+        });
+
+    let (category_id, mut details, new_vehicle) = match validated {
+        Ok(v) => v,
+        Err(e) => {
+            let html = render_form(&db, &tera, ctx, user.id, &form, None, Some(&e)).await?;
+            return Ok((StatusCode::UNPROCESSABLE_ENTITY, Html(html)).into_response());
+        }
+    };
+
+    let user_id = user.id;
+    let (title, body, tags, save_to_garage) = (form.title, form.body, form.tags, form.save_to_garage);
+    let post_id = db.write(move |conn| {
+        let tx = conn.unchecked_transaction()?;
+        keep_own_images(&tx, user_id, &mut details)?;
+        let vehicle_id = post_vehicle_id(&tx, user_id, garage_vehicle, new_vehicle, save_to_garage)?;
+        let post_id = db::create_post_with_tags(&tx, user_id, category_id, &title, &body, &tags, vehicle_id)?;
+        db::add_procedure(&tx, post_id, &details)?;
+        db::log_activity(&tx, user_id, "create_post", Some("post"), Some(post_id), None, None)?;
+        tx.commit()?;
+        Ok(post_id)
+    }).await?;
+
+    Ok(redirect(&headers, &format!("/post/{}", post_id)))
+}
+
+pub async fn edit_procedure_page(
+    CurrentUser(user): CurrentUser,
+    PageContext(ctx): PageContext,
+    Path(id): Path<i64>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let post = editable_procedure(&db, id, &user).await?;
+
+    let details = post.procedure.as_ref().unwrap();
+    let form = form_from_details(&post.title, &post.body, details);
+    let html = render_form(&db, &tera, ctx, user.id, &form, Some(id), None).await?;
+    Ok(Html(html))
+}
+
+pub async fn edit_procedure_submit(
+    CurrentUser(user): CurrentUser,
+    PageContext(ctx): PageContext,
+    Path(id): Path<i64>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    headers: HeaderMap,
+    Form(fields): Form<Vec<(String, String)>>,
+) -> Result<Response, AppError> {
+    let post = editable_procedure(&db, id, &user).await?;
+
+    let form = ProcedureForm::from_fields(fields);
+    let mut details = match validate_post_fields(&form).and_then(|_| form.details()) {
+        Ok(details) => details,
+        Err(e) => {
+            let html = render_form(&db, &tera, ctx, user.id, &form, Some(id), Some(&e)).await?;
+            return Ok((StatusCode::UNPROCESSABLE_ENTITY, Html(html)).into_response());
+        }
+    };
+
+    let (user_id, author_id) = (user.id, post.user_id);
+    let (title, body) = (form.title, form.body);
+    db.write(move |conn| {
+        let tx = conn.unchecked_transaction()?;
+        keep_own_images(&tx, author_id, &mut details)?;
+        db::update_post(&tx, id, user_id, &title, &body)?;
+        db::update_procedure(&tx, id, &details)?;
+        db::log_activity(&tx, user_id, "edit_post", Some("post"), Some(id), None, None)?;
+        tx.commit()
+    }).await?;
+
+    Ok(redirect(&headers, &format!("/post/{}", id)))
+}
+
+/// Printable shop-floor sheet: large type, checkboxes, no forum chrome
+pub async fn print_procedure(
+    MaybeUser(user): MaybeUser,
+    Path(id): Path<i64>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let user_id = user.map(|u| u.id);
+
+    let post = db.read(move |conn| db::get_post_by_id(conn, id))
+        .await?
+        .filter(|post| post.procedure.is_some() && (!post.removed || user_id == Some(post.user_id)))
+        .ok_or_else(|| AppError::not_found("Procedure not found"))?;
+
+    let mut ctx = Context::new();
+    ctx.insert("post", &post);
+    Ok(Html(telemetry::render(&tera, "procedure_print.html", &ctx)?))
+}
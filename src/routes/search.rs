@@ -2,13 +2,14 @@ use axum::{
     extract::{Query, State},
     response::{Html, Json},
 };
-use axum_extra::extract::CookieJar;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 use tera::{Context, Tera};
 
-use crate::auth::ensure_session;
+use crate::auth::PageContext;
 use crate::db::{self, Db};
+use crate::error::{AppError, HtmlResult};
+use crate::telemetry;
 
 #[derive(Deserialize)]
 pub struct SearchQuery {
@@ -26,82 +27,80 @@ pub struct SearchSuggestion {
 }
 
 pub async fn search_page(
-    jar: CookieJar,
+    PageContext(mut ctx): PageContext,
     Query(query): Query<SearchQuery>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
-    let mut ctx = Context::new();
-    
-    let jar = if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        let conn = db.lock().unwrap();
-        let unread_count = db::get_unread_notification_count(&conn, user.id).unwrap_or(0);
-        ctx.insert("user", &user);
-        ctx.insert("unread_notifications", &unread_count);
-        jar
-    } else {
-        jar
-    };
+) -> HtmlResult {
+    let q = query.q.clone().filter(|q| !q.trim().is_empty());
+    let category = query.category.clone().filter(|c| !c.is_empty());
+    let sort = query.sort.unwrap_or_else(|| "relevance".to_string());
+    let time = query.time.unwrap_or_else(|| "all".to_string());
     
-    let conn = db.lock().unwrap();
-    let categories = db::get_categories(&conn).unwrap_or_default();
+    let (search_sort, search_time) = (sort.clone(), time.clone());
+    let (categories, results) = db.read(move |conn| {
+        let categories = db::get_categories(conn)?;
+        let results = match q {
+            Some(q) => {
+                // Search posts and stores
+                let posts = db::search_posts_sorted(conn, &q, category.as_deref(), &search_sort, &search_time, 50)?;
+                // A category filter only applies to posts
+                let stores = if category.is_none() {
+                    db::search_stores_sorted(conn, &q, &search_sort, &search_time)?
+                } else {
+                    Vec::new()
+                };
+                Some((q, posts, stores))
+            }
+            None => None,
+        };
+        Ok((categories, results))
+    }).await?;
     ctx.insert("categories", &categories);
     
-    if let Some(ref q) = query.q {
-        if !q.trim().is_empty() {
-            // Search posts
-            let posts = db::search_posts(&conn, q, query.category.as_deref(), 50).unwrap_or_default();
-            ctx.insert("posts", &posts);
-            
-            // Search stores
-            let stores = db::search_stores(&conn, q).unwrap_or_default();
-            ctx.insert("stores", &stores);
-            
-            ctx.insert("query", q);
-            ctx.insert("result_count", &(posts.len() + stores.len()));
-        }
+    if let Some((q, posts, stores)) = results {
+        ctx.insert("posts", &posts);
+        ctx.insert("stores", &stores);
+        ctx.insert("query", &q);
+        ctx.insert("result_count", &(posts.len() + stores.len()));
     }
     
     ctx.insert("selected_category", &query.category);
-    ctx.insert("sort", &query.sort.unwrap_or_else(|| "relevance".to_string()));
-    ctx.insert("time", &query.time.unwrap_or_else(|| "all".to_string()));
+    ctx.insert("sort", &sort);
+    ctx.insert("time", &time);
     
-    let html = tera.render("search.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-    (jar, Html(html))
+    Ok(Html(telemetry::render(&tera, "search.html", &ctx)?))
 }
 
 pub async fn search_api(
-    jar: CookieJar,
     Query(query): Query<SearchQuery>,
     State((db, tera)): State<(Db, Arc<Tera>)>,
-) -> (CookieJar, Html<String>) {
+) -> HtmlResult {
     let mut ctx = Context::new();
     
-    if let Some((user, _)) = ensure_session(jar.clone(), &db) {
-        ctx.insert("user", &user);
-    }
-    
-    let conn = db.lock().unwrap();
-    
-    if let Some(ref q) = query.q {
-        if !q.trim().is_empty() {
-            let posts = db::search_posts(&conn, q, query.category.as_deref(), 20).unwrap_or_default();
-            ctx.insert("posts", &posts);
-            ctx.insert("query", q);
-        }
+    if let Some(q) = query.q.filter(|q| !q.trim().is_empty()) {
+        let category = query.category.filter(|c| !c.is_empty());
+        let sort = query.sort.unwrap_or_else(|| "relevance".to_string());
+        let time = query.time.unwrap_or_else(|| "all".to_string());
+        let search_q = q.clone();
+        let posts = db.read(move |conn| {
+            db::search_posts_sorted(conn, &search_q, category.as_deref(), &sort, &time, 20)
+        }).await?;
+        ctx.insert("posts", &posts);
+        ctx.insert("query", &q);
     }
     
-    let html = tera.render("partials/search_results.html", &ctx).unwrap_or_else(|e| format!("Error: {}", e));
-    (jar, Html(html))
+    Ok(Html(telemetry::render(&tera, "partials/search_results.html", &ctx)?))
 }
 
 pub async fn search_suggestions(
     Query(query): Query<SearchQuery>,
     State((db, _)): State<(Db, Arc<Tera>)>,
-) -> Json<Vec<SearchSuggestion>> {
+) -> Result<Json<Vec<SearchSuggestion>>, AppError> {
     if let Some(q) = query.q {
         if q.len() >= 2 {
-            let conn = db.lock().unwrap();
-            let results = db::global_search(&conn, &q, 5).unwrap_or_default();
+            let sort = query.sort.unwrap_or_else(|| "relevance".to_string());
+            let time = query.time.unwrap_or_else(|| "all".to_string());
+            let results = db.read(move |conn| db::global_search_sorted(conn, &q, &sort, &time, 5)).await?;
             
             let suggestions: Vec<SearchSuggestion> = results.into_iter().map(|r| {
                 SearchSuggestion {
@@ -111,9 +110,9 @@ pub async fn search_suggestions(
                 }
             }).collect();
             
-            return Json(suggestions);
+            return Ok(Json(suggestions));
         }
     }
     
-    Json(vec![])
+    Ok(Json(vec![]))
 }
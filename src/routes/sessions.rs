@@ -0,0 +1,155 @@
+use axum::{
+    extract::{Path, State},
+    response::Html,
+    Form,
+};
+use axum_extra::extract::CookieJar;
+use serde::Deserialize;
+use std::sync::Arc;
+use tera::{Context, Tera};
+
+use crate::auth::{describe_user_agent, now_timestamp, session_public_id, CurrentUser, PageContext};
+use crate::db::{self, Db};
+use crate::error::{AppError, HtmlResult};
+use crate::models::{ActiveSession, Session};
+use crate::telemetry;
+
+/// Longest name a session can be given
+const SESSION_NAME_MAX: usize = 50;
+
+#[derive(Deserialize)]
+pub struct SessionNameForm {
+    pub name: String,
+}
+
+fn active_sessions(sessions: Vec<Session>, current_token: &str) -> Vec<ActiveSession> {
+    sessions
+        .into_iter()
+        .map(|s| ActiveSession {
+            id: session_public_id(&s.token),
+            current: s.token == current_token,
+            device: describe_user_agent(s.user_agent.as_deref()),
+            last_seen_at: s.last_seen_at.unwrap_or_else(|| s.created_at.clone()),
+            name: s.name,
+            ip_address: s.ip_address,
+            created_at: s.created_at,
+        })
+        .collect()
+}
+
+/// The session with public id `id`, if it belongs to the user
+fn find_session_token(conn: &rusqlite::Connection, user_id: i64, id: &str) -> rusqlite::Result<Option<String>> {
+    Ok(db::get_user_sessions(conn, user_id, &now_timestamp())?
+        .into_iter()
+        .map(|s| s.token)
+        .find(|token| session_public_id(token) == id))
+}
+
+fn render_list(tera: &Tera, sessions: Vec<ActiveSession>, toast: &str) -> HtmlResult {
+    let mut ctx = Context::new();
+    ctx.insert("sessions", &sessions);
+    let html = telemetry::render(tera, "partials/session_list.html", &ctx)?;
+    Ok(Html(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">{}</div>
+        </div>"#,
+        html, toast
+    )))
+}
+
+/// The user's sessions after a change, for swapping into the page
+async fn reload_sessions(db: &Db, user_id: i64, current_token: String) -> Result<Vec<ActiveSession>, AppError> {
+    let sessions = db.read(move |conn| db::get_user_sessions(conn, user_id, &now_timestamp())).await?;
+    Ok(active_sessions(sessions, &current_token))
+}
+
+pub async fn sessions_page(
+    CurrentUser(user): CurrentUser,
+    PageContext(mut ctx): PageContext,
+    jar: CookieJar,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let current_token = jar.get("session").map(|c| c.value().to_string()).unwrap_or_default();
+    let user_id = user.id;
+    let sessions = db.read(move |conn| db::get_user_sessions(conn, user_id, &now_timestamp())).await?;
+
+    ctx.insert("sessions", &active_sessions(sessions, &current_token));
+
+    Ok(Html(telemetry::render(&tera, "sessions.html", &ctx)?))
+}
+
+pub async fn rename_session(
+    CurrentUser(user): CurrentUser,
+    jar: CookieJar,
+    Path(id): Path<String>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Form(form): Form<SessionNameForm>,
+) -> HtmlResult {
+    let current_token = jar.get("session").map(|c| c.value().to_string()).unwrap_or_default();
+    let user_id = user.id;
+    let name: String = form.name.trim().chars().take(SESSION_NAME_MAX).collect();
+    let found = db.write(move |conn| {
+        let Some(token) = find_session_token(conn, user_id, &id)? else {
+            return Ok(false);
+        };
+        db::rename_session(conn, user_id, &token, Some(name.as_str()).filter(|n| !n.is_empty()))?;
+        Ok(true)
+    }).await?;
+    if !found {
+        return Err(AppError::not_found("That session has already ended"));
+    }
+
+    let sessions = reload_sessions(&db, user_id, current_token).await?;
+    render_list(&tera, sessions, "Session renamed")
+}
+
+/// Sign out one of the user's other sessions
+pub async fn revoke_session(
+    CurrentUser(user): CurrentUser,
+    jar: CookieJar,
+    Path(id): Path<String>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let current_token = jar.get("session").map(|c| c.value().to_string()).unwrap_or_default();
+    let user_id = user.id;
+    let keep_token = current_token.clone();
+    let found = db.write(move |conn| {
+        // This device signs out with the logout link instead
+        let Some(token) = find_session_token(conn, user_id, &id)?.filter(|t| *t != keep_token) else {
+            return Ok(false);
+        };
+        db::delete_session(conn, &token)?;
+        db::log_activity(conn, user_id, "revoke_session", Some("user"), Some(user_id), None, None)?;
+        Ok(true)
+    }).await?;
+    if !found {
+        return Err(AppError::not_found("That session has already ended"));
+    }
+
+    let sessions = reload_sessions(&db, user_id, current_token).await?;
+    render_list(&tera, sessions, "Session signed out")
+}
+
+pub async fn revoke_other_sessions(
+    CurrentUser(user): CurrentUser,
+    jar: CookieJar,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let current_token = jar.get("session").map(|c| c.value().to_string()).unwrap_or_default();
+    let user_id = user.id;
+    let keep_token = current_token.clone();
+    let revoked = db.write(move |conn| {
+        let revoked = db::delete_other_sessions(conn, user_id, &keep_token)?;
+        let details = format!("{} sessions", revoked);
+        db::log_activity(conn, user_id, "revoke_other_sessions", Some("user"), Some(user_id), Some(&details), None)?;
+        Ok(revoked)
+    }).await?;
+
+    let sessions = reload_sessions(&db, user_id, current_token).await?;
+    let toast = match revoked {
+        1 => "Signed out 1 other session".to_string(),
+        n => format!("Signed out {} other sessions", n),
+    };
+    render_list(&tera, sessions, &toast)
+}
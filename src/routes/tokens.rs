@@ -0,0 +1,134 @@
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    Form,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tera::{Context, Tera};
+
+use crate::api;
+use crate::auth::{hash_token, CurrentUser, PageContext};
+use crate::db::{self, Db};
+use crate::error::{AppError, HtmlResult};
+use crate::models::ApiScope;
+use crate::telemetry;
+
+/// Longest name a token can be given
+const TOKEN_NAME_MAX: usize = 50;
+
+/// Tokens one user can have at a time
+const MAX_TOKENS_PER_USER: usize = 20;
+
+#[derive(Deserialize)]
+pub struct NewTokenForm {
+    pub name: String,
+    pub scope_read: Option<String>,
+    pub scope_write: Option<String>,
+    pub scope_notifications: Option<String>,
+    pub scope_metrics: Option<String>,
+}
+
+impl NewTokenForm {
+    fn scopes(&self) -> Vec<ApiScope> {
+        [
+            (ApiScope::Read, &self.scope_read),
+            (ApiScope::Write, &self.scope_write),
+            (ApiScope::Notifications, &self.scope_notifications),
+            (ApiScope::Metrics, &self.scope_metrics),
+        ]
+        .into_iter()
+        .filter(|(_, checked)| checked.is_some())
+        .map(|(scope, _)| scope)
+        .collect()
+    }
+}
+
+async fn render_page(db: &Db, tera: &Tera, user_id: i64, mut ctx: Context) -> HtmlResult {
+    let tokens = db.read(move |conn| db::get_user_api_tokens(conn, user_id)).await?;
+
+    ctx.insert("tokens", &tokens);
+    Ok(Html(telemetry::render(tera, "tokens.html", &ctx)?))
+}
+
+pub async fn tokens_page(
+    CurrentUser(user): CurrentUser,
+    PageContext(ctx): PageContext,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    render_page(&db, &tera, user.id, ctx).await
+}
+
+/// Create a token and show it, the only time it's shown
+pub async fn create_token(
+    CurrentUser(user): CurrentUser,
+    PageContext(mut ctx): PageContext,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Form(form): Form<NewTokenForm>,
+) -> Result<Response, AppError> {
+    let name: String = form.name.trim().chars().take(TOKEN_NAME_MAX).collect();
+    let scopes = form.scopes();
+
+    let error = if name.is_empty() {
+        Some((StatusCode::UNPROCESSABLE_ENTITY, "Give the token a name so you can tell it apart later".to_string()))
+    } else if scopes.is_empty() {
+        Some((StatusCode::UNPROCESSABLE_ENTITY, "Choose at least one scope".to_string()))
+    } else {
+        let token = api::create_token();
+        let (token_hash, prefix) = (hash_token(&token), api::display_prefix(&token));
+        let user_id = user.id;
+        let created = db.write(move |conn| {
+            if db::get_user_api_tokens(conn, user_id)?.len() >= MAX_TOKENS_PER_USER {
+                return Ok(false);
+            }
+            let token_id = db::create_api_token(conn, user_id, &name, &token_hash, &prefix, &scopes)?;
+            db::log_activity(conn, user_id, "create_api_token", Some("api_token"), Some(token_id), Some(&ApiScope::join(&scopes)), None)?;
+            Ok(true)
+        }).await?;
+
+        if created {
+            ctx.insert("new_token", &token);
+            None
+        } else {
+            Some((StatusCode::CONFLICT, format!("You can have at most {} tokens. Revoke one you no longer use first.", MAX_TOKENS_PER_USER)))
+        }
+    };
+
+    let status = match error {
+        Some((status, message)) => {
+            ctx.insert("error", &message);
+            status
+        }
+        None => StatusCode::OK,
+    };
+    let html = render_page(&db, &tera, user.id, ctx).await?;
+    Ok((status, html).into_response())
+}
+
+pub async fn revoke_token(
+    CurrentUser(user): CurrentUser,
+    Path(id): Path<i64>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let user_id = user.id;
+    let tokens = db.write(move |conn| {
+        if !db::delete_api_token(conn, user_id, id)? {
+            return Ok(None);
+        }
+        db::log_activity(conn, user_id, "revoke_api_token", Some("api_token"), Some(id), None, None)?;
+        db::get_user_api_tokens(conn, user_id).map(Some)
+    }).await?;
+    let tokens = tokens.ok_or_else(|| AppError::not_found("Token not found"))?;
+
+    let mut ctx = Context::new();
+    ctx.insert("tokens", &tokens);
+    let html = telemetry::render(&tera, "partials/token_list.html", &ctx)?;
+    Ok(Html(format!(
+        r#"{}
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Token revoked</div>
+        </div>"#,
+        html
+    )))
+}
@@ -0,0 +1,251 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    response::{Html, Json},
+    Form,
+};
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tera::{Context, Tera};
+
+use crate::auth::{PageContext, Poster, RequireRole};
+use crate::db::{self, Db};
+use crate::error::{toast, AppError, HtmlResult};
+use crate::models::{TorqueSpec, TorqueSpecDetails, TorqueSpecFilter, TORQUE_UNITS};
+use crate::telemetry;
+
+const MAX_NAME_LEN: usize = 40;
+const MAX_FASTENER_LEN: usize = 120;
+const MAX_NOTE_LEN: usize = 500;
+const MAX_RESULTS: i64 = 200;
+
+#[derive(Deserialize, Serialize, Default)]
+pub struct TorqueQuery {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub year: Option<String>,
+    pub fastener: Option<String>,
+}
+
+impl TorqueQuery {
+    fn filter(&self) -> TorqueSpecFilter {
+        let text = |v: &Option<String>| v.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(String::from);
+        TorqueSpecFilter {
+            make: text(&self.make),
+            model: text(&self.model),
+            year: self.year.as_deref().and_then(|v| v.trim().parse().ok()),
+            fastener: text(&self.fastener),
+        }
+    }
+}
+
+/// Spec fields as submitted. Everything arrives as text so blank optional
+/// inputs don't fail to deserialize.
+#[derive(Deserialize, Default)]
+pub struct TorqueSpecForm {
+    #[serde(default)]
+    pub make: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub year_from: String,
+    #[serde(default)]
+    pub year_to: String,
+    #[serde(default)]
+    pub engine_code: String,
+    #[serde(default)]
+    pub fastener: String,
+    #[serde(default)]
+    pub value: String,
+    #[serde(default)]
+    pub unit: String,
+    #[serde(default)]
+    pub sequence: String,
+    #[serde(default)]
+    pub angle_degrees: String,
+    #[serde(default)]
+    pub torque_to_yield: String,
+    #[serde(default)]
+    pub source: String,
+}
+
+fn optional_field(value: &str, name: &str, max_len: usize) -> Result<Option<String>, String> {
+    let value = value.trim();
+    if value.chars().count() > max_len {
+        return Err(format!("{} must be at most {} characters", name, max_len));
+    }
+    Ok(if value.is_empty() { None } else { Some(value.to_string()) })
+}
+
+impl TorqueSpecForm {
+    pub fn to_details(&self) -> Result<TorqueSpecDetails, String> {
+        let max_year = chrono::Utc::now().year() as i64 + 2;
+        let parse_year = |text: &str| match text.trim().parse::<i64>() {
+            Ok(year) if (1886..=max_year).contains(&year) => Ok(year),
+            _ => Err(format!("Enter model years between 1886 and {}", max_year)),
+        };
+        let year_from = parse_year(&self.year_from)?;
+        let year_to = if self.year_to.trim().is_empty() { year_from } else { parse_year(&self.year_to)? };
+        if year_to < year_from {
+            return Err("The last model year can't be before the first".to_string());
+        }
+
+        let make = optional_field(&self.make, "Make", MAX_NAME_LEN)?.ok_or("Make is required")?;
+        let model = optional_field(&self.model, "Model", MAX_NAME_LEN)?.ok_or("Model is required")?;
+        let fastener = optional_field(&self.fastener, "Fastener", MAX_FASTENER_LEN)?.ok_or("Fastener is required")?;
+
+        let value = match self.value.trim().parse::<f64>() {
+            Ok(value) if value > 0.0 && value < 10_000.0 => value,
+            _ => return Err("Torque must be a positive number".to_string()),
+        };
+        let unit = self.unit.trim();
+        if !TORQUE_UNITS.contains(&unit) {
+            return Err("Pick a torque unit".to_string());
+        }
+
+        let angle_degrees = if self.angle_degrees.trim().is_empty() {
+            None
+        } else {
+            match self.angle_degrees.trim().parse::<i64>() {
+                Ok(angle) if (1..=720).contains(&angle) => Some(angle),
+                _ => return Err("Angle must be a whole number of degrees up to 720".to_string()),
+            }
+        };
+
+        Ok(TorqueSpecDetails {
+            make,
+            model,
+            year_from,
+            year_to,
+            engine_code: optional_field(&self.engine_code, "Engine code", MAX_NAME_LEN)?.map(|e| e.to_uppercase()),
+            fastener,
+            value,
+            unit: unit.to_string(),
+            sequence: optional_field(&self.sequence, "Sequence", MAX_NOTE_LEN)?,
+            angle_degrees,
+            torque_to_yield: !self.torque_to_yield.is_empty(),
+            source: optional_field(&self.source, "Source", MAX_NOTE_LEN)?,
+        })
+    }
+}
+
+#[derive(Deserialize)]
+pub struct TorqueVoteForm {
+    pub confirmed: bool,
+}
+
+#[derive(Serialize)]
+pub struct TorqueLookupError {
+    pub error: String,
+}
+
+pub async fn torque_page(
+    PageContext(mut ctx): PageContext,
+    Query(query): Query<TorqueQuery>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    let filter = query.filter();
+    let lookup = filter.clone();
+    let (specs, makes) = db.read(move |conn| {
+        Ok((
+            db::get_torque_specs(conn, &lookup, MAX_RESULTS)?,
+            db::get_torque_spec_makes(conn)?,
+        ))
+    }).await?;
+
+    ctx.insert("specs", &specs);
+    ctx.insert("makes", &makes);
+    ctx.insert("filter", &filter);
+    ctx.insert("query", &query);
+    ctx.insert("torque_units", TORQUE_UNITS);
+    ctx.insert("current_page", &"torque");
+
+    Ok(Html(telemetry::render(&tera, "torque.html", &ctx)?))
+}
+
+/// Verified mechanics add a spec; the list swaps to the spec's vehicle so
+/// the submitter sees it alongside any existing specs
+pub async fn submit_torque_spec(
+    RequireRole(user, _): RequireRole<Poster>,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Form(form): Form<TorqueSpecForm>,
+) -> HtmlResult {
+    let details = form.to_details().map_err(AppError::validation)?;
+
+    let user_id = user.id;
+    let filter = TorqueSpecFilter {
+        make: Some(details.make.clone()),
+        model: Some(details.model.clone()),
+        ..Default::default()
+    };
+    let specs = db.write(move |conn| {
+        db::create_torque_spec(conn, &details, user_id)?;
+        db::get_torque_specs(conn, &filter, MAX_RESULTS)
+    }).await?;
+
+    let mut ctx = Context::new();
+    ctx.insert("specs", &specs);
+    ctx.insert("user", &user);
+    let list = telemetry::render(&tera, "partials/torque_list.html", &ctx)?;
+    Ok(Html(format!(
+        r#"<div id="torque-list" hx-swap-oob="true">{}</div>{}"#,
+        list,
+        toast("success", "Torque spec added. Thanks!")
+    )))
+}
+
+pub async fn vote_torque_spec(
+    RequireRole(user, _): RequireRole<Poster>,
+    Path(spec_id): Path<i64>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+    Form(form): Form<TorqueVoteForm>,
+) -> HtmlResult {
+    let user_id = user.id;
+    let confirmed = form.confirmed;
+    let spec = db.write(move |conn| {
+        let Some(spec) = db::get_torque_spec(conn, spec_id)? else {
+            return Ok(Err(AppError::not_found("That spec no longer exists")));
+        };
+        if spec.submitted_by == user_id {
+            return Ok(Err(AppError::forbidden("You can't vote on your own spec")));
+        }
+        if db::get_user_torque_spec_vote(conn, spec_id, user_id)? != Some(confirmed) {
+            db::vote_torque_spec(conn, spec_id, user_id, confirmed)?;
+        }
+        Ok(Ok(db::get_torque_spec(conn, spec_id)?.unwrap_or(spec)))
+    }).await??;
+
+    Ok(Html(format!(
+        r#"<span class="torque-votes">
+            <span class="torque-confirmations">✓ {}</span>
+            <span class="torque-disputes">✗ {}</span>
+        </span>{}"#,
+        spec.confirmations,
+        spec.disputes,
+        toast("success", if confirmed { "Spec confirmed" } else { "Spec disputed" })
+    )))
+}
+
+/// `GET /api/torque?make=&model=&year=&fastener=`. Make and model are
+/// required so the endpoint can't be used to dump the whole library.
+pub async fn torque_api(
+    Query(query): Query<TorqueQuery>,
+    State((db, _)): State<(Db, Arc<Tera>)>,
+) -> Result<Json<Vec<TorqueSpec>>, (StatusCode, Json<TorqueLookupError>)> {
+    let filter = query.filter();
+    if filter.make.is_none() || filter.model.is_none() {
+        return Err((
+            StatusCode::UNPROCESSABLE_ENTITY,
+            Json(TorqueLookupError { error: "make and model are required".to_string() }),
+        ));
+    }
+
+    db.read(move |conn| db::get_torque_specs(conn, &filter, MAX_RESULTS))
+        .await
+        .map(Json)
+        .map_err(|_| (
+            StatusCode::INTERNAL_SERVER_ERROR,
+            Json(TorqueLookupError { error: "Failed to load torque specs".to_string() }),
+        ))
+}
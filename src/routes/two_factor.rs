@@ -0,0 +1,203 @@
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    Form,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use tera::{Context, Tera};
+
+use crate::auth::{
+    check_second_factor, hash_token, now_timestamp, verify_password, RECOVERY_CODE_COUNT, TOTP_ISSUER,
+    CurrentUser, PageContext,
+};
+use crate::db::{self, Db};
+use crate::error::{AppError, HtmlResult};
+use crate::models::User;
+use crate::telemetry;
+use crate::totp;
+
+#[derive(Deserialize)]
+pub struct CodeForm {
+    pub code: String,
+}
+
+#[derive(Deserialize)]
+pub struct DisableForm {
+    pub password: String,
+    pub code: String,
+}
+
+/// What to show above the settings besides the current state
+#[derive(Default)]
+struct Notice<'a> {
+    error: Option<&'a str>,
+    success: Option<&'a str>,
+    /// Freshly issued recovery codes, shown exactly once
+    recovery_codes: Option<&'a [String]>,
+}
+
+/// The settings page. Without two-factor enabled it shows enrollment for a
+/// pending secret, creating one if needed.
+async fn render_page(db: &Db, tera: &Tera, mut ctx: Context, user: &User, notice: Notice<'_>) -> HtmlResult {
+    let page_user = user.clone();
+    let (user_totp, required, recovery_codes_left) = db.write(move |conn| {
+        let user_id = page_user.id;
+        let mut user_totp = db::get_user_totp(conn, user_id)?;
+        if user_totp.is_none() {
+            db::start_totp_enrollment(conn, user_id, &totp::base32_encode(&totp::generate_secret()))?;
+            user_totp = db::get_user_totp(conn, user_id)?;
+        }
+        Ok((
+            user_totp,
+            db::get_two_factor_roles(conn)?.contains(&page_user.role),
+            db::count_unused_recovery_codes(conn, user_id)?,
+        ))
+    }).await?;
+    // Just created if it was missing
+    let user_totp = user_totp.ok_or(AppError::Internal)?;
+
+    if !user_totp.enabled {
+        let secret = totp::base32_decode(&user_totp.secret).unwrap_or_default();
+        let uri = totp::provisioning_uri(TOTP_ISSUER, &user.email, &secret);
+        ctx.insert("secret", &user_totp.secret);
+        ctx.insert("provisioning_uri", &uri);
+        ctx.insert("qr_svg", &totp::qr_svg(&uri).unwrap_or_default());
+    }
+
+    // Replaces the page context's user, which may predate a change just made
+    ctx.insert("user", user);
+    ctx.insert("enabled", &user_totp.enabled);
+    ctx.insert("required", &required);
+    ctx.insert("recovery_codes_left", &recovery_codes_left);
+    ctx.insert("error", &notice.error);
+    ctx.insert("success", &notice.success);
+    ctx.insert("recovery_codes", &notice.recovery_codes);
+    Ok(Html(telemetry::render(tera, "two_factor.html", &ctx)?))
+}
+
+/// The settings page with `message` as an error, sent with `status`
+async fn render_error(db: &Db, tera: &Tera, ctx: Context, user: &User, status: StatusCode, message: &str) -> Result<Response, AppError> {
+    let html = render_page(db, tera, ctx, user, Notice { error: Some(message), ..Default::default() }).await?;
+    Ok((status, html).into_response())
+}
+
+/// New recovery codes and their hashes
+fn new_recovery_codes() -> (Vec<String>, Vec<String>) {
+    let codes = totp::generate_recovery_codes(RECOVERY_CODE_COUNT);
+    let hashes = codes.iter().map(|c| hash_token(&totp::normalize_recovery_code(c))).collect();
+    (codes, hashes)
+}
+
+/// Reload the user so `two_factor_enabled` reflects a change just made
+async fn reload_user(db: &Db, user: User) -> Result<User, AppError> {
+    let user_id = user.id;
+    Ok(db.read(move |conn| db::get_user_by_id(conn, user_id)).await?.unwrap_or(user))
+}
+
+pub async fn two_factor_page(
+    CurrentUser(user): CurrentUser,
+    PageContext(ctx): PageContext,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+) -> HtmlResult {
+    render_page(&db, &tera, ctx, &user, Notice::default()).await
+}
+
+/// Finish enrollment with the first code from the app
+pub async fn enable_two_factor(
+    CurrentUser(user): CurrentUser,
+    PageContext(ctx): PageContext,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Form(form): Form<CodeForm>,
+) -> Result<Response, AppError> {
+    let user_id = user.id;
+    let (codes, hashes) = new_recovery_codes();
+    let result = db.write(move |conn| {
+        let Some(pending) = db::get_user_totp(conn, user_id)?.filter(|t| !t.enabled) else {
+            return Ok(Err((StatusCode::CONFLICT, "Two-factor authentication is already enabled")));
+        };
+        let secret = totp::base32_decode(&pending.secret).unwrap_or_default();
+        let now = chrono::Utc::now().timestamp() as u64;
+        let Some(step) = totp::verify(&secret, &form.code, now, None) else {
+            return Ok(Err((
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "That code didn't match. Check the time on your phone and try the current code.",
+            )));
+        };
+        db::enable_totp(conn, user_id, step as i64, &hashes, &now_timestamp())?;
+        db::log_activity(conn, user_id, "enable_two_factor", Some("user"), Some(user_id), None, None)?;
+        Ok(Ok(()))
+    }).await?;
+
+    if let Err((status, message)) = result {
+        return render_error(&db, &tera, ctx, &user, status, message).await;
+    }
+    let user = reload_user(&db, user).await?;
+    let html = render_page(&db, &tera, ctx, &user, Notice {
+        success: Some("Two-factor authentication is on"),
+        recovery_codes: Some(&codes),
+        ..Default::default()
+    }).await?;
+    Ok(html.into_response())
+}
+
+/// Turn two-factor off. Needs the password and a code, and isn't allowed
+/// for roles an admin has required it for.
+pub async fn disable_two_factor(
+    CurrentUser(user): CurrentUser,
+    PageContext(ctx): PageContext,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Form(form): Form<DisableForm>,
+) -> Result<Response, AppError> {
+    let (user_id, role, email) = (user.id, user.role.clone(), user.email.clone());
+    let password_ok = db.read(move |conn| db::get_user_by_email(conn, &email))
+        .await?
+        .is_some_and(|(_, hash)| verify_password(&form.password, &hash));
+    let result = db.write(move |conn| {
+        if db::get_two_factor_roles(conn)?.contains(&role) {
+            return Ok(Err((StatusCode::FORBIDDEN, "Your role requires two-factor authentication")));
+        }
+        if !password_ok || !check_second_factor(conn, user_id, &form.code)? {
+            return Ok(Err((StatusCode::UNPROCESSABLE_ENTITY, "Wrong password or code")));
+        }
+        db::disable_totp(conn, user_id)?;
+        db::log_activity(conn, user_id, "disable_two_factor", Some("user"), Some(user_id), None, None)?;
+        Ok(Ok(()))
+    }).await?;
+
+    if let Err((status, message)) = result {
+        return render_error(&db, &tera, ctx, &user, status, message).await;
+    }
+    let user = reload_user(&db, user).await?;
+    let html = render_page(&db, &tera, ctx, &user, Notice { success: Some("Two-factor authentication is off"), ..Default::default() }).await?;
+    Ok(html.into_response())
+}
+
+/// Replace all recovery codes, e.g. after using a few
+pub async fn regenerate_recovery_codes(
+    CurrentUser(user): CurrentUser,
+    PageContext(ctx): PageContext,
+    State((db, tera)): State<(Db, Arc<Tera>)>,
+    Form(form): Form<CodeForm>,
+) -> Result<Response, AppError> {
+    let user_id = user.id;
+    let (codes, hashes) = new_recovery_codes();
+    let replaced = db.write(move |conn| {
+        if !check_second_factor(conn, user_id, &form.code)? {
+            return Ok(false);
+        }
+        db::replace_recovery_codes(conn, user_id, &hashes)?;
+        Ok(true)
+    }).await?;
+
+    if !replaced {
+        return render_error(&db, &tera, ctx, &user, StatusCode::UNPROCESSABLE_ENTITY, "That code didn't work").await;
+    }
+    let html = render_page(&db, &tera, ctx, &user, Notice {
+        success: Some("New recovery codes issued. The old ones no longer work."),
+        recovery_codes: Some(&codes),
+        ..Default::default()
+    }).await?;
+    Ok(html.into_response())
+}
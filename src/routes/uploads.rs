@@ -1,152 +1,159 @@
 use axum::{
     extract::{Multipart, State},
-    response::Html,
+    http::StatusCode,
+    response::{Html, Json},
+    Extension,
 };
-use axum_extra::extract::CookieJar;
+use serde::Serialize;
 use std::sync::Arc;
 use tera::Tera;
 use uuid::Uuid;
 
-use crate::auth::ensure_session;
+use crate::auth::{CurrentUser, MaybeUser};
+use crate::config::{Config, UPLOADS_URL_PREFIX};
 use crate::db::{self, Db};
+use crate::error::{AppError, HtmlResult};
 
-const MAX_FILE_SIZE: usize = 5 * 1024 * 1024; // 5MB
 const ALLOWED_TYPES: &[&str] = &["image/jpeg", "image/png", "image/gif", "image/webp"];
 
+#[derive(Serialize)]
+pub struct UploadResult {
+    pub success: bool,
+    pub url: String,
+    pub id: i64,
+}
+
+#[derive(Serialize)]
+pub struct UploadError {
+    pub error: String,
+}
+
+fn upload_error(status: StatusCode, message: &str) -> (StatusCode, Json<UploadError>) {
+    (status, Json(UploadError { error: message.to_string() }))
+}
+
+/// Called from script rather than htmx, so errors are JSON too
 pub async fn upload_file(
-    jar: CookieJar,
+    MaybeUser(user): MaybeUser,
     State((db, _)): State<(Db, Arc<Tera>)>,
+    Extension(config): Extension<Arc<Config>>,
     mut multipart: Multipart,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        while let Some(field) = multipart.next_field().await.ok().flatten() {
-            let content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
-            let original_name = field.file_name().unwrap_or("upload").to_string();
-            
-            // Check content type
-            if !ALLOWED_TYPES.contains(&content_type.as_str()) {
-                return (jar, Html(format!(
-                    r#"{{"error": "Invalid file type. Allowed: JPEG, PNG, GIF, WebP"}}"#
-                )));
-            }
-            
-            let data = match field.bytes().await {
-                Ok(d) => d,
-                Err(_) => {
-                    return (jar, Html(r#"{"error": "Failed to read file"}"#.to_string()));
-                }
-            };
-            
-            // Check file size
-            if data.len() > MAX_FILE_SIZE {
-                return (jar, Html(r#"{"error": "File too large. Maximum 5MB."}"#.to_string()));
-            }
-            
-            // Generate unique filename
-            let ext = match content_type.as_str() {
-                "image/jpeg" => "jpg",
-                "image/png" => "png",
-                "image/gif" => "gif",
-                "image/webp" => "webp",
-                _ => "bin",
-            };
-            let filename = format!("{}.{}", Uuid::new_v4(), ext);
-            let path = format!("static/uploads/{}", filename);
-            
-            // Write file
-            if let Err(_) = std::fs::write(&path, &data) {
-                return (jar, Html(r#"{"error": "Failed to save file"}"#.to_string()));
-            }
-            
-            // Save to database
-            let conn = db.lock().unwrap();
-            match db::create_upload(&conn, user.id, &filename, &original_name, &path, &content_type, data.len() as i64) {
-                Ok(upload_id) => {
-                    let url = format!("/static/uploads/{}", filename);
-                    return (jar, Html(format!(
-                        r#"{{"success": true, "url": "{}", "id": {}}}"#,
-                        url, upload_id
-                    )));
-                }
-                Err(_) => {
-                    // Clean up file on db error
-                    let _ = std::fs::remove_file(&path);
-                    return (jar, Html(r#"{"error": "Failed to save upload record"}"#.to_string()));
-                }
-            }
-        }
-        
-        return (jar, Html(r#"{"error": "No file provided"}"#.to_string()));
+) -> Result<Json<UploadResult>, (StatusCode, Json<UploadError>)> {
+    let Some(user) = user else {
+        return Err(upload_error(StatusCode::UNAUTHORIZED, "Please log in"));
+    };
+    let Some(field) = multipart.next_field().await.ok().flatten() else {
+        return Err(upload_error(StatusCode::UNPROCESSABLE_ENTITY, "No file provided"));
+    };
+    let content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+    let original_name = field.file_name().unwrap_or("upload").to_string();
+    
+    // Check content type
+    if !ALLOWED_TYPES.contains(&content_type.as_str()) {
+        return Err(upload_error(StatusCode::UNPROCESSABLE_ENTITY, "Invalid file type. Allowed: JPEG, PNG, GIF, WebP"));
+    }
+    
+    let data = field.bytes().await
+        .map_err(|_| upload_error(StatusCode::BAD_REQUEST, "Failed to read file"))?;
+    
+    // Check file size
+    if data.len() > config.uploads.max_file_bytes() {
+        let message = format!("File too large. Maximum {}MB.", config.uploads.max_file_mb);
+        return Err(upload_error(StatusCode::PAYLOAD_TOO_LARGE, &message));
+    }
+    
+    // Generate unique filename
+    let ext = match content_type.as_str() {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        _ => "bin",
+    };
+    let filename = format!("{}.{}", Uuid::new_v4(), ext);
+    let path = config.uploads.path_for(&filename).to_string_lossy().into_owned();
+    
+    // Write file
+    if let Err(e) = std::fs::write(&path, &data) {
+        tracing::error!(path = %path, error = %e, "failed to write upload");
+        return Err(upload_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save file"));
     }
     
-    (jar, Html(r#"{"error": "Please log in"}"#.to_string()))
+    // Save to database
+    let (user_id, size_bytes) = (user.id, data.len() as i64);
+    let (record_name, record_path) = (filename.clone(), path.clone());
+    let result = db.write(move |conn| {
+        db::create_upload(conn, user_id, &record_name, &original_name, &record_path, &content_type, size_bytes)
+    }).await;
+    match result {
+        Ok(upload_id) => Ok(Json(UploadResult {
+            success: true,
+            url: format!("{}/{}", UPLOADS_URL_PREFIX, filename),
+            id: upload_id,
+        })),
+        Err(e) => {
+            tracing::error!(path = %path, error = %e, "failed to record upload");
+            // Clean up file on db error
+            let _ = std::fs::remove_file(&path);
+            Err(upload_error(StatusCode::INTERNAL_SERVER_ERROR, "Failed to save upload record"))
+        }
+    }
 }
 
 pub async fn upload_avatar(
-    jar: CookieJar,
+    CurrentUser(user): CurrentUser,
     State((db, _)): State<(Db, Arc<Tera>)>,
+    Extension(config): Extension<Arc<Config>>,
     mut multipart: Multipart,
-) -> (CookieJar, Html<String>) {
-    if let Some((user, jar)) = ensure_session(jar.clone(), &db) {
-        while let Some(field) = multipart.next_field().await.ok().flatten() {
-            let content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
-            
-            if !ALLOWED_TYPES.contains(&content_type.as_str()) {
-                return (jar, Html("<div class=\"toast error\">Invalid file type</div>".to_string()));
-            }
-            
-            let data = match field.bytes().await {
-                Ok(d) => d,
-                Err(_) => {
-                    return (jar, Html("<div class=\"toast error\">Failed to read file</div>".to_string()));
-                }
-            };
-            
-            // Smaller limit for avatars
-            if data.len() > 2 * 1024 * 1024 {
-                return (jar, Html("<div class=\"toast error\">Avatar must be under 2MB</div>".to_string()));
-            }
-            
-            let ext = match content_type.as_str() {
-                "image/jpeg" => "jpg",
-                "image/png" => "png",
-                "image/gif" => "gif",
-                "image/webp" => "webp",
-                _ => "bin",
-            };
-            let filename = format!("avatar_{}_{}.{}", user.id, Uuid::new_v4(), ext);
-            let path = format!("static/uploads/{}", filename);
-            
-            if let Err(_) = std::fs::write(&path, &data) {
-                return (jar, Html("<div class=\"toast error\">Failed to save avatar</div>".to_string()));
-            }
-            
-            let conn = db.lock().unwrap();
-            let avatar_url = format!("/static/uploads/{}", filename);
-            
-            // Delete old avatar if exists
-            if let Ok(Some(profile)) = db::get_user_profile(&conn, user.id) {
-                if let Some(old_path) = profile.avatar_path {
-                    if old_path.starts_with("/static/uploads/") {
-                        let old_file = old_path.trim_start_matches('/');
-                        let _ = std::fs::remove_file(old_file);
-                    }
-                }
-            }
-            
-            let _ = db::update_user_avatar(&conn, user.id, &avatar_url);
-            
-            return (jar, Html(format!(
-                r#"<img src="{}" class="avatar-preview" alt="Avatar">
-                <div id="toast-container" hx-swap-oob="beforeend">
-                    <div class="toast success">Avatar updated!</div>
-                </div>"#,
-                avatar_url
-            )));
+) -> HtmlResult {
+    let field = multipart.next_field().await.ok().flatten()
+        .ok_or_else(|| AppError::validation("No file provided"))?;
+    let content_type = field.content_type().unwrap_or("application/octet-stream").to_string();
+    
+    if !ALLOWED_TYPES.contains(&content_type.as_str()) {
+        return Err(AppError::validation("Invalid file type"));
+    }
+    
+    let data = field.bytes().await.map_err(|_| AppError::validation("Failed to read file"))?;
+    
+    // Smaller limit for avatars
+    if data.len() > config.uploads.max_avatar_bytes() {
+        return Err(AppError::validation(format!("Avatar must be under {}MB", config.uploads.max_avatar_mb)));
+    }
+    
+    let ext = match content_type.as_str() {
+        "image/jpeg" => "jpg",
+        "image/png" => "png",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        _ => "bin",
+    };
+    let filename = format!("avatar_{}_{}.{}", user.id, Uuid::new_v4(), ext);
+    let path = config.uploads.path_for(&filename);
+    
+    if let Err(e) = std::fs::write(&path, &data) {
+        tracing::error!(path = %path.display(), error = %e, "failed to write avatar");
+        return Err(AppError::Internal);
+    }
+    
+    let avatar_url = format!("{}/{}", UPLOADS_URL_PREFIX, filename);
+    
+    // Delete old avatar if exists
+    let user_id = user.id;
+    if let Some(profile) = db.read(move |conn| db::get_user_profile(conn, user_id)).await? {
+        if let Some(old_file) = profile.avatar_path.as_deref().and_then(|url| config.uploads.path_for_url(url)) {
+            let _ = std::fs::remove_file(old_file);
         }
-        
-        return (jar, Html("<div class=\"toast error\">No file provided</div>".to_string()));
     }
     
-    (jar, Html("<div class=\"toast error\">Please log in</div>".to_string()))
+    let new_avatar = avatar_url.clone();
+    db.write(move |conn| db::update_user_avatar(conn, user_id, &new_avatar)).await?;
+    
+    Ok(Html(format!(
+        r#"<img src="{}" class="avatar-preview" alt="Avatar">
+        <div id="toast-container" hx-swap-oob="beforeend">
+            <div class="toast success">Avatar updated!</div>
+        </div>"#,
+        avatar_url
+    )))
 }
@@ -0,0 +1,28 @@
+use axum::{
+    http::StatusCode,
+    response::Json,
+    Form,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::vin::{self, DecodedVin};
+
+#[derive(Deserialize)]
+pub struct VinForm {
+    #[serde(default)]
+    pub vin: String,
+}
+
+#[derive(Serialize)]
+pub struct VinDecodeError {
+    pub error: String,
+}
+
+/// Decodes a VIN from the bundled offline dataset
+pub async fn decode_vin(
+    Form(form): Form<VinForm>,
+) -> Result<Json<DecodedVin>, (StatusCode, Json<VinDecodeError>)> {
+    vin::decode(&form.vin)
+        .map(Json)
+        .map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, Json(VinDecodeError { error: e.to_string() })))
+}
@@ -0,0 +1,98 @@
+//! Graceful shutdown.
+//!
+//! On SIGTERM or Ctrl-C the server stops accepting connections, lets
+//! in-flight requests finish and tells background jobs to stop at their
+//! next safe point, so a deploy never cuts off a write. Work a handler
+//! leaves running after its response, like an email, is started with
+//! [`Shutdown::spawn`] so it gets to finish too. `/readyz` starts failing
+//! as soon as shutdown begins.
+
+use std::future::Future;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+
+/// A shutdown flag shared by the server and its background jobs
+#[derive(Clone)]
+pub struct Shutdown {
+    sender: Arc<watch::Sender<bool>>,
+    tasks: Arc<Mutex<JoinSet<()>>>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        let (sender, _) = watch::channel(false);
+        Shutdown { sender: Arc::new(sender), tasks: Arc::default() }
+    }
+
+    /// Start shutting down. Calling it again does nothing.
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.sender.borrow()
+    }
+
+    /// Resolves once shutdown has started, immediately if it already has
+    pub async fn wait(&self) {
+        let mut receiver = self.sender.subscribe();
+        // Only fails if the sender is gone, and `self` holds it
+        let _ = receiver.wait_for(|triggered| *triggered).await;
+    }
+
+    /// Run `task` in the background and have shutdown wait for it
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let mut tasks = self.tasks.lock().unwrap_or_else(PoisonError::into_inner);
+        // Drop finished ones so the set doesn't grow for the life of the server
+        while tasks.try_join_next().is_some() {}
+        tasks.spawn(task);
+    }
+
+    /// Resolves once every task from [`Shutdown::spawn`] has finished. Call
+    /// it after the server has stopped, so no handler can start another.
+    pub async fn tasks_finished(&self) {
+        let mut tasks = std::mem::take(&mut *self.tasks.lock().unwrap_or_else(PoisonError::into_inner));
+        while tasks.join_next().await.is_some() {}
+    }
+}
+
+/// Resolves on Ctrl-C, or SIGTERM on Unix
+pub async fn signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %e, "failed to listen for Ctrl-C");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+}
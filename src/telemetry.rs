@@ -0,0 +1,227 @@
+//! Logging, tracing and metrics.
+//!
+//! Every request gets an ID, taken from `X-Request-Id` if a proxy set one,
+//! and a span carrying it with the method and route. Everything logged
+//! while handling the request, database calls included, happens inside
+//! that span, so it can be traced back to the handler responsible. Logs go
+//! to stdout as text, or as one JSON object per line with
+//! `logging.format = "json"`.
+//!
+//! Metrics are kept in a process-wide Prometheus registry and served as
+//! text at `/metrics`:
+//!
+//! - `wrench_http_request_duration_seconds{method, route, status}`
+//! - `wrench_http_requests_in_flight`
+//! - `wrench_db_wait_seconds{kind}`: time a `read` spends waiting for a
+//!   pooled connection, or a `write` for the write lock
+//! - `wrench_db_query_duration_seconds{kind, operation}`: time spent on the
+//!   queries once connected, by the function that ran them
+//! - `wrench_template_render_duration_seconds{template}`
+
+use std::io::IsTerminal;
+use std::sync::LazyLock;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{MatchedPath, Request},
+    middleware::Next,
+    response::Response,
+};
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntGauge, Opts, Registry, TextEncoder};
+use tera::{Context, Tera};
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::LoggingConfig;
+
+/// `Content-Type` of [`gather`]'s output
+pub const METRICS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Request header carrying the request ID, echoed on the response
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Database calls slower than this, waiting included, are logged as warnings
+const SLOW_DB_CALL: Duration = Duration::from_millis(500);
+
+/// Requests slower than this are logged as warnings
+const SLOW_REQUEST: Duration = Duration::from_secs(2);
+
+/// Routes polled by machines, logged at debug so they don't drown out the rest
+const QUIET_ROUTES: &[&str] = &["/healthz", "/readyz", "/metrics"];
+
+struct Metrics {
+    registry: Registry,
+    http_requests: HistogramVec,
+    in_flight: IntGauge,
+    db_wait: HistogramVec,
+    db_query: HistogramVec,
+    template_render: HistogramVec,
+}
+
+/// Buckets for work that usually takes well under a millisecond
+const FAST_BUCKETS: &[f64] = &[0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 5.0];
+
+impl Metrics {
+    fn new() -> Self {
+        let histogram = |name: &str, help: &str, labels: &[&str], buckets: &[f64]| {
+            HistogramVec::new(HistogramOpts::new(name, help).buckets(buckets.to_vec()), labels).expect("valid metric")
+        };
+        let metrics = Metrics {
+            registry: Registry::new(),
+            http_requests: histogram(
+                "wrench_http_request_duration_seconds",
+                "Time to handle a request, by route and response status",
+                &["method", "route", "status"],
+                prometheus::DEFAULT_BUCKETS,
+            ),
+            in_flight: IntGauge::with_opts(Opts::new("wrench_http_requests_in_flight", "Requests being handled")).expect("valid metric"),
+            db_wait: histogram(
+                "wrench_db_wait_seconds",
+                "Time waiting for a read connection or the write lock",
+                &["kind"],
+                FAST_BUCKETS,
+            ),
+            db_query: histogram(
+                "wrench_db_query_duration_seconds",
+                "Time running queries once connected, by the function that ran them",
+                &["kind", "operation"],
+                FAST_BUCKETS,
+            ),
+            template_render: histogram(
+                "wrench_template_render_duration_seconds",
+                "Time rendering a template",
+                &["template"],
+                FAST_BUCKETS,
+            ),
+        };
+        for collector in [&metrics.http_requests, &metrics.db_wait, &metrics.db_query, &metrics.template_render] {
+            metrics.registry.register(Box::new(collector.clone())).expect("metric registered once");
+        }
+        metrics.registry.register(Box::new(metrics.in_flight.clone())).expect("metric registered once");
+        metrics
+    }
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Send logs to stdout in the configured format. `RUST_LOG`, if set,
+/// replaces `logging.level`. Does nothing if logging is already set up.
+pub fn init_logging(config: &LoggingConfig) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(&config.level));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_ansi(std::io::stdout().is_terminal());
+    let _ = if config.format == "json" {
+        builder.json().with_current_span(true).with_span_list(false).try_init()
+    } else {
+        builder.try_init()
+    };
+}
+
+/// Every metric, in the Prometheus text format
+pub fn gather() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&METRICS.registry.gather(), &mut buffer)
+        .expect("metrics encode to a Vec");
+    String::from_utf8(buffer).expect("metrics are UTF-8")
+}
+
+/// The route label for a request: the pattern it matched, so `/post/1` and
+/// `/post/2` are counted together. Anything unmatched shares one label to
+/// keep random URLs from creating new series.
+fn route_label(request: &Request) -> String {
+    match request.extensions().get::<MatchedPath>() {
+        Some(path) => path.as_str().to_string(),
+        // Static files are served by a nested service, which has no route
+        None if request.uri().path().starts_with("/static/") => "/static/*".to_string(),
+        None => "unmatched".to_string(),
+    }
+}
+
+/// Counts the request as in flight for as long as it lives, including when
+/// the client goes away and the handler is dropped
+struct InFlight;
+
+impl InFlight {
+    fn start() -> Self {
+        METRICS.in_flight.inc();
+        InFlight
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        METRICS.in_flight.dec();
+    }
+}
+
+/// Middleware for the whole router: runs the request inside a span with
+/// its ID, method and route, records how long it took and logs the result
+pub async fn track_requests(request: Request, next: Next) -> Response {
+    let method = request.method().clone();
+    let route = route_label(&request);
+    let request_id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .to_string();
+    let span = tracing::info_span!("request", id = %request_id, method = %method, route = %route);
+
+    let _in_flight = InFlight::start();
+    let start = Instant::now();
+    let response = next.run(request).instrument(span.clone()).await;
+    let elapsed = start.elapsed();
+
+    let status = response.status();
+    METRICS
+        .http_requests
+        .with_label_values(&[method.as_str(), &route, status.as_str()])
+        .observe(elapsed.as_secs_f64());
+
+    let _entered = span.enter();
+    let latency_ms = elapsed.as_millis() as u64;
+    if status.is_server_error() || elapsed > SLOW_REQUEST {
+        tracing::warn!(status = status.as_u16(), latency_ms, "request finished");
+    } else if QUIET_ROUTES.contains(&route.as_str()) || route == "/static/*" {
+        tracing::debug!(status = status.as_u16(), latency_ms, "request finished");
+    } else {
+        tracing::info!(status = status.as_u16(), latency_ms, "request finished");
+    }
+    response
+}
+
+/// Render a template, timing it
+pub fn render(tera: &Tera, template: &str, ctx: &Context) -> tera::Result<String> {
+    let start = Instant::now();
+    let result = tera.render(template, ctx);
+    METRICS
+        .template_render
+        .with_label_values(&[template])
+        .observe(start.elapsed().as_secs_f64());
+    result
+}
+
+/// Name for the closure or function `F` passed to [`crate::Db::read`] or
+/// [`crate::Db::write`]: the function it was written in, like
+/// `routes::forum::view_post`, or the `db` function itself
+pub fn operation_name<F>() -> String {
+    let name = std::any::type_name::<F>();
+    let name = name.split("::{{closure}}").next().unwrap_or(name);
+    name.strip_prefix("wrench_forum::").unwrap_or(name).to_string()
+}
+
+/// Record one database call. `kind` is `read` or `write`.
+pub fn observe_db(kind: &'static str, operation: &str, wait: Duration, query: Duration) {
+    METRICS.db_wait.with_label_values(&[kind]).observe(wait.as_secs_f64());
+    METRICS.db_query.with_label_values(&[kind, operation]).observe(query.as_secs_f64());
+
+    let wait_ms = wait.as_secs_f64() * 1000.0;
+    let query_ms = query.as_secs_f64() * 1000.0;
+    if wait + query > SLOW_DB_CALL {
+        tracing::warn!(kind, operation, wait_ms, query_ms, "slow database call");
+    } else {
+        tracing::trace!(kind, operation, wait_ms, query_ms, "database call");
+    }
+}
@@ -0,0 +1,139 @@
+//! Torque units.
+//!
+//! Converts between newton-metres, foot-pounds and inch-pounds, and finds
+//! torque values written in free text so posts can show them in the other
+//! common unit. Units are stored as `Nm`, `ft-lb` or `in-lb`.
+
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::LazyLock;
+
+const NM_PER_FT_LB: f64 = 1.355_817_948;
+const IN_LB_PER_FT_LB: f64 = 12.0;
+
+/// Below this many ft-lb, values are usually quoted in in-lb instead
+const SMALL_FT_LB: f64 = 10.0;
+
+/// Canonical unit for the many ways people write one, e.g. `ft. lbs`,
+/// `lb-ft` or `N·m`
+pub fn normalize_unit(text: &str) -> Option<&'static str> {
+    let compact: String = text
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    match compact.as_str() {
+        "nm" => Some("Nm"),
+        "ftlb" | "ftlbs" | "ftlbf" | "lbft" | "lbsft" | "lbfft" => Some("ft-lb"),
+        "inlb" | "inlbs" | "inlbf" | "lbin" | "lbsin" | "lbfin" => Some("in-lb"),
+        _ => None,
+    }
+}
+
+fn to_nm(value: f64, unit: &str) -> Option<f64> {
+    match normalize_unit(unit)? {
+        "Nm" => Some(value),
+        "ft-lb" => Some(value * NM_PER_FT_LB),
+        _ => Some(value * NM_PER_FT_LB / IN_LB_PER_FT_LB),
+    }
+}
+
+/// Convert `value` between any two supported units
+pub fn convert(value: f64, from: &str, to: &str) -> Option<f64> {
+    let nm = to_nm(value, from)?;
+    match normalize_unit(to)? {
+        "Nm" => Some(nm),
+        "ft-lb" => Some(nm / NM_PER_FT_LB),
+        _ => Some(nm / NM_PER_FT_LB * IN_LB_PER_FT_LB),
+    }
+}
+
+/// Rounds to one decimal place, dropping a trailing `.0`
+pub fn format_value(value: f64) -> String {
+    let rounded = (value * 10.0).round() / 10.0;
+    if rounded.fract() == 0.0 {
+        format!("{}", rounded as i64)
+    } else {
+        format!("{:.1}", rounded)
+    }
+}
+
+/// A torque value in all three units, rounded for display
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TorqueValues {
+    pub nm: f64,
+    pub ft_lb: f64,
+    pub in_lb: f64,
+}
+
+impl TorqueValues {
+    pub fn new(value: f64, unit: &str) -> Option<Self> {
+        let round = |v: f64| (v * 10.0).round() / 10.0;
+        Some(TorqueValues {
+            nm: round(convert(value, unit, "Nm")?),
+            ft_lb: round(convert(value, unit, "ft-lb")?),
+            in_lb: round(convert(value, unit, "in-lb")?),
+        })
+    }
+}
+
+/// The value in the unit a reader is most likely to want alongside the
+/// original: Nm for imperial values, ft-lb (or in-lb for small fasteners)
+/// for metric ones. E.g. `80 ft-lb` gives `108.5 Nm`.
+pub fn alternate(value: f64, unit: &str) -> Option<String> {
+    let target = match normalize_unit(unit)? {
+        "Nm" if convert(value, "Nm", "ft-lb")? < SMALL_FT_LB => "in-lb",
+        "Nm" => "ft-lb",
+        _ => "Nm",
+    };
+    Some(format!("{} {}", format_value(convert(value, unit, target)?), target))
+}
+
+// `lb in` needs the hyphen, otherwise "5 lbs in the bed" would match
+static TORQUE_IN_TEXT: LazyLock<regex::Regex> = LazyLock::new(|| {
+    regex::Regex::new(
+        r"(?i)\b(\d{1,4}(?:\.\d{1,2})?)\s?(n[·.\-]?m|ft\.?[\s\-·]?lbf?s?|lbf?s?\.?[\s\-·]?ft|in\.?[\s\-·]?lbf?s?|lbf?s?-in)\b",
+    )
+    .unwrap()
+});
+
+/// Torque values in `text`: the byte range of each match and the value in
+/// its alternate unit. Values the author already gave in both units, like
+/// `80 ft-lb (108 Nm)` or `25 Nm / 18 ft-lb`, are skipped.
+pub fn find_in_text(text: &str) -> Vec<(Range<usize>, String)> {
+    let matches: Vec<_> = TORQUE_IN_TEXT
+        .captures_iter(text)
+        .filter_map(|caps| {
+            let value: f64 = caps[1].parse().ok()?;
+            let unit = normalize_unit(&caps[2])?;
+            Some((caps.get(0)?.range(), unit, alternate(value, unit)?))
+        })
+        .collect();
+
+    let mut found = Vec::new();
+    let mut i = 0;
+    while i < matches.len() {
+        let (range, unit, converted) = &matches[i];
+        if let Some((next, next_unit, _)) = matches.get(i + 1) {
+            let between = text[range.end..next.start].trim();
+            if unit != next_unit && matches!(between, "(" | "/" | "=" | "or") {
+                i += 2;
+                continue;
+            }
+        }
+        found.push((range.clone(), converted.clone()));
+        i += 1;
+    }
+    found
+}
+
+/// Tera filter: `{{ spec.value | torque_alternate(unit=spec.unit) }}`
+pub fn tera_filter(value: &tera::Value, args: &HashMap<String, tera::Value>) -> tera::Result<tera::Value> {
+    let number = value.as_f64().ok_or_else(|| tera::Error::msg("torque_alternate expects a number"))?;
+    let unit = args
+        .get("unit")
+        .and_then(|u| u.as_str())
+        .ok_or_else(|| tera::Error::msg("torque_alternate needs a `unit` argument"))?;
+    Ok(tera::Value::String(alternate(number, unit).unwrap_or_default()))
+}
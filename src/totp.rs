@@ -0,0 +1,167 @@
+//! Time-based one-time passwords (RFC 6238).
+//!
+//! Codes are HOTP (RFC 4226) over HMAC-SHA1 with a 30 second step and six
+//! digits, which is what every authenticator app assumes when a provisioning
+//! URI leaves the parameters out. Secrets are exchanged as unpadded RFC 4648
+//! base32.
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha1::Sha1;
+
+/// Seconds each code is valid for
+pub const STEP_SECONDS: u64 = 30;
+
+/// Digits in a code
+pub const DIGITS: u32 = 6;
+
+/// Bytes of secret, the 160 bits RFC 4226 recommends
+pub const SECRET_LEN: usize = 20;
+
+/// Steps either side of the current one that are still accepted, to allow
+/// for clock drift between the server and the phone
+pub const ALLOWED_DRIFT: u64 = 1;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A new random secret
+pub fn generate_secret() -> Vec<u8> {
+    let mut secret = vec![0u8; SECRET_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut secret);
+    secret
+}
+
+/// Base32 without padding, as authenticator apps expect
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for &byte in data {
+        buffer = (buffer << 8) | byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    out
+}
+
+/// Decodes base32, ignoring case, spaces, hyphens and padding. Returns None
+/// for any other character.
+pub fn base32_decode(text: &str) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(text.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u32, 0u32);
+    for c in text.chars().filter(|c| !matches!(c, ' ' | '-' | '=')) {
+        let value = BASE32_ALPHABET.iter().position(|&a| a as char == c.to_ascii_uppercase())? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    Some(out)
+}
+
+/// RFC 4226 HOTP value for `counter`, truncated to `digits` digits
+pub fn hotp(secret: &[u8], counter: u64, digits: u32) -> u32 {
+    let mut mac = Hmac::<Sha1>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset], hash[offset + 1], hash[offset + 2], hash[offset + 3]]) & 0x7fff_ffff;
+    binary % 10u32.pow(digits)
+}
+
+/// The time step a Unix timestamp falls in
+pub fn step_at(unix_time: u64) -> u64 {
+    unix_time / STEP_SECONDS
+}
+
+/// The code for a time step, zero-padded
+pub fn code_for_step(secret: &[u8], step: u64) -> String {
+    format!("{:0width$}", hotp(secret, step, DIGITS), width = DIGITS as usize)
+}
+
+/// The code an authenticator app shows at `unix_time`
+pub fn code_at(secret: &[u8], unix_time: u64) -> String {
+    code_for_step(secret, step_at(unix_time))
+}
+
+/// Checks `code` against the steps around `unix_time` and returns the step
+/// it matched. Steps at or before `last_used_step` are rejected so a code
+/// can't be replayed.
+pub fn verify(secret: &[u8], code: &str, unix_time: u64, last_used_step: Option<u64>) -> Option<u64> {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let current = step_at(unix_time);
+    (current.saturating_sub(ALLOWED_DRIFT)..=current + ALLOWED_DRIFT)
+        .filter(|&step| last_used_step.is_none_or(|last| step > last))
+        .find(|&step| constant_time_eq(code_for_step(secret, step).as_bytes(), code.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// `otpauth://` URI for enrolling an authenticator app, usually shown as a
+/// QR code
+pub fn provisioning_uri(issuer: &str, account: &str, secret: &[u8]) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(issuer),
+        percent_encode(account),
+        base32_encode(secret),
+        percent_encode(issuer),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// The provisioning URI as an inline SVG QR code
+pub fn qr_svg(uri: &str) -> Option<String> {
+    let code = qrcode::QrCode::new(uri.as_bytes()).ok()?;
+    Some(
+        code.render::<qrcode::render::svg::Color>()
+            .min_dimensions(200, 200)
+            .quiet_zone(true)
+            .build(),
+    )
+}
+
+/// Recovery codes: `count` codes like `k3f9x-2mqpd`, each 50 random bits
+pub fn generate_recovery_codes(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|_| {
+            let mut bytes = [0u8; 7];
+            rand::rngs::OsRng.fill_bytes(&mut bytes);
+            let text = base32_encode(&bytes).to_lowercase();
+            format!("{}-{}", &text[..5], &text[5..10])
+        })
+        .collect()
+}
+
+/// Recovery codes as typed, in the form they were hashed in: lowercase,
+/// without spaces or hyphens
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
@@ -0,0 +1,273 @@
+//! Offline VIN decoding.
+//!
+//! Validates the ISO 3779 check digit and decodes the manufacturer (WMI),
+//! model line (VDS) and model year from the dataset bundled in `data/vin.json`.
+//! No network lookups are made; unknown VINs still decode as far as the
+//! dataset allows.
+
+use chrono::Datelike;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::models::VehicleDetails;
+
+const VIN_DATA: &str = include_str!("../data/vin.json");
+
+/// Position weights used by the check digit (position 9 itself weighs 0)
+const WEIGHTS: [u32; 17] = [8, 7, 6, 5, 4, 3, 2, 10, 0, 9, 8, 7, 6, 5, 4, 3, 2];
+
+/// Model year codes in position 10, starting at 1980. The cycle repeats
+/// every 30 years.
+const YEAR_CODES: &str = "ABCDEFGHJKLMNPRSTVWXY123456789";
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VinError {
+    /// VINs are exactly 17 characters
+    Length(usize),
+    /// A character outside the VIN alphabet (I, O and Q are never used)
+    InvalidChar(char),
+    CheckDigit { expected: char, found: char },
+}
+
+impl std::fmt::Display for VinError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VinError::Length(len) => write!(f, "VIN must be 17 characters, got {}", len),
+            VinError::InvalidChar(c) => write!(f, "'{}' is not a valid VIN character", c),
+            VinError::CheckDigit { expected, found } => {
+                write!(f, "Check digit mismatch: expected '{}', found '{}'. Double-check the VIN for typos", expected, found)
+            }
+        }
+    }
+}
+
+impl std::error::Error for VinError {}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodedVin {
+    pub vin: String,
+    pub wmi: String,
+    pub vds: String,
+    pub vis: String,
+    pub check_digit_valid: bool,
+    pub region: &'static str,
+    pub country: Option<String>,
+    pub manufacturer: Option<String>,
+    pub make: Option<String>,
+    pub model_year: Option<i64>,
+    pub model: Option<String>,
+    pub trim: Option<String>,
+    pub body: Option<String>,
+    pub engine: Option<String>,
+    pub engine_code: Option<String>,
+    pub transmission: Option<String>,
+    pub serial: String,
+}
+
+impl DecodedVin {
+    /// Vehicle fields for a garage entry or post, once year, make and model
+    /// are all known
+    pub fn vehicle_details(&self) -> Option<VehicleDetails> {
+        Some(VehicleDetails {
+            year: self.model_year?,
+            make: self.make.clone()?,
+            model: self.model.clone()?,
+            trim: self.trim.clone(),
+            engine_code: self.engine_code.clone(),
+            transmission: self.transmission.clone(),
+            mileage: None,
+        })
+    }
+}
+
+// ============ Bundled Dataset ============
+
+#[derive(Deserialize)]
+struct VinData {
+    countries: Vec<CountryEntry>,
+    manufacturers: Vec<ManufacturerEntry>,
+    models: Vec<ModelEntry>,
+}
+
+#[derive(Deserialize)]
+struct CountryEntry {
+    prefix: String,
+    country: String,
+}
+
+#[derive(Deserialize)]
+struct ManufacturerEntry {
+    wmi: String,
+    manufacturer: String,
+    make: String,
+}
+
+#[derive(Deserialize)]
+struct ModelEntry {
+    wmi: Vec<String>,
+    /// Positions 4-8, with `*` matching any character
+    vds: String,
+    years: [i64; 2],
+    model: String,
+    trim: Option<String>,
+    body: Option<String>,
+    engine: Option<String>,
+    engine_code: Option<String>,
+    transmission: Option<String>,
+}
+
+impl ModelEntry {
+    fn matches(&self, wmi: &str, vds: &str, year: Option<i64>) -> bool {
+        self.wmi.iter().any(|w| w == wmi)
+            && self.vds.len() == vds.len()
+            && self.vds.chars().zip(vds.chars()).all(|(p, c)| p == '*' || p == c)
+            && year.is_none_or(|y| (self.years[0]..=self.years[1]).contains(&y))
+    }
+
+    /// Entries with fewer wildcards win when several match
+    fn specificity(&self) -> usize {
+        self.vds.chars().filter(|&c| c != '*').count()
+    }
+}
+
+fn data() -> &'static VinData {
+    static DATA: OnceLock<VinData> = OnceLock::new();
+    DATA.get_or_init(|| serde_json::from_str(VIN_DATA).expect("data/vin.json is malformed"))
+}
+
+// ============ Decoding ============
+
+/// Numeric value of a VIN character for the check digit, or None if the
+/// character can't appear in a VIN
+fn transliterate(c: char) -> Option<u32> {
+    match c {
+        '0'..='9' => c.to_digit(10),
+        'A' | 'J' => Some(1),
+        'B' | 'K' | 'S' => Some(2),
+        'C' | 'L' | 'T' => Some(3),
+        'D' | 'M' | 'U' => Some(4),
+        'E' | 'N' | 'V' => Some(5),
+        'F' | 'W' => Some(6),
+        'G' | 'P' | 'X' => Some(7),
+        'H' | 'Y' => Some(8),
+        'R' | 'Z' => Some(9),
+        _ => None,
+    }
+}
+
+/// Uppercases the VIN and drops the spaces and dashes people paste in,
+/// then checks the length and alphabet
+pub fn normalize(input: &str) -> Result<String, VinError> {
+    let vin: String = input
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+
+    if let Some(c) = vin.chars().find(|&c| transliterate(c).is_none()) {
+        return Err(VinError::InvalidChar(c));
+    }
+    if vin.len() != 17 {
+        return Err(VinError::Length(vin.len()));
+    }
+    Ok(vin)
+}
+
+/// The check digit (position 9) a normalized VIN should carry
+pub fn check_digit(vin: &str) -> Option<char> {
+    if vin.len() != 17 {
+        return None;
+    }
+    let mut sum = 0;
+    for (c, weight) in vin.chars().zip(WEIGHTS) {
+        sum += transliterate(c)? * weight;
+    }
+    Some(match sum % 11 {
+        10 => 'X',
+        n => char::from_digit(n, 10)?,
+    })
+}
+
+fn region(first: char) -> &'static str {
+    match first {
+        'A'..='H' => "Africa",
+        'J'..='R' => "Asia",
+        'S'..='Z' => "Europe",
+        '1'..='5' => "North America",
+        '6' | '7' => "Oceania",
+        '8' | '9' => "South America",
+        _ => "Unknown",
+    }
+}
+
+/// North American VINs are required to carry a valid check digit; elsewhere
+/// many manufacturers leave position 9 unused
+fn check_digit_required(vin: &str) -> bool {
+    matches!(vin.as_bytes()[0], b'1'..=b'5')
+}
+
+fn model_year(vin: &str) -> Option<i64> {
+    let code = vin.chars().nth(9)?;
+    let base = 1980 + YEAR_CODES.find(code)? as i64;
+
+    // North American passenger vehicles use a letter in position 7 from
+    // 2010 onward, which tells the two 30-year cycles apart. Older VINs
+    // sometimes had a letter there too, so never pick a year that hasn't
+    // happened yet.
+    let latest = chrono::Utc::now().year() as i64 + 1;
+    let newer = base + 30 <= latest;
+    if check_digit_required(vin) {
+        return Some(if newer && vin.as_bytes()[6].is_ascii_alphabetic() { base + 30 } else { base });
+    }
+    Some(if newer { base + 30 } else { base })
+}
+
+/// Decodes a VIN against the bundled dataset. Fails on malformed input or,
+/// for North American VINs, a check digit that doesn't match.
+pub fn decode(input: &str) -> Result<DecodedVin, VinError> {
+    let vin = normalize(input)?;
+    let data = data();
+
+    let expected = check_digit(&vin).ok_or(VinError::Length(vin.len()))?;
+    let found = vin.as_bytes()[8] as char;
+    let check_digit_valid = expected == found;
+    if !check_digit_valid && check_digit_required(&vin) {
+        return Err(VinError::CheckDigit { expected, found });
+    }
+
+    let (wmi, vds, vis) = (&vin[0..3], &vin[3..8], &vin[9..17]);
+    let model_year = model_year(&vin);
+
+    let country = data
+        .countries
+        .iter()
+        .filter(|c| vin.starts_with(&c.prefix))
+        .max_by_key(|c| c.prefix.len())
+        .map(|c| c.country.clone());
+    let manufacturer = data.manufacturers.iter().find(|m| m.wmi == wmi);
+    let model = data
+        .models
+        .iter()
+        .filter(|m| m.matches(wmi, vds, model_year))
+        .max_by_key(|m| m.specificity());
+
+    Ok(DecodedVin {
+        vin: vin.clone(),
+        wmi: wmi.to_string(),
+        vds: vds.to_string(),
+        vis: vis.to_string(),
+        check_digit_valid,
+        region: region(vin.chars().next().unwrap_or('0')),
+        country,
+        manufacturer: manufacturer.map(|m| m.manufacturer.clone()),
+        make: manufacturer.map(|m| m.make.clone()),
+        model_year,
+        model: model.map(|m| m.model.clone()),
+        trim: model.and_then(|m| m.trim.clone()),
+        body: model.and_then(|m| m.body.clone()),
+        engine: model.and_then(|m| m.engine.clone()),
+        engine_code: model.and_then(|m| m.engine_code.clone()),
+        transmission: model.and_then(|m| m.transmission.clone()),
+        serial: vin[11..17].to_string(),
+    })
+}
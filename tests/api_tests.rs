@@ -0,0 +1,374 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request, StatusCode},
+    middleware,
+    routing::get,
+    Extension, Router,
+};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tera::Tera;
+use tower::ServiceExt;
+use wrench_forum::config::Config;
+use wrench_forum::{api, auth, csrf, db, routes};
+use wrench_forum::models::ApiScope;
+
+mod common;
+use common::setup_test_db;
+
+fn app(db: db::Db) -> Router {
+    Router::new()
+        .nest("/api/v1", routes::api::router())
+        .route("/api/openapi.json", get(routes::api::openapi_json))
+        // The API is exempt, so none of the requests below send a CSRF token
+        .layer(middleware::from_fn(csrf::protect))
+        .layer(Extension(Arc::new(Config::default())))
+        .with_state((db, Arc::new(Tera::default())))
+}
+
+/// A confirmed user with the given role and a token with `scopes`
+fn user_with_token(db: &db::Db, username: &str, role: &str, scopes: &[ApiScope]) -> (i64, String) {
+    let conn = db.write_conn();
+    let user_id = db::create_user(&conn, &format!("{}@example.com", username), "hash", username).unwrap();
+    db::update_user_role(&conn, user_id, role).unwrap();
+    conn.execute("UPDATE users SET email_confirmed_at = datetime('now') WHERE id = ?1", [user_id]).unwrap();
+    let token = api::create_token();
+    db::create_api_token(&conn, user_id, "test", &auth::hash_token(&token), &api::display_prefix(&token), scopes).unwrap();
+    (user_id, token)
+}
+
+async fn send(app: &Router, method: &str, uri: &str, token: Option<&str>, body: Option<Value>) -> (StatusCode, Value) {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(token) = token {
+        request = request.header(header::AUTHORIZATION, format!("Bearer {}", token));
+    }
+    let request = match body {
+        Some(body) => request.header(header::CONTENT_TYPE, "application/json").body(Body::from(body.to_string())),
+        None => request.body(Body::empty()),
+    };
+    let response = app.clone().oneshot(request.unwrap()).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+}
+
+#[tokio::test]
+async fn test_requires_valid_token() {
+    let db = setup_test_db();
+    let (user_id, token) = user_with_token(&db, "mech", "verified_mechanic", &[ApiScope::Read]);
+    let app = app(db.clone());
+
+    let response = app.clone().oneshot(Request::get("/api/v1/me").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers().get(header::WWW_AUTHENTICATE).unwrap(), "Bearer");
+
+    let (status, body) = send(&app, "GET", "/api/v1/me", Some("wf_not-a-real-token"), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body["error"]["code"], "unauthorized");
+
+    let (status, body) = send(&app, "GET", "/api/v1/me", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["id"], user_id);
+    assert_eq!(body["username"], "mech");
+    assert_eq!(body["scopes"], json!(["read"]));
+    let tokens = db::get_user_api_tokens(&db.write_conn(), user_id).unwrap();
+    assert!(tokens[0].last_used_at.is_some());
+
+    // Banned users' tokens stop working
+    db::set_user_banned(&db.write_conn(), user_id, true).unwrap();
+    let (status, _) = send(&app, "GET", "/api/v1/me", Some(&token), None).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn test_scopes_and_roles() {
+    let db = setup_test_db();
+    let (_, read_only) = user_with_token(&db, "reader", "verified_mechanic", &[ApiScope::Read]);
+    let (_, unverified) = user_with_token(&db, "newbie", "unverified", &[ApiScope::Read, ApiScope::Write]);
+    let app = app(db.clone());
+    let post = json!({ "category_id": 1, "title": "Misfire on cold start", "body": "P0301 below 10C" });
+
+    let (status, body) = send(&app, "POST", "/api/v1/posts", Some(&read_only), Some(post.clone())).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"]["message"], "This token doesn't have the write scope");
+
+    let (status, body) = send(&app, "POST", "/api/v1/posts", Some(&unverified), Some(post)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"]["code"], "forbidden");
+
+    let (status, _) = send(&app, "GET", "/api/v1/notifications", Some(&read_only), None).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_posts_and_cursor_pagination() {
+    let db = setup_test_db();
+    let (user_id, token) = user_with_token(&db, "mech", "verified_mechanic", &[ApiScope::Read, ApiScope::Write]);
+    let app = app(db.clone());
+
+    let mut ids = Vec::new();
+    for n in 0..3 {
+        let post = json!({ "category_id": 1, "title": format!("Post {}", n), "body": "Body", "tags": [1, 999] });
+        let (status, body) = send(&app, "POST", "/api/v1/posts", Some(&token), Some(post)).await;
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(body["author"]["id"], user_id);
+        assert_eq!(body["tags"].as_array().unwrap().len(), 1);
+        ids.push(body["id"].as_i64().unwrap());
+    }
+
+    let (status, page) = send(&app, "GET", "/api/v1/posts?limit=2", Some(&token), None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(page["data"].as_array().unwrap().iter().map(|p| p["id"].as_i64().unwrap()).collect::<Vec<_>>(), vec![ids[2], ids[1]]);
+    let cursor = page["next_cursor"].as_str().unwrap();
+
+    let (_, page) = send(&app, "GET", &format!("/api/v1/posts?limit=2&cursor={}", cursor), Some(&token), None).await;
+    assert_eq!(page["data"].as_array().unwrap().len(), 1);
+    assert_eq!(page["data"][0]["id"], ids[0]);
+    assert_eq!(page["next_cursor"], Value::Null);
+
+    let (status, body) = send(&app, "GET", "/api/v1/posts?cursor=abc", Some(&token), None).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"]["code"], "invalid_request");
+
+    let (status, body) = send(&app, "POST", "/api/v1/posts", Some(&token), Some(json!({ "category_id": 999, "title": "T", "body": "B" }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["code"], "validation_failed");
+
+    let (status, body) = send(&app, "GET", "/api/v1/posts/99999", Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "not_found");
+
+    let (status, body) = send(&app, "GET", "/api/v1/no-such-thing", Some(&token), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"]["code"], "not_found");
+}
+
+#[tokio::test]
+async fn test_comments_votes_and_notifications() {
+    let db = setup_test_db();
+    let all = [ApiScope::Read, ApiScope::Write, ApiScope::Notifications];
+    let (_, author) = user_with_token(&db, "author", "verified_mechanic", &all);
+    let (_, replier) = user_with_token(&db, "replier", "verified_mechanic", &all);
+    let app = app(db.clone());
+
+    let (_, post) = send(&app, "POST", "/api/v1/posts", Some(&author), Some(json!({ "category_id": 1, "title": "Brake fade", "body": "After towing" }))).await;
+    let post_id = post["id"].as_i64().unwrap();
+
+    let (status, comment) = send(&app, "POST", &format!("/api/v1/posts/{}/comments", post_id), Some(&replier), Some(json!({ "body": "Check the fluid" }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, reply) = send(&app, "POST", &format!("/api/v1/posts/{}/comments", post_id), Some(&author), Some(json!({ "body": "It's new", "parent_id": comment["id"] }))).await;
+    assert_eq!(status, StatusCode::CREATED);
+    assert_eq!(reply["parent_id"], comment["id"]);
+    let (status, _) = send(&app, "POST", &format!("/api/v1/posts/{}/comments", post_id), Some(&author), Some(json!({ "body": "Hm", "parent_id": 99999 }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (_, comments) = send(&app, "GET", &format!("/api/v1/posts/{}/comments", post_id), Some(&author), None).await;
+    assert_eq!(comments["data"].as_array().unwrap().len(), 2);
+    assert_eq!(comments["data"][0]["id"], comment["id"]);
+
+    let (status, _) = send(&app, "POST", &format!("/api/v1/posts/{}/vote", post_id), Some(&replier), Some(json!({ "value": 2 }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let (status, score) = send(&app, "POST", &format!("/api/v1/posts/{}/vote", post_id), Some(&replier), Some(json!({ "value": 1 }))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(score["score"], post["score"].as_i64().unwrap() + 1);
+
+    let (status, _) = send(&app, "POST", &format!("/api/v1/posts/{}/vote", post_id), Some(&replier), Some(json!({ "valu": 1 }))).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let (_, notifications) = send(&app, "GET", "/api/v1/notifications?unread=true", Some(&author), None).await;
+    let notification = &notifications["data"][0];
+    assert_eq!(notification["kind"], "post_reply");
+    assert_eq!(notification["from_username"], "replier");
+
+    let uri = format!("/api/v1/notifications/{}/read", notification["id"]);
+    let (status, _) = send(&app, "POST", &uri, Some(&replier), None).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, "POST", &uri, Some(&author), None).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    let (_, notifications) = send(&app, "GET", "/api/v1/notifications?unread=true", Some(&author), None).await;
+    assert!(notifications["data"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_stores_and_search() {
+    let db = setup_test_db();
+    let (_, token) = user_with_token(&db, "mech", "verified_mechanic", &[ApiScope::Read, ApiScope::Write]);
+    let app = app(db.clone());
+
+    let store = json!({ "name": "Rock Auto", "url": "ftp://example.com", "category": "parts" });
+    let (status, _) = send(&app, "POST", "/api/v1/stores", Some(&token), Some(store)).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    let store = json!({ "name": "Rock Auto", "url": "https://example.com", "category": "parts" });
+    let (status, store) = send(&app, "POST", "/api/v1/stores", Some(&token), Some(store)).await;
+    assert_eq!(status, StatusCode::CREATED);
+
+    let (_, voted) = send(&app, "POST", &format!("/api/v1/stores/{}/vote", store["id"]), Some(&token), Some(json!({ "positive": true }))).await;
+    assert_eq!(voted["total_votes"], 1);
+    assert_eq!(voted["reliability_score"], 100.0);
+    let (status, _) = send(&app, "POST", "/api/v1/stores/99999/vote", Some(&token), Some(json!({ "positive": true }))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    for title in ["Alternator whine", "Alternator belt squeal", "Alternator test"] {
+        send(&app, "POST", "/api/v1/posts", Some(&token), Some(json!({ "category_id": 1, "title": title, "body": "Body" }))).await;
+    }
+    let (status, body) = send(&app, "GET", "/api/v1/search", Some(&token), None).await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(body["error"]["message"], "q is required");
+
+    let (_, first) = send(&app, "GET", "/api/v1/search?q=alternator&limit=2", Some(&token), None).await;
+    assert_eq!(first["data"].as_array().unwrap().len(), 2);
+    let cursor = first["next_cursor"].as_str().unwrap();
+    let (_, second) = send(&app, "GET", &format!("/api/v1/search?q=alternator&limit=2&cursor={}", cursor), Some(&token), None).await;
+    assert_eq!(second["data"].as_array().unwrap().len(), 1);
+    assert_eq!(second["next_cursor"], Value::Null);
+}
+
+/// Checks `value` against `schema`, following `$ref`s into the document's
+/// components. Objects may not have properties the schema doesn't list.
+fn check_schema(spec: &Value, schema: &Value, value: &Value, at: &str) -> Result<(), String> {
+    if let Some(reference) = schema["$ref"].as_str() {
+        let name = reference.trim_start_matches("#/components/schemas/");
+        return check_schema(spec, &spec["components"]["schemas"][name], value, at);
+    }
+    if let Some(options) = schema["oneOf"].as_array() {
+        return match options.iter().any(|option| check_schema(spec, option, value, at).is_ok()) {
+            true => Ok(()),
+            false => Err(format!("{}: {} matches none of {}", at, value, schema)),
+        };
+    }
+
+    let types: Vec<&str> = match &schema["type"] {
+        Value::String(t) => vec![t.as_str()],
+        Value::Array(ts) => ts.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    let type_matches = |t: &str| match t {
+        "null" => value.is_null(),
+        "boolean" => value.is_boolean(),
+        "integer" => value.is_i64() || value.is_u64(),
+        "number" => value.is_number(),
+        "string" => value.is_string(),
+        "array" => value.is_array(),
+        "object" => value.is_object(),
+        _ => false,
+    };
+    if !types.is_empty() && !types.iter().any(|t| type_matches(t)) {
+        return Err(format!("{}: expected {:?}, got {}", at, types, value));
+    }
+    if let Some(allowed) = schema["enum"].as_array() {
+        if !allowed.contains(value) {
+            return Err(format!("{}: {} isn't one of {:?}", at, value, allowed));
+        }
+    }
+
+    match value {
+        Value::Object(fields) => {
+            for required in schema["required"].as_array().into_iter().flatten() {
+                if !fields.contains_key(required.as_str().unwrap()) {
+                    return Err(format!("{}: missing required {}", at, required));
+                }
+            }
+            for (key, field) in fields {
+                let Some(field_schema) = schema["properties"].get(key) else {
+                    return Err(format!("{}: {} isn't in the schema", at, key));
+                };
+                check_schema(spec, field_schema, field, &format!("{}.{}", at, key))?;
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter().enumerate() {
+                check_schema(spec, &schema["items"], item, &format!("{}[{}]", at, i))?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Sends an API request and checks the response against the OpenAPI
+/// document. Returns the documented path `uri` matched and the body.
+async fn send_checked(app: &Router, spec: &Value, method: &str, uri: &str, token: &str, body: Option<Value>) -> (String, Value) {
+    let (status, response) = send(app, method, &format!("/api/v1{}", uri), Some(token), body).await;
+    let segments: Vec<&str> = uri.split('?').next().unwrap().split('/').collect();
+    let path = spec["paths"]
+        .as_object()
+        .unwrap()
+        .keys()
+        .find(|path| {
+            let template: Vec<&str> = path.split('/').collect();
+            template.len() == segments.len() && template.iter().zip(&segments).all(|(t, s)| t == s || t.starts_with('{'))
+        })
+        .unwrap_or_else(|| panic!("{} isn't documented", uri))
+        .clone();
+
+    let documented = &spec["paths"][&path][method.to_lowercase()]["responses"][status.as_str()];
+    assert!(documented.is_object(), "{} {} returned undocumented {}", method, uri, status);
+    if let Some(schema) = documented["content"]["application/json"].get("schema") {
+        if let Err(e) = check_schema(spec, schema, &response, "body") {
+            panic!("{} {} ({}): {}", method, uri, status, e);
+        }
+    }
+    (path, response)
+}
+
+#[tokio::test]
+async fn test_openapi_document_matches_handlers() {
+    let db = setup_test_db();
+    let all = [ApiScope::Read, ApiScope::Write, ApiScope::Notifications];
+    let (_, token) = user_with_token(&db, "author", "verified_mechanic", &all);
+    let (_, other) = user_with_token(&db, "replier", "verified_mechanic", &all);
+    let app = app(db.clone());
+
+    let (status, spec) = send(&app, "GET", "/api/openapi.json", None, None).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(spec["openapi"].as_str().unwrap().starts_with("3."));
+    assert_eq!(spec["servers"][0]["url"], "/api/v1");
+
+    // Every documented operation is routed: without a token it gets 401
+    // rather than the fallback's 404 or a 405
+    let mut operations = Vec::new();
+    for (path, item) in spec["paths"].as_object().unwrap() {
+        for method in item.as_object().unwrap().keys() {
+            let uri = format!("/api/v1{}", path.replace("{id}", "1"));
+            let (status, _) = send(&app, &method.to_uppercase(), &uri, None, None).await;
+            assert_eq!(status, StatusCode::UNAUTHORIZED, "{} {} isn't routed", method, path);
+            operations.push((method.to_uppercase(), path.clone()));
+        }
+    }
+
+    // Real responses, errors included, have a documented status and match
+    // its schema
+    let mut exercised = Vec::new();
+    let mut call = async |method: &str, uri: String, token: &str, body: Option<Value>| -> Value {
+        let (path, response) = send_checked(&app, &spec, method, &uri, token, body).await;
+        exercised.push((method.to_string(), path));
+        response
+    };
+
+    call("GET", "/me".into(), &token, None).await;
+    call("GET", "/categories".into(), &token, None).await;
+    let post = call("POST", "/posts".into(), &token, Some(json!({ "category_id": 1, "title": "Idle hunt", "body": "Only when warm", "tags": [1] }))).await;
+    call("POST", "/posts".into(), &token, Some(json!({ "category_id": 1, "title": "", "body": "B" }))).await;
+    call("GET", "/posts?limit=1".into(), &token, None).await;
+    call("GET", "/posts?cursor=abc".into(), &token, None).await;
+    call("GET", format!("/posts/{}", post["id"]), &token, None).await;
+    call("GET", "/posts/99999".into(), &token, None).await;
+    let comment = call("POST", format!("/posts/{}/comments", post["id"]), &other, Some(json!({ "body": "Vacuum leak?" }))).await;
+    call("GET", format!("/posts/{}/comments", post["id"]), &token, None).await;
+    call("POST", format!("/posts/{}/vote", post["id"]), &other, Some(json!({ "value": 1 }))).await;
+    call("POST", format!("/posts/{}/vote", post["id"]), &other, Some(json!({ "value": 5 }))).await;
+    call("POST", format!("/comments/{}/vote", comment["id"]), &token, Some(json!({ "value": -1 }))).await;
+    let store = call("POST", "/stores".into(), &token, Some(json!({ "name": "Summit", "url": "https://example.com", "category": "performance" }))).await;
+    call("GET", "/stores".into(), &token, None).await;
+    call("POST", format!("/stores/{}/vote", store["id"]), &other, Some(json!({ "positive": false }))).await;
+    let notifications = call("GET", "/notifications".into(), &token, None).await;
+    call("POST", format!("/notifications/{}/read", notifications["data"][0]["id"]), &token, None).await;
+    call("POST", "/notifications/read-all".into(), &token, None).await;
+    call("GET", "/search?q=idle".into(), &token, None).await;
+    call("GET", "/search".into(), &token, None).await;
+
+    // A handler added to the document needs a call here too
+    for operation in &operations {
+        assert!(exercised.contains(operation), "{} {} isn't exercised", operation.0, operation.1);
+    }
+}
@@ -45,12 +45,15 @@ fn test_session_token_generation() {
 
 #[test]
 fn test_session_expiry() {
-    let expiry = auth::session_expiry();
+    let expiry = auth::session_expiry(30);
     
     // Should be a valid datetime string
-    assert!(expiry.len() > 0);
+    assert!(!expiry.is_empty());
     assert!(expiry.contains("-")); // Date format
     assert!(expiry.contains(":")); // Time format
+    
+    // Same format, so shorter lifetimes sort earlier
+    assert!(auth::session_expiry(7) < expiry);
 }
 
 #[test]
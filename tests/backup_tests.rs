@@ -0,0 +1,255 @@
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request, StatusCode},
+    middleware,
+    routing::{get, post},
+    Extension, Router,
+};
+use chrono::{TimeZone, Utc};
+use tempfile::TempDir;
+use tera::Tera;
+use tower::ServiceExt;
+use wrench_forum::backup::{self, BackupError, SNAPSHOT_DB_FILE, SNAPSHOT_UPLOADS_DIR};
+use wrench_forum::config::Config;
+use wrench_forum::{db, error, routes, torque};
+
+/// A config with the database, uploads and backups all inside `dir`
+fn test_config(dir: &Path) -> Config {
+    let mut config = Config::default();
+    config.database.path = dir.join("forum.db").display().to_string();
+    config.uploads.dir = dir.join("uploads").display().to_string();
+    config.backup.dir = dir.join("backups").display().to_string();
+    config.backup.keep = 2;
+    fs::create_dir_all(&config.uploads.dir).unwrap();
+    config
+}
+
+fn open_db(config: &Config) -> db::Db {
+    db::Db::open(&config.database.path, &config.database.options()).unwrap()
+}
+
+#[test]
+fn test_snapshot_while_writing() {
+    let dir = TempDir::new().unwrap();
+    let config = test_config(dir.path());
+    let db = open_db(&config);
+    fs::write(Path::new(&config.uploads.dir).join("avatar_1.png"), b"png").unwrap();
+
+    // Hold a write transaction open: the snapshot mustn't wait for it or see it
+    let conn = db.write_conn();
+    db::create_user(&conn, "before@example.com", "hash", "before").unwrap();
+    conn.execute_batch("BEGIN IMMEDIATE").unwrap();
+    db::create_user(&conn, "during@example.com", "hash", "during").unwrap();
+
+    let dest = dir.path().join("export");
+    let snapshot = backup::create_snapshot(&config.database.path, &config.uploads.dir, &dest).unwrap();
+    conn.execute_batch("COMMIT").unwrap();
+
+    assert_eq!(snapshot.schema_version, db::latest_schema_version());
+    assert_eq!(snapshot.upload_files, 1);
+    assert!(dest.join(SNAPSHOT_UPLOADS_DIR).join("avatar_1.png").is_file());
+    let copy = rusqlite::Connection::open(dest.join(SNAPSHOT_DB_FILE)).unwrap();
+    assert!(db::get_user_by_username(&copy, "before").unwrap().is_some());
+    assert!(db::get_user_by_username(&copy, "during").unwrap().is_none());
+
+    // Never over an existing snapshot
+    assert!(matches!(
+        backup::create_snapshot(&config.database.path, &config.uploads.dir, &dest),
+        Err(BackupError::Io(..))
+    ));
+}
+
+#[test]
+fn test_rotation_keeps_newest() {
+    let dir = TempDir::new().unwrap();
+    for name in ["wrench-forum-20260101-000000", "wrench-forum-20260102-000000", "wrench-forum-20260103-000000", "unrelated"] {
+        fs::create_dir_all(dir.path().join(name)).unwrap();
+    }
+
+    let removed = backup::rotate(dir.path(), 2).unwrap();
+    assert_eq!(removed, vec![dir.path().join("wrench-forum-20260101-000000")]);
+    assert_eq!(backup::list_snapshots(dir.path()).unwrap().len(), 2);
+    assert!(dir.path().join("unrelated").is_dir());
+}
+
+#[test]
+fn test_backup_to_dir_rotates() {
+    let dir = TempDir::new().unwrap();
+    let config = test_config(dir.path());
+    open_db(&config);
+
+    for _ in 0..3 {
+        backup::backup_to_dir(&config).unwrap();
+    }
+    assert_eq!(backup::list_snapshots(Path::new(&config.backup.dir)).unwrap().len(), 2);
+}
+
+#[test]
+fn test_restore_replaces_database_and_uploads() {
+    let dir = TempDir::new().unwrap();
+    let config = test_config(dir.path());
+    let db = open_db(&config);
+    let upload = Path::new(&config.uploads.dir).join("photo.jpg");
+    db::create_user(&db.write_conn(), "kept@example.com", "hash", "kept").unwrap();
+    fs::write(&upload, b"jpg").unwrap();
+    let snapshot = backup::backup_to_dir(&config).unwrap();
+
+    db::create_user(&db.write_conn(), "later@example.com", "hash", "later").unwrap();
+    fs::remove_file(&upload).unwrap();
+    drop(db);
+
+    let summary = backup::restore(&config, Path::new(&snapshot.path)).unwrap();
+    assert_eq!(summary.schema_version, db::latest_schema_version());
+    assert!(summary.migrations_applied.is_empty());
+    assert_eq!(summary.upload_files, 1);
+    assert!(upload.is_file());
+
+    let db = open_db(&config);
+    let conn = db.write_conn();
+    assert!(db::get_user_by_username(&conn, "kept").unwrap().is_some());
+    assert!(db::get_user_by_username(&conn, "later").unwrap().is_none());
+
+    // The replaced database was saved first
+    let previous = rusqlite::Connection::open(summary.previous.unwrap().join(SNAPSHOT_DB_FILE)).unwrap();
+    assert!(db::get_user_by_username(&previous, "later").unwrap().is_some());
+}
+
+#[test]
+fn test_restore_checks_schema() {
+    let dir = TempDir::new().unwrap();
+    let config = test_config(dir.path());
+    let db = open_db(&config);
+    let snapshot = dir.path().join("export");
+    backup::create_snapshot(&config.database.path, &config.uploads.dir, &snapshot).unwrap();
+
+    let copy = rusqlite::Connection::open(snapshot.join(SNAPSHOT_DB_FILE)).unwrap();
+    copy.execute(
+        "INSERT INTO schema_version (version, name) VALUES (?1, 'from the future')",
+        [db::latest_schema_version() + 1],
+    )
+    .unwrap();
+    drop(copy);
+    let error = backup::restore(&config, &snapshot).unwrap_err();
+    assert!(matches!(error, BackupError::Incompatible(_)));
+    assert!(error.to_string().contains("newer wrench-forum"));
+
+    // Not a backup at all
+    assert!(matches!(backup::check_snapshot(dir.path()), Err(BackupError::Incompatible(_))));
+
+    // The live database is untouched
+    assert_eq!(db::get_schema_version(&db.write_conn()).unwrap(), db::latest_schema_version());
+}
+
+#[test]
+fn test_restore_refuses_a_database_in_use() {
+    let dir = TempDir::new().unwrap();
+    let config = test_config(dir.path());
+    let db = open_db(&config);
+    let snapshot = backup::backup_to_dir(&config).unwrap();
+
+    // The server is in the middle of a write
+    let conn = db.write_conn();
+    conn.execute_batch("BEGIN IMMEDIATE").unwrap();
+    db::create_user(&conn, "during@example.com", "hash", "during").unwrap();
+    let error = backup::restore(&config, Path::new(&snapshot.path)).unwrap_err();
+    assert!(matches!(error, BackupError::Busy(_)), "{}", error);
+    assert!(error.to_string().contains("stop the server"));
+    conn.execute_batch("COMMIT").unwrap();
+
+    // Nothing was replaced, and a server sitting idle still counts
+    assert!(db::get_user_by_username(&conn, "during").unwrap().is_some());
+    drop(conn);
+    assert!(matches!(backup::restore(&config, Path::new(&snapshot.path)), Err(BackupError::Busy(_))));
+    drop(db);
+    assert!(backup::restore(&config, Path::new(&snapshot.path)).is_ok());
+}
+
+#[test]
+fn test_backup_records_and_schedule() {
+    let dir = TempDir::new().unwrap();
+    let config = test_config(dir.path());
+    let db = open_db(&config);
+    let conn = db.write_conn();
+
+    let snapshot = backup::backup_to_dir(&config).unwrap();
+    db::record_backup(&conn, "2026-03-01 02:00:00", Ok(&snapshot)).unwrap();
+    db::record_backup(&conn, "2026-03-02 02:00:00", Err("disk full")).unwrap();
+
+    let last = db::get_last_successful_backup(&conn).unwrap().unwrap();
+    assert_eq!(last.path, Some(snapshot.path));
+    let recent = db::get_recent_backups(&conn, 5).unwrap();
+    assert_eq!(recent.len(), 2);
+    assert_eq!(recent[0].error.as_deref(), Some("disk full"));
+
+    let now = Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap();
+    assert_eq!(backup::next_backup_delay(None, 24, now).as_secs(), 0);
+    assert_eq!(backup::next_backup_delay(Some("2026-03-01 02:00:00"), 24, now).as_secs(), 14 * 3600);
+    assert_eq!(backup::next_backup_delay(Some("2026-02-01 02:00:00"), 24, now).as_secs(), 0);
+}
+
+fn app(db: db::Db, config: Config) -> Router {
+    let mut tera = Tera::new("templates/**/*.html").unwrap();
+    tera.register_filter("torque_alternate", torque::tera_filter);
+    let state = (db, Arc::new(tera));
+    Router::new()
+        .route("/admin", get(routes::admin::admin_panel))
+        .route("/admin/backup", post(routes::admin::backup_now))
+        .layer(Extension(Arc::new(config)))
+        .layer(middleware::from_fn_with_state(state.clone(), error::render_errors))
+        .with_state(state)
+}
+
+fn request(method: &str, uri: &str, session: &str) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::COOKIE, format!("session={}", session))
+        .header("hx-request", "true")
+        .body(Body::empty())
+        .unwrap()
+}
+
+async fn body_text(response: axum::response::Response) -> String {
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+#[tokio::test]
+async fn test_admin_backup_status() {
+    let dir = TempDir::new().unwrap();
+    let config = test_config(dir.path());
+    let db = open_db(&config);
+    for (username, role) in [("admin", "admin"), ("mod", "moderator")] {
+        let conn = db.write_conn();
+        let user_id = db::create_user(&conn, &format!("{}@example.com", username), "hash", username).unwrap();
+        db::update_user_role(&conn, user_id, role).unwrap();
+        db::create_session(&conn, &format!("{}_token", username), user_id, "2099-01-01 00:00:00", None, None).unwrap();
+    }
+
+    let response = app(db.clone(), config.clone()).oneshot(request("GET", "/admin", "admin_token")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body_text(response).await.contains("No successful backups yet"));
+
+    let response = app(db.clone(), config.clone()).oneshot(request("POST", "/admin/backup", "mod_token")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = app(db.clone(), config.clone()).oneshot(request("POST", "/admin/backup", "admin_token")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let html = body_text(response).await;
+    assert!(html.contains("Backup complete"));
+    assert!(html.contains("Last successful backup"));
+    assert_eq!(backup::list_snapshots(Path::new(&config.backup.dir)).unwrap().len(), 1);
+
+    // A failure is shown and recorded, and doesn't hide the last success
+    fs::write(dir.path().join("not-a-dir"), b"").unwrap();
+    let mut broken = config.clone();
+    broken.backup.dir = dir.path().join("not-a-dir").display().to_string();
+    let response = app(db.clone(), broken).oneshot(request("POST", "/admin/backup", "admin_token")).await.unwrap();
+    let html = body_text(response).await;
+    assert!(html.contains("Backup failed"));
+    assert!(html.contains("Last successful backup"));
+}
@@ -0,0 +1,93 @@
+use wrench_forum::auth::verify_password;
+use wrench_forum::cli::{self, CliError};
+use wrench_forum::db;
+use wrench_forum::models::UserRole;
+
+mod common;
+use common::setup_test_db;
+
+fn can_sign_in(conn: &rusqlite::Connection, email: &str, password: &str) -> bool {
+    let (_, hash) = db::get_user_by_email(conn, email).unwrap().unwrap();
+    verify_password(password, &hash)
+}
+
+#[test]
+fn test_create_admin() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = cli::create_admin(&conn, "ops@example.com", "ops", "correct horse").unwrap();
+    let user = db::get_user_by_id(&conn, user_id).unwrap().unwrap();
+    assert_eq!(user.role, UserRole::Admin);
+    assert!(user.email_confirmed);
+    assert!(can_sign_in(&conn, "ops@example.com", "correct horse"));
+
+    for (email, username, password) in [
+        ("ops@example.com", "ops2", "correct horse"),
+        ("ops2@example.com", "ops", "correct horse"),
+        ("not-an-email", "ops3", "correct horse"),
+        ("ops3@example.com", "ops3", "short"),
+    ] {
+        assert!(matches!(cli::create_admin(&conn, email, username, password), Err(CliError::Usage(_))));
+    }
+}
+
+#[test]
+fn test_set_role_and_ban() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    let user_id = db::create_user(&conn, "member@example.com", "hash", "member").unwrap();
+    db::create_session(&conn, "member_token", user_id, "2099-01-01 00:00:00", None, None).unwrap();
+
+    cli::set_role(&conn, "member", "moderator").unwrap();
+    assert_eq!(db::get_user_by_id(&conn, user_id).unwrap().unwrap().role, UserRole::Moderator);
+    assert!(db::get_session(&conn, "member_token").unwrap().unwrap().rotate_pending);
+    assert!(matches!(cli::set_role(&conn, "member", "wizard"), Err(CliError::Usage(_))));
+    assert!(matches!(cli::set_role(&conn, "nobody", "admin"), Err(CliError::Usage(_))));
+
+    cli::set_banned(&conn, "member", true).unwrap();
+    assert!(db::get_user_by_id(&conn, user_id).unwrap().unwrap().banned);
+    assert!(db::get_session(&conn, "member_token").unwrap().is_none());
+    cli::set_banned(&conn, "member", false).unwrap();
+    assert!(!db::get_user_by_id(&conn, user_id).unwrap().unwrap().banned);
+}
+
+#[test]
+fn test_reset_password_signs_out_and_unlocks() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    let user_id = cli::create_admin(&conn, "ops@example.com", "ops", "old password").unwrap();
+    db::create_session(&conn, "ops_token", user_id, "2099-01-01 00:00:00", None, None).unwrap();
+    db::lock_user(&conn, user_id, "2099-01-01 00:00:00").unwrap();
+
+    cli::reset_password(&conn, "ops", "new password").unwrap();
+    assert!(can_sign_in(&conn, "ops@example.com", "new password"));
+    assert!(!can_sign_in(&conn, "ops@example.com", "old password"));
+    assert!(db::get_session(&conn, "ops_token").unwrap().is_none());
+    assert!(db::get_user_locked_until(&conn, user_id, "2026-01-01 00:00:00").unwrap().is_none());
+}
+
+#[test]
+fn test_seed_demo_accounts_can_sign_in() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let summary = cli::seed_demo(&conn, "demo password").unwrap();
+    assert!(summary.users.iter().any(|(name, role)| name == "admin" && role == "admin"));
+    assert!(can_sign_in(&conn, "admin@wrench.forum", "demo password"));
+    assert!(can_sign_in(&conn, "newbie@email.com", "demo password"));
+    let newbie = db::get_user_by_username(&conn, "CarNewbie").unwrap().unwrap();
+    assert!(newbie.email_confirmed);
+
+    let stats = db::get_forum_stats(&conn).unwrap();
+    assert_eq!(stats.total_users as usize, summary.users.len());
+    assert_eq!(stats.total_posts as usize, summary.posts);
+    assert_eq!(stats.total_comments as usize, summary.comments);
+    assert_eq!(stats.total_stores as usize, summary.stores);
+    let question = db::get_posts_by_user(&conn, newbie.id).unwrap().remove(0);
+    assert!(question.best_answer_id.is_some());
+
+    // Only into an empty forum
+    assert!(matches!(cli::seed_demo(&conn, "demo password"), Err(CliError::Usage(_))));
+    db::vacuum(&conn).unwrap();
+}
@@ -0,0 +1,29 @@
+//! Fixtures shared by the integration tests
+
+use std::ops::Deref;
+
+use tempfile::TempDir;
+use wrench_forum::db;
+
+/// A migrated database in its own temporary directory. The directory, WAL
+/// files included, is removed when this is dropped, so keep it alive for
+/// the whole test.
+pub struct TestDb {
+    db: db::Db,
+    _dir: TempDir,
+}
+
+impl Deref for TestDb {
+    type Target = db::Db;
+
+    fn deref(&self) -> &db::Db {
+        &self.db
+    }
+}
+
+pub fn setup_test_db() -> TestDb {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("forum.db");
+    let db = db::init_db_with_path(path.to_str().unwrap()).expect("Failed to init test db");
+    TestDb { db, _dir: dir }
+}
@@ -0,0 +1,181 @@
+use std::io::Write;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+
+use tempfile::NamedTempFile;
+use wrench_forum::config::{Config, ConfigError, Overrides};
+use wrench_forum::rate_limit::RateLimits;
+
+fn config_file(text: &str) -> NamedTempFile {
+    let mut file = NamedTempFile::new().unwrap();
+    file.write_all(text.as_bytes()).unwrap();
+    file
+}
+
+#[test]
+fn test_defaults_match_the_old_hard_coded_values() {
+    let config = Config::default();
+    assert_eq!(config.server.bind, "0.0.0.0:3000".parse::<SocketAddr>().unwrap());
+    assert_eq!(config.server.templates, "templates/**/*.html");
+    assert_eq!(config.database.path, "wrench-forum.db");
+    assert_eq!(config.uploads.dir, "static/uploads");
+    assert_eq!(config.uploads.max_file_bytes(), 5 * 1024 * 1024);
+    assert_eq!(config.uploads.max_avatar_bytes(), 2 * 1024 * 1024);
+    assert_eq!(config.sessions.lifetime_days, 30);
+    assert!(config.validate().is_ok());
+}
+
+#[test]
+fn test_partial_file_keeps_other_defaults() {
+    let config = Config::from_toml(
+        r#"
+        [server]
+        bind = "127.0.0.1:3001"
+
+        [sessions]
+        lifetime_days = 7
+        "#,
+    )
+    .unwrap();
+    assert_eq!(config.server.bind.port(), 3001);
+    assert_eq!(config.sessions.lifetime_days, 7);
+    assert_eq!(config.server.templates, "templates/**/*.html");
+    assert_eq!(config.uploads, Config::default().uploads);
+}
+
+#[test]
+fn test_unknown_and_mistyped_settings_are_rejected() {
+    let error = Config::from_toml("[server]\nport = 3001\n").unwrap_err();
+    assert!(error.to_string().contains("unknown field `port`"));
+    assert!(Config::from_toml("[uploads]\nmax_file_mb = \"5MB\"\n").is_err());
+    assert!(Config::from_toml("[server]\nbind = \"localhost\"\n").is_err());
+}
+
+#[test]
+fn test_overrides_win_over_file() {
+    let file = config_file("[database]\npath = \"staging.db\"\nread_pool_size = 4\n[sessions]\nlifetime_days = 14\n");
+    let overrides = Overrides {
+        config: Some(file.path().to_path_buf()),
+        database: Some("production.db".to_string()),
+        bind: Some("127.0.0.1:4000".parse().unwrap()),
+        ..Default::default()
+    };
+    let config = Config::load(&overrides).unwrap();
+    assert_eq!(config.database.path, "production.db");
+    assert_eq!(config.server.bind.port(), 4000);
+    // Settings without an override come from the file
+    assert_eq!(config.database.read_pool_size, 4);
+    assert_eq!(config.sessions.lifetime_days, 14);
+}
+
+#[test]
+fn test_load_reports_file_problems() {
+    let overrides = Overrides {
+        config: Some(PathBuf::from("no-such-config.toml")),
+        ..Default::default()
+    };
+    assert!(matches!(Config::load(&overrides), Err(ConfigError::Read(..))));
+
+    let file = config_file("[server\n");
+    let overrides = Overrides {
+        config: Some(file.path().to_path_buf()),
+        ..Default::default()
+    };
+    let error = Config::load(&overrides).unwrap_err();
+    assert!(matches!(error, ConfigError::Parse(..)));
+    assert!(error.to_string().contains(&file.path().display().to_string()));
+}
+
+#[test]
+fn test_validation_reports_every_problem() {
+    let mut config = Config::default();
+    config.server.static_dir = "no-such-dir".to_string();
+    config.database.path = "no-such-dir/forum.db".to_string();
+    config.database.read_pool_size = 0;
+    config.uploads.max_avatar_mb = 0;
+    config.sessions.lifetime_days = 0;
+
+    let Err(ConfigError::Invalid(problems)) = config.validate() else {
+        panic!("expected the config to be rejected");
+    };
+    assert_eq!(problems.len(), 5);
+    assert!(problems.iter().any(|p| p.starts_with("server.static_dir")));
+    assert!(problems.iter().any(|p| p.starts_with("database.path")));
+    assert!(problems.iter().any(|p| p.starts_with("database.read_pool_size")));
+    assert!(problems.iter().any(|p| p.starts_with("uploads.max_avatar_mb")));
+    assert!(problems.iter().any(|p| p.starts_with("sessions.lifetime_days")));
+}
+
+#[test]
+fn test_mail_rate_limit_and_proxy_settings() {
+    let file = config_file(
+        r#"
+        [server]
+        trust_proxy = true
+        secure_cookies = false
+
+        [mail]
+        base_url = "https://wrench.example"
+        smtp_host = "smtp.example.com"
+        smtp_username = "forum"
+        smtp_password = "hunter2"
+
+        [rate_limit]
+        login_lockout_threshold = 5
+        "#,
+    );
+    let overrides = Overrides {
+        config: Some(file.path().to_path_buf()),
+        register_max_per_ip: Some(2),
+        smtp_port: Some(2525),
+        ..Default::default()
+    };
+    let config = Config::load(&overrides).unwrap();
+    assert!(!config.server.secure_cookies);
+    assert_eq!(config.mail.smtp_host.as_deref(), Some("smtp.example.com"));
+    assert_eq!(config.mail.smtp_port, 2525);
+    assert_eq!(
+        config.rate_limits(),
+        RateLimits { lockout_threshold: 5, max_registrations_per_ip: 2, trust_proxy: true, ..RateLimits::default() }
+    );
+
+    // The password isn't printed by `wrench-forum config`
+    let printed = toml::to_string(&config).unwrap();
+    assert!(printed.contains("smtp.example.com"));
+    assert!(!printed.contains("hunter2"));
+
+    assert_eq!(Config::default().rate_limits(), RateLimits::default());
+}
+
+#[test]
+fn test_mail_and_rate_limit_settings_are_validated() {
+    let mut config = Config::default();
+    config.mail.base_url = "wrench.example".to_string();
+    config.mail.from = "not an address".to_string();
+    config.mail.smtp_password = Some("hunter2".to_string());
+    config.rate_limit.login_window_minutes = 0;
+    config.rate_limit.password_reset_max_per_account = -1;
+
+    let Err(ConfigError::Invalid(problems)) = config.validate() else {
+        panic!("expected the config to be rejected");
+    };
+    assert_eq!(problems.len(), 5);
+    assert!(problems.iter().any(|p| p.starts_with("mail.base_url")));
+    assert!(problems.iter().any(|p| p.starts_with("mail.from")));
+    assert!(problems.iter().any(|p| p.starts_with("mail.smtp_password")));
+    assert!(problems.iter().any(|p| p.starts_with("rate_limit.login_window_minutes")));
+    assert!(problems.iter().any(|p| p.starts_with("rate_limit.password_reset_max_per_account")));
+}
+
+#[test]
+fn test_upload_urls_map_into_uploads_dir() {
+    let mut config = Config::default();
+    config.uploads.dir = "/srv/staging/uploads".to_string();
+    assert_eq!(
+        config.uploads.path_for_url("/static/uploads/avatar_1_abc.png"),
+        Some(PathBuf::from("/srv/staging/uploads/avatar_1_abc.png"))
+    );
+    assert_eq!(config.uploads.path_for_url("/static/uploads/../wrench-forum.db"), None);
+    assert_eq!(config.uploads.path_for_url("/static/style.css"), None);
+    assert_eq!(config.uploads.path_for_url("https://example.com/avatar.png"), None);
+}
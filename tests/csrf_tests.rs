@@ -0,0 +1,148 @@
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    middleware,
+    routing::{get, post},
+    Extension, Router,
+};
+use axum_extra::extract::CookieJar;
+use std::sync::Arc;
+use tower::ServiceExt;
+use wrench_forum::config::Config;
+use wrench_forum::{auth, csrf};
+
+fn app() -> Router {
+    app_with(Config::default())
+}
+
+fn app_with(config: Config) -> Router {
+    let secure = config.server.secure_cookies;
+    Router::new()
+        .route("/", get(|| async { "home" }))
+        .route("/vote", post(|| async { "voted" }))
+        .route("/login", post(move |jar: CookieJar| async move { (auth::set_session_cookie(jar, "new-session", 30, secure), "welcome") }))
+        .route("/logout", post(|jar: CookieJar| async move { (auth::clear_session_cookie(jar), "bye") }))
+        .layer(middleware::from_fn(csrf::protect))
+        .layer(Extension(Arc::new(config)))
+}
+
+fn set_cookies(response: &axum::response::Response) -> Vec<String> {
+    response
+        .headers()
+        .get_all(header::SET_COOKIE)
+        .iter()
+        .map(|v| v.to_str().unwrap().to_string())
+        .collect()
+}
+
+fn post_with(uri: &str, cookie: Option<&str>, token: Option<&str>) -> Request<Body> {
+    signed_in_post(uri, None, cookie, token)
+}
+
+fn signed_in_post(uri: &str, session: Option<&str>, cookie: Option<&str>, token: Option<&str>) -> Request<Body> {
+    let mut request = Request::post(uri);
+    let cookies: Vec<String> = [session.map(|s| format!("session={}", s)), cookie.map(|c| format!("csrf={}", c))]
+        .into_iter()
+        .flatten()
+        .collect();
+    if !cookies.is_empty() {
+        request = request.header(header::COOKIE, cookies.join("; "));
+    }
+    if let Some(token) = token {
+        request = request.header("X-CSRF-Token", token);
+    }
+    request.body(Body::empty()).unwrap()
+}
+
+#[test]
+fn test_token_matches() {
+    assert!(csrf::token_matches(Some("abc"), Some("abc")));
+    assert!(!csrf::token_matches(Some("abc"), Some("abd")));
+    assert!(!csrf::token_matches(Some("abc"), Some("abcd")));
+    assert!(!csrf::token_matches(Some("abc"), None));
+    assert!(!csrf::token_matches(None, Some("abc")));
+    assert!(!csrf::token_matches(Some(""), Some("")));
+}
+
+#[tokio::test]
+async fn test_get_issues_token_cookie() {
+    let response = app().oneshot(Request::get("/").body(Body::empty()).unwrap()).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookies = set_cookies(&response);
+    assert_eq!(cookies.len(), 1);
+    assert!(cookies[0].starts_with("csrf="));
+    assert!(cookies[0].contains("SameSite=Lax"));
+    assert!(cookies[0].contains("Secure"));
+    assert!(!cookies[0].contains("HttpOnly"));
+
+    // Already has one
+    let request = Request::get("/").header(header::COOKIE, "csrf=abc").body(Body::empty()).unwrap();
+    let response = app().oneshot(request).await.unwrap();
+    assert!(set_cookies(&response).is_empty());
+}
+
+#[tokio::test]
+async fn test_post_requires_matching_token() {
+    for (cookie, token) in [(None, None), (Some("abc"), None), (None, Some("abc")), (Some("abc"), Some("xyz"))] {
+        let response = app().oneshot(post_with("/vote", cookie, token)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN, "{:?} {:?}", cookie, token);
+    }
+
+    let response = app().oneshot(post_with("/vote", Some("abc"), Some("abc"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_token_rotates_with_session() {
+    let response = app().oneshot(post_with("/login", Some("abc"), Some("abc"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookies = set_cookies(&response);
+    let session = cookies.iter().find(|c| c.starts_with("session=")).unwrap();
+    assert!(session.contains("HttpOnly") && session.contains("SameSite=Lax"));
+    let csrf = cookies.iter().find(|c| c.starts_with("csrf=")).unwrap();
+    assert!(csrf.starts_with(&format!("csrf={};", csrf::session_token("new-session"))));
+}
+
+#[tokio::test]
+async fn test_secure_flag_follows_config() {
+    let mut config = Config::default();
+    config.server.secure_cookies = false;
+    let response = app_with(config).oneshot(post_with("/login", Some("abc"), Some("abc"))).await.unwrap();
+    let cookies = set_cookies(&response);
+    assert_eq!(cookies.len(), 2);
+    assert!(cookies.iter().all(|c| !c.contains("Secure")));
+}
+
+#[tokio::test]
+async fn test_signed_in_token_is_bound_to_the_session() {
+    let token = csrf::session_token("my-session");
+    assert_ne!(token, csrf::session_token("other-session"));
+
+    // A planted cookie with a matching header isn't enough once signed in
+    let response = app().oneshot(signed_in_post("/vote", Some("my-session"), Some("abc"), Some("abc"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app().oneshot(signed_in_post("/vote", Some("my-session"), None, Some(&csrf::session_token("other-session")))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = app().oneshot(signed_in_post("/vote", Some("my-session"), Some("abc"), Some(&token))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    // And the page is handed the right token
+    let cookies = set_cookies(&response);
+    assert_eq!(cookies.len(), 1);
+    assert!(cookies[0].starts_with(&format!("csrf={};", token)));
+
+    let request = Request::get("/").header(header::COOKIE, format!("session=my-session; csrf={}", token)).body(Body::empty()).unwrap();
+    let response = app().oneshot(request).await.unwrap();
+    assert!(set_cookies(&response).is_empty());
+}
+
+#[tokio::test]
+async fn test_logout_issues_a_fresh_random_token() {
+    let token = csrf::session_token("my-session");
+    let response = app().oneshot(signed_in_post("/logout", Some("my-session"), Some(&token), Some(&token))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let cookies = set_cookies(&response);
+    let csrf = cookies.iter().find(|c| c.starts_with("csrf=")).unwrap();
+    assert!(!csrf.starts_with(&format!("csrf={};", token)));
+    assert!(!csrf.starts_with("csrf=;"));
+}
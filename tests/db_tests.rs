@@ -1,11 +1,159 @@
 use wrench_forum::db;
 use wrench_forum::models::*;
-use tempfile::NamedTempFile;
+use tempfile::TempDir;
 
-fn setup_test_db() -> db::Db {
-    let temp_file = NamedTempFile::new().unwrap();
-    let path = temp_file.path().to_str().unwrap();
-    db::init_db_with_path(path).expect("Failed to init test db")
+mod common;
+use common::setup_test_db;
+
+// ============ Migration Tests ============
+
+#[test]
+fn test_migrations_applied_on_init() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    
+    assert_eq!(db::get_schema_version(&conn).unwrap(), db::latest_schema_version());
+    
+    let status = db::get_migration_status(&conn).unwrap().unwrap();
+    assert_eq!(status.len(), db::MIGRATIONS.len());
+    assert!(status.iter().all(|m| m.applied_at.is_some()));
+    
+    // Running again is a no-op
+    assert!(db::run_migrations(&conn).unwrap().is_empty());
+}
+
+#[test]
+fn test_migrate_to_target_version() {
+    let dir = TempDir::new().unwrap();
+    let path = dir.path().join("forum.db");
+    let conn = db::open_db(path.to_str().unwrap()).unwrap();
+    
+    // Asking for the status of a fresh file changes nothing
+    let read_only = db::open_db_read_only(path.to_str().unwrap()).unwrap();
+    assert!(db::get_migration_status(&read_only).unwrap().is_none());
+    assert!(db::get_migration_status(&conn).unwrap().is_none());
+    
+    assert_eq!(db::get_schema_version(&conn).unwrap(), 0);
+    
+    let applied = db::migrate_to(&conn, 1).unwrap();
+    assert_eq!(applied, vec![1]);
+    assert_eq!(db::get_schema_version(&conn).unwrap(), 1);
+    
+    // Forward-only: asking for an older version changes nothing
+    assert!(db::migrate_to(&conn, 0).unwrap().is_empty());
+    assert_eq!(db::get_schema_version(&conn).unwrap(), 1);
+}
+
+#[test]
+fn test_migrations_adopt_unversioned_database() {
+    let dir = TempDir::new().unwrap();
+    let conn = db::open_db(dir.path().join("forum.db").to_str().unwrap()).unwrap();
+    
+    // A database from before versioning already has its tables and data
+    conn.execute_batch(
+        "CREATE TABLE users (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            email TEXT UNIQUE NOT NULL,
+            password_hash TEXT NOT NULL,
+            username TEXT UNIQUE NOT NULL,
+            role TEXT NOT NULL DEFAULT 'unverified',
+            created_at TEXT NOT NULL DEFAULT (datetime('now')),
+            banned INTEGER NOT NULL DEFAULT 0,
+            karma INTEGER NOT NULL DEFAULT 0,
+            flair TEXT
+        );
+        INSERT INTO users (email, password_hash, username) VALUES ('old@example.com', 'hash', 'olduser');"
+    ).unwrap();
+    
+    db::run_migrations(&conn).unwrap();
+    
+    assert_eq!(db::get_schema_version(&conn).unwrap(), db::latest_schema_version());
+    let user = db::get_user_by_username(&conn, "olduser").unwrap();
+    assert!(user.is_some());
+}
+
+#[test]
+fn test_migration_sanitizes_stored_html() {
+    let dir = TempDir::new().unwrap();
+    let conn = db::open_db(dir.path().join("forum.db").to_str().unwrap()).unwrap();
+    db::migrate_to(&conn, 15).unwrap();
+
+    let user_id = db::create_user(&conn, "old@example.com", "hash", "olduser").unwrap();
+    let category_id = db::create_category(&conn, "General", "general", "").unwrap();
+    let post_id = db::create_post(&conn, user_id, category_id, "Old post", "Hello").unwrap();
+    conn.execute("UPDATE posts SET body = 'Hello <script>alert(1)</script>', body_html = '<p>Hello <script>alert(1)</script></p>' WHERE id = ?1", [post_id]).unwrap();
+
+    db::run_migrations(&conn).unwrap();
+    let post = db::get_post_by_id(&conn, post_id).unwrap().unwrap();
+    assert!(!post.body_html.unwrap().contains("<script"));
+}
+
+#[test]
+fn test_migrations_render_stored_html_once() {
+    let dir = TempDir::new().unwrap();
+    let conn = db::open_db(dir.path().join("forum.db").to_str().unwrap()).unwrap();
+    db::migrate_to(&conn, 3).unwrap();
+
+    conn.execute_batch(
+        "INSERT INTO users (email, password_hash, username) VALUES ('old@example.com', 'hash', 'olduser');
+         INSERT INTO categories (name, slug, description) VALUES ('General', 'general', '');
+         INSERT INTO posts (user_id, category_id, title, body, body_html) VALUES (1, 1, 'Misfire', 'Threw P0300', '<p>stale</p>');"
+    ).unwrap();
+
+    // The DTC and torque migrations leave stored HTML for the final re-render
+    db::migrate_to(&conn, 15).unwrap();
+    let stale: String = conn.query_row("SELECT body_html FROM posts", [], |r| r.get(0)).unwrap();
+    assert_eq!(stale, "<p>stale</p>");
+
+    db::run_migrations(&conn).unwrap();
+    let rendered: String = conn.query_row("SELECT body_html FROM posts", [], |r| r.get(0)).unwrap();
+    assert!(rendered.contains(r#"href="/dtc/P0300""#));
+}
+
+// ============ Connection Pool Tests ============
+
+#[test]
+fn test_db_uses_wal_journal() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let mode: String = conn.query_row("PRAGMA journal_mode", [], |r| r.get(0)).unwrap();
+    assert_eq!(mode, "wal");
+}
+
+#[test]
+fn test_read_connections_are_read_only() {
+    let db = setup_test_db();
+    let conn = db.conn().unwrap();
+
+    assert!(!db::get_categories(&conn).unwrap().is_empty());
+    assert!(db::create_user(&conn, "test@example.com", "hash", "testuser").is_err());
+}
+
+#[tokio::test]
+async fn test_async_read_sees_committed_write() {
+    let db = setup_test_db();
+
+    let user_id = db.write(|conn| db::create_user(conn, "test@example.com", "hash", "testuser"))
+        .await
+        .unwrap();
+
+    let user = db.read(move |conn| db::get_user_by_id(conn, user_id)).await.unwrap();
+    assert_eq!(user.unwrap().username, "testuser");
+}
+
+#[test]
+fn test_write_conn_survives_poisoning() {
+    let db = setup_test_db();
+
+    let poisoner = db.clone();
+    let _ = std::thread::spawn(move || {
+        let _conn = poisoner.write_conn();
+        panic!("handler panicked while holding the writer");
+    }).join();
+
+    let conn = db.write_conn();
+    db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
 }
 
 // ============ User Tests ============
@@ -13,7 +161,7 @@ fn setup_test_db() -> db::Db {
 #[test]
 fn test_create_and_get_user() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser")
         .expect("Failed to create user");
@@ -35,7 +183,7 @@ fn test_create_and_get_user() {
 #[test]
 fn test_get_user_by_email() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     
@@ -50,7 +198,7 @@ fn test_get_user_by_email() {
 #[test]
 fn test_get_user_by_username() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     
@@ -64,7 +212,7 @@ fn test_get_user_by_username() {
 #[test]
 fn test_update_user_role() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     
@@ -77,7 +225,7 @@ fn test_update_user_role() {
 #[test]
 fn test_ban_user() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     
@@ -97,7 +245,7 @@ fn test_ban_user() {
 #[test]
 fn test_user_karma() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     
@@ -115,11 +263,11 @@ fn test_user_karma() {
 #[test]
 fn test_session_lifecycle() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     
-    db::create_session(&conn, "token123", user_id, "2030-01-01 00:00:00").unwrap();
+    db::create_session(&conn, "token123", user_id, "2030-01-01 00:00:00", None, None).unwrap();
     
     let session = db::get_session(&conn, "token123")
         .expect("Query failed")
@@ -134,12 +282,387 @@ fn test_session_lifecycle() {
     assert!(session.is_none());
 }
 
+#[test]
+fn test_session_details_and_revocation() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    
+    let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
+    let other_id = db::create_user(&conn, "other@example.com", "hash123", "otheruser").unwrap();
+    db::create_session(&conn, "laptop", user_id, "2030-01-01 00:00:00", Some("10.0.0.1"), Some("Firefox/128.0")).unwrap();
+    db::create_session(&conn, "shop_pc", user_id, "2030-01-01 00:00:00", Some("10.0.0.2"), None).unwrap();
+    db::create_session(&conn, "expired", user_id, "2020-01-01 00:00:00", None, None).unwrap();
+    db::create_session(&conn, "theirs", other_id, "2030-01-01 00:00:00", None, None).unwrap();
+    
+    let laptop = db::get_session(&conn, "laptop").unwrap().unwrap();
+    assert_eq!(laptop.ip_address.as_deref(), Some("10.0.0.1"));
+    assert_eq!(laptop.user_agent.as_deref(), Some("Firefox/128.0"));
+    assert!(laptop.last_seen_at.is_some());
+    
+    db::touch_session(&conn, "shop_pc", "2029-01-01 00:00:00").unwrap();
+    let sessions = db::get_user_sessions(&conn, user_id, NOW).unwrap();
+    let tokens: Vec<_> = sessions.iter().map(|s| s.token.as_str()).collect();
+    assert_eq!(tokens, vec!["shop_pc", "laptop"]);
+    
+    // Names are per user
+    db::rename_session(&conn, user_id, "shop_pc", Some("Shop PC")).unwrap();
+    db::rename_session(&conn, other_id, "laptop", Some("Stolen")).unwrap();
+    assert_eq!(db::get_session(&conn, "shop_pc").unwrap().unwrap().name.as_deref(), Some("Shop PC"));
+    assert_eq!(db::get_session(&conn, "laptop").unwrap().unwrap().name, None);
+    
+    assert_eq!(db::delete_other_sessions(&conn, user_id, "laptop").unwrap(), 2);
+    assert!(db::get_session(&conn, "laptop").unwrap().is_some());
+    assert!(db::get_session(&conn, "shop_pc").unwrap().is_none());
+    assert!(db::get_session(&conn, "theirs").unwrap().is_some());
+}
+
+#[test]
+fn test_role_change_marks_sessions_for_rotation() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    
+    let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
+    db::create_session(&conn, "old_token", user_id, "2030-01-01 00:00:00", None, None).unwrap();
+    assert!(!db::rotate_session_token(&conn, "old_token", "new_token").unwrap());
+    
+    db::update_user_role(&conn, user_id, "moderator").unwrap();
+    assert!(db::get_session(&conn, "old_token").unwrap().unwrap().rotate_pending);
+    
+    assert!(db::rotate_session_token(&conn, "old_token", "new_token").unwrap());
+    assert!(db::get_session(&conn, "old_token").unwrap().is_none());
+    let session = db::get_session(&conn, "new_token").unwrap().unwrap();
+    assert_eq!(session.user_id, user_id);
+    assert!(!session.rotate_pending);
+    
+    // Only once
+    assert!(!db::rotate_session_token(&conn, "new_token", "newer_token").unwrap());
+}
+
+#[test]
+fn test_api_tokens() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    
+    let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
+    let other_id = db::create_user(&conn, "other@example.com", "hash123", "otheruser").unwrap();
+    let token_id = db::create_api_token(&conn, user_id, "Shop sync", "hash_a", "wf_aaaaaaaa", &[ApiScope::Read, ApiScope::Notifications]).unwrap();
+    db::create_api_token(&conn, user_id, "Old script", "hash_b", "wf_bbbbbbbb", &[ApiScope::Write]).unwrap();
+    
+    let token = db::get_api_token_by_hash(&conn, "hash_a").unwrap().unwrap();
+    assert_eq!(token.user_id, user_id);
+    assert_eq!(token.name, "Shop sync");
+    assert_eq!(token.scopes, vec![ApiScope::Read, ApiScope::Notifications]);
+    assert_eq!(token.last_used_at, None);
+    assert!(db::get_api_token_by_hash(&conn, "wf_aaaaaaaa").unwrap().is_none());
+    
+    db::touch_api_token(&conn, token_id, NOW).unwrap();
+    assert_eq!(db::get_api_token_by_hash(&conn, "hash_a").unwrap().unwrap().last_used_at.as_deref(), Some(NOW));
+    
+    let names: Vec<_> = db::get_user_api_tokens(&conn, user_id).unwrap().into_iter().map(|t| t.name).collect();
+    assert_eq!(names, vec!["Old script", "Shop sync"]);
+    
+    // Only the owner can revoke a token
+    assert!(!db::delete_api_token(&conn, other_id, token_id).unwrap());
+    assert!(db::delete_api_token(&conn, user_id, token_id).unwrap());
+    assert!(db::get_api_token_by_hash(&conn, "hash_a").unwrap().is_none());
+}
+
+#[test]
+fn test_keyset_pages() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    
+    let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
+    let other_id = db::create_user(&conn, "other@example.com", "hash123", "otheruser").unwrap();
+    let posts: Vec<i64> = (0..3).map(|n| db::create_post(&conn, user_id, 1, &format!("Post {}", n), "Body").unwrap()).collect();
+    db::remove_post(&conn, posts[1]).unwrap();
+    
+    let ids = |page: Vec<Post>| page.into_iter().map(|p| p.id).collect::<Vec<_>>();
+    assert_eq!(ids(db::get_posts_before(&conn, None, None, 10).unwrap()), vec![posts[2], posts[0]]);
+    assert_eq!(ids(db::get_posts_before(&conn, None, Some(posts[2]), 10).unwrap()), vec![posts[0]]);
+    
+    let comments: Vec<i64> = (0..3).map(|_| db::create_comment(&conn, posts[0], other_id, None, "Reply").unwrap()).collect();
+    let page: Vec<i64> = db::get_comments_after(&conn, posts[0], Some(comments[0]), 10).unwrap().into_iter().map(|c| c.id).collect();
+    assert_eq!(page, vec![comments[1], comments[2]]);
+    
+    // Each comment notified the post's author
+    let notifications = db::get_notifications_before(&conn, user_id, true, None, 2).unwrap();
+    assert_eq!(notifications.len(), 2);
+    assert!(notifications[0].id > notifications[1].id);
+    assert!(!db::mark_user_notification_read(&conn, other_id, notifications[0].id).unwrap());
+    assert!(db::mark_user_notification_read(&conn, user_id, notifications[0].id).unwrap());
+    assert_eq!(db::get_notifications_before(&conn, user_id, true, None, 10).unwrap().len(), 2);
+    assert_eq!(db::get_notifications_before(&conn, user_id, false, None, 10).unwrap().len(), 3);
+}
+
+// ============ Password Reset Tests ============
+
+const NOW: &str = "2026-01-01 12:00:00";
+
+#[test]
+fn test_password_reset_is_single_use_and_revokes_sessions() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "reset@example.com", "old_hash", "reset").unwrap();
+    db::create_session(&conn, "laptop", user_id, "2030-01-01 00:00:00", None, None).unwrap();
+    db::create_session(&conn, "phone", user_id, "2030-01-01 00:00:00", None, None).unwrap();
+    db::create_password_reset_token(&conn, user_id, "token_hash", "2026-01-01 13:00:00").unwrap();
+
+    assert_eq!(db::get_password_reset_user(&conn, "token_hash", NOW).unwrap(), Some(user_id));
+    assert_eq!(db::reset_password(&conn, "token_hash", "new_hash", NOW).unwrap(), Some(user_id));
+
+    let (_, hash) = db::get_user_by_email(&conn, "reset@example.com").unwrap().unwrap();
+    assert_eq!(hash, "new_hash");
+    assert!(db::get_session(&conn, "laptop").unwrap().is_none());
+    assert!(db::get_session(&conn, "phone").unwrap().is_none());
+
+    // Spent
+    assert_eq!(db::get_password_reset_user(&conn, "token_hash", NOW).unwrap(), None);
+    assert_eq!(db::reset_password(&conn, "token_hash", "other_hash", NOW).unwrap(), None);
+    let (_, hash) = db::get_user_by_email(&conn, "reset@example.com").unwrap().unwrap();
+    assert_eq!(hash, "new_hash");
+}
+
+#[test]
+fn test_password_reset_token_expiry_and_replacement() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "reset@example.com", "old_hash", "reset").unwrap();
+    db::create_password_reset_token(&conn, user_id, "expired", "2026-01-01 11:59:59").unwrap();
+    assert_eq!(db::reset_password(&conn, "expired", "new_hash", NOW).unwrap(), None);
+
+    // Asking again invalidates the previous link
+    db::create_password_reset_token(&conn, user_id, "first", "2026-01-01 13:00:00").unwrap();
+    db::create_password_reset_token(&conn, user_id, "second", "2026-01-01 13:00:00").unwrap();
+    assert_eq!(db::get_password_reset_user(&conn, "first", NOW).unwrap(), None);
+    assert_eq!(db::get_password_reset_user(&conn, "second", NOW).unwrap(), Some(user_id));
+    assert_eq!(db::get_password_reset_user(&conn, "unknown", NOW).unwrap(), None);
+}
+
+// ============ Email Confirmation Tests ============
+
+#[test]
+fn test_email_confirmation_is_single_use() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "new@example.com", "hash", "newuser").unwrap();
+    assert!(!db::get_user_by_id(&conn, user_id).unwrap().unwrap().email_confirmed);
+
+    db::create_email_confirmation_token(&conn, user_id, "confirm_hash", "2026-01-03 12:00:00").unwrap();
+    assert_eq!(db::confirm_email(&conn, "confirm_hash", NOW).unwrap(), Some(user_id));
+    assert!(db::get_user_by_id(&conn, user_id).unwrap().unwrap().email_confirmed);
+    let (user, _) = db::get_user_by_email(&conn, "new@example.com").unwrap().unwrap();
+    assert!(user.email_confirmed);
+
+    assert_eq!(db::confirm_email(&conn, "confirm_hash", NOW).unwrap(), None);
+}
+
+#[test]
+fn test_email_confirmation_token_expiry_and_replacement() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "new@example.com", "hash", "newuser").unwrap();
+    assert_eq!(db::get_last_email_confirmation_sent(&conn, user_id).unwrap(), None);
+
+    db::create_email_confirmation_token(&conn, user_id, "expired", "2026-01-01 11:59:59").unwrap();
+    assert_eq!(db::confirm_email(&conn, "expired", NOW).unwrap(), None);
+    assert!(db::get_last_email_confirmation_sent(&conn, user_id).unwrap().is_some());
+
+    // Resending invalidates the earlier link
+    db::create_email_confirmation_token(&conn, user_id, "first", "2026-01-03 12:00:00").unwrap();
+    db::create_email_confirmation_token(&conn, user_id, "second", "2026-01-03 12:00:00").unwrap();
+    assert_eq!(db::confirm_email(&conn, "first", NOW).unwrap(), None);
+    assert!(!db::get_user_by_id(&conn, user_id).unwrap().unwrap().email_confirmed);
+    assert_eq!(db::confirm_email(&conn, "second", NOW).unwrap(), Some(user_id));
+}
+
+// ============ Two-Factor Tests ============
+
+#[test]
+fn test_totp_enrollment_and_replay() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "mod@example.com", "hash", "moduser").unwrap();
+    assert!(db::get_user_totp(&conn, user_id).unwrap().is_none());
+
+    // Reloading the setup page swaps the pending secret
+    db::start_totp_enrollment(&conn, user_id, "FIRSTSECRET").unwrap();
+    db::start_totp_enrollment(&conn, user_id, "SECONDSECRET").unwrap();
+    let pending = db::get_user_totp(&conn, user_id).unwrap().unwrap();
+    assert_eq!(pending.secret, "SECONDSECRET");
+    assert!(!pending.enabled);
+    assert!(!db::get_user_by_id(&conn, user_id).unwrap().unwrap().two_factor_enabled);
+
+    db::enable_totp(&conn, user_id, 100, &["a".to_string(), "b".to_string()], NOW).unwrap();
+    assert!(db::get_user_by_id(&conn, user_id).unwrap().unwrap().two_factor_enabled);
+
+    // ...but not once it's enabled
+    db::start_totp_enrollment(&conn, user_id, "THIRDSECRET").unwrap();
+    let enabled = db::get_user_totp(&conn, user_id).unwrap().unwrap();
+    assert_eq!(enabled.secret, "SECONDSECRET");
+    assert_eq!(enabled.last_used_step, Some(100));
+
+    assert!(!db::use_totp_step(&conn, user_id, 100).unwrap());
+    assert!(db::use_totp_step(&conn, user_id, 101).unwrap());
+    assert!(!db::use_totp_step(&conn, user_id, 100).unwrap());
+
+    db::disable_totp(&conn, user_id).unwrap();
+    assert!(db::get_user_totp(&conn, user_id).unwrap().is_none());
+    assert_eq!(db::count_unused_recovery_codes(&conn, user_id).unwrap(), 0);
+}
+
+#[test]
+fn test_recovery_codes_are_single_use() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "mod@example.com", "hash", "moduser").unwrap();
+    db::start_totp_enrollment(&conn, user_id, "SECRET").unwrap();
+    db::enable_totp(&conn, user_id, 1, &["a".to_string(), "b".to_string()], NOW).unwrap();
+    assert_eq!(db::count_unused_recovery_codes(&conn, user_id).unwrap(), 2);
+
+    assert!(db::use_recovery_code(&conn, user_id, "a", NOW).unwrap());
+    assert!(!db::use_recovery_code(&conn, user_id, "a", NOW).unwrap());
+    assert!(!db::use_recovery_code(&conn, user_id, "unknown", NOW).unwrap());
+    assert_eq!(db::count_unused_recovery_codes(&conn, user_id).unwrap(), 1);
+
+    db::replace_recovery_codes(&conn, user_id, &["c".to_string()]).unwrap();
+    assert!(!db::use_recovery_code(&conn, user_id, "b", NOW).unwrap());
+    assert!(db::use_recovery_code(&conn, user_id, "c", NOW).unwrap());
+}
+
+#[test]
+fn test_login_challenge_lifecycle() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "mod@example.com", "hash", "moduser").unwrap();
+    db::create_login_challenge(&conn, "expired", user_id, "2026-01-01 11:59:59").unwrap();
+    db::create_login_challenge(&conn, "pending", user_id, "2026-01-01 12:05:00").unwrap();
+    assert!(db::get_login_challenge(&conn, "expired", NOW).unwrap().is_none());
+
+    db::record_login_challenge_failure(&conn, "pending").unwrap();
+    let challenge = db::get_login_challenge(&conn, "pending", NOW).unwrap().unwrap();
+    assert_eq!(challenge.user_id, user_id);
+    assert_eq!(challenge.attempts, 1);
+
+    db::delete_login_challenge(&conn, "pending").unwrap();
+    assert!(db::get_login_challenge(&conn, "pending", NOW).unwrap().is_none());
+}
+
+#[test]
+fn test_two_factor_role_policy() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    assert!(db::get_two_factor_roles(&conn).unwrap().is_empty());
+    db::set_two_factor_roles(&conn, &[UserRole::Moderator, UserRole::Admin]).unwrap();
+    assert_eq!(db::get_two_factor_roles(&conn).unwrap(), vec![UserRole::Moderator, UserRole::Admin]);
+    db::set_two_factor_roles(&conn, &[]).unwrap();
+    assert!(db::get_two_factor_roles(&conn).unwrap().is_empty());
+}
+
+#[test]
+fn test_builtin_role_permissions() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "member@example.com", "hash", "member").unwrap();
+    let permissions = |role: &UserRole| db::get_user_permissions(&conn, user_id, role).unwrap();
+    assert_eq!(permissions(&UserRole::Unverified).granted, vec![Permission::Comment]);
+    assert_eq!(permissions(&UserRole::VerifiedMechanic).granted, vec![Permission::Post, Permission::Comment, Permission::VoteStore]);
+    let moderator = permissions(&UserRole::Moderator);
+    assert!(moderator.has(Permission::RemoveContent) && moderator.has(Permission::Ban));
+    assert!(moderator.can_moderate() && !moderator.can_administer());
+    assert_eq!(permissions(&UserRole::Admin).granted, Permission::ALL.to_vec());
+    // An unknown role name can't do anything
+    assert!(permissions(&UserRole::from_str("ghost")).granted.is_empty());
+
+    let roles = db::get_roles(&conn).unwrap();
+    let names: Vec<_> = roles.iter().map(|r| r.name.as_str()).collect();
+    assert_eq!(names, vec!["unverified", "verified_mechanic", "moderator", "admin"]);
+    assert!(roles.iter().all(|r| r.builtin));
+}
+
+#[test]
+fn test_custom_roles() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    db::create_role(&conn, "store_curator", "Store Curator", &[Permission::Comment, Permission::VoteStore]).unwrap();
+    let user_id = db::create_user(&conn, "curator@example.com", "hash", "curator").unwrap();
+    db::update_user_role(&conn, user_id, "store_curator").unwrap();
+
+    let user = db::get_user_by_id(&conn, user_id).unwrap().unwrap();
+    assert_eq!(user.role, UserRole::Custom("store_curator".to_string()));
+    let permissions = db::get_user_permissions(&conn, user_id, &user.role).unwrap();
+    assert!(permissions.has(Permission::VoteStore));
+    assert!(!permissions.has(Permission::Post));
+
+    let role = db::get_role(&conn, "store_curator").unwrap().unwrap();
+    assert_eq!(role.label, "Store Curator");
+    assert!(!role.builtin);
+    assert_eq!(role.user_count, 1);
+
+    db::set_role_permissions(&conn, "store_curator", &[Permission::VoteStore]).unwrap();
+    assert_eq!(db::get_role_permissions(&conn, "store_curator").unwrap(), vec![Permission::VoteStore]);
+
+    db::update_user_role(&conn, user_id, "unverified").unwrap();
+    db::delete_role(&conn, "store_curator").unwrap();
+    assert!(db::get_role(&conn, "store_curator").unwrap().is_none());
+    assert!(db::get_role_permissions(&conn, "store_curator").unwrap().is_empty());
+    // Built-in roles stay
+    db::delete_role(&conn, "moderator").unwrap();
+    assert!(db::get_role(&conn, "moderator").unwrap().is_some());
+}
+
+#[test]
+fn test_category_moderators() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let categories = db::get_categories(&conn).unwrap();
+    let transmission = categories[0].id;
+    let other = categories[1].id;
+    let user_id = db::create_user(&conn, "trans@example.com", "hash", "transexpert").unwrap();
+
+    assert!(db::add_category_moderator(&conn, user_id, transmission).unwrap());
+    assert!(!db::add_category_moderator(&conn, user_id, transmission).unwrap());
+    let permissions = db::get_user_permissions(&conn, user_id, &UserRole::Unverified).unwrap();
+    assert_eq!(permissions.categories, vec![transmission]);
+    assert!(permissions.can_moderate());
+    assert!(permissions.can_moderate_category(transmission));
+    assert!(!permissions.can_moderate_category(other));
+
+    let moderators = db::get_category_moderators(&conn).unwrap();
+    assert_eq!(moderators.len(), 1);
+    assert_eq!(moderators[0].username, "transexpert");
+    assert_eq!(moderators[0].category_id, transmission);
+
+    // Reports carry the category of the post, or of the comment's post
+    let post_id = db::create_post(&conn, user_id, transmission, "Slipping", "Slips in third").unwrap();
+    let comment_id = db::create_comment(&conn, post_id, user_id, None, "Check the fluid").unwrap();
+    db::create_report(&conn, user_id, Some(post_id), None, "spam").unwrap();
+    db::create_report(&conn, user_id, None, Some(comment_id), "rude").unwrap();
+    let reports = db::get_unresolved_reports(&conn).unwrap();
+    assert!(reports.iter().all(|r| r.category_id == Some(transmission)));
+
+    assert!(db::remove_category_moderator(&conn, user_id, transmission).unwrap());
+    assert!(!db::remove_category_moderator(&conn, user_id, transmission).unwrap());
+    assert!(db::get_category_moderators(&conn).unwrap().is_empty());
+}
+
 // ============ Category Tests ============
 
 #[test]
 fn test_categories() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     // Default categories are seeded
     let categories = db::get_categories(&conn).unwrap();
@@ -155,7 +678,7 @@ fn test_categories() {
 #[test]
 fn test_create_and_get_post() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     let categories = db::get_categories(&conn).unwrap();
@@ -175,7 +698,7 @@ fn test_create_and_get_post() {
 #[test]
 fn test_get_posts_sorted() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     let categories = db::get_categories(&conn).unwrap();
@@ -195,7 +718,7 @@ fn test_get_posts_sorted() {
 #[test]
 fn test_post_pagination() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     let categories = db::get_categories(&conn).unwrap();
@@ -216,7 +739,7 @@ fn test_post_pagination() {
 #[test]
 fn test_update_post() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     let categories = db::get_categories(&conn).unwrap();
@@ -232,7 +755,7 @@ fn test_update_post() {
 #[test]
 fn test_remove_and_restore_post() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     let categories = db::get_categories(&conn).unwrap();
@@ -252,13 +775,13 @@ fn test_remove_and_restore_post() {
 #[test]
 fn test_create_comment() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     let categories = db::get_categories(&conn).unwrap();
     let post_id = db::create_post(&conn, user_id, categories[0].id, "Test", "Body").unwrap();
     
-    let comment_id = db::create_comment(&conn, post_id, user_id, None, "My comment").unwrap();
+    db::create_comment(&conn, post_id, user_id, None, "My comment").unwrap();
     
     let comments = db::get_comments_for_post(&conn, post_id).unwrap();
     assert_eq!(comments.len(), 1);
@@ -269,7 +792,7 @@ fn test_create_comment() {
 #[test]
 fn test_nested_comments() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash123", "testuser").unwrap();
     let categories = db::get_categories(&conn).unwrap();
@@ -287,7 +810,7 @@ fn test_nested_comments() {
 #[test]
 fn test_vote_post() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user1_id = db::create_user(&conn, "user1@example.com", "hash", "user1").unwrap();
     let user2_id = db::create_user(&conn, "user2@example.com", "hash", "user2").unwrap();
@@ -314,7 +837,7 @@ fn test_vote_post() {
 #[test]
 fn test_get_user_vote() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user1_id = db::create_user(&conn, "user1@example.com", "hash", "user1").unwrap();
     let user2_id = db::create_user(&conn, "user2@example.com", "hash", "user2").unwrap();
@@ -335,11 +858,11 @@ fn test_get_user_vote() {
 #[test]
 fn test_create_and_get_store() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
     
-    let store_id = db::create_store(&conn, "Test Store", "https://test.com", Some("A great store"), "General", user_id).unwrap();
+    db::create_store(&conn, "Test Store", "https://test.com", Some("A great store"), "General", user_id).unwrap();
     
     let stores = db::get_stores(&conn, None).unwrap();
     assert_eq!(stores.len(), 1);
@@ -349,7 +872,7 @@ fn test_create_and_get_store() {
 #[test]
 fn test_store_voting() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user1_id = db::create_user(&conn, "user1@example.com", "hash", "user1").unwrap();
     let user2_id = db::create_user(&conn, "user2@example.com", "hash", "user2").unwrap();
@@ -371,7 +894,7 @@ fn test_store_voting() {
 #[test]
 fn test_bookmarks() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
     let categories = db::get_categories(&conn).unwrap();
@@ -394,7 +917,7 @@ fn test_bookmarks() {
 #[test]
 fn test_notifications() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
     
@@ -417,7 +940,7 @@ fn test_notifications() {
 #[test]
 fn test_verification_request() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
     
@@ -434,7 +957,7 @@ fn test_verification_request() {
 #[test]
 fn test_reports() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
     let categories = db::get_categories(&conn).unwrap();
@@ -455,7 +978,7 @@ fn test_reports() {
 #[test]
 fn test_search_posts() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
     let categories = db::get_categories(&conn).unwrap();
@@ -468,12 +991,493 @@ fn test_search_posts() {
     assert!(results[0].title.contains("brakes"));
 }
 
+#[test]
+fn test_search_matches_whole_terms_and_comments() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
+    let categories = db::get_categories(&conn).unwrap();
+
+    let answered = db::create_post(&conn, user_id, categories[0].id, "Rough idle on cold start", "Shakes for a minute").unwrap();
+    db::create_comment(&conn, answered, user_id, None, "Pull codes, mine was a P0300 misfire from a cracked coil pack").unwrap();
+    db::create_post(&conn, user_id, categories[0].id, "Misfires everywhere", "Not sure where to start").unwrap();
+
+    // Both terms must appear; the comment-only answer is found
+    let results = db::search_posts(&conn, "P0300 misfire", None, 10).unwrap();
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].id, answered);
+
+    // Unbalanced quotes and stray operators are treated as plain text
+    assert!(db::search_posts(&conn, "\"coil -", None, 10).is_ok());
+    assert!(db::search_posts(&conn, "***", None, 10).unwrap().is_empty());
+}
+
+#[test]
+fn test_search_ranks_title_hits_first() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
+    let categories = db::get_categories(&conn).unwrap();
+
+    let body_hit = db::create_post(&conn, user_id, categories[0].id, "Weird noise", "Could it be the alternator bearing?").unwrap();
+    let title_hit = db::create_post(&conn, user_id, categories[0].id, "Alternator whine at idle", "High pitched noise").unwrap();
+
+    let results = db::search_posts(&conn, "alternator", None, 10).unwrap();
+    let ids: Vec<i64> = results.iter().map(|p| p.id).collect();
+    assert_eq!(ids, vec![title_hit, body_hit]);
+}
+
+#[test]
+fn test_search_index_follows_edits_and_removals() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
+    let categories = db::get_categories(&conn).unwrap();
+    let post_id = db::create_post(&conn, user_id, categories[0].id, "Thermostat stuck", "Runs cold").unwrap();
+    let comment_id = db::create_comment(&conn, post_id, user_id, None, "Check the radiator cap").unwrap();
+
+    db::update_post(&conn, post_id, user_id, "Water pump leaking", "Runs cold").unwrap();
+    assert!(db::search_posts(&conn, "thermostat", None, 10).unwrap().is_empty());
+    assert_eq!(db::search_posts(&conn, "pump", None, 10).unwrap().len(), 1);
+
+    db::remove_comment(&conn, comment_id).unwrap();
+    assert!(db::search_posts(&conn, "radiator", None, 10).unwrap().is_empty());
+
+    db::remove_post(&conn, post_id).unwrap();
+    assert!(db::search_posts(&conn, "pump", None, 10).unwrap().is_empty());
+
+    db::restore_post(&conn, post_id).unwrap();
+    assert_eq!(db::search_posts(&conn, "pump", None, 10).unwrap().len(), 1);
+}
+
+#[test]
+fn test_search_snippet_highlights_escaped_text() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
+    let categories = db::get_categories(&conn).unwrap();
+    db::create_post(&conn, user_id, categories[0].id, "Sensor question", "<b>O2</b> sensor reads lean").unwrap();
+
+    let results = db::search_posts(&conn, "lean", None, 10).unwrap();
+    let snippet = results[0].snippet.as_deref().unwrap();
+    assert!(snippet.contains("<mark>lean</mark>"));
+    assert!(snippet.contains("&lt;b&gt;O2&lt;/b&gt;"));
+
+    let global = db::global_search(&conn, "lean", 10).unwrap();
+    assert!(global[0].snippet.contains("<mark>lean</mark>"));
+}
+
+#[test]
+fn test_search_snippet_ignores_typed_markers() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
+    let categories = db::get_categories(&conn).unwrap();
+    let post_id = db::create_post(&conn, user_id, categories[0].id, "Odd \u{E001}title", "Idle \u{E000}surge after warmup").unwrap();
+    db::create_comment(&conn, post_id, user_id, None, "Check the \u{E001}IAC valve").unwrap();
+
+    for query in ["surge", "valve"] {
+        let results = db::search_posts(&conn, query, None, 10).unwrap();
+        let snippet = results[0].snippet.as_deref().unwrap();
+        assert_eq!(snippet.matches("<mark>").count(), snippet.matches("</mark>").count(), "{}", snippet);
+        assert!(!snippet.contains('\u{E000}') && !snippet.contains('\u{E001}'));
+    }
+}
+
+#[test]
+fn test_search_sort_and_time_window() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
+    let other_id = db::create_user(&conn, "other@example.com", "hash", "other").unwrap();
+    let categories = db::get_categories(&conn).unwrap();
+
+    let old = db::create_post(&conn, user_id, categories[0].id, "Clutch slipping", "Old clutch thread").unwrap();
+    let popular = db::create_post(&conn, user_id, categories[0].id, "Clutch judder", "Clutch chatter").unwrap();
+    let discussed = db::create_post(&conn, user_id, categories[0].id, "Clutch pedal soft", "Clutch bleeding").unwrap();
+    conn.execute("UPDATE posts SET created_at = datetime('now', '-40 days') WHERE id = ?1", [old]).unwrap();
+    db::vote_post(&conn, other_id, popular, 1).unwrap();
+    db::create_comment(&conn, discussed, other_id, None, "Try a pressure bleeder").unwrap();
+
+    let ids = |sort: &str, time: &str| -> Vec<i64> {
+        db::search_posts_sorted(&conn, "clutch", None, sort, time, 10).unwrap().iter().map(|p| p.id).collect()
+    };
+
+    assert_eq!(ids("new", "all"), vec![discussed, popular, old]);
+    assert_eq!(ids("top", "all")[0], popular);
+    assert_eq!(ids("comments", "all")[0], discussed);
+    assert_eq!(ids("new", "month"), vec![discussed, popular]);
+    assert_eq!(ids("new", "year").len(), 3);
+
+    let global = db::global_search_sorted(&conn, "clutch", "new", "week", 10).unwrap();
+    assert_eq!(global.len(), 2);
+    assert_eq!(global[0].id, discussed);
+}
+
+#[test]
+fn test_search_advanced_syntax() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let dave = db::create_user(&conn, "dave@example.com", "hash", "dave").unwrap();
+    let sam = db::create_user(&conn, "sam@example.com", "hash", "sam").unwrap();
+    let categories = db::get_categories(&conn).unwrap();
+    let engine = categories.iter().find(|c| c.slug == "engine").unwrap().id;
+    let other_category = categories.iter().find(|c| c.slug != "engine").unwrap().id;
+    let tags = db::get_all_tags(&conn).unwrap();
+
+    let solved = db::create_post_with_tags(&conn, dave, engine, "Coil pack failure", "Misfire on cylinder 3", &[tags[0].id], None).unwrap();
+    let answer = db::create_comment(&conn, solved, sam, None, "Swap the coil pack").unwrap();
+    db::set_best_answer(&conn, solved, Some(answer)).unwrap();
+    let plugs = db::create_post(&conn, sam, engine, "Misfire after plugs", "Fouled spark plugs cause misfire").unwrap();
+    let elsewhere = db::create_post(&conn, sam, other_category, "Pack of misfire questions", "Misfire pack").unwrap();
+
+    let ids = |q: &str| -> Vec<i64> {
+        let mut ids: Vec<i64> = db::search_posts(&conn, q, None, 10).unwrap().iter().map(|p| p.id).collect();
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(ids("misfire author:dave"), vec![solved]);
+    assert_eq!(ids("author:SAM"), vec![plugs, elsewhere]);
+    assert_eq!(ids(&format!("misfire tag:\"{}\"", tags[0].name)), vec![solved]);
+    assert_eq!(ids("misfire category:engine"), vec![solved, plugs]);
+    assert_eq!(ids("misfire is:solved"), vec![solved]);
+    assert_eq!(ids("misfire -plugs"), vec![solved, elsewhere]);
+    assert_eq!(ids("\"coil pack\""), vec![solved]);
+    assert_eq!(ids("\"pack coil\""), Vec::<i64>::new());
+    assert!(ids("-misfire").is_empty());
+
+    // Post-only operators never match stores
+    db::create_store(&conn, "Misfire Parts", "https://example.com", None, "Parts", dave).unwrap();
+    assert_eq!(db::search_stores(&conn, "misfire").unwrap().len(), 1);
+    assert!(db::search_stores(&conn, "misfire author:dave").unwrap().is_empty());
+}
+
+// ============ Vehicle Tests ============
+
+fn vehicle(year: i64, make: &str, model: &str) -> VehicleDetails {
+    VehicleDetails { year, make: make.to_string(), model: model.to_string(), ..Default::default() }
+}
+
+#[test]
+fn test_garage_add_and_remove() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "garage@example.com", "hash", "garage").unwrap();
+    let category_id = db::get_categories(&conn).unwrap()[0].id;
+
+    let civic = db::create_vehicle(&conn, user_id, &vehicle(2008, "Honda", "Civic"), true).unwrap();
+    let details = VehicleDetails { engine_code: Some("LS3".to_string()), mileage: Some(42_000), ..vehicle(2012, "Chevrolet", "Camaro") };
+    let camaro = db::create_vehicle(&conn, user_id, &details, true).unwrap();
+    db::create_vehicle(&conn, user_id, &vehicle(1999, "Ford", "Ranger"), false).unwrap();
+
+    let garage = db::get_user_garage(&conn, user_id).unwrap();
+    assert_eq!(garage.iter().map(|v| v.id).collect::<Vec<_>>(), vec![camaro, civic]);
+    assert_eq!(garage[0].engine_code.as_deref(), Some("LS3"));
+    assert_eq!(garage[0].mileage, Some(42_000));
+
+    // A vehicle referenced by a post leaves the garage but keeps its row
+    let post_id = db::create_post_with_tags(&conn, user_id, category_id, "Camaro idle", "Rough idle", &[], Some(camaro)).unwrap();
+    db::remove_vehicle_from_garage(&conn, camaro).unwrap();
+    db::remove_vehicle_from_garage(&conn, civic).unwrap();
+
+    assert!(db::get_user_garage(&conn, user_id).unwrap().is_empty());
+    assert!(db::get_vehicle_by_id(&conn, civic).unwrap().is_none());
+    let post = db::get_post_by_id(&conn, post_id).unwrap().unwrap();
+    assert_eq!(post.vehicle.unwrap().model, "Camaro");
+}
+
+#[test]
+fn test_posts_filtered_by_vehicle() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "filter@example.com", "hash", "filter").unwrap();
+    let category_id = db::get_categories(&conn).unwrap()[0].id;
+
+    let post_for = |details: Option<VehicleDetails>| {
+        let vehicle_id = details.map(|d| db::create_vehicle(&conn, user_id, &d, false).unwrap());
+        db::create_post_with_tags(&conn, user_id, category_id, "Post", "Body", &[], vehicle_id).unwrap()
+    };
+    let civic_08 = post_for(Some(vehicle(2008, "Honda", "Civic")));
+    let civic_15 = post_for(Some(vehicle(2015, "Honda", "Civic")));
+    let accord = post_for(Some(vehicle(2010, "Honda", "Accord")));
+    let f150 = post_for(Some(vehicle(2010, "Ford", "F-150")));
+    let no_vehicle = post_for(None);
+
+    let ids = |filter: VehicleFilter| -> Vec<i64> {
+        let (posts, _) = db::get_posts_paginated_filtered(&conn, None, &filter, "new", 1, 25).unwrap();
+        let mut ids: Vec<i64> = posts.iter().map(|p| p.id).collect();
+        ids.sort();
+        ids
+    };
+
+    assert_eq!(ids(VehicleFilter::default()), vec![civic_08, civic_15, accord, f150, no_vehicle]);
+    assert_eq!(ids(VehicleFilter { make: Some("honda".to_string()), ..Default::default() }), vec![civic_08, civic_15, accord]);
+    assert_eq!(
+        ids(VehicleFilter { make: Some("Honda".to_string()), model: Some("Civic".to_string()), ..Default::default() }),
+        vec![civic_08, civic_15]
+    );
+    assert_eq!(ids(VehicleFilter { year_min: Some(2009), year_max: Some(2012), ..Default::default() }), vec![accord, f150]);
+
+    let (posts, pagination) = db::get_posts_paginated_filtered(
+        &conn, None, &VehicleFilter { make: Some("Ford".to_string()), ..Default::default() }, "new", 1, 25,
+    ).unwrap();
+    assert_eq!(pagination.total_items, 1);
+    assert_eq!(posts[0].vehicle.as_ref().unwrap().make, "Ford");
+
+    // Search results carry the vehicle like the listings do
+    let results = db::search_posts(&conn, "author:filter", None, 10).unwrap();
+    let f150_result = results.iter().find(|p| p.id == f150).unwrap();
+    assert_eq!(f150_result.vehicle.as_ref().unwrap().model, "F-150");
+    assert!(results.iter().find(|p| p.id == no_vehicle).unwrap().vehicle.is_none());
+
+    assert_eq!(db::get_vehicle_makes(&conn).unwrap(), vec!["Ford".to_string(), "Honda".to_string()]);
+}
+
+// ============ Procedure Tests ============
+
+fn brake_job() -> ProcedureDetails {
+    ProcedureDetails {
+        difficulty: "intermediate".to_string(),
+        labor_minutes: Some(90),
+        steps: vec![
+            ProcedureStep { body: "Loosen the lugs and raise the car".to_string(), ..Default::default() },
+            ProcedureStep { body: "Remove the **caliper**".to_string(), ..Default::default() },
+        ],
+        tools: vec!["18mm socket".to_string(), "C-clamp".to_string()],
+        parts: vec![ProcedurePart { name: "Pads".to_string(), part_number: Some("D1234".to_string()), quantity: 1 }],
+        torque_specs: vec![ProcedureTorqueSpec { fastener: "Caliper bracket".to_string(), value: 80.0, unit: "ft-lb".to_string() }],
+    }
+}
+
+#[test]
+fn test_procedure_round_trip() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "tech@example.com", "hash", "tech").unwrap();
+    let category_id = db::get_categories(&conn).unwrap()[0].id;
+    let upload_id = db::create_upload(&conn, user_id, "a.png", "a.png", "static/uploads/a.png", "image/png", 10).unwrap();
+
+    let mut details = brake_job();
+    details.steps[0].upload_id = Some(upload_id);
+    let post_id = db::create_post_with_tags(&conn, user_id, category_id, "Front brakes", "Pads and rotors", &[], None).unwrap();
+    db::add_procedure(&conn, post_id, &details).unwrap();
+
+    let post = db::get_post_by_id(&conn, post_id).unwrap().unwrap();
+    let procedure = post.procedure.unwrap();
+    assert_eq!(procedure.labor_minutes, Some(90));
+    assert_eq!(procedure.steps.len(), 2);
+    assert_eq!(procedure.steps[0].image_url.as_deref(), Some("/static/uploads/a.png"));
+    assert!(procedure.steps[1].body_html.as_deref().unwrap().contains("<strong>caliper</strong>"));
+    assert_eq!(procedure.tools, vec!["18mm socket", "C-clamp"]);
+    assert_eq!(procedure.parts, details.parts);
+    assert_eq!(procedure.torque_specs, details.torque_specs);
+
+    // Updating replaces every list in order
+    let mut updated = brake_job();
+    updated.difficulty = "advanced".to_string();
+    updated.steps.reverse();
+    updated.tools.clear();
+    db::update_procedure(&conn, post_id, &updated).unwrap();
+    let procedure = db::get_procedure(&conn, post_id).unwrap().unwrap();
+    assert_eq!(procedure.difficulty, "advanced");
+    assert_eq!(procedure.steps[0].body, "Remove the **caliper**");
+    assert!(procedure.tools.is_empty());
+}
+
+#[test]
+fn test_procedures_marked_in_listings() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "tech@example.com", "hash", "tech").unwrap();
+    let category_id = db::get_categories(&conn).unwrap()[0].id;
+    let discussion = db::create_post(&conn, user_id, category_id, "Squeaky brakes", "Any ideas?").unwrap();
+    let procedure = db::create_post(&conn, user_id, category_id, "Front brakes", "Pads and rotors").unwrap();
+    db::add_procedure(&conn, procedure, &brake_job()).unwrap();
+
+    assert!(db::get_procedure(&conn, discussion).unwrap().is_none());
+
+    let posts = db::get_posts(&conn, None, "new", 10, 0).unwrap();
+    let listed = posts.iter().find(|p| p.id == procedure).unwrap().procedure.as_ref().unwrap();
+    assert_eq!(listed.difficulty, "intermediate");
+    assert!(listed.steps.is_empty(), "listings only load the summary");
+    assert!(posts.iter().find(|p| p.id == discussion).unwrap().procedure.is_none());
+}
+
+// ============ DTC Tests ============
+
+#[test]
+fn test_dtc_lookup() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let misfire = db::get_dtc_code(&conn, "P0300").unwrap();
+    assert_eq!(misfire.description.as_deref(), Some("Random/Multiple Cylinder Misfire Detected"));
+    assert_eq!(misfire.system, "Powertrain");
+    assert!(misfire.generic);
+
+    // Manufacturer-specific codes aren't seeded but still resolve
+    let custom = db::get_dtc_code(&conn, "P1456").unwrap();
+    assert!(custom.description.is_none());
+    assert!(!custom.generic);
+
+    assert!(db::is_dtc_code("U0100"));
+    assert!(!db::is_dtc_code("P0300X"));
+    assert!(!db::is_dtc_code("p0300"));
+
+    let lean: Vec<String> = db::search_dtc_codes(&conn, "too lean", 10).unwrap().into_iter().map(|c| c.code).collect();
+    assert!(lean.contains(&"P0171".to_string()));
+    assert_eq!(db::search_dtc_codes(&conn, "p030", 50).unwrap().len(), 9);
+}
+
+#[test]
+fn test_posts_mentioning_dtc_solved_first() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "dtc@example.com", "hash", "dtc").unwrap();
+    let other_id = db::create_user(&conn, "other@example.com", "hash", "other").unwrap();
+    let category_id = db::get_categories(&conn).unwrap()[0].id;
+
+    let open = db::create_post(&conn, user_id, category_id, "New cat, light still on", "Threw P0420 again").unwrap();
+    let solved = db::create_post(&conn, user_id, category_id, "Check engine light", "Codes came back").unwrap();
+    let answer = db::create_comment(&conn, solved, other_id, None, "P0420 on that engine is usually the rear O2 sensor").unwrap();
+    db::set_best_answer(&conn, solved, Some(answer)).unwrap();
+    db::create_post(&conn, user_id, category_id, "P0430 only", "Bank 2").unwrap();
+
+    let posts = db::get_posts_mentioning_dtc(&conn, "P0420", 10).unwrap();
+    assert_eq!(posts.iter().map(|p| p.id).collect::<Vec<_>>(), vec![solved, open]);
+
+    let post = db::get_post_by_id(&conn, open).unwrap().unwrap();
+    assert!(post.body_html.unwrap().contains(r#"<a href="/dtc/P0420" class="dtc-link" rel="noopener noreferrer">P0420</a>"#));
+}
+
+#[test]
+fn test_dtc_cause_suggestions_reviewed() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let mechanic = db::create_user(&conn, "mech@example.com", "hash", "mech").unwrap();
+    let moderator = db::create_user(&conn, "mod@example.com", "hash", "moderator").unwrap();
+
+    let add = db::create_dtc_suggestion(&conn, "P0171", mechanic, "add", "Vacuum leak at the intake gasket", None).unwrap();
+    let rejected = db::create_dtc_suggestion(&conn, "P0171", mechanic, "add", "Bad vibes", None).unwrap();
+    assert_eq!(db::count_pending_dtc_suggestions(&conn, "P0171").unwrap(), 2);
+    assert!(db::get_dtc_causes(&conn, "P0171").unwrap().is_empty());
+
+    db::review_dtc_suggestion(&conn, add, moderator, true).unwrap().unwrap();
+    db::review_dtc_suggestion(&conn, rejected, moderator, false).unwrap().unwrap();
+    let causes = db::get_dtc_causes(&conn, "P0171").unwrap();
+    assert_eq!(causes.iter().map(|c| c.cause.as_str()).collect::<Vec<_>>(), vec!["Vacuum leak at the intake gasket"]);
+    assert!(db::get_pending_dtc_suggestions(&conn).unwrap().is_empty());
+
+    // Already-reviewed suggestions can't be applied twice
+    assert!(db::review_dtc_suggestion(&conn, add, moderator, true).unwrap().is_none());
+
+    let remove = db::create_dtc_suggestion(&conn, "P0171", mechanic, "remove", &causes[0].cause, Some(causes[0].id)).unwrap();
+    let pending = db::get_pending_dtc_suggestions(&conn).unwrap();
+    assert_eq!(pending[0].username.as_deref(), Some("mech"));
+    db::review_dtc_suggestion(&conn, remove, moderator, true).unwrap().unwrap();
+    assert!(db::get_dtc_causes(&conn, "P0171").unwrap().is_empty());
+}
+
+// ============ Torque Spec Tests ============
+
+fn head_bolt_spec() -> TorqueSpecDetails {
+    TorqueSpecDetails {
+        make: "Honda".to_string(),
+        model: "Civic".to_string(),
+        year_from: 2006,
+        year_to: 2011,
+        engine_code: Some("R18A1".to_string()),
+        fastener: "Cylinder head bolts".to_string(),
+        value: 29.0,
+        unit: "Nm".to_string(),
+        sequence: Some("Spiral out from the center, two stages".to_string()),
+        angle_degrees: Some(90),
+        torque_to_yield: true,
+        source: Some("FSM 6-14".to_string()),
+    }
+}
+
+#[test]
+fn test_torque_spec_lookup_by_vehicle() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let user_id = db::create_user(&conn, "torque@example.com", "hash", "torque").unwrap();
+    let spec_id = db::create_torque_spec(&conn, &head_bolt_spec(), user_id).unwrap();
+    db::create_torque_spec(&conn, &TorqueSpecDetails {
+        model: "Accord".to_string(),
+        fastener: "Lug nuts".to_string(),
+        value: 80.0,
+        unit: "ft-lb".to_string(),
+        ..head_bolt_spec()
+    }, user_id).unwrap();
+
+    let civic = |year: Option<i64>| TorqueSpecFilter {
+        make: Some("honda".to_string()),
+        model: Some("CIVIC".to_string()),
+        year,
+        fastener: None,
+    };
+    let specs = db::get_torque_specs(&conn, &civic(Some(2009)), 50).unwrap();
+    assert_eq!(specs.len(), 1);
+    assert_eq!(specs[0].id, spec_id);
+    assert!(specs[0].torque_to_yield);
+    assert_eq!(specs[0].angle_degrees, Some(90));
+    assert_eq!(specs[0].submitter_name.as_deref(), Some("torque"));
+    assert_eq!(specs[0].values.as_ref().unwrap().ft_lb, 21.4);
+
+    // Outside the year range
+    assert!(db::get_torque_specs(&conn, &civic(Some(2012)), 50).unwrap().is_empty());
+
+    let lugs = TorqueSpecFilter { fastener: Some("lug".to_string()), ..Default::default() };
+    assert_eq!(db::get_torque_specs(&conn, &lugs, 50).unwrap()[0].model, "Accord");
+    assert_eq!(db::get_torque_spec_makes(&conn).unwrap(), vec!["Honda".to_string()]);
+}
+
+#[test]
+fn test_torque_spec_confirm_and_dispute() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+
+    let author = db::create_user(&conn, "author@example.com", "hash", "author").unwrap();
+    let user1 = db::create_user(&conn, "user1@example.com", "hash", "user1").unwrap();
+    let user2 = db::create_user(&conn, "user2@example.com", "hash", "user2").unwrap();
+    let spec_id = db::create_torque_spec(&conn, &head_bolt_spec(), author).unwrap();
+
+    db::vote_torque_spec(&conn, spec_id, user1, true).unwrap();
+    db::vote_torque_spec(&conn, spec_id, user2, false).unwrap();
+    let spec = db::get_torque_spec(&conn, spec_id).unwrap().unwrap();
+    assert_eq!((spec.confirmations, spec.disputes), (1, 1));
+
+    // Changing a vote replaces it
+    db::vote_torque_spec(&conn, spec_id, user2, true).unwrap();
+    let spec = db::get_torque_spec(&conn, spec_id).unwrap().unwrap();
+    assert_eq!((spec.confirmations, spec.disputes), (2, 0));
+    assert_eq!(db::get_user_torque_spec_vote(&conn, spec_id, user2).unwrap(), Some(true));
+    assert_eq!(db::get_user_torque_spec_vote(&conn, spec_id, author).unwrap(), None);
+    assert!(db::get_torque_spec(&conn, spec_id + 1).unwrap().is_none());
+}
+
 // ============ Stats Tests ============
 
 #[test]
 fn test_forum_stats() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "test@example.com", "hash", "testuser").unwrap();
     let categories = db::get_categories(&conn).unwrap();
@@ -0,0 +1,187 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request, StatusCode},
+    middleware,
+    response::Response,
+    routing::{get, post},
+    Router,
+};
+use std::sync::Arc;
+use tera::Tera;
+use tower::ServiceExt;
+use wrench_forum::error::{self, AppError};
+use wrench_forum::{db, routes, torque};
+
+mod common;
+use common::setup_test_db;
+
+fn app(db: db::Db) -> Router {
+    let mut tera = Tera::new("templates/**/*.html").unwrap();
+    tera.register_filter("torque_alternate", torque::tera_filter);
+    let state = (db, Arc::new(tera));
+    Router::new()
+        .route("/post/{id}", get(routes::forum::view_post))
+        .route("/post/{id}/delete", post(routes::forum::delete_post))
+        .route("/notifications/{id}/read", post(routes::notifications::mark_read))
+        .route("/admin/verify/{id}/approve", post(routes::admin::approve_verification))
+        .layer(middleware::from_fn_with_state(state.clone(), error::render_errors))
+        .with_state(state)
+}
+
+fn request(method: &str, uri: &str, session: Option<&str>, headers: &[&str]) -> Request<Body> {
+    let mut request = Request::builder().method(method).uri(uri);
+    if let Some(token) = session {
+        request = request.header(header::COOKIE, format!("session={}", token));
+    }
+    for name in headers {
+        request = request.header(*name, "true");
+    }
+    request.body(Body::empty()).unwrap()
+}
+
+async fn body_text(response: Response) -> String {
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+/// A signed-in user with the given role, returning their id
+fn sign_in(db: &db::Db, username: &str, role: &str, token: &str) -> i64 {
+    let conn = db.write_conn();
+    let user_id = db::create_user(&conn, &format!("{}@example.com", username), "hash", username).unwrap();
+    db::update_user_role(&conn, user_id, role).unwrap();
+    db::create_session(&conn, token, user_id, "2099-01-01 00:00:00", None, None).unwrap();
+    user_id
+}
+
+#[test]
+fn test_status_codes() {
+    assert_eq!(AppError::Unauthorized.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(AppError::forbidden("no").status(), StatusCode::FORBIDDEN);
+    assert_eq!(AppError::not_found("gone").status(), StatusCode::NOT_FOUND);
+    assert_eq!(AppError::conflict("taken").status(), StatusCode::CONFLICT);
+    assert_eq!(AppError::validation("bad").status(), StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(AppError::Internal.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+    // Internal details stay out of the message
+    let from_db = AppError::from(db::DbError::TaskFailed);
+    assert_eq!(from_db.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    assert!(!from_db.message().contains("task"));
+    let missing_row = AppError::from(db::DbError::Sqlite(rusqlite::Error::QueryReturnedNoRows));
+    assert_eq!(missing_row.status(), StatusCode::NOT_FOUND);
+}
+
+#[test]
+fn test_fragment_request_detection() {
+    let headers = |names: &[&'static str]| {
+        let mut map = axum::http::HeaderMap::new();
+        for name in names {
+            map.insert(*name, "true".parse().unwrap());
+        }
+        map
+    };
+    assert!(!error::is_fragment_request(&headers(&[])));
+    assert!(error::is_fragment_request(&headers(&["hx-request"])));
+    // Boosted links replace the whole page
+    assert!(!error::is_fragment_request(&headers(&["hx-request", "hx-boosted"])));
+}
+
+#[test]
+fn test_toast_escapes_its_message() {
+    let html = error::toast("success", "Sent to <script>alert(1)</script>");
+    assert!(html.contains(r#"<div class="toast success">Sent to &lt;script&gt;"#));
+    assert!(!html.contains("<script>"));
+}
+
+#[tokio::test]
+async fn test_missing_post_is_404_page() {
+    let db = setup_test_db();
+
+    for headers in [&[][..], &["hx-request", "hx-boosted"][..]] {
+        let response = app(db.clone()).oneshot(request("GET", "/post/999", None, headers)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert!(response.headers().get("hx-reswap").is_none());
+        let body = body_text(response).await;
+        assert!(body.contains("<html"), "{}", body);
+        assert!(body.contains("Post not found"));
+    }
+}
+
+#[tokio::test]
+async fn test_fragment_errors_are_toasts() {
+    let db = setup_test_db();
+
+    let response = app(db.clone()).oneshot(request("GET", "/post/999", None, &["hx-request"])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    assert_eq!(response.headers().get("hx-reswap").unwrap(), "none");
+    let body = body_text(response).await;
+    assert!(!body.contains("<html"));
+    assert!(body.contains(r#"hx-swap-oob="beforeend""#));
+    assert!(body.contains("toast error"));
+    assert!(body.contains("Post not found"));
+}
+
+#[tokio::test]
+async fn test_signed_out_goes_to_login() {
+    let db = setup_test_db();
+    let author_id = sign_in(&db, "author", "verified_mechanic", "author_token");
+    let post_id = db::create_post(&db.write_conn(), author_id, 1, "Title", "Body").unwrap();
+
+    let uri = format!("/post/{}/delete", post_id);
+    let response = app(db.clone()).oneshot(request("POST", &uri, None, &[])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/login");
+
+    let response = app(db.clone()).oneshot(request("POST", &uri, None, &["hx-request"])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    assert_eq!(response.headers().get("hx-redirect").unwrap(), "/login");
+    assert!(db::get_post_by_id(&db.write_conn(), post_id).unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_finished_forms_redirect() {
+    let db = setup_test_db();
+    let author_id = sign_in(&db, "author", "verified_mechanic", "author_token");
+    let first = db::create_post(&db.write_conn(), author_id, 1, "First", "Body").unwrap();
+    let second = db::create_post(&db.write_conn(), author_id, 1, "Second", "Body").unwrap();
+
+    let uri = format!("/post/{}/delete", first);
+    let response = app(db.clone()).oneshot(request("POST", &uri, Some("author_token"), &[])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/");
+
+    // htmx would follow a 303 itself and swap the page into the target
+    let uri = format!("/post/{}/delete", second);
+    let response = app(db.clone()).oneshot(request("POST", &uri, Some("author_token"), &["hx-request"])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers().get("hx-redirect").unwrap(), "/");
+    assert!(response.headers().get(header::LOCATION).is_none());
+}
+
+#[tokio::test]
+async fn test_forbidden_and_not_found() {
+    let db = setup_test_db();
+    let author_id = sign_in(&db, "author", "verified_mechanic", "author_token");
+    let other_id = sign_in(&db, "other", "verified_mechanic", "other_token");
+    sign_in(&db, "boss", "admin", "admin_token");
+    let post_id = db::create_post(&db.write_conn(), author_id, 1, "Title", "Body").unwrap();
+
+    // Someone else's post
+    let uri = format!("/post/{}/delete", post_id);
+    let response = app(db.clone()).oneshot(request("POST", &uri, Some("other_token"), &["hx-request"])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    assert!(db::get_post_by_id(&db.write_conn(), post_id).unwrap().is_some());
+
+    // Someone else's notification is as good as missing
+    let notification_id = db::create_notification(&db.write_conn(), author_id, "reply", "Hi", None, None, Some(other_id)).unwrap();
+    let uri = format!("/notifications/{}/read", notification_id);
+    let response = app(db.clone()).oneshot(request("POST", &uri, Some("other_token"), &["hx-request"])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    let response = app(db.clone()).oneshot(request("POST", &uri, Some("author_token"), &["hx-request"])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // Not an admin, then a request that doesn't exist
+    let response = app(db.clone()).oneshot(request("POST", "/admin/verify/999/approve", Some("other_token"), &["hx-request"])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app(db.clone()).oneshot(request("POST", "/admin/verify/999/approve", Some("admin_token"), &["hx-request"])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::NOT_FOUND);
+}
@@ -0,0 +1,151 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request, StatusCode},
+    middleware,
+    response::Response,
+    routing::get,
+    Router,
+};
+use std::sync::Arc;
+use tera::Tera;
+use tower::ServiceExt;
+use wrench_forum::auth::{CurrentUser, MaybeUser, Moderator, PageContext, RequireRole};
+use wrench_forum::{db, error, routes, torque};
+
+mod common;
+use common::setup_test_db;
+
+async fn whoami(MaybeUser(user): MaybeUser) -> String {
+    user.map(|u| u.username).unwrap_or_default()
+}
+
+async fn me(CurrentUser(user): CurrentUser) -> String {
+    user.username
+}
+
+async fn moderator_only(RequireRole(user, _): RequireRole<Moderator>) -> String {
+    user.username
+}
+
+async fn page_context(PageContext(ctx): PageContext) -> String {
+    ctx.into_json().to_string()
+}
+
+fn app(db: db::Db) -> Router {
+    let mut tera = Tera::new("templates/**/*.html").unwrap();
+    tera.register_filter("torque_alternate", torque::tera_filter);
+    let state = (db, Arc::new(tera));
+    Router::new()
+        .route("/whoami", get(whoami))
+        .route("/me", get(me))
+        .route("/moderator", get(moderator_only))
+        .route("/context", get(page_context))
+        .route("/notifications", get(routes::notifications::list_notifications))
+        .layer(middleware::from_fn_with_state(state.clone(), error::render_errors))
+        .with_state(state)
+}
+
+fn request(uri: &str, session: Option<&str>, headers: &[&str]) -> Request<Body> {
+    let mut request = Request::builder().uri(uri);
+    if let Some(token) = session {
+        request = request.header(header::COOKIE, format!("session={}", token));
+    }
+    for name in headers {
+        request = request.header(*name, "true");
+    }
+    request.body(Body::empty()).unwrap()
+}
+
+async fn body_text(response: Response) -> String {
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+/// A signed-in user with the given role, returning their id
+fn sign_in(db: &db::Db, username: &str, role: &str, token: &str) -> i64 {
+    let conn = db.write_conn();
+    let user_id = db::create_user(&conn, &format!("{}@example.com", username), "hash", username).unwrap();
+    db::update_user_role(&conn, user_id, role).unwrap();
+    db::create_session(&conn, token, user_id, "2099-01-01 00:00:00", None, None).unwrap();
+    user_id
+}
+
+#[tokio::test]
+async fn test_maybe_and_current_user() {
+    let db = setup_test_db();
+    sign_in(&db, "alice", "unverified", "alice_token");
+
+    let response = app(db.clone()).oneshot(request("/whoami", None, &[])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(body_text(response).await, "");
+    let response = app(db.clone()).oneshot(request("/whoami", Some("alice_token"), &[])).await.unwrap();
+    assert_eq!(body_text(response).await, "alice");
+
+    let response = app(db.clone()).oneshot(request("/me", Some("alice_token"), &[])).await.unwrap();
+    assert_eq!(body_text(response).await, "alice");
+    // An expired or unknown session is the same as none
+    let response = app(db.clone()).oneshot(request("/me", Some("stale_token"), &[])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+}
+
+#[tokio::test]
+async fn test_signed_out_redirects() {
+    let db = setup_test_db();
+
+    let response = app(db.clone()).oneshot(request("/notifications", None, &[])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers().get(header::LOCATION).unwrap(), "/login");
+
+    // htmx swaps a 303's target into the page, so it's told to navigate instead
+    for headers in [&["hx-request"][..], &["hx-request", "hx-boosted"][..]] {
+        let response = app(db.clone()).oneshot(request("/notifications", None, headers)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers().get("hx-redirect").unwrap(), "/login");
+        assert!(response.headers().get(header::LOCATION).is_none());
+    }
+}
+
+#[tokio::test]
+async fn test_require_role() {
+    let db = setup_test_db();
+    sign_in(&db, "member", "unverified", "member_token");
+    sign_in(&db, "mechanic", "verified_mechanic", "mechanic_token");
+    sign_in(&db, "mod", "moderator", "mod_token");
+    sign_in(&db, "boss", "admin", "admin_token");
+
+    let response = app(db.clone()).oneshot(request("/moderator", None, &[])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+
+    for token in ["member_token", "mechanic_token"] {
+        let response = app(db.clone()).oneshot(request("/moderator", Some(token), &["hx-request"])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(body_text(response).await.contains("Moderator access required"));
+    }
+
+    for (token, username) in [("mod_token", "mod"), ("admin_token", "boss")] {
+        let response = app(db.clone()).oneshot(request("/moderator", Some(token), &[])).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body_text(response).await, username);
+    }
+}
+
+#[tokio::test]
+async fn test_page_context() {
+    let db = setup_test_db();
+    let user_id = sign_in(&db, "alice", "unverified", "alice_token");
+    db::create_notification(&db.write_conn(), user_id, "reply", "Hi", None, None, None).unwrap();
+    db::create_notification(&db.write_conn(), user_id, "reply", "Again", None, None, None).unwrap();
+
+    let response = app(db.clone()).oneshot(request("/context", None, &[])).await.unwrap();
+    assert_eq!(body_text(response).await, "{}");
+
+    let response = app(db.clone()).oneshot(request("/context", Some("alice_token"), &[])).await.unwrap();
+    let ctx: serde_json::Value = serde_json::from_str(&body_text(response).await).unwrap();
+    assert_eq!(ctx["user"]["username"], "alice");
+    assert_eq!(ctx["unread_notifications"], 2);
+
+    // A real page gets the same context
+    let response = app(db.clone()).oneshot(request("/notifications", Some("alice_token"), &[])).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body_text(response).await.contains(r#"<span class="notification-badge">2</span>"#));
+}
@@ -0,0 +1,126 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{Request, StatusCode},
+    routing::get,
+    Extension, Router,
+};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempDir;
+use tera::Tera;
+use tower::ServiceExt;
+use wrench_forum::config::Config;
+use wrench_forum::shutdown::Shutdown;
+use wrench_forum::{backup, db, routes};
+
+mod common;
+use common::setup_test_db;
+
+fn app(db: db::Db, tera: Tera, shutdown: Shutdown) -> Router {
+    Router::new()
+        .route("/healthz", get(routes::health::healthz))
+        .route("/readyz", get(routes::health::readyz))
+        .layer(Extension(shutdown))
+        .with_state((db, Arc::new(tera)))
+}
+
+fn templates() -> Tera {
+    Tera::new("templates/**/*.html").unwrap()
+}
+
+async fn get_json(app: Router, uri: &str) -> (StatusCode, serde_json::Value) {
+    let response = app.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+    let status = response.status();
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (status, serde_json::from_slice(&bytes).unwrap_or(serde_json::Value::Null))
+}
+
+#[tokio::test]
+async fn test_ready_when_everything_is_up() {
+    let db = setup_test_db();
+    let response = app(db.clone(), templates(), Shutdown::new())
+        .oneshot(Request::get("/healthz").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+
+    let (status, body) = get_json(app(db.clone(), templates(), Shutdown::new()), "/readyz").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["ready"], true);
+    for check in ["database", "migrations", "templates", "shutdown"] {
+        assert_eq!(body[check], "ok", "{}", check);
+    }
+}
+
+#[tokio::test]
+async fn test_not_ready_while_shutting_down() {
+    let db = setup_test_db();
+    let shutdown = Shutdown::new();
+    shutdown.trigger();
+
+    let (status, body) = get_json(app(db.clone(), templates(), shutdown.clone()), "/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["ready"], false);
+    assert_eq!(body["shutdown"], "shutting down");
+    assert_eq!(body["database"], "ok");
+
+    // Still alive, just draining
+    let response = app(db.clone(), templates(), shutdown)
+        .oneshot(Request::get("/healthz").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+}
+
+#[tokio::test]
+async fn test_not_ready_with_wrong_schema_or_templates() {
+    let db = setup_test_db();
+    db.write_conn()
+        .execute("INSERT INTO schema_version (version, name) VALUES (?1, 'from the future')", [db::latest_schema_version() + 1])
+        .unwrap();
+    let (status, body) = get_json(app(db.clone(), templates(), Shutdown::new()), "/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body["migrations"].as_str().unwrap().contains("newer than this build"));
+
+    let db = setup_test_db();
+    let (status, body) = get_json(app(db.clone(), Tera::default(), Shutdown::new()), "/readyz").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert!(body["templates"].as_str().unwrap().contains("error.html"));
+}
+
+#[tokio::test]
+async fn test_shutdown_wakes_waiters() {
+    let shutdown = Shutdown::new();
+    assert!(!shutdown.is_triggered());
+    let waiter = tokio::spawn({
+        let shutdown = shutdown.clone();
+        async move { shutdown.wait().await }
+    });
+    shutdown.trigger();
+    tokio::time::timeout(Duration::from_secs(1), waiter).await.unwrap().unwrap();
+
+    // Already triggered: returns straight away, and triggering again is fine
+    shutdown.trigger();
+    tokio::time::timeout(Duration::from_secs(1), shutdown.wait()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_backup_schedule_stops_on_shutdown() {
+    let dir = TempDir::new().unwrap();
+    let mut config = Config::default();
+    config.database.path = dir.path().join("forum.db").display().to_string();
+    config.uploads.dir = dir.path().join("uploads").display().to_string();
+    config.backup.dir = dir.path().join("backups").display().to_string();
+    let db = db::Db::open(&config.database.path, &config.database.options()).unwrap();
+
+    // A fresh backup, so the next one is a day away
+    let snapshot = backup::backup_to_dir(&config).unwrap();
+    db::record_backup(&db.write_conn(), "2026-03-01 02:00:00", Ok(&snapshot)).unwrap();
+
+    let shutdown = Shutdown::new();
+    let schedule = tokio::spawn(backup::run_schedule(db, Arc::new(config), shutdown.clone()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!schedule.is_finished());
+    shutdown.trigger();
+    tokio::time::timeout(Duration::from_secs(5), schedule).await.unwrap().unwrap();
+}
@@ -1,19 +1,15 @@
 use wrench_forum::db;
 use wrench_forum::models::*;
-use tempfile::NamedTempFile;
 
-fn setup_test_db() -> db::Db {
-    let temp_file = NamedTempFile::new().unwrap();
-    let path = temp_file.path().to_str().unwrap();
-    db::init_db_with_path(path).expect("Failed to init test db")
-}
+mod common;
+use common::setup_test_db;
 
 // ============ Integration: User Registration Flow ============
 
 #[test]
 fn test_user_registration_flow() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     // 1. Create user
     let password_hash = wrench_forum::auth::hash_password("securepassword123").unwrap();
@@ -22,7 +18,7 @@ fn test_user_registration_flow() {
     // 2. User should be unverified by default
     let user = db::get_user_by_id(&conn, user_id).unwrap().unwrap();
     assert_eq!(user.role, UserRole::Unverified);
-    assert!(!user.role.can_post());
+    assert!(!db::get_user_permissions(&conn, user.id, &user.role).unwrap().has(Permission::Post));
     
     // 3. User profile should be created
     let profile = db::get_user_profile(&conn, user_id).unwrap();
@@ -39,7 +35,7 @@ fn test_user_registration_flow() {
 #[test]
 fn test_verification_flow() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     // 1. Create user
     let user_id = db::create_user(&conn, "mechanic@example.com", "hash", "mechanic").unwrap();
@@ -57,7 +53,7 @@ fn test_verification_flow() {
     // 5. User is now verified
     let user = db::get_user_by_id(&conn, user_id).unwrap().unwrap();
     assert_eq!(user.role, UserRole::VerifiedMechanic);
-    assert!(user.role.can_post());
+    assert!(db::get_user_permissions(&conn, user.id, &user.role).unwrap().has(Permission::Post));
     
     // 6. User should have received notification
     let notifications = db::get_user_notifications(&conn, user_id, 10).unwrap();
@@ -69,7 +65,7 @@ fn test_verification_flow() {
 #[test]
 fn test_post_and_comment_flow() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     // 1. Create verified user
     let user_id = db::create_user(&conn, "poster@example.com", "hash", "poster").unwrap();
@@ -93,12 +89,22 @@ fn test_post_and_comment_flow() {
     assert!(has_reply_notif);
     
     // 6. OP marks as best answer
-    db::set_best_answer(&conn, post_id, Some(comment_id)).unwrap();
+    assert!(db::set_best_answer(&conn, post_id, Some(comment_id)).unwrap());
     
     // 7. Commenter should receive notification
     let commenter_notifs = db::get_user_notifications(&conn, commenter_id, 10).unwrap();
     let has_best_notif = commenter_notifs.iter().any(|n| n.notification_type == NotificationType::BestAnswer);
     assert!(has_best_notif);
+    
+    // 8. A comment from another thread can't be the answer
+    let other_post = db::create_post(&conn, user_id, categories[0].id, "Another question", "Body").unwrap();
+    let stranger_id = db::create_user(&conn, "stranger@example.com", "hash", "stranger").unwrap();
+    let elsewhere = db::create_comment(&conn, other_post, stranger_id, None, "Unrelated").unwrap();
+    assert!(!db::set_best_answer(&conn, post_id, Some(elsewhere)).unwrap());
+    let post = db::get_post_by_id(&conn, post_id).unwrap().unwrap();
+    assert_eq!(post.best_answer_id, Some(comment_id));
+    let stranger_notifs = db::get_user_notifications(&conn, stranger_id, 10).unwrap();
+    assert!(!stranger_notifs.iter().any(|n| n.notification_type == NotificationType::BestAnswer));
 }
 
 // ============ Integration: Voting and Karma Flow ============
@@ -106,7 +112,7 @@ fn test_post_and_comment_flow() {
 #[test]
 fn test_voting_karma_flow() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     // 1. Create poster and voter
     let poster_id = db::create_user(&conn, "poster@example.com", "hash", "poster").unwrap();
@@ -135,7 +141,7 @@ fn test_voting_karma_flow() {
 #[test]
 fn test_store_rating_flow() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     // 1. Create verified users
     let submitter_id = db::create_user(&conn, "submitter@example.com", "hash", "submitter").unwrap();
@@ -172,7 +178,7 @@ fn test_store_rating_flow() {
 #[test]
 fn test_moderation_flow() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     // 1. Create users
     let poster_id = db::create_user(&conn, "poster@example.com", "hash", "poster").unwrap();
@@ -215,7 +221,7 @@ fn test_moderation_flow() {
 #[test]
 fn test_bookmark_flow() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "user@example.com", "hash", "user").unwrap();
     db::update_user_role(&conn, user_id, "verified_mechanic").unwrap();
@@ -245,7 +251,7 @@ fn test_bookmark_flow() {
 #[test]
 fn test_search_flow() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     let user_id = db::create_user(&conn, "user@example.com", "hash", "user").unwrap();
     db::update_user_role(&conn, user_id, "verified_mechanic").unwrap();
@@ -277,7 +283,7 @@ fn test_search_flow() {
 #[test]
 fn test_mention_flow() {
     let db = setup_test_db();
-    let conn = db.lock().unwrap();
+    let conn = db.write_conn();
     
     // Create users
     let poster_id = db::create_user(&conn, "poster@example.com", "hash", "poster").unwrap();
@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use tempfile::NamedTempFile;
+use wrench_forum::mail::{self, Email, FileMailer, Mail, Mailer, SmtpMailer};
+
+#[test]
+fn test_file_mailer_appends_messages() {
+    let file = NamedTempFile::new().unwrap();
+    let mailer = FileMailer::file(file.path());
+
+    for subject in ["First", "Second"] {
+        mailer.send(&Email {
+            to: "mechanic@example.com".to_string(),
+            subject: subject.to_string(),
+            body: "Hello".to_string(),
+        }).unwrap();
+    }
+
+    let written = std::fs::read_to_string(file.path()).unwrap();
+    assert!(written.contains("To: mechanic@example.com\nSubject: First\n\nHello"));
+    assert!(written.contains("Subject: Second"));
+}
+
+#[tokio::test]
+async fn test_mail_links_and_reset_email() {
+    let file = NamedTempFile::new().unwrap();
+    let mail = Mail::new(Arc::new(FileMailer::file(file.path())), "https://wrench.example/");
+    let link = mail.link("/reset-password/abc123");
+    assert_eq!(link, "https://wrench.example/reset-password/abc123");
+
+    mail.send(mail::password_reset_email("bob@example.com", "bob", &link, 60)).await.unwrap();
+
+    let written = std::fs::read_to_string(file.path()).unwrap();
+    assert!(written.contains("To: bob@example.com"));
+    assert!(written.contains("Hi bob,"));
+    assert!(written.contains(&link));
+    assert!(written.contains("60 minutes"));
+}
+
+#[test]
+fn test_smtp_mailer_rejects_bad_addresses() {
+    assert!(SmtpMailer::new("localhost", 25, None, "not an address", false).is_err());
+
+    let smtp = SmtpMailer::new("localhost", 25, None, "Forum <noreply@example.com>", false).unwrap();
+    let result = smtp.send(&Email { to: "nope".to_string(), subject: String::new(), body: String::new() });
+    assert!(matches!(result, Err(mail::MailError::Address(_))));
+}
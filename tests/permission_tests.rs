@@ -0,0 +1,209 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request, StatusCode},
+    middleware,
+    response::Response,
+    routing::{get, post},
+    Router,
+};
+use std::sync::Arc;
+use tera::Tera;
+use tower::ServiceExt;
+use wrench_forum::auth::{Poster, RequireRole, StoreVoter};
+use wrench_forum::models::Permission;
+use wrench_forum::{db, error, routes, torque};
+
+mod common;
+use common::setup_test_db;
+
+async fn poster_only(RequireRole(user, _): RequireRole<Poster>) -> String {
+    user.username
+}
+
+async fn store_voter_only(RequireRole(user, _): RequireRole<StoreVoter>) -> String {
+    user.username
+}
+
+fn app(db: db::Db) -> Router {
+    let mut tera = Tera::new("templates/**/*.html").unwrap();
+    tera.register_filter("torque_alternate", torque::tera_filter);
+    let state = (db, Arc::new(tera));
+    Router::new()
+        .route("/poster", get(poster_only))
+        .route("/store-voter", get(store_voter_only))
+        .route("/post/{id}", get(routes::forum::view_post))
+        .route("/mod", get(routes::moderation::mod_queue))
+        .route("/mod/post/{id}/remove", post(routes::moderation::remove_post))
+        .route("/admin", get(routes::admin::admin_panel))
+        .route("/admin/user/{id}/role", post(routes::admin::update_user_role))
+        .route("/admin/roles", post(routes::admin::create_role))
+        .route("/admin/roles/{name}", post(routes::admin::update_role_permissions))
+        .route("/admin/category-moderators", post(routes::admin::add_category_moderator))
+        .layer(middleware::from_fn_with_state(state.clone(), error::render_errors))
+        .with_state(state)
+}
+
+fn request(method: &str, uri: &str, session: &str, form: Option<&str>) -> Request<Body> {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::COOKIE, format!("session={}", session))
+        .header("hx-request", "true");
+    match form {
+        Some(form) => request
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(form.to_string()))
+            .unwrap(),
+        None => request.body(Body::empty()).unwrap(),
+    }
+}
+
+async fn body_text(response: Response) -> String {
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+/// A signed-in user with the given role, returning their id
+fn sign_in(db: &db::Db, username: &str, role: &str, token: &str) -> i64 {
+    let conn = db.write_conn();
+    let user_id = db::create_user(&conn, &format!("{}@example.com", username), "hash", username).unwrap();
+    db::update_user_role(&conn, user_id, role).unwrap();
+    db::create_session(&conn, token, user_id, "2099-01-01 00:00:00", None, None).unwrap();
+    user_id
+}
+
+#[tokio::test]
+async fn test_custom_role_from_admin_panel() {
+    let db = setup_test_db();
+    sign_in(&db, "boss", "admin", "admin_token");
+    let curator_id = sign_in(&db, "curator", "unverified", "curator_token");
+
+    let form = "name=store_curator&label=Store+Curator&permission=comment&permission=vote_store";
+    let response = app(db.clone()).oneshot(request("POST", "/admin/roles", "admin_token", Some(form))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body_text(response).await.contains("Store Curator"));
+
+    // Names are unique
+    let response = app(db.clone()).oneshot(request("POST", "/admin/roles", "admin_token", Some(form))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    let response = app(db.clone()).oneshot(request("POST", "/admin/roles", "admin_token", Some("name=Bad+Name&label=Bad"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let uri = format!("/admin/user/{}/role", curator_id);
+    let response = app(db.clone()).oneshot(request("POST", &uri, "admin_token", Some("role=store_curator"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app(db.clone()).oneshot(request("POST", &uri, "admin_token", Some("role=no_such_role"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = app(db.clone()).oneshot(request("GET", "/store-voter", "curator_token", None)).await.unwrap();
+    assert_eq!(body_text(response).await, "curator");
+    let response = app(db.clone()).oneshot(request("GET", "/poster", "curator_token", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Takes effect on the next request
+    let response = app(db.clone()).oneshot(request("POST", "/admin/roles/store_curator", "admin_token", Some("permission=post"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app(db.clone()).oneshot(request("GET", "/poster", "curator_token", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app(db.clone()).oneshot(request("GET", "/store-voter", "curator_token", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // Nobody can take permissions away from admins
+    let response = app(db.clone()).oneshot(request("POST", "/admin/roles/admin", "admin_token", Some("permission=post"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn test_role_managers_cannot_escalate() {
+    let db = setup_test_db();
+    {
+        let conn = db.write_conn();
+        db::create_role(&conn, "role_manager", "Role Manager", &[Permission::Comment, Permission::ManageRoles]).unwrap();
+    }
+    sign_in(&db, "manager", "role_manager", "manager_token");
+    let member_id = sign_in(&db, "member", "unverified", "member_token");
+    let mod_id = sign_in(&db, "mod", "moderator", "mod_token");
+
+    // The admin panel only shows what they can manage
+    let response = app(db.clone()).oneshot(request("GET", "/admin", "manager_token", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = body_text(response).await;
+    assert!(page.contains("Roles &amp; Permissions"));
+    assert!(!page.contains("id=\"announcement-list\""));
+    let response = app(db.clone()).oneshot(request("GET", "/admin", "member_token", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let response = app(db.clone()).oneshot(request("POST", "/admin/roles", "manager_token", Some("name=helper&label=Helper&permission=comment"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app(db.clone()).oneshot(request("POST", "/admin/roles", "manager_token", Some("name=banner&label=Banner&permission=ban"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let uri = format!("/admin/user/{}/role", member_id);
+    let response = app(db.clone()).oneshot(request("POST", &uri, "manager_token", Some("role=helper"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = app(db.clone()).oneshot(request("POST", &uri, "manager_token", Some("role=admin"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    // Nor demote someone with permissions they lack
+    let uri = format!("/admin/user/{}/role", mod_id);
+    let response = app(db.clone()).oneshot(request("POST", &uri, "manager_token", Some("role=unverified"))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn test_category_moderator() {
+    let db = setup_test_db();
+    sign_in(&db, "boss", "admin", "admin_token");
+    let expert_id = sign_in(&db, "expert", "verified_mechanic", "expert_token");
+    let (transmission, other) = {
+        let conn = db.write_conn();
+        let categories = db::get_categories(&conn).unwrap();
+        (categories[0].id, categories[1].id)
+    };
+    let (in_category, elsewhere) = {
+        let conn = db.write_conn();
+        let in_category = db::create_post(&conn, expert_id, transmission, "Slipping", "Slips in third").unwrap();
+        let elsewhere = db::create_post(&conn, expert_id, other, "Squeal", "Squeals on startup").unwrap();
+        db::create_comment(&conn, in_category, expert_id, None, "Check the fluid level first").unwrap();
+        db::create_report(&conn, expert_id, Some(in_category), None, "Report in my category").unwrap();
+        db::create_report(&conn, expert_id, Some(elsewhere), None, "Report somewhere else").unwrap();
+        (in_category, elsewhere)
+    };
+
+    let response = app(db.clone()).oneshot(request("GET", "/mod", "expert_token", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    let form = format!("username=expert&category_id={}", transmission);
+    let response = app(db.clone()).oneshot(request("POST", "/admin/category-moderators", "admin_token", Some(&form))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(body_text(response).await.contains("expert"));
+    let response = app(db.clone()).oneshot(request("POST", "/admin/category-moderators", "admin_token", Some(&form))).await.unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    // Only their category's reports are queued for them
+    let response = app(db.clone()).oneshot(request("GET", "/mod", "expert_token", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let page = body_text(response).await;
+    assert!(page.contains("Report in my category"));
+    assert!(!page.contains("Report somewhere else"));
+    assert!(!page.contains("id=\"ban-list\""));
+
+    // Mod actions only show on their category's posts
+    let response = app(db.clone()).oneshot(request("GET", &format!("/post/{}", in_category), "expert_token", None)).await.unwrap();
+    let page = body_text(response).await;
+    assert!(page.contains(&format!("/mod/post/{}/remove", in_category)));
+    assert!(page.contains("/mod/comment/"));
+    let response = app(db.clone()).oneshot(request("GET", &format!("/post/{}", elsewhere), "expert_token", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!body_text(response).await.contains(&format!("/mod/post/{}/remove", elsewhere)));
+    let response = app(db.clone()).oneshot(request("GET", &format!("/post/{}", in_category), "signed_out", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(!body_text(response).await.contains("/mod/comment/"));
+
+    let response = app(db.clone()).oneshot(request("POST", &format!("/mod/post/{}/remove", elsewhere), "expert_token", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let response = app(db.clone()).oneshot(request("POST", &format!("/mod/post/{}/remove", in_category), "expert_token", None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let conn = db.write_conn();
+    assert!(db::get_post_by_id(&conn, in_category).unwrap().unwrap().removed);
+    assert!(!db::get_post_by_id(&conn, elsewhere).unwrap().unwrap().removed);
+}
@@ -0,0 +1,228 @@
+use axum::{
+    body::Body,
+    http::{header, Request, StatusCode},
+    routing::post,
+    Extension, Router,
+};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::sync::{Arc, Mutex};
+use tera::Tera;
+use tower::ServiceExt;
+use wrench_forum::mail::{Email, Mail, MailError, Mailer};
+use wrench_forum::rate_limit::{self, LoginCheck, RateLimits};
+use wrench_forum::shutdown::Shutdown;
+use wrench_forum::{db, routes};
+
+mod common;
+use common::setup_test_db;
+
+fn noon() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap()
+}
+
+#[test]
+fn test_delay_escalates_and_caps() {
+    let limits = RateLimits::default();
+    assert_eq!(limits.delay_after(0), 0);
+    assert_eq!(limits.delay_after(2), 0);
+    assert_eq!(limits.delay_after(3), 2);
+    assert_eq!(limits.delay_after(4), 4);
+    assert_eq!(limits.delay_after(5), 8);
+    assert_eq!(limits.delay_after(9), 60);
+    assert_eq!(limits.delay_after(1000), 60);
+}
+
+#[test]
+fn test_describe_wait() {
+    assert_eq!(rate_limit::describe_wait(1), "1 second");
+    assert_eq!(rate_limit::describe_wait(45), "45 seconds");
+    assert_eq!(rate_limit::describe_wait(60), "1 minute");
+    assert_eq!(rate_limit::describe_wait(61), "2 minutes");
+}
+
+#[test]
+fn test_failed_logins_delay_then_lock_account() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    let limits = RateLimits { lockout_threshold: 5, ..RateLimits::default() };
+
+    let user_id = db::create_user(&conn, "mech@example.com", "hash", "mech").unwrap();
+    let account = rate_limit::normalize_account(" Mech@Example.com ");
+    let check = |now| rate_limit::check_login(&conn, &limits, "10.0.0.1", &account, Some(user_id), now).unwrap();
+
+    let mut now = noon();
+    for _ in 0..3 {
+        assert_eq!(check(now), LoginCheck::Allowed);
+        assert!(!rate_limit::record_login_failure(&conn, &limits, "10.0.0.1", &account, Some(user_id), now).unwrap());
+    }
+    // The third failure starts the delays
+    assert_eq!(check(now + Duration::seconds(1)), LoginCheck::Delayed(1));
+    now += Duration::seconds(2);
+    assert_eq!(check(now), LoginCheck::Allowed);
+    assert!(!rate_limit::record_login_failure(&conn, &limits, "10.0.0.1", &account, Some(user_id), now).unwrap());
+    assert_eq!(check(now), LoginCheck::Delayed(4));
+
+    now += Duration::seconds(4);
+    assert!(rate_limit::record_login_failure(&conn, &limits, "10.0.0.1", &account, Some(user_id), now).unwrap());
+    let until = "2026-01-01 12:15:06".to_string();
+    assert_eq!(check(now), LoginCheck::Locked(until.clone()));
+
+    let locked = db::get_locked_accounts(&conn, "2026-01-01 12:10:00").unwrap();
+    assert_eq!(locked.len(), 1);
+    assert_eq!(locked[0].username, "mech");
+    assert_eq!(locked[0].recent_failures, 5);
+    assert_eq!(locked[0].last_ip_address.as_deref(), Some("10.0.0.1"));
+
+    let failures = db::get_recent_activity(&conn, 10).unwrap();
+    assert_eq!(failures.iter().filter(|a| a.action == "login_failed").count(), 5);
+    assert!(failures.iter().all(|a| a.ip_address.as_deref() == Some("10.0.0.1")));
+    assert!(failures.iter().any(|a| a.action == "account_locked" && a.details.as_deref() == Some(until.as_str())));
+
+    // An admin unlock clears the lock and the failures behind it
+    db::unlock_user(&conn, user_id).unwrap();
+    assert!(db::get_locked_accounts(&conn, "2026-01-01 12:10:00").unwrap().is_empty());
+    assert_eq!(check(now), LoginCheck::Allowed);
+}
+
+#[test]
+fn test_started_logins_count_before_the_password_is_checked() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    let limits = RateLimits { lockout_threshold: 5, ..RateLimits::default() };
+    let user_id = db::create_user(&conn, "mech@example.com", "hash", "mech").unwrap();
+    let start = |now| rate_limit::start_login(&conn, &limits, "10.0.0.1", "mech@example.com", Some(user_id), now).unwrap();
+
+    // Guesses in flight together: only the free ones get through
+    let now = noon();
+    let started: Vec<_> = (0..10).map(|_| start(now)).collect();
+    assert!(started[..3].iter().all(Result::is_ok));
+    assert!(started[3..].iter().all(|s| matches!(s, Err(LoginCheck::Delayed(_)))));
+
+    // A right password takes its attempt back; wrong ones stay counted
+    rate_limit::withdraw_login_attempt(&conn, *started[0].as_ref().unwrap()).unwrap();
+    assert!(!rate_limit::finish_failed_login(&conn, &limits, "10.0.0.1", "mech@example.com", Some(user_id), now).unwrap());
+    let (failures, _) = db::get_account_login_failures(&conn, "mech@example.com", "2026-01-01 11:45:00").unwrap();
+    assert_eq!(failures, 2);
+    assert!(start(now).is_ok());
+}
+
+#[test]
+fn test_successful_login_resets_account_failures() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    let limits = RateLimits::default();
+
+    let now = noon();
+    for _ in 0..3 {
+        rate_limit::record_login_failure(&conn, &limits, "10.0.0.1", "a@example.com", None, now).unwrap();
+    }
+    let later = now + Duration::seconds(5);
+    rate_limit::record_login_success(&conn, "10.0.0.1", "a@example.com", later).unwrap();
+    rate_limit::record_login_failure(&conn, &limits, "10.0.0.1", "a@example.com", None, later).unwrap();
+    assert_eq!(
+        rate_limit::check_login(&conn, &limits, "10.0.0.1", "a@example.com", None, later).unwrap(),
+        LoginCheck::Allowed
+    );
+
+    // Only the failure after the success counts
+    let (failures, last) = db::get_account_login_failures(&conn, "a@example.com", "2026-01-01 11:45:00").unwrap();
+    assert_eq!(failures, 1);
+    assert_eq!(last.as_deref(), Some("2026-01-01 12:00:05"));
+}
+
+#[test]
+fn test_ip_limit_spans_accounts() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    let limits = RateLimits { max_failures_per_ip: 4, ..RateLimits::default() };
+
+    let now = noon();
+    for n in 0..4 {
+        let account = format!("user{}@example.com", n);
+        rate_limit::record_login_failure(&conn, &limits, "10.0.0.9", &account, None, now).unwrap();
+    }
+    let check = |ip, now| rate_limit::check_login(&conn, &limits, ip, "new@example.com", None, now).unwrap();
+    assert_eq!(check("10.0.0.9", now), LoginCheck::IpLimited);
+    assert_eq!(check("10.0.0.10", now), LoginCheck::Allowed);
+    assert_eq!(check("10.0.0.9", now + Duration::minutes(16)), LoginCheck::Allowed);
+}
+
+#[test]
+fn test_registration_limit_per_ip() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    let limits = RateLimits { max_registrations_per_ip: 2, ..RateLimits::default() };
+
+    let now = noon();
+    assert!(rate_limit::allow_registration(&conn, &limits, "10.0.0.1", now).unwrap());
+    assert!(rate_limit::allow_registration(&conn, &limits, "10.0.0.1", now).unwrap());
+    assert!(!rate_limit::allow_registration(&conn, &limits, "10.0.0.1", now).unwrap());
+    assert!(rate_limit::allow_registration(&conn, &limits, "10.0.0.2", now).unwrap());
+    assert!(rate_limit::allow_registration(&conn, &limits, "10.0.0.1", now + Duration::minutes(61)).unwrap());
+}
+
+#[test]
+fn test_password_reset_limits_per_ip_and_address() {
+    let db = setup_test_db();
+    let conn = db.write_conn();
+    let limits = RateLimits { max_password_resets_per_ip: 3, max_password_resets_per_account: 2, ..RateLimits::default() };
+
+    let now = noon();
+    let allow = |ip, account, now| rate_limit::allow_password_reset(&conn, &limits, ip, account, now).unwrap();
+    assert!(allow("10.0.0.1", "a@example.com", now));
+    assert!(allow("10.0.0.2", "a@example.com", now));
+    // The address has had its two, from any IP
+    assert!(!allow("10.0.0.3", "a@example.com", now));
+    assert!(allow("10.0.0.1", "b@example.com", now));
+    assert!(allow("10.0.0.1", "c@example.com", now));
+    // And this IP its three, for any address
+    assert!(!allow("10.0.0.1", "d@example.com", now));
+    assert!(allow("10.0.0.1", "a@example.com", now + Duration::minutes(61)));
+}
+
+/// Keeps sent messages for the test to look at
+#[derive(Default)]
+struct RecordingMailer(Mutex<Vec<Email>>);
+
+impl Mailer for RecordingMailer {
+    fn send(&self, email: &Email) -> Result<(), MailError> {
+        self.0.lock().unwrap().push(email.clone());
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_forgot_password_is_throttled_without_revealing_accounts() {
+    let db = setup_test_db();
+    db::create_user(&db.write_conn(), "mech@example.com", "hash", "mech").unwrap();
+    let mailer = Arc::new(RecordingMailer::default());
+    let limits = RateLimits { max_password_resets_per_account: 2, ..RateLimits::default() };
+    let shutdown = Shutdown::new();
+    let app = Router::new()
+        .route("/forgot-password", post(routes::auth::forgot_password_submit))
+        .layer(Extension(Mail::new(mailer.clone(), "https://wrench.example")))
+        .layer(Extension(limits))
+        .layer(Extension(shutdown.clone()))
+        .with_state((db.clone(), Arc::new(Tera::new("templates/**/*.html").unwrap())));
+    let submit = |email: &str| {
+        let request = Request::post("/forgot-password")
+            .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Body::from(format!("email={}", email)))
+            .unwrap();
+        let app = app.clone();
+        async move { app.oneshot(request).await.unwrap().status() }
+    };
+
+    // Registered or not, the same answer
+    for email in ["mech@example.com", "nobody@example.com"] {
+        assert_eq!(submit(email).await, StatusCode::OK);
+        assert_eq!(submit(email).await, StatusCode::OK);
+        assert_eq!(submit(email).await, StatusCode::TOO_MANY_REQUESTS);
+    }
+
+    // The emails go out in the background, and shutdown waits for them
+    shutdown.tasks_finished().await;
+    let sent = mailer.0.lock().unwrap();
+    assert_eq!(sent.len(), 2);
+    assert!(sent.iter().all(|email| email.to == "mech@example.com"));
+}
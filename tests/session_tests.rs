@@ -0,0 +1,76 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request},
+    middleware,
+    routing::get,
+    Extension, Router,
+};
+use axum_extra::extract::CookieJar;
+use std::sync::Arc;
+use tera::Tera;
+use tower::ServiceExt;
+use wrench_forum::config::Config;
+use wrench_forum::{auth, db};
+
+mod common;
+use common::setup_test_db;
+
+/// Echoes the session token the handler sees
+fn app(db: db::Db) -> Router {
+    let state = (db, Arc::new(Tera::default()));
+    Router::new()
+        .route("/", get(|jar: CookieJar| async move { jar.get("session").map(|c| c.value().to_string()).unwrap_or_default() }))
+        .layer(middleware::from_fn_with_state(state.clone(), auth::track_session))
+        .layer(Extension(Arc::new(Config::default())))
+        .with_state(state)
+}
+
+fn request(token: &str) -> Request<Body> {
+    Request::get("/")
+        .header(header::COOKIE, format!("csrf=abc; session={}", token))
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[tokio::test]
+async fn test_session_rotated_after_role_change() {
+    let db = setup_test_db();
+    let user_id = {
+        let conn = db.write_conn();
+        let user_id = db::create_user(&conn, "mod@example.com", "hash", "moduser").unwrap();
+        db::create_session(&conn, "old_token", user_id, "2099-01-01 00:00:00", None, None).unwrap();
+        user_id
+    };
+
+    // Nothing to do yet
+    let response = app(db.clone()).oneshot(request("old_token")).await.unwrap();
+    assert!(response.headers().get(header::SET_COOKIE).is_none());
+
+    db::update_user_role(&db.write_conn(), user_id, "moderator").unwrap();
+
+    let response = app(db.clone()).oneshot(request("old_token")).await.unwrap();
+    let set_cookie = response.headers().get(header::SET_COOKIE).unwrap().to_str().unwrap().to_string();
+    let seen = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let new_token = String::from_utf8(seen.to_vec()).unwrap();
+
+    assert_ne!(new_token, "old_token");
+    assert!(set_cookie.starts_with(&format!("session={};", new_token)));
+    assert!(set_cookie.contains("HttpOnly"));
+    assert!(db::get_session(&db.write_conn(), "old_token").unwrap().is_none());
+    assert_eq!(db::get_session(&db.write_conn(), &new_token).unwrap().unwrap().user_id, user_id);
+}
+
+#[tokio::test]
+async fn test_last_seen_refreshed_when_stale() {
+    let db = setup_test_db();
+    {
+        let conn = db.write_conn();
+        let user_id = db::create_user(&conn, "user@example.com", "hash", "someuser").unwrap();
+        db::create_session(&conn, "token", user_id, "2099-01-01 00:00:00", None, None).unwrap();
+        db::touch_session(&conn, "token", "2020-01-01 00:00:00").unwrap();
+    }
+
+    app(db.clone()).oneshot(request("token")).await.unwrap();
+    let session = db::get_session(&db.write_conn(), "token").unwrap().unwrap();
+    assert!(session.last_seen_at.unwrap().as_str() > "2020-01-01 00:00:00");
+}
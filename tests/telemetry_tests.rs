@@ -0,0 +1,147 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{header, Request, StatusCode},
+    middleware,
+    response::Response,
+    routing::get,
+    Router,
+};
+use std::sync::Arc;
+use tera::{Context, Tera};
+use tower::ServiceExt;
+use tower_http::request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer};
+use wrench_forum::config::{Config, ConfigError};
+use wrench_forum::models::ApiScope;
+use wrench_forum::{api, auth, db, routes, telemetry, torque};
+
+mod common;
+use common::setup_test_db;
+
+fn app(db: db::Db) -> Router {
+    let mut tera = Tera::new("templates/**/*.html").unwrap();
+    tera.register_filter("torque_alternate", torque::tera_filter);
+    let state = (db, Arc::new(tera));
+    Router::new()
+        .route("/post/{id}", get(routes::forum::view_post))
+        .route("/metrics", get(routes::metrics::metrics))
+        .layer(middleware::from_fn(telemetry::track_requests))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .with_state(state)
+}
+
+async fn body_text(response: Response) -> String {
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    String::from_utf8(bytes.to_vec()).unwrap()
+}
+
+/// A confirmed, signed-in user with the given role and a token with `scopes`,
+/// returning the token
+fn user_with_token(db: &db::Db, username: &str, role: &str, scopes: &[ApiScope]) -> String {
+    let conn = db.write_conn();
+    let user_id = db::create_user(&conn, &format!("{}@example.com", username), "hash", username).unwrap();
+    db::update_user_role(&conn, user_id, role).unwrap();
+    conn.execute("UPDATE users SET email_confirmed_at = datetime('now') WHERE id = ?1", [user_id]).unwrap();
+    db::create_session(&conn, &format!("{}_session", username), user_id, "2099-01-01 00:00:00", None, None).unwrap();
+    let token = api::create_token();
+    db::create_api_token(&conn, user_id, "scraper", &auth::hash_token(&token), &api::display_prefix(&token), scopes).unwrap();
+    token
+}
+
+fn metrics_request(authorization: Option<&str>, cookie: Option<&str>) -> Request<Body> {
+    let mut request = Request::get("/metrics");
+    if let Some(value) = authorization {
+        request = request.header(header::AUTHORIZATION, value);
+    }
+    if let Some(value) = cookie {
+        request = request.header(header::COOKIE, value);
+    }
+    request.body(Body::empty()).unwrap()
+}
+
+#[tokio::test]
+async fn test_requests_are_timed_by_route_with_an_id() {
+    let db = setup_test_db();
+    let token = user_with_token(&db, "admin", "admin", &[ApiScope::Metrics]);
+    let post_id = {
+        let conn = db.write_conn();
+        let user_id = db::get_user_by_username(&conn, "admin").unwrap().unwrap().id;
+        db::create_post(&conn, user_id, 1, "Timed post", "Body").unwrap()
+    };
+
+    let request = Request::get(format!("/post/{}", post_id)).header("x-request-id", "trace-me").body(Body::empty()).unwrap();
+    let response = app(db.clone()).oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["x-request-id"], "trace-me");
+
+    // One is made up when the client didn't send one
+    let response = app(db.clone()).oneshot(Request::get("/post/999999").body(Body::empty()).unwrap()).await.unwrap();
+    assert!(!response.headers()["x-request-id"].is_empty());
+
+    let response = app(db.clone()).oneshot(metrics_request(Some(&format!("Bearer {}", token)), None)).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers()[header::CONTENT_TYPE].to_str().unwrap().starts_with("text/plain"));
+    let metrics = body_text(response).await;
+    assert!(metrics.contains(r#"wrench_http_request_duration_seconds_count{method="GET",route="/post/{id}",status="200"}"#));
+    assert!(metrics.contains(r#"route="/post/{id}",status="404""#));
+    assert!(metrics.contains(r#"wrench_db_wait_seconds_count{kind="read"}"#));
+    assert!(metrics.contains(r#"operation="routes::forum::view_post""#));
+    assert!(metrics.contains(r#"wrench_template_render_duration_seconds_count{template="post.html"}"#));
+    assert!(metrics.contains("wrench_http_requests_in_flight"));
+}
+
+#[tokio::test]
+async fn test_metrics_are_for_site_managers() {
+    let db = setup_test_db();
+    let admin_token = user_with_token(&db, "admin", "admin", &[ApiScope::Metrics]);
+    let read_only = user_with_token(&db, "admin2", "admin", &[ApiScope::Read]);
+    let member_token = user_with_token(&db, "member", "verified", &[ApiScope::Metrics]);
+
+    let status = |request: Request<Body>| {
+        let app = app(db.clone());
+        async move { app.oneshot(request).await.unwrap().status() }
+    };
+    assert_eq!(status(metrics_request(None, None)).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(metrics_request(Some("Bearer wf_nonsense"), None)).await, StatusCode::UNAUTHORIZED);
+    assert_eq!(status(metrics_request(Some(&format!("Bearer {}", read_only)), None)).await, StatusCode::FORBIDDEN);
+    assert_eq!(status(metrics_request(Some(&format!("Bearer {}", member_token)), None)).await, StatusCode::FORBIDDEN);
+    assert_eq!(status(metrics_request(Some(&format!("Bearer {}", admin_token)), None)).await, StatusCode::OK);
+
+    // Or signed in, from a browser
+    assert_eq!(status(metrics_request(None, Some("session=admin_session"))).await, StatusCode::OK);
+    assert_eq!(status(metrics_request(None, Some("session=member_session"))).await, StatusCode::FORBIDDEN);
+}
+
+#[test]
+fn test_operation_names_and_render_timing() {
+    assert_eq!(telemetry::operation_name::<fn()>(), "fn()");
+    fn named<F>(_: F) -> String {
+        telemetry::operation_name::<F>()
+    }
+    assert_eq!(named(db::get_forum_stats), "db::get_forum_stats");
+    assert_eq!(named(|| ()), "telemetry_tests::test_operation_names_and_render_timing");
+
+    let mut tera = Tera::default();
+    tera.add_raw_template("hello.html", "Hello {{ name }}").unwrap();
+    let mut ctx = Context::new();
+    ctx.insert("name", "wrench");
+    assert_eq!(telemetry::render(&tera, "hello.html", &ctx).unwrap(), "Hello wrench");
+    assert!(telemetry::render(&tera, "missing.html", &ctx).is_err());
+    assert!(telemetry::gather().contains(r#"template="hello.html""#));
+}
+
+#[test]
+fn test_logging_settings_are_validated() {
+    let config = Config::from_toml("[logging]\nformat = \"json\"\nlevel = \"info,wrench_forum::db=debug\"\n").unwrap();
+    assert!(config.validate().is_ok());
+
+    let mut config = Config::default();
+    config.logging.format = "xml".to_string();
+    config.logging.level = "info,[".to_string();
+    let Err(ConfigError::Invalid(problems)) = config.validate() else {
+        panic!("expected the config to be rejected");
+    };
+    assert_eq!(problems.len(), 2);
+    assert!(problems.iter().any(|p| p.starts_with("logging.format")));
+    assert!(problems.iter().any(|p| p.starts_with("logging.level")));
+}
@@ -0,0 +1,56 @@
+use wrench_forum::torque::{self, TorqueValues};
+
+#[test]
+fn test_normalize_unit() {
+    for text in ["Nm", "N·m", "n-m", "N.m"] {
+        assert_eq!(torque::normalize_unit(text), Some("Nm"), "{}", text);
+    }
+    for text in ["ft-lb", "ft-lbs", "ft. lbs", "lb-ft", "lbs ft", "ft-lbf"] {
+        assert_eq!(torque::normalize_unit(text), Some("ft-lb"), "{}", text);
+    }
+    for text in ["in-lb", "in-lbs", "in. lbs", "lb-in"] {
+        assert_eq!(torque::normalize_unit(text), Some("in-lb"), "{}", text);
+    }
+    assert_eq!(torque::normalize_unit("psi"), None);
+    assert_eq!(torque::normalize_unit("lb"), None);
+}
+
+#[test]
+fn test_convert() {
+    let close = |a: f64, b: f64| (a - b).abs() < 0.01;
+    assert!(close(torque::convert(100.0, "ft-lb", "Nm").unwrap(), 135.58));
+    assert!(close(torque::convert(135.58, "Nm", "ft-lb").unwrap(), 100.0));
+    assert!(close(torque::convert(10.0, "ft-lb", "in-lb").unwrap(), 120.0));
+    assert!(close(torque::convert(120.0, "in-lb", "Nm").unwrap(), 13.56));
+    assert_eq!(torque::convert(10.0, "ft-lb", "psi"), None);
+
+    assert_eq!(
+        TorqueValues::new(80.0, "ft-lb"),
+        Some(TorqueValues { nm: 108.5, ft_lb: 80.0, in_lb: 960.0 })
+    );
+}
+
+#[test]
+fn test_alternate_unit() {
+    assert_eq!(torque::alternate(80.0, "ft-lb").as_deref(), Some("108.5 Nm"));
+    assert_eq!(torque::alternate(100.0, "Nm").as_deref(), Some("73.8 ft-lb"));
+    // Small metric values read better in in-lb
+    assert_eq!(torque::alternate(10.0, "Nm").as_deref(), Some("88.5 in-lb"));
+    assert_eq!(torque::alternate(89.0, "in-lb").as_deref(), Some("10.1 Nm"));
+    assert_eq!(torque::format_value(12.0), "12");
+}
+
+#[test]
+fn test_find_in_text() {
+    let text = "Drain plug 30 Nm, head bolts 22 lb-ft + 90°, 5 lbs in the bed";
+    let found = torque::find_in_text(text);
+    assert_eq!(found.len(), 2);
+    assert_eq!(&text[found[0].0.clone()], "30 Nm");
+    assert_eq!(found[0].1, "22.1 ft-lb");
+    assert_eq!(&text[found[1].0.clone()], "22 lb-ft");
+    assert_eq!(found[1].1, "29.8 Nm");
+
+    // Authors who already gave both units don't get a third
+    assert!(torque::find_in_text("25 Nm / 18 ft-lb").is_empty());
+    assert_eq!(torque::find_in_text("25 Nm then 18 ft-lb").len(), 2);
+}
@@ -0,0 +1,99 @@
+use wrench_forum::totp;
+
+/// The ASCII secret from the RFC 4226 and RFC 6238 test vectors
+const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+#[test]
+fn test_base32_round_trip() {
+    // RFC 4648 section 10, without padding
+    let vectors = [
+        ("", ""),
+        ("f", "MY"),
+        ("fo", "MZXQ"),
+        ("foo", "MZXW6"),
+        ("foob", "MZXW6YQ"),
+        ("fooba", "MZXW6YTB"),
+        ("foobar", "MZXW6YTBOI"),
+    ];
+    for (plain, encoded) in vectors {
+        assert_eq!(totp::base32_encode(plain.as_bytes()), encoded);
+        assert_eq!(totp::base32_decode(encoded).unwrap(), plain.as_bytes());
+    }
+
+    assert_eq!(totp::base32_decode("mzxw 6ytb-oi======").unwrap(), b"foobar");
+    assert_eq!(totp::base32_decode("MZXW1"), None);
+}
+
+#[test]
+fn test_hotp_rfc4226_vectors() {
+    let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+    for (counter, value) in expected.into_iter().enumerate() {
+        assert_eq!(totp::hotp(RFC_SECRET, counter as u64, 6), value, "counter {}", counter);
+    }
+}
+
+#[test]
+fn test_totp_rfc6238_vectors() {
+    // The SHA1 rows of RFC 6238 appendix B, cut to six digits
+    let vectors = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+        (20000000000, "353130"),
+    ];
+    for (time, code) in vectors {
+        assert_eq!(totp::code_at(RFC_SECRET, time), code, "time {}", time);
+    }
+    assert_eq!(totp::hotp(RFC_SECRET, totp::step_at(59), 8), 94287082);
+}
+
+#[test]
+fn test_verify_allows_drift_and_rejects_replay() {
+    let secret = totp::generate_secret();
+    assert_eq!(secret.len(), totp::SECRET_LEN);
+
+    let now = 1_700_000_000;
+    let step = totp::step_at(now);
+    let code = totp::code_at(&secret, now);
+
+    assert_eq!(totp::verify(&secret, &code, now, None), Some(step));
+    assert_eq!(totp::verify(&secret, &format!("{} {}", &code[..3], &code[3..]), now, None), Some(step));
+    // The phone a step ahead or behind
+    assert_eq!(totp::verify(&secret, &code, now + totp::STEP_SECONDS, None), Some(step));
+    assert_eq!(totp::verify(&secret, &code, now - totp::STEP_SECONDS, None), Some(step));
+    assert_eq!(totp::verify(&secret, &code, now + 3 * totp::STEP_SECONDS, None), None);
+
+    // Already used
+    assert_eq!(totp::verify(&secret, &code, now, Some(step)), None);
+    assert_eq!(totp::verify(&secret, &code, now, Some(step - 1)), Some(step));
+
+    assert_eq!(totp::verify(&secret, "12345", now, None), None);
+    assert_eq!(totp::verify(&secret, "abcdef", now, None), None);
+}
+
+#[test]
+fn test_provisioning_uri() {
+    let uri = totp::provisioning_uri("Wrench Forum", "joe+cars@example.com", b"foobar");
+    assert_eq!(
+        uri,
+        "otpauth://totp/Wrench%20Forum:joe%2Bcars@example.com?secret=MZXW6YTBOI&issuer=Wrench%20Forum&algorithm=SHA1&digits=6&period=30"
+    );
+    assert!(totp::qr_svg(&uri).unwrap().contains("<svg"));
+}
+
+#[test]
+fn test_recovery_codes() {
+    let codes = totp::generate_recovery_codes(10);
+    assert_eq!(codes.len(), 10);
+    for code in &codes {
+        assert_eq!(code.len(), 11);
+        assert_eq!(&code[5..6], "-");
+        assert_eq!(totp::normalize_recovery_code(&code.to_uppercase()), code.replace('-', ""));
+    }
+    let mut unique = codes.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), codes.len());
+}
@@ -0,0 +1,74 @@
+use wrench_forum::vin::{self, VinError};
+
+#[test]
+fn test_check_digit() {
+    // The worked example from the check digit specification
+    assert_eq!(vin::check_digit("1M8GDM9AXKP042788"), Some('X'));
+    assert_eq!(vin::check_digit("1HGFA16586L000000"), Some('8'));
+    assert_eq!(vin::check_digit("1HGFA165"), None);
+}
+
+#[test]
+fn test_decode_north_american_vin() {
+    let decoded = vin::decode("1hgfa16586l000000").unwrap();
+
+    assert_eq!(decoded.vin, "1HGFA16586L000000");
+    assert_eq!(decoded.wmi, "1HG");
+    assert!(decoded.check_digit_valid);
+    assert_eq!(decoded.region, "North America");
+    assert_eq!(decoded.country.as_deref(), Some("United States"));
+    assert_eq!(decoded.make.as_deref(), Some("Honda"));
+    assert_eq!(decoded.model.as_deref(), Some("Civic"));
+    assert_eq!(decoded.model_year, Some(2006));
+    assert_eq!(decoded.engine_code.as_deref(), Some("R18A1"));
+    assert_eq!(decoded.serial, "000000");
+
+    let details = decoded.vehicle_details().unwrap();
+    assert_eq!((details.year, details.make.as_str(), details.model.as_str()), (2006, "Honda", "Civic"));
+}
+
+#[test]
+fn test_decode_model_year_cycle() {
+    // A letter in position 7 puts the year code in the 2010-2039 cycle
+    let accord = vin::decode("1HGCP3F89AA012345").unwrap();
+    assert_eq!(accord.model_year, Some(2010));
+    assert_eq!(accord.engine_code.as_deref(), Some("J35Z2"));
+
+    let mustang = vin::decode("1ZVFT82H055000123").unwrap();
+    assert_eq!(mustang.model_year, Some(2005));
+    assert_eq!(mustang.trim.as_deref(), Some("GT"));
+}
+
+#[test]
+fn test_decode_rejects_bad_vins() {
+    assert_eq!(vin::decode("1HGFA1658").unwrap_err(), VinError::Length(9));
+    assert_eq!(vin::decode("1HGFA1658OL000000").unwrap_err(), VinError::InvalidChar('O'));
+    assert_eq!(
+        vin::decode("1HGFA16596L000000").unwrap_err(),
+        VinError::CheckDigit { expected: '8', found: '9' }
+    );
+
+    // Spaces and dashes from copy/paste are ignored
+    assert!(vin::decode(" 1HG-FA165-86L000000 ").is_ok());
+}
+
+#[test]
+fn test_decode_partial_matches() {
+    // Outside North America the check digit is advisory
+    let jetta = vin::decode("WVWZZZ1KZ8W000001").unwrap();
+    assert!(!jetta.check_digit_valid);
+    assert_eq!(jetta.country.as_deref(), Some("Germany"));
+    assert_eq!(jetta.model.as_deref(), Some("Jetta"));
+    assert_eq!(jetta.model_year, Some(2008));
+
+    let ford = vin::decode("1FTFW1EF2DFA00001").unwrap();
+    assert_eq!(ford.make.as_deref(), Some("Ford"));
+    assert_eq!(ford.model_year, Some(2013));
+    assert_eq!(ford.model.as_deref(), Some("F-150"));
+
+    // Known manufacturer, model line not in the dataset
+    let unknown = vin::decode("1FAFP4044WF000000").unwrap();
+    assert_eq!(unknown.make.as_deref(), Some("Ford"));
+    assert!(unknown.model.is_none());
+    assert!(unknown.vehicle_details().is_none());
+}